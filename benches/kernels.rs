@@ -0,0 +1,85 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+// These benchmarks measure the routines in the dispatch table individually,
+// once for the scalar kernels and once for the kernels of the detected level,
+// so a specialized kernel can be compared against the portable one.
+
+#![feature(test)]
+
+extern crate claxon;
+extern crate test;
+
+use claxon::dispatch::{self, Kernels, SimdLevel};
+use test::Bencher;
+
+fn bench_predict_lpc_low_order(kernels: &Kernels, bencher: &mut Bencher) {
+    let coefficients = [-75, 166, 121, -269, -75, -399, 1042, 119, -255, 555, -836, 879];
+    let residual: Vec<i32> = (0..4096).map(|i| (i * 37 % 201) - 100).collect();
+    let mut buffer = residual.clone();
+    bencher.bytes = 4096 * 4;
+    bencher.iter(|| {
+        buffer.copy_from_slice(&residual);
+        (kernels.predict_lpc_low_order)(&coefficients, 12, &mut buffer);
+        test::black_box(&buffer);
+    });
+}
+
+fn bench_interleave_pcm_le(kernels: &Kernels, bencher: &mut Bencher) {
+    let planar: Vec<i32> = (0..2 * 4096).map(|i| (i * 37 % 65536) - 32768).collect();
+    let mut output = vec![0u8; 2 * 4096 * 2];
+    bencher.bytes = output.len() as u64;
+    bencher.iter(|| {
+        (kernels.interleave_pcm_le)(&planar, 4096, 2, 2, 0, 0, 0, &mut output);
+        test::black_box(&output);
+    });
+}
+
+fn bench_crc16_update(kernels: &Kernels, bencher: &mut Bencher) {
+    let data: Vec<u8> = (0..16384).map(|i| (i * 7 + i / 13) as u8).collect();
+    bencher.bytes = data.len() as u64;
+    bencher.iter(|| test::black_box((kernels.crc16_update)(0, &data)));
+}
+
+#[bench]
+fn bench_predict_lpc_low_order_scalar(bencher: &mut Bencher) {
+    bench_predict_lpc_low_order(dispatch::kernels_for(SimdLevel::Scalar), bencher);
+}
+
+#[bench]
+fn bench_predict_lpc_low_order_detected(bencher: &mut Bencher) {
+    bench_predict_lpc_low_order(dispatch::kernels(), bencher);
+}
+
+#[bench]
+fn bench_predict_lpc_low_order_mac32(bencher: &mut Bencher) {
+    let kernels = Kernels {
+        predict_lpc_low_order: claxon::subframe::predict_lpc_low_order_mac32,
+        ..*dispatch::kernels_for(SimdLevel::Scalar)
+    };
+    bench_predict_lpc_low_order(&kernels, bencher);
+}
+
+#[bench]
+fn bench_interleave_pcm_le_scalar(bencher: &mut Bencher) {
+    bench_interleave_pcm_le(dispatch::kernels_for(SimdLevel::Scalar), bencher);
+}
+
+#[bench]
+fn bench_interleave_pcm_le_detected(bencher: &mut Bencher) {
+    bench_interleave_pcm_le(dispatch::kernels(), bencher);
+}
+
+#[bench]
+fn bench_crc16_update_scalar(bencher: &mut Bencher) {
+    bench_crc16_update(dispatch::kernels_for(SimdLevel::Scalar), bencher);
+}
+
+#[bench]
+fn bench_crc16_update_detected(bencher: &mut Bencher) {
+    bench_crc16_update(dispatch::kernels(), bencher);
+}
@@ -97,3 +97,122 @@ fn bench_p3_stereo_16bit(bencher: &mut Bencher) {
 fn bench_p4_stereo_16bit(bencher: &mut Bencher) {
     bench_decode("testsamples/p4.flac", bencher);
 }
+
+/// Computes the CRC-8 of a frame header, with polynomial x^8 + x^2 + x + 1.
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Computes the CRC-16 of a frame, with polynomial x^16 + x^15 + x^2 + 1.
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Builds frames of 16-bit stereo audio stored as 24 bits per sample.
+///
+/// The frames use left-side stereo, with 8 wasted bits per sample in both
+/// subframes. The subframes are constant, so decoding them is mostly a matter
+/// of passes over the buffer, which is what the wasted bits affect. Claxon has
+/// no encoder, so the frames are assembled here.
+fn build_stereo_wasted_bits_frames(n_frames: u8) -> Vec<u8> {
+    let mut data = Vec::new();
+    for frame in 0..n_frames {
+        assert!(frame < 0x80, "frame number must be a single byte");
+
+        // Block size 4096, 44.1 kHz, left-side stereo, 24 bits per sample.
+        let mut bytes = vec![0xff, 0xf8, 0b1100_1001, 0b1000_1100, frame];
+        let header_crc = crc8(&bytes);
+        bytes.push(header_crc);
+
+        let mut acc = 0u64;
+        let mut n_acc = 0;
+        {
+            let mut write_bits = |value: u64, n: u32| {
+                acc = (acc << n) | (value & ((1 << n) - 1));
+                n_acc += n;
+                while n_acc >= 8 {
+                    n_acc -= 8;
+                    bytes.push((acc >> n_acc) as u8);
+                }
+            };
+            let left = frame as u64 * 997;
+            let side = frame as u64 * 331;
+            for &(value, bits) in &[(left, 16), (side, 17)] {
+                // A constant subframe with 8 wasted bits, unary coded as 7 + 1.
+                write_bits(0b0_000000_1, 8);
+                write_bits(0b0000_0001, 8);
+                write_bits(value, bits);
+            }
+            // The subframes take 65 bits, pad them to a byte boundary.
+            write_bits(0, 7);
+        }
+
+        let frame_crc = crc16(&bytes);
+        bytes.push((frame_crc >> 8) as u8);
+        bytes.push(frame_crc as u8);
+        data.extend_from_slice(&bytes);
+    }
+    data
+}
+#[bench]
+fn bench_stereo_24bit_wasted_bits(bencher: &mut Bencher) {
+    let data = build_stereo_wasted_bits_frames(32);
+    let mut buffer = Vec::new();
+    bencher.iter(|| {
+        let mut reader = claxon::frame::FrameReader::new(Cursor::new(&data[..]));
+        while let Some(block) = reader.read_next_or_eof(buffer.split_off(0)).unwrap() {
+            buffer = test::black_box(block.into_buffer());
+        }
+    });
+    bencher.bytes = 32 * 4096 * 2 * 3;
+}
+
+#[bench]
+fn bench_stereo_24bit_interleaved(bencher: &mut Bencher) {
+    let data = build_stereo_wasted_bits_frames(32);
+    let mut buffer = Vec::new();
+    bencher.iter(|| {
+        let mut reader = claxon::frame::FrameReader::new(Cursor::new(&data[..]));
+        while let Some(block) = reader.read_next_interleaved_or_eof(buffer.split_off(0))
+                                      .unwrap() {
+            buffer = test::black_box(block.into_buffer());
+        }
+    });
+    bencher.bytes = 32 * 4096 * 2 * 3;
+}
+
+#[bench]
+fn bench_non_subset_stereo_24bit(bencher: &mut Bencher) {
+    // Unlike the files above, this one is in the repository, so it is always
+    // available. Its frames start at byte 42, after the streaminfo block. The
+    // input is buffered, like it is for a `FlacReader`.
+    let mut data = Vec::new();
+    File::open("testsamples/non_subset.flac").unwrap().read_to_end(&mut data).unwrap();
+    let frames = &data[42..];
+    let mut buffer = Vec::new();
+    let mut bytes = 0;
+    bencher.iter(|| {
+        bytes = 0;
+        let input = claxon::input::BufferedReader::new(Cursor::new(frames));
+        let mut reader = claxon::frame::FrameReader::new(input);
+        while let Some(block) = reader.read_next_or_eof(buffer.split_off(0)).unwrap() {
+            bytes += block.len() as u64 * 3;
+            buffer = test::black_box(block.into_buffer());
+        }
+    });
+    bencher.bytes = bytes;
+}
@@ -0,0 +1,173 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+// This file implements `flacdump`, a tool that prints low-level information
+// about the structure of FLAC files. With `--frames`, it prints a table of all
+// frames, with their offsets, times, sizes, and CRC status. With `--explain`,
+// it prints every element of the bitstream of every frame. With `--widths`, it
+// prints how the sample width of every subframe comes about: the bits per
+// sample of the frame, the extra bit of a side channel, and the wasted bits.
+// With `--stats`, it decodes all frames, and prints the compression ratio and
+// a histogram of the frame sizes. It is built only with the `tools` feature
+// enabled.
+
+extern crate claxon;
+
+use claxon::frame::FrameReader;
+use claxon::input::BufferedReader;
+use claxon::metadata::MetadataBlockReader;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::process;
+
+/// Opens the file, and skips over the metadata blocks to the first frame.
+fn open_frames(fname: &str) -> claxon::Result<BufferedReader<fs::File>> {
+    let mut blocks = try!(MetadataBlockReader::open(fname));
+    for block_result in &mut blocks {
+        try!(block_result);
+    }
+    Ok(blocks.into_inner())
+}
+
+fn explain_frames(fname: &str) -> claxon::Result<()> {
+    let reader = try!(open_frames(fname));
+    let mut frame_reader = FrameReader::new(reader);
+
+    println!("{}:", fname);
+    let mut frame_index = 0u64;
+    while let Some(elements) = try!(frame_reader.explain_next_or_eof()) {
+        println!("frame {}", frame_index);
+        for element in elements {
+            println!("  {:?}", element);
+        }
+        frame_index += 1;
+    }
+
+    Ok(())
+}
+
+fn dump_widths(fname: &str) -> claxon::Result<()> {
+    let reader = try!(open_frames(fname));
+    let mut frame_reader = FrameReader::new(reader);
+
+    println!("{}:", fname);
+    println!("{:>8} {:>12}  {}", "frame", "time", "bps+side-wasted=coded per subframe");
+
+    let mut buffer = Vec::new();
+    let mut frame_index = 0u64;
+    while let Some(block) = try!(frame_reader.read_next_or_eof(buffer)) {
+        let widths: Vec<String> = frame_reader.subframe_widths().iter().map(|w| {
+            let side = if w.is_side { "+1" } else { "" };
+            format!("{}{}-{}={}", w.frame_bits_per_sample, side, w.wasted_bits,
+                    w.coded_bits_per_sample())
+        }).collect();
+        println!("{:>8} {:>12}  {}", frame_index, block.time(), widths.join(" "));
+        buffer = block.into_buffer();
+        frame_index += 1;
+    }
+
+    Ok(())
+}
+
+fn dump_stats(fname: &str) -> claxon::Result<()> {
+    let reader = try!(open_frames(fname));
+    let mut frame_reader = FrameReader::new(reader);
+    frame_reader.set_collect_stats(true);
+
+    let mut buffer = Vec::new();
+    while let Some(block) = try!(frame_reader.read_next_or_eof(buffer)) {
+        buffer = block.into_buffer();
+    }
+
+    let stats = frame_reader.frame_stats();
+    println!("{}:", fname);
+    println!("  frames: {}", stats.frames);
+    println!("  samples: {}", stats.samples);
+    println!("  compressed bytes: {}", stats.compressed_bytes);
+    println!("  uncompressed bytes: {}", (stats.uncompressed_bits + 7) / 8);
+    match stats.compression_ratio() {
+        Some(ratio) => println!("  compression ratio: {:.3}", ratio),
+        None => println!("  compression ratio: -"),
+    }
+    println!("  frame size: {} to {} bytes", stats.min_frame_size, stats.max_frame_size);
+    for (class, &count) in stats.size_histogram.iter().enumerate() {
+        if count > 0 {
+            println!("  {:>8} to {:>8} bytes: {}", 1u32 << class, (2u32 << class) - 1, count);
+        }
+    }
+
+    Ok(())
+}
+
+fn dump_frames(fname: &str) -> claxon::Result<()> {
+    let mut reader = try!(open_frames(fname));
+
+    println!("{}:", fname);
+    println!("{:>8} {:>10} {:>12} {:>8} {:>8}  {}", "frame", "offset", "time", "samples", "bytes", "crc");
+
+    let mut buffer = Vec::new();
+    let mut frame_index = 0u64;
+    loop {
+        let offset = reader.position();
+        let mut frame_reader = FrameReader::new(&mut reader);
+        match frame_reader.read_next_or_eof(buffer) {
+            Ok(Some(block)) => {
+                let size = frame_reader.into_inner().position() - offset;
+                println!("{:>8} {:>10} {:>12} {:>8} {:>8}  ok",
+                         frame_index, offset, block.time(), block.duration(), size);
+                buffer = block.into_buffer();
+            }
+            Ok(None) => break,
+            Err(err) => {
+                // If the frame was read completely, only its checksum was
+                // wrong, and we can continue with the next frame.
+                if !frame_reader.is_at_frame_boundary() {
+                    return Err(err);
+                }
+                let size = frame_reader.into_inner().position() - offset;
+                println!("{:>8} {:>10} {:>12} {:>8} {:>8}  {}",
+                         frame_index, offset, "-", "-", size, err);
+                buffer = Vec::new();
+            }
+        }
+        frame_index += 1;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let modes = ["--frames", "--explain", "--widths", "--stats"];
+    if args.len() < 2 || !modes.contains(&&args[0][..]) {
+        writeln!(io::stderr(), "usage: flacdump --frames <file.flac>...").unwrap();
+        writeln!(io::stderr(), "       flacdump --explain <file.flac>...").unwrap();
+        writeln!(io::stderr(), "       flacdump --widths <file.flac>...").unwrap();
+        writeln!(io::stderr(), "       flacdump --stats <file.flac>...").unwrap();
+        process::exit(1);
+    }
+
+    let mut has_failed = false;
+    for fname in &args[1..] {
+        let result = match &args[0][..] {
+            "--explain" => explain_frames(fname),
+            "--widths" => dump_widths(fname),
+            "--stats" => dump_stats(fname),
+            _ => dump_frames(fname),
+        };
+        if let Err(err) = result {
+            writeln!(io::stderr(), "{}: error: {}", fname, err).unwrap();
+            has_failed = true;
+        }
+    }
+
+    if has_failed {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,142 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+// This file implements `flacinfo`, a tool that prints the metadata of FLAC
+// files: the streaminfo, tags, seek table, and a summary of the other metadata
+// blocks. It is built only with the `tools` feature enabled.
+
+extern crate claxon;
+
+use claxon::input::{BufferedReader, ReadBytes};
+use claxon::metadata::{MetadataBlock, MetadataBlockReader, StreamInfo};
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::process;
+
+fn print_streaminfo(si: &StreamInfo) {
+    println!("  min block size: {}", si.min_block_size);
+    println!("  max block size: {}", si.max_block_size);
+    match si.min_frame_size {
+        Some(size) => println!("  min frame size: {}", size),
+        None => println!("  min frame size: unknown"),
+    }
+    match si.max_frame_size {
+        Some(size) => println!("  max frame size: {}", size),
+        None => println!("  max frame size: unknown"),
+    }
+    println!("  sample rate: {} Hz", si.sample_rate);
+    println!("  channels: {}", si.channels);
+    println!("  bits per sample: {}", si.bits_per_sample);
+    match si.samples {
+        Some(samples) => {
+            let seconds = samples as f64 / si.sample_rate as f64;
+            println!("  total samples: {} ({:.3} s)", samples, seconds);
+        }
+        None => println!("  total samples: unknown"),
+    }
+    print!("  md5sum: ");
+    for b in &si.md5sum {
+        print!("{:02x}", b);
+    }
+    println!("");
+}
+
+fn print_metadata(fname: &str) -> claxon::Result<()> {
+    let file = try!(fs::File::open(fname));
+    let mut reader = BufferedReader::new(file);
+
+    if try!(reader.read_be_u32()) != 0x664c6143 {
+        return Err(claxon::Error::FormatError("invalid stream header"));
+    }
+
+    println!("{}:", fname);
+
+    let mut num_pictures = 0;
+    for (i, block_result) in MetadataBlockReader::new(&mut reader).enumerate() {
+        match try!(block_result) {
+            MetadataBlock::StreamInfo(si) => {
+                println!("block {}: streaminfo", i);
+                print_streaminfo(&si);
+            }
+            MetadataBlock::Padding { length } => {
+                println!("block {}: padding, {} bytes", i, length);
+            }
+            MetadataBlock::Application { id, data } => {
+                println!("block {}: application {:08x}, {} bytes", i, id, data.len());
+            }
+            MetadataBlock::SeekTable(table) => {
+                println!("block {}: seek table, {} points", i, table.seekpoints().len());
+                for point in table.seekpoints() {
+                    if point.sample == 0xffff_ffff_ffff_ffff {
+                        println!("  placeholder");
+                    } else {
+                        println!("  sample {}, offset {}, {} samples",
+                                 point.sample, point.offset, point.samples);
+                    }
+                }
+            }
+            MetadataBlock::VorbisComment(vc) => {
+                println!("block {}: vorbis comment, {} tags", i, vc.len());
+                println!("  vendor: {}", vc.vendor);
+                for (name, value) in vc.tags() {
+                    println!("  {}={}", name, value);
+                }
+            }
+            MetadataBlock::CueSheet(cue_sheet) => {
+                println!("block {}: cue sheet, {} tracks", i, cue_sheet.tracks.len());
+                if !cue_sheet.media_catalog_number.is_empty() {
+                    println!("  media catalog number: {}", cue_sheet.media_catalog_number);
+                }
+                for track in &cue_sheet.tracks {
+                    print!("  track {}, offset {}", track.number, track.offset);
+                    if let Some(ref isrc) = track.isrc {
+                        print!(", isrc {}", isrc);
+                    }
+                    println!(", {} index points", track.indices.len());
+                }
+            }
+            MetadataBlock::Picture(picture) => {
+                println!("block {}: picture, type {}, {} bytes",
+                         i, picture.picture_type, picture.data.len());
+                println!("  {}, {}x{}, {} bits per pixel",
+                         picture.mime_type, picture.width, picture.height, picture.depth);
+                if !picture.description.is_empty() {
+                    println!("  description: {}", picture.description);
+                }
+                num_pictures += 1;
+            }
+            MetadataBlock::Reserved => {
+                println!("block {}: reserved block type", i);
+            }
+        }
+    }
+
+    println!("pictures: {}", num_pictures);
+    Ok(())
+}
+
+fn main() {
+    let fnames: Vec<String> = env::args().skip(1).collect();
+    if fnames.is_empty() {
+        writeln!(io::stderr(), "usage: flacinfo <file.flac>...").unwrap();
+        process::exit(1);
+    }
+
+    let mut has_failed = false;
+    for fname in &fnames {
+        if let Err(err) = print_metadata(fname) {
+            writeln!(io::stderr(), "{}: error: {}", fname, err).unwrap();
+            has_failed = true;
+        }
+    }
+
+    if has_failed {
+        process::exit(1);
+    }
+}
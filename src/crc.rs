@@ -6,6 +6,7 @@
 // A copy of the License has been included in the root of the repository.
 
 use std::io;
+use dispatch;
 use input::ReadBytes;
 
 // These tables were taken from the tables in crc.c in libflac.
@@ -70,6 +71,7 @@ pub struct Crc8Reader<R: ReadBytes> {
 pub struct Crc16Reader<R: ReadBytes> {
     inner: R,
     state: u16,
+    num_read: u32,
 }
 
 impl<R: ReadBytes> Crc8Reader<R> {
@@ -98,6 +100,7 @@ impl<R: ReadBytes> Crc16Reader<R> {
         Crc16Reader {
             inner: inner,
             state: 0,
+            num_read: 0,
         }
     }
 
@@ -106,12 +109,53 @@ impl<R: ReadBytes> Crc16Reader<R> {
         self.state
     }
 
+    /// Returns the number of bytes read thus far.
+    pub fn num_read(&self) -> u32 {
+        self.num_read
+    }
+
     #[inline(always)]
     fn update_state(&mut self, byte: u8) {
+        self.num_read = self.num_read.wrapping_add(1);
         self.state = (self.state << 8) ^ CRC16_TABLE[((self.state >> 8) as u8 ^ byte) as usize];
     }
 }
 
+/// Computes the CRC-8 of the data, with the same parameters as `Crc8Reader`.
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |state, &byte| CRC8_TABLE[(state ^ byte) as usize])
+}
+
+/// Computes the CRC-16 of the data, with the same parameters as `Crc16Reader`.
+pub fn crc16(data: &[u8]) -> u16 {
+    let crc16_update = dispatch::kernels().crc16_update;
+    crc16_update(0, data)
+}
+
+/// Continues a CRC-16 computation from `state` over the data.
+///
+/// This is the scalar kernel, `crc16()` calls the one selected by `dispatch`.
+pub fn crc16_update(state: u16, data: &[u8]) -> u16 {
+    data.iter().fold(state, |state, &byte| {
+        (state << 8) ^ CRC16_TABLE[((state >> 8) as u8 ^ byte) as usize]
+    })
+}
+
+/// The initial state of a 64-bit FNV-1a hash.
+pub const FNV1A_64_INIT: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Continues a 64-bit FNV-1a hash from `state` over the data.
+///
+/// Unlike the CRCs, this is not part of the format. It is a simple hash to
+/// detect changes in data, start with `FNV1A_64_INIT`. It processes one byte
+/// at a time, so the result does not depend on how the data is split into
+/// chunks, but it is not as fast as hashes that process a word at a time.
+pub fn fnv1a_64_update(state: u64, data: &[u8]) -> u64 {
+    data.iter().fold(state, |state, &byte| {
+        (state ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
 impl<R: ReadBytes> ReadBytes for Crc8Reader<R> {
     #[inline(always)]
     fn read_u8(&mut self) -> io::Result<u8> {
@@ -199,6 +243,7 @@ fn verify_crc8_test_vectors() {
     verify_crc8(vec![0x1f], 0x5d);
     verify_crc8(vec![0x04, 0x01], 0x53);
     verify_crc8(vec![0x61, 0x62, 0x63], 0x5f);
+    assert_eq!(crc8(&[0x61, 0x62, 0x63]), 0x5f);
 }
 
 #[test]
@@ -206,4 +251,14 @@ fn verify_crc16_test_vectors() {
     verify_crc16(vec![0x1f], 0x8041);
     verify_crc16(vec![0x04, 0x01], 0x1806);
     verify_crc16(vec![0x61, 0x62, 0x63], 0xcadb);
+    assert_eq!(crc16(&[0x61, 0x62, 0x63]), 0xcadb);
+}
+
+#[test]
+fn verify_fnv1a_64_test_vectors() {
+    assert_eq!(fnv1a_64_update(FNV1A_64_INIT, b""), 0xcbf29ce484222325);
+    assert_eq!(fnv1a_64_update(FNV1A_64_INIT, b"a"), 0xaf63dc4c8601ec8c);
+    assert_eq!(fnv1a_64_update(FNV1A_64_INIT, b"foobar"), 0x85944171f73967e8);
+    let state = fnv1a_64_update(FNV1A_64_INIT, b"foo");
+    assert_eq!(fnv1a_64_update(state, b"bar"), 0x85944171f73967e8);
 }
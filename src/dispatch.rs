@@ -0,0 +1,278 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `dispatch` module selects implementations of hot decoding routines.
+//!
+//! Some routines, such as LPC prediction, benefit from instruction set
+//! extensions that not every CPU has. Such routines are called through a table
+//! of function pointers, the `Kernels`, which is selected once based on the
+//! detected `SimdLevel`. Every table must produce exactly the same output as
+//! the scalar one; the tests in this module check that for all tables.
+//!
+//! Claxon supports Rust versions that predate `std::arch`, so the level is
+//! currently detected from the target features enabled at compile time, and
+//! all levels use the portable kernels, which the compiler vectorizes where it
+//! can. The table is the extension point for specialized kernels.
+//!
+//! On 32-bit targets, the scalar table uses LPC prediction with 32x32 to
+//! 64-bit multiply-accumulates, see `subframe::predict_lpc_low_order_mac32()`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+// `AtomicUsize::new` is not a const fn in the oldest Rust versions we support.
+#[allow(deprecated)]
+use std::sync::atomic::ATOMIC_USIZE_INIT;
+
+use crc;
+use frame;
+use subframe;
+
+/// An instruction set extension that decoding routines can take advantage of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimdLevel {
+    /// No extensions, only portable code.
+    Scalar,
+    /// x86 SSE 4.1.
+    Sse41,
+    /// x86 AVX2.
+    Avx2,
+    /// ARM NEON.
+    Neon,
+}
+
+impl SimdLevel {
+    fn from_index(index: usize) -> SimdLevel {
+        match index {
+            0 => SimdLevel::Scalar,
+            1 => SimdLevel::Sse41,
+            2 => SimdLevel::Avx2,
+            3 => SimdLevel::Neon,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns whether a CPU at this level can run code that requires `other`.
+    fn includes(self, other: SimdLevel) -> bool {
+        match (self, other) {
+            (_, SimdLevel::Scalar) => true,
+            (SimdLevel::Avx2, SimdLevel::Sse41) => true,
+            (a, b) => a == b,
+        }
+    }
+}
+
+/// Detects the best extension available, this is constant for a build.
+fn detect() -> SimdLevel {
+    if cfg!(target_feature = "avx2") {
+        SimdLevel::Avx2
+    } else if cfg!(target_feature = "sse4.1") {
+        SimdLevel::Sse41
+    } else if cfg!(all(target_arch = "aarch64", target_feature = "neon")) {
+        SimdLevel::Neon
+    } else {
+        SimdLevel::Scalar
+    }
+}
+
+/// The detected level plus one, or zero if detection has not happened yet.
+#[allow(deprecated)]
+static DETECTED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Returns the instruction set extensions that were detected.
+///
+/// This reports what the CPU supports, as far as Claxon can tell, not which
+/// kernels are used: there are no specialized kernels yet, so decoding uses
+/// the scalar kernels at every level, see `kernels_for()`. Detection happens
+/// on the first call, the result is cached afterwards.
+pub fn simd_level() -> SimdLevel {
+    match DETECTED.load(Ordering::Relaxed) {
+        0 => {
+            let level = detect();
+            DETECTED.store(level as usize + 1, Ordering::Relaxed);
+            level
+        }
+        n => SimdLevel::from_index(n - 1),
+    }
+}
+
+/// Implementations of the routines that have specialized versions.
+///
+/// This is a low-level interface, exposed for benchmarking and for testing the
+/// specialized kernels against the scalar ones. The arguments are those of the
+/// scalar implementations that are referenced in the field documentation.
+pub struct Kernels {
+    /// The extension that these kernels require.
+    pub level: SimdLevel,
+
+    /// LPC prediction for orders of at most 12, see `subframe::predict_lpc_low_order()`.
+    pub predict_lpc_low_order: fn(&[i16], i16, &mut [i32]),
+
+    /// Interleaving into packed PCM, see `frame::interleave_pcm_le()`.
+    pub interleave_pcm_le: fn(&[i32], usize, usize, usize, u32, u32, usize, &mut [u8]),
+
+    /// CRC-16 over a slice of bytes, see `crc::crc16_update()`.
+    pub crc16_update: fn(u16, &[u8]) -> u16,
+}
+
+static SCALAR: Kernels = Kernels {
+    level: SimdLevel::Scalar,
+    predict_lpc_low_order: subframe::predict_lpc_low_order,
+    interleave_pcm_le: frame::interleave_pcm_le,
+    crc16_update: crc::crc16_update,
+};
+
+/// The scalar kernels for targets without native 64-bit multiplication.
+static SCALAR_32: Kernels = Kernels {
+    level: SimdLevel::Scalar,
+    predict_lpc_low_order: subframe::predict_lpc_low_order_mac32,
+    interleave_pcm_le: frame::interleave_pcm_le,
+    crc16_update: crc::crc16_update,
+};
+
+/// Returns the kernels for the given level.
+///
+/// The kernels may require a lower level than the one asked for, when there
+/// are no specialized kernels for that level. Currently that is the case for
+/// every level, so this always returns scalar kernels, and their `level` is
+/// `SimdLevel::Scalar`.
+///
+/// # Panics
+///
+/// Panics if `level` is not included in what `simd_level()` reports, because
+/// the kernels might then use unavailable instructions.
+pub fn kernels_for(level: SimdLevel) -> &'static Kernels {
+    assert!(simd_level().includes(level), "SIMD level is not available on this CPU");
+    if cfg!(target_pointer_width = "32") {
+        &SCALAR_32
+    } else {
+        &SCALAR
+    }
+}
+
+/// Returns the kernels for the detected level.
+#[inline]
+pub fn kernels() -> &'static Kernels {
+    kernels_for(simd_level())
+}
+
+/// Returns all levels that the detected one includes.
+#[cfg(test)]
+fn available_levels() -> Vec<SimdLevel> {
+    (0..4).map(SimdLevel::from_index).filter(|&l| simd_level().includes(l)).collect()
+}
+
+#[test]
+fn verify_simd_level_is_cached() {
+    let first = simd_level();
+    assert_eq!(simd_level(), first);
+    assert_eq!(kernels().level, kernels_for(first).level);
+    assert!(first.includes(kernels().level));
+    assert!(first.includes(SimdLevel::Scalar));
+
+    // There are no specialized kernels yet, as documented.
+    assert_eq!(kernels().level, SimdLevel::Scalar);
+    assert!(!::capabilities().simd);
+}
+
+#[test]
+fn verify_kernels_predict_lpc_low_order() {
+    let coefficients = [-75, 166,  121, -269, -75, -399, 1042];
+    let input = [-796, -547, -285, -32, 199, 443, 670, -2, -23, 14, 6, 3, -4, 12, -2, 10];
+    for level in available_levels() {
+        for order in 1..coefficients.len() + 1 {
+            let coefs = &coefficients[coefficients.len() - order..];
+            let mut expected = input;
+            subframe::predict_lpc_low_order(coefs, 9, &mut expected);
+            let mut actual = input;
+            (kernels_for(level).predict_lpc_low_order)(coefs, 9, &mut actual);
+            assert_eq!(actual, expected);
+        }
+    }
+}
+
+#[test]
+fn verify_kernels_interleave_pcm_le() {
+    let planar: Vec<i32> = (0..30).map(|i| i * 0x010203 - 0x7f0000).collect();
+    for level in available_levels() {
+        for &(bytes, shift_left, shift_right) in &[(2, 0, 8), (3, 0, 0), (4, 8, 0)] {
+            let mut expected = vec![0u8; 3 * 10 * bytes];
+            frame::interleave_pcm_le(&planar, 10, 3, bytes, shift_left, shift_right, 0,
+                                     &mut expected);
+            let mut actual = vec![0u8; 3 * 10 * bytes];
+            (kernels_for(level).interleave_pcm_le)(&planar, 10, 3, bytes, shift_left,
+                                                   shift_right, 0, &mut actual);
+            assert_eq!(actual, expected);
+        }
+    }
+}
+
+#[test]
+fn verify_kernels_crc16_update() {
+    let data: Vec<u8> = (0..1000).map(|i| (i * 7 + i / 13) as u8).collect();
+    for level in available_levels() {
+        for &len in &[0, 1, 15, 16, 17, 1000] {
+            let expected = crc::crc16_update(0, &data[..len]);
+            assert_eq!((kernels_for(level).crc16_update)(0, &data[..len]), expected);
+        }
+    }
+}
+
+/// Returns the lines of non-test code in `source`, without comments.
+///
+/// The bodies of the functions named in `skip` are left out too.
+#[cfg(test)]
+fn code_lines<'a>(source: &'a str, skip: &[&str]) -> Vec<&'a str> {
+    let mut lines = Vec::new();
+    let mut in_test = false;
+    let mut skip_until = None;
+    for line in source.lines() {
+        if let Some(end) = skip_until {
+            if line == end {
+                skip_until = None;
+            }
+        } else if line == "#[test]" || line == "#[cfg(test)]" {
+            in_test = true;
+        } else if in_test {
+            in_test = line != "}";
+        } else if skip.iter().any(|name| line.contains(&format!("fn {}(", name)[..])) {
+            // The function ends at the closing brace at its own indentation.
+            let indent = line.len() - line.trim_left().len();
+            skip_until = Some(&"    }"[4 - indent..]);
+        } else {
+            lines.push(line.split("//").next().unwrap());
+        }
+    }
+    lines
+}
+
+#[test]
+fn verify_decode_core_is_integer_only() {
+    // Some microcontrollers have no floating point unit, and no instruction
+    // for 64-bit division, so the compiler would emit slow calls into its
+    // runtime for those. The modules that decode frames must not use them.
+    // This cannot see the types of expressions, so it is a conservative check
+    // on the source text: a line that divides must not mention a 64-bit type.
+    // Conversions between samples and time, and statistics, are not needed
+    // to decode, so they are exempt.
+    let sources = [
+        ("crc.rs", include_str!("crc.rs"), &[][..]),
+        ("dispatch.rs", include_str!("dispatch.rs"), &[][..]),
+        ("frame.rs", include_str!("frame.rs"), &["duration_to_samples", "compression_ratio"][..]),
+        ("input.rs", include_str!("input.rs"), &[][..]),
+        ("rice.rs", include_str!("rice.rs"), &[][..]),
+        ("subframe.rs", include_str!("subframe.rs"), &[][..]),
+    ];
+    for &(fname, source, skip) in &sources {
+        for line in code_lines(source, skip) {
+            let words: Vec<&str> = line.split(|c: char| !c.is_alphanumeric()).collect();
+            assert!(!words.iter().any(|&w| w == "f32" || w == "f64"),
+                    "floating point in {}: {}", fname, line);
+            let divides = [" / ", " % ", " /= ", " %= "].iter().any(|op| line.contains(op));
+            assert!(!(divides && line.contains("64")),
+                    "possible 64-bit division in {}: {}", fname, line);
+        }
+    }
+}
@@ -13,6 +13,8 @@ use std::io;
 use std::result;
 use std::string;
 
+use input;
+
 /// An error that prevents successful decoding of the FLAC stream.
 #[derive(Debug)]
 pub enum Error {
@@ -25,21 +27,156 @@ pub enum Error {
     /// A currently unsupported feature of the FLAC format was encountered.
     ///
     /// Claxon reads the FLAC format as it was with FLAC 1.3.1. Values in the
-    /// specification that are marked as reserved will cause a `FormatError`;
-    /// `Unsupported` is used for features that are in the specification, but
-    /// which are not implemented by Claxon.
-    Unsupported(&'static str),
+    /// specification that are marked as reserved will cause a `FormatError`,
+    /// unless `frame::ReservedValues` says otherwise; `Unsupported` is used
+    /// for features that are in the specification, but which are not
+    /// implemented by Claxon.
+    Unsupported(UnsupportedFeature),
+
+    /// A subframe residual uses a partition order that is invalid for its block.
+    ///
+    /// The residual is divided into 2<sup>`order`</sup> partitions of equal
+    /// size, so the block size must be a multiple of the number of partitions,
+    /// and the first partition must be large enough to hold the warm-up
+    /// samples of the predictor.
+    InvalidPartitionOrder {
+        /// The block size of the frame, in inter-channel samples.
+        block_size: u16,
+        /// The partition order read from the residual header.
+        order: u8,
+        /// The sample number of the first sample in the frame, if known.
+        frame_sample: Option<u64>,
+    },
+
+    /// A frame exceeds one of the configured `FrameLimits`.
+    ///
+    /// The stream is not necessarily ill-formed, but decoding it would take
+    /// more resources than allowed.
+    LimitExceeded {
+        /// The quantity that exceeded its limit, such as `"block size"`.
+        limit: &'static str,
+        /// The value encountered in the stream.
+        value: u64,
+        /// The configured maximum.
+        max: u64,
+    },
+
+    /// Reading needed bytes past the bound of a stream of known length.
+    ///
+    /// See `FlacReader::new_bounded()`. This means that the stream is
+    /// truncated, or that the bound was wrong.
+    BoundExceeded {
+        /// The bound, the length of the stream in bytes.
+        bound: u64,
+    },
+}
+
+/// A feature that Claxon does not support, the reason for `Error::Unsupported`.
+///
+/// This makes it possible to fall back to a different decoder only for the
+/// streams that Claxon cannot handle. More variants may be added in the
+/// future, so a match should include a wildcard arm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnsupportedFeature {
+    /// A residual partition with an escape code, which stores the residual as
+    /// unencoded binary.
+    EscapedPartition,
+    /// An LPC subframe with a negative quantized linear predictor coefficient
+    /// shift.
+    NegativeQlpShift,
+    /// A frame header that does not specify the bits per sample, where the
+    /// streaminfo is not available to take it from.
+    MissingBpsInHeader,
+    /// A frame header that does not specify the sample rate, where the
+    /// streaminfo is not available to take it from.
+    MissingSampleRateInHeader,
+    /// A stream with more than 24 bits per sample.
+    BitsPerSampleAbove24,
+    /// A Vorbis comment or application block larger than 10 MiB.
+    TooLargeMetadata,
+    /// Metadata that is too large to be written in a single metadata block.
+    MetadataTooLargeToWrite,
+    /// Writing a reserved metadata block, the contents of which Claxon does
+    /// not parse.
+    UnwritableMetadataBlock,
+    /// Writing a frame header for a sample rate it cannot express.
+    SampleRateNotCodable,
+    /// An MP4 FLAC specific box with a version other than 0.
+    Mp4BoxVersion,
+    /// A reserved value in a frame header, see `frame::ReservedValues`.
+    ReservedFrameHeader,
+    /// A reserved value in a subframe header, see `frame::ReservedValues`.
+    ReservedSubframeHeader,
+    /// A reserved residual coding method, see `frame::ReservedValues`.
+    ReservedResidual,
+}
+
+impl UnsupportedFeature {
+    /// Returns a description of the feature, as used in the error message.
+    pub fn description(&self) -> &'static str {
+        match *self {
+            UnsupportedFeature::EscapedPartition => "unencoded binary is not yet implemented",
+            UnsupportedFeature::NegativeQlpShift => {
+                "a negative quantized linear predictor coefficient shift is not supported, \
+                 please file a bug."
+            }
+            UnsupportedFeature::MissingBpsInHeader => "header without bits per sample info",
+            UnsupportedFeature::MissingSampleRateInHeader => {
+                "frame header without sample rate info"
+            }
+            UnsupportedFeature::BitsPerSampleAbove24 => {
+                "more than 24 bits per sample is not supported"
+            }
+            UnsupportedFeature::TooLargeMetadata => {
+                "metadata blocks larger than 10 MiB are not supported"
+            }
+            UnsupportedFeature::MetadataTooLargeToWrite => {
+                "metadata does not fit in a metadata block"
+            }
+            UnsupportedFeature::UnwritableMetadataBlock => "cannot write reserved block",
+            UnsupportedFeature::SampleRateNotCodable => {
+                "sample rate cannot be coded in frame header"
+            }
+            UnsupportedFeature::Mp4BoxVersion => "FLAC specific box version is not 0",
+            UnsupportedFeature::ReservedFrameHeader => RESERVED_FRAME_HEADER,
+            UnsupportedFeature::ReservedSubframeHeader => RESERVED_SUBFRAME_HEADER,
+            UnsupportedFeature::ReservedResidual => RESERVED_RESIDUAL,
+        }
+    }
+
+    /// Returns the feature for a reserved value format error reason, if it is one.
+    pub fn from_reserved_value(reason: &'static str) -> Option<UnsupportedFeature> {
+        match reason {
+            RESERVED_FRAME_HEADER => Some(UnsupportedFeature::ReservedFrameHeader),
+            RESERVED_SUBFRAME_HEADER => Some(UnsupportedFeature::ReservedSubframeHeader),
+            RESERVED_RESIDUAL => Some(UnsupportedFeature::ReservedResidual),
+            _ => None,
+        }
+    }
 }
 
 impl PartialEq for Error {
     fn eq(&self, other: &Error) -> bool {
-        use error::Error::{IoError, FormatError, Unsupported};
+        use error::Error::{IoError, FormatError, Unsupported, InvalidPartitionOrder, LimitExceeded,
+                           BoundExceeded};
         match (self, other) {
             (&FormatError(r1), &FormatError(r2)) => r1 == r2,
             (&Unsupported(f1), &Unsupported(f2)) => f1 == f2,
+            (&InvalidPartitionOrder { block_size: b1, order: o1, frame_sample: s1 },
+             &InvalidPartitionOrder { block_size: b2, order: o2, frame_sample: s2 }) => {
+                b1 == b2 && o1 == o2 && s1 == s2
+            }
+            (&LimitExceeded { limit: l1, value: v1, max: m1 },
+             &LimitExceeded { limit: l2, value: v2, max: m2 }) => {
+                l1 == l2 && v1 == v2 && m1 == m2
+            }
+            (&BoundExceeded { bound: b1 }, &BoundExceeded { bound: b2 }) => b1 == b2,
             (&IoError(_), _) => false,
             (&FormatError(_), _) => false,
             (&Unsupported(_), _) => false,
+            (&InvalidPartitionOrder { .. }, _) => false,
+            (&LimitExceeded { .. }, _) => false,
+            (&BoundExceeded { .. }, _) => false,
         }
     }
 }
@@ -55,7 +192,27 @@ impl fmt::Display for Error {
             Error::Unsupported(feature) => {
                 try!(formatter.write_str("A currently unsupported feature of the FLAC format \
                                           was encountered: "));
-                formatter.write_str(feature)
+                formatter.write_str(feature.description())
+            }
+            Error::InvalidPartitionOrder { block_size, order, frame_sample } => {
+                try!(write!(formatter,
+                            "Ill-formed FLAC stream: invalid partition order {} \
+                             for block size {}",
+                            order, block_size));
+                match frame_sample {
+                    Some(sample) => write!(formatter, " in frame at sample {}", sample),
+                    None => Ok(()),
+                }
+            }
+            Error::LimitExceeded { limit, value, max } => {
+                write!(formatter,
+                       "Frame exceeds decoding limit: {} of {} exceeds the maximum of {}",
+                       limit, value, max)
+            }
+            Error::BoundExceeded { bound } => {
+                write!(formatter,
+                       "Ill-formed FLAC stream: expected more bytes than the bound of {} bytes",
+                       bound)
             }
         }
     }
@@ -67,6 +224,9 @@ impl error::Error for Error {
             Error::IoError(ref err) => err.description(),
             Error::FormatError(reason) => reason,
             Error::Unsupported(_) => "unsupported feature",
+            Error::InvalidPartitionOrder { .. } => "invalid partition order",
+            Error::LimitExceeded { .. } => "decoding limit exceeded",
+            Error::BoundExceeded { .. } => "read past the bound of the stream",
         }
     }
 
@@ -75,13 +235,22 @@ impl error::Error for Error {
             Error::IoError(ref err) => Some(err),
             Error::FormatError(_) => None,
             Error::Unsupported(_) => None,
+            Error::InvalidPartitionOrder { .. } => None,
+            Error::LimitExceeded { .. } => None,
+            Error::BoundExceeded { .. } => None,
         }
     }
 }
 
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
-        Error::IoError(err)
+        let bound = err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<input::BoundExceeded>())
+            .map(|exceeded| exceeded.bound);
+        match bound {
+            Some(bound) => Error::BoundExceeded { bound: bound },
+            None => Error::IoError(err),
+        }
     }
 }
 
@@ -97,6 +266,74 @@ impl From<string::FromUtf8Error> for Error {
 pub type Result<T> = result::Result<T, Error>;
 
 /// Shorthand for producing a format error with reason.
+///
+/// Errors are the exception when decoding, marking this cold moves the error
+/// paths out of the hot loops that check for them.
+#[cold]
 pub fn fmt_err<T>(reason: &'static str) -> Result<T> {
     Err(Error::FormatError(reason))
 }
+
+/// Format error reason for a reserved value in a frame header.
+pub const RESERVED_FRAME_HEADER: &'static str = "invalid frame header, encountered reserved value";
+
+/// Format error reason for a reserved value in a subframe header.
+pub const RESERVED_SUBFRAME_HEADER: &'static str =
+    "invalid subframe header, encountered reserved value";
+
+/// Format error reason for a reserved residual coding method.
+pub const RESERVED_RESIDUAL: &'static str = "invalid residual, encountered reserved value";
+
+impl Error {
+    /// Returns whether the error is caused by a value that the format reserves.
+    ///
+    /// Such values are not valid in the format as Claxon implements it, but a
+    /// future revision of the format might assign a meaning to them. This
+    /// holds for both the `FormatError` that Claxon returns by default, and
+    /// the `Unsupported` error returned with `ReservedValues::Unsupported`.
+    pub fn is_reserved_value(&self) -> bool {
+        match *self {
+            Error::FormatError(reason) => {
+                UnsupportedFeature::from_reserved_value(reason).is_some()
+            }
+            Error::Unsupported(UnsupportedFeature::ReservedFrameHeader) => true,
+            Error::Unsupported(UnsupportedFeature::ReservedSubframeHeader) => true,
+            Error::Unsupported(UnsupportedFeature::ReservedResidual) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether the error is confined to the frame that was being decoded.
+    ///
+    /// After such an error, decoding can continue with the next frame, see
+    /// `FlacSamples::resync()`. Errors of the underlying reader, and reading
+    /// past the bound of the stream, are not resolved by skipping ahead.
+    pub fn is_frame_local(&self) -> bool {
+        match *self {
+            Error::FormatError(..) => true,
+            Error::Unsupported(..) => true,
+            Error::InvalidPartitionOrder { .. } => true,
+            Error::LimitExceeded { .. } => true,
+            Error::IoError(..) => false,
+            Error::BoundExceeded { .. } => false,
+        }
+    }
+}
+
+#[test]
+fn verify_is_reserved_value() {
+    assert!(Error::FormatError(RESERVED_RESIDUAL).is_reserved_value());
+    assert!(Error::Unsupported(UnsupportedFeature::ReservedFrameHeader).is_reserved_value());
+    assert!(!Error::Unsupported(UnsupportedFeature::EscapedPartition).is_reserved_value());
+    assert!(!Error::FormatError("frame CRC mismatch").is_reserved_value());
+}
+
+#[test]
+fn verify_is_frame_local() {
+    assert!(Error::FormatError("frame CRC mismatch").is_frame_local());
+    assert!(Error::Unsupported(UnsupportedFeature::EscapedPartition).is_frame_local());
+    let order = Error::InvalidPartitionOrder { block_size: 16, order: 5, frame_sample: None };
+    assert!(order.is_frame_local());
+    assert!(!Error::IoError(io::Error::new(io::ErrorKind::Other, "")).is_frame_local());
+    assert!(!Error::BoundExceeded { bound: 0 }.is_frame_local());
+}
@@ -0,0 +1,152 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `explain` module describes frames element by element, as they are parsed.
+//!
+//! This is meant for learning how the FLAC format works, and for debugging
+//! encoders: comparing the elements that an encoder meant to write with the
+//! elements that Claxon reads pinpoints where a bitstream goes wrong. See
+//! `FrameReader::explain_next_or_eof()`. With the `serde` feature enabled, the
+//! elements implement `Serialize`, so a trace can be written as JSON, for
+//! instance.
+
+/// How the channels of a frame are coded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum ChannelCoding {
+    /// Every channel is coded as-is, in its own subframe.
+    Independent {
+        /// The number of channels.
+        channels: u8,
+    },
+    /// The subframes hold the left channel and the side channel.
+    LeftSide,
+    /// The subframes hold the side channel and the right channel.
+    RightSide,
+    /// The subframes hold the mid channel and the side channel.
+    MidSide,
+}
+
+/// The type of a subframe, from its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum SubframeKind {
+    /// A single sample value, repeated for the entire block.
+    Constant,
+    /// Samples stored as they are, without prediction.
+    Verbatim,
+    /// Prediction with one of the fixed polynomial predictors.
+    Fixed {
+        /// The order of the predictor, at most 4.
+        order: u8,
+    },
+    /// Prediction with linear predictor coefficients stored in the subframe.
+    Lpc {
+        /// The order of the predictor, at most 32.
+        order: u8,
+    },
+}
+
+/// An element of the bitstream of a frame.
+///
+/// A frame yields a `FrameHeader`, then for every subframe a `SubframeHeader`
+/// followed by the elements of that subframe, and finally a `FrameFooter`.
+/// The subframes are in the order in which they are coded, so for a mid-side
+/// frame the first subframe is the mid channel, and the second the side
+/// channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Element {
+    /// The frame header, after its CRC-8 was verified.
+    FrameHeader {
+        /// Whether the frame is part of a variable block size stream.
+        variable_block_size: bool,
+        /// The sample number if the block size is variable, the frame number otherwise.
+        number: u64,
+        /// The number of inter-channel samples in the frame.
+        block_size: u16,
+        /// The sample rate in Hz, if the header specifies it.
+        sample_rate: Option<u32>,
+        /// How the channels are coded.
+        channels: ChannelCoding,
+        /// The bits per sample, if the header specifies it.
+        bits_per_sample: Option<u32>,
+    },
+    /// A subframe header.
+    SubframeHeader {
+        /// The type of the subframe.
+        kind: SubframeKind,
+        /// The number of wasted bits per sample.
+        wasted_bits: u32,
+        /// The bits per sample of the samples stored in the subframe.
+        ///
+        /// This is the bits per sample of the frame, plus one for a side
+        /// channel, minus the wasted bits.
+        bits_per_sample: u32,
+    },
+    /// A sample stored as it is.
+    ///
+    /// This is the value of a constant subframe, a sample of a verbatim
+    /// subframe, or a warm-up sample of a predictor. The wasted bits are not
+    /// included in the value.
+    Sample {
+        /// The value of the sample.
+        value: i32,
+    },
+    /// The precision and shift of the coefficients of an LPC subframe.
+    LpcParameters {
+        /// The number of bits per coefficient.
+        precision: u32,
+        /// The number of bits by which predictions are shifted right.
+        shift: i32,
+    },
+    /// A quantized linear predictor coefficient, in the order of the bitstream.
+    LpcCoefficient {
+        /// The value of the coefficient.
+        value: i32,
+    },
+    /// The header of the residual of a predicted subframe.
+    ResidualHeader {
+        /// The number of bits of the Rice parameters: 4, or 5 for Rice2.
+        parameter_bits: u32,
+        /// The partition order, there are 2<sup>order</sup> partitions.
+        partition_order: u8,
+    },
+    /// A partition of the residual.
+    RicePartition {
+        /// The Rice parameter of the partition.
+        parameter: u32,
+        /// The number of residual samples in the partition.
+        samples: u16,
+    },
+    /// The frame footer, after the CRC-16 was verified.
+    FrameFooter {
+        /// The CRC-16 of the frame.
+        crc16: u16,
+    },
+}
+
+/// Receives the elements of a frame as they are parsed.
+///
+/// Parsing functions take a tap to report the elements to. The `()` tap
+/// ignores them, and compiles down to nothing, so parsing without a trace does
+/// not pay for the ability to explain. A `Vec<Element>` collects them.
+pub trait Tap {
+    /// Reports the next element.
+    fn element(&mut self, element: Element);
+}
+
+impl Tap for () {
+    #[inline(always)]
+    fn element(&mut self, _element: Element) {}
+}
+
+impl Tap for Vec<Element> {
+    fn element(&mut self, element: Element) {
+        self.push(element);
+    }
+}
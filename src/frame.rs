@@ -7,12 +7,30 @@
 
 //! The `frame` module deals with the frames that make up a FLAC stream.
 
+use std::cmp;
+use std::fmt;
+use std::fs;
+use std::i16;
 use std::i32;
+use std::io;
+use std::mem;
+use std::path;
+use std::result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use crc;
 use crc::{Crc8Reader, Crc16Reader};
-use error::{Error, Result, fmt_err};
-use input::{Bitstream, ReadBytes};
+use dispatch;
+use error;
+use error::{Error, Result, UnsupportedFeature, fmt_err};
+use explain::{ChannelCoding, Element};
+use input::{Bitstream, BufferedReader, PeekBytes, ReadBytes, SeekBytes, SliceProvider};
+use input::UnbufferedReader;
+use metadata;
+use metadata::{StreamInfo, StreamSpec};
 use subframe;
+use MAX_BITS_PER_SAMPLE;
 
 #[derive(Clone, Copy)]
 enum BlockingStrategy {
@@ -38,6 +56,18 @@ enum ChannelAssignment {
     MidSideStereo,
 }
 
+impl ChannelAssignment {
+    /// Returns the public description of the channel assignment.
+    fn coding(self) -> ChannelCoding {
+        match self {
+            ChannelAssignment::Independent(n) => ChannelCoding::Independent { channels: n },
+            ChannelAssignment::LeftSideStereo => ChannelCoding::LeftSide,
+            ChannelAssignment::RightSideStereo => ChannelCoding::RightSide,
+            ChannelAssignment::MidSideStereo => ChannelCoding::MidSide,
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 struct FrameHeader {
     pub block_time: BlockTime,
@@ -61,7 +91,11 @@ impl FrameHeader {
 /// Reads a variable-length integer encoded as what is called "UTF-8" coding
 /// in the specification. (It is not real UTF-8.) This function can read
 /// integers encoded in this way up to 36-bit integers.
-fn read_var_length_int<R: ReadBytes>(input: &mut R) -> Result<u64> {
+///
+/// This coding is used for the frame number or sample number in a frame
+/// header. Returns a format error if the first byte is a continuation byte,
+/// if a continuation byte is missing, or if the integer would exceed 36 bits.
+pub fn read_var_length_int<R: ReadBytes>(input: &mut R) -> Result<u64> {
     // The number of consecutive 1s followed by a 0 is the number of additional
     // bytes to read.
     let first = try!(input.read_u8());
@@ -69,6 +103,13 @@ fn read_var_length_int<R: ReadBytes>(input: &mut R) -> Result<u64> {
     let mut mask_data = 0b0111_1111u8;
     let mut mask_mark = 0b1000_0000u8;
 
+    // Eight leading 1s would indicate 7 continuation bytes, which is 42 bits,
+    // more than the 36 bits allowed. This must be checked before counting, as
+    // the loop below assumes there is a 0 bit to stop at.
+    if first == 0xff {
+        return fmt_err("invalid variable-length integer, exceeds 36 bits");
+    }
+
     // Determine the number of leading 1s.
     while first & mask_mark != 0 {
         read_additional = read_additional + 1;
@@ -128,7 +169,44 @@ fn verify_read_var_length_int() {
                Error::FormatError("invalid variable-length integer"));
 }
 
-fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameHeader>> {
+#[test]
+fn verify_read_var_length_int_bounds() {
+    use error::Error;
+
+    // The largest integer that can be coded is 36 bits.
+    let max = [0xfe, 0xbf, 0xbf, 0xbf, 0xbf, 0xbf, 0xbf];
+    assert_eq!(read_var_length_int(&mut io::Cursor::new(&max[..])).unwrap(), 0xf_ffff_ffff);
+
+    // Anything longer is invalid.
+    let too_long = [0xff, 0xbf, 0xbf, 0xbf, 0xbf, 0xbf, 0xbf, 0xbf];
+    assert_eq!(read_var_length_int(&mut io::Cursor::new(&too_long[..])).err().unwrap(),
+               Error::FormatError("invalid variable-length integer, exceeds 36 bits"));
+
+    // A missing continuation byte is an IO error, not a panic.
+    let truncated = [0xfe, 0xbf, 0xbf];
+    assert!(read_var_length_int(&mut io::Cursor::new(&truncated[..])).is_err());
+
+    // Whatever the bytes, the result is either an error or fits in 36 bits.
+    for first in 0..256 {
+        for &next in &[0x00, 0x80, 0xbf, 0xc0, 0xff] {
+            let mut bytes = vec![first as u8];
+            bytes.extend(vec![next; 7]);
+            match read_var_length_int(&mut io::Cursor::new(&bytes[..])) {
+                Ok(n) => assert!(n <= 0xf_ffff_ffff),
+                Err(..) => {}
+            }
+        }
+    }
+}
+
+/// Reads a frame header, or returns `None` if the input is at its end.
+///
+/// When `lenient` is true, reserved bits and a CRC mismatch are recorded in
+/// `issues` rather than returned as error.
+fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R,
+                                          lenient: bool,
+                                          issues: &mut Vec<FrameIssue>)
+                                          -> Result<Option<FrameHeader>> {
     // The frame header includes a CRC-8 at the end. It can be computed
     // automatically while reading, by wrapping the input reader in a reader
     // that computes the CRC.
@@ -154,7 +232,10 @@ fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameH
     // the spec that are not implemented as `Error::Unsupported`, and this is
     // a format error.
     if sync_res_block & 0b0000_0000_0000_0010 != 0 {
-        return fmt_err("invalid frame header, encountered reserved value");
+        if !lenient {
+            return fmt_err(error::RESERVED_FRAME_HEADER);
+        }
+        issues.push(FrameIssue::ReservedBit);
     }
 
     // The final bit determines the blocking strategy.
@@ -174,7 +255,7 @@ fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameH
     // header instead'.
     match bs_sr >> 4 {
         // The value 0000 is reserved.
-        0b0000 => return fmt_err("invalid frame header, encountered reserved value"),
+        0b0000 => return fmt_err(error::RESERVED_FRAME_HEADER),
         0b0001 => block_size = 192,
         n if 0b0010 <= n && n <= 0b0101 => block_size = 576 * (1 << (n - 2) as usize),
         0b0110 => read_8bit_bs = true,
@@ -202,7 +283,7 @@ fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameH
         0b1001 => sample_rate = Some(44_100),
         0b1010 => sample_rate = Some(48_000),
         0b1011 => sample_rate = Some(96_000),
-        0b1100 => read_8bit_sr = true, // Read Hz from end of header.
+        0b1100 => read_8bit_sr = true, // Read kHz from end of header.
         0b1101 => read_16bit_sr = true, // Read Hz from end of header.
         0b1110 => read_16bit_sr_ten = true, // Read tens of Hz from end of header.
         // 1111 is invalid to prevent sync-fooling.
@@ -221,7 +302,7 @@ fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameH
         0b1001 => ChannelAssignment::RightSideStereo,
         0b1010 => ChannelAssignment::MidSideStereo,
         // Values 1011 through 1111 are reserved and thus invalid.
-        _ => return fmt_err("invalid frame header, encountered reserved value"),
+        _ => return fmt_err(error::RESERVED_FRAME_HEADER),
     };
 
     // The next three bits indicate bits per sample.
@@ -233,18 +314,25 @@ fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameH
         0b101 => Some(20),
         0b110 => Some(24),
         // Values 011 and 111 are reserved. Other values are impossible.
-        _ => return fmt_err("invalid frame header, encountered reserved value"),
+        _ => return fmt_err(error::RESERVED_FRAME_HEADER),
     };
 
     // The final bit has a mandatory value of 0, it is a reserved bit.
     if chan_bps_res & 0b0000_0001 != 0 {
-        return fmt_err("invalid frame header, encountered reserved value");
+        if !lenient {
+            return fmt_err(error::RESERVED_FRAME_HEADER);
+        }
+        issues.push(FrameIssue::ReservedBit);
     }
 
     let block_time = match blocking_strategy {
         BlockingStrategy::Variable => {
             // The sample number is encoded in 8-56 bits, at most a 36-bit int.
+            // The integer reader already enforces this, but be explicit.
             let sample = try!(read_var_length_int(&mut crc_input));
+            if sample > 0xf_ffff_ffff {
+                return fmt_err("invalid frame header, sample number too large");
+            }
             BlockTime::SampleNumber(sample)
         }
         BlockingStrategy::Fixed => {
@@ -276,8 +364,8 @@ fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameH
     }
 
     if read_8bit_sr {
-        let sr = try!(crc_input.read_u8());
-        sample_rate = Some(sr as u32);
+        let sr_khz = try!(crc_input.read_u8());
+        sample_rate = Some(sr_khz as u32 * 1000);
     }
     if read_16bit_sr {
         let sr = try!(crc_input.read_be_u16());
@@ -288,6 +376,17 @@ fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameH
         sample_rate = Some(sr_ten as u32 * 10);
     }
 
+    // The coded rates are at most 0xffff * 10 Hz, which is the maximum that
+    // the streaminfo allows too. A rate of 0 Hz is invalid there, so it is
+    // invalid here as well; otherwise a frame could have a sample rate that
+    // no stream can have.
+    if let Some(sr) = sample_rate {
+        debug_assert!(sr <= metadata::MAX_SAMPLE_RATE);
+        if !metadata::is_valid_sample_rate(sr) {
+            return fmt_err("invalid frame header, sample rate is zero");
+        }
+    }
+
     // Next is an 8-bit CRC that is computed over the entire header so far.
     let computed_crc = crc_input.crc();
     let presumed_crc = try!(crc_input.read_u8());
@@ -297,7 +396,15 @@ fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameH
     #[cfg(not(fuzzing))]
     {
         if computed_crc != presumed_crc {
-            return fmt_err("frame header CRC mismatch");
+            warn!("frame header CRC mismatch, computed {:02x}, stored {:02x}",
+                  computed_crc, presumed_crc);
+            if !lenient {
+                return fmt_err("frame header CRC mismatch");
+            }
+            issues.push(FrameIssue::HeaderCrcMismatch {
+                computed: computed_crc,
+                stored: presumed_crc,
+            });
         }
     }
 
@@ -315,77 +422,126 @@ fn read_frame_header_or_eof<R: ReadBytes>(input: &mut R) -> Result<Option<FrameH
     Ok(Some(frame_header))
 }
 
-/// Converts a buffer with left samples and a side channel in-place to left ++ right.
-fn decode_left_side(buffer: &mut [i32]) {
+/// Undoes left/side decorrelation of a pair of samples, returns left and right.
+///
+/// The two samples are first shifted left by their number of `wasted` bits,
+/// so the shift does not need a separate pass over the buffer.
+#[inline(always)]
+fn left_side_pair(fst: i32, snd: i32, wasted: [u32; 2]) -> (i32, i32) {
+    let left = fst.wrapping_shl(wasted[0]);
+    let side = snd.wrapping_shl(wasted[1]);
+
+    // Left is correct already, only the right channel needs to be decoded.
+    // side = left - right => right = left - side. A valid FLAC file will
+    // never overflow here. If we do have an overflow then we decode
+    // garbage, but at least Rust does not panic in debug mode due to
+    // overflow.
+    let right = left.wrapping_sub(side);
+    (left, right)
+}
+
+/// Undoes right/side decorrelation of a pair of samples, returns left and right.
+///
+/// Like `left_side_pair()`, this applies the `wasted` bits shift first.
+#[inline(always)]
+fn right_side_pair(fst: i32, snd: i32, wasted: [u32; 2]) -> (i32, i32) {
+    let side = fst.wrapping_shl(wasted[0]);
+    let right = snd.wrapping_shl(wasted[1]);
+
+    // Right is correct already, only the left channel needs to be decoded.
+    // side = left - right => left = side + right. A valid FLAC file will
+    // never overflow here. If we do have an overflow then we decode
+    // garbage, but at least Rust does not panic in debug mode due to
+    // overflow.
+    let left = side.wrapping_add(right);
+    (left, right)
+}
+
+/// Undoes mid/side decorrelation of a pair of samples, returns left and right.
+///
+/// Like `left_side_pair()`, this applies the `wasted` bits shift first.
+#[inline(always)]
+fn mid_side_pair(fst: i32, snd: i32, wasted: [u32; 2]) -> (i32, i32) {
+    let mid = fst.wrapping_shl(wasted[0]);
+    let side = snd.wrapping_shl(wasted[1]);
+
+    // Double mid first, and then correct for truncated rounding that
+    // will have occured if side is odd. Note that samples are never
+    // expected to exceed 25 bits, so the wrapping multiplication does not
+    // actually wrap for valid files.
+    let mid = mid.wrapping_mul(2) | (side & 1);
+    let left = mid.wrapping_add(side) / 2;
+    let right = mid.wrapping_sub(side) / 2;
+    (left, right)
+}
+
+/// Undoes decorrelation of a buffer that holds two channels, in place.
+#[inline(always)]
+fn undo_planar<F: Fn(i32, i32) -> (i32, i32)>(buffer: &mut [i32], undo: F) {
     let block_size = buffer.len() / 2;
-    let (mids, sides) = buffer.split_at_mut(block_size);
-    for (fst, snd) in mids.iter_mut().zip(sides) {
-        let left = *fst;
-        let side = *snd;
-
-        // Left is correct already, only the right channel needs to be decoded.
-        // side = left - right => right = left - side. A valid FLAC file will
-        // never overflow here. If we do have an overflow then we decode
-        // garbage, but at least Rust does not panic in debug mode due to
-        // overflow.
-        let right = left.wrapping_sub(side);
+    let (fsts, snds) = buffer.split_at_mut(block_size);
+    for (fst, snd) in fsts.iter_mut().zip(snds) {
+        let (left, right) = undo(*fst, *snd);
+        *fst = left;
         *snd = right;
     }
 }
 
+/// Undoes decorrelation of a buffer that holds two channels, into interleaved output.
+#[inline(always)]
+fn undo_interleaved<F: Fn(i32, i32) -> (i32, i32)>(planar: &[i32], output: &mut [i32], undo: F) {
+    let (fsts, snds) = planar.split_at(planar.len() / 2);
+    // Slicing to the exact lengths up front lets the compiler elide the bounds
+    // checks in the loop, which is about twice as fast as `chunks_mut(2)`.
+    let n = fsts.len();
+    let snds = &snds[..n];
+    let output = &mut output[..n * 2];
+    for i in 0..n {
+        let (left, right) = undo(fsts[i], snds[i]);
+        output[i * 2] = left;
+        output[i * 2 + 1] = right;
+    }
+}
+
+/// Converts a buffer with left samples and a side channel in-place to left ++ right.
+fn decode_left_side(buffer: &mut [i32], wasted: [u32; 2]) {
+    undo_planar(buffer, |fst, snd| left_side_pair(fst, snd, wasted));
+}
+
 #[test]
 fn verify_decode_left_side() {
     let mut buffer = vec![2, 5, 83, 113, 127, -63, -45, -15, 7, 38, 142, 238, 0, -152, -52, -18];
     let result = vec![2, 5, 83, 113, 127, -63, -45, -15, -5, -33, -59, -125, 127, 89, 7, 3];
-    decode_left_side(&mut buffer);
+    decode_left_side(&mut buffer, [0, 0]);
+    assert_eq!(buffer, result);
+
+    let mut buffer = vec![1, 5, 3, 0, -1, 2, 2, 1];
+    let result = vec![4, 20, 12, 0, 6, 16, 8, -2];
+    decode_left_side(&mut buffer, [2, 1]);
     assert_eq!(buffer, result);
 }
 
 /// Converts a buffer with right samples and a side channel in-place to left ++ right.
-fn decode_right_side(buffer: &mut [i32]) {
-    let block_size = buffer.len() / 2;
-    let (mids, sides) = buffer.split_at_mut(block_size);
-    for (fst, snd) in mids.iter_mut().zip(sides) {
-        let side = *fst;
-        let right = *snd;
-
-        // Right is correct already, only the left channel needs to be decoded.
-        // side = left - right => left = side + right. A valid FLAC file will
-        // never overflow here. If we do have an overflow then we decode
-        // garbage, but at least Rust does not panic in debug mode due to
-        // overflow.
-        let left = side.wrapping_add(right);
-        *fst = left;
-    }
+fn decode_right_side(buffer: &mut [i32], wasted: [u32; 2]) {
+    undo_planar(buffer, |fst, snd| right_side_pair(fst, snd, wasted));
 }
 
 #[test]
 fn verify_decode_right_side() {
     let mut buffer = vec![7, 38, 142, 238, 0, -152, -52, -18, -5, -33, -59, -125, 127, 89, 7, 3];
     let result = vec![2, 5, 83, 113, 127, -63, -45, -15, -5, -33, -59, -125, 127, 89, 7, 3];
-    decode_right_side(&mut buffer);
+    decode_right_side(&mut buffer, [0, 0]);
+    assert_eq!(buffer, result);
+
+    let mut buffer = vec![1, 5, 3, 0, -1, 2, 2, 1];
+    let result = vec![1, 12, 8, 1, -1, 2, 2, 1];
+    decode_right_side(&mut buffer, [1, 0]);
     assert_eq!(buffer, result);
 }
 
 /// Converts a buffer with mid samples and a side channel in-place to left ++ right.
-fn decode_mid_side(buffer: &mut [i32]) {
-    let block_size = buffer.len() / 2;
-    let (mids, sides) = buffer.split_at_mut(block_size);
-    for (fst, snd) in mids.iter_mut().zip(sides) {
-        let mid = *fst;
-        let side = *snd;
-
-        // Double mid first, and then correct for truncated rounding that
-        // will have occured if side is odd. Note that samples are never
-        // expected to exceed 25 bits, so the wrapping multiplication does not
-        // actually wrap for valid files.
-        let mid = mid.wrapping_mul(2) | (side & 1);
-        let left = mid.wrapping_add(side) / 2;
-        let right = mid.wrapping_sub(side) / 2;
-
-        *fst = left;
-        *snd = right;
-    }
+fn decode_mid_side(buffer: &mut [i32], wasted: [u32; 2]) {
+    undo_planar(buffer, |fst, snd| mid_side_pair(fst, snd, wasted));
 }
 
 #[test]
@@ -394,11 +550,291 @@ fn verify_decode_mid_side() {
                            7,  38, 142,  238,   0, -152, -52, -18);
     let result =      vec!(2,   5,  83,  113, 127,  -63, -45, -15,
                           -5, -33, -59, -125, 127,   89,   7,   3);
-    decode_mid_side(&mut buffer);
+    decode_mid_side(&mut buffer, [0, 0]);
+    assert_eq!(buffer, result);
+
+    // The shift applies to mid and side, before they are decorrelated.
+    let mut buffer = vec![3, -2, 0, 5, 1, 1, -3, 2];
+    let result = vec![8, -2, -6, 14, 4, -6, 6, 6];
+    decode_mid_side(&mut buffer, [1, 2]);
     assert_eq!(buffer, result);
 }
 
+/// Checks that undoing the decorrelation of a channel pair does not overflow.
+///
+/// This is used with the `checked-arith` feature. The buffer holds the two
+/// channels consecutively, `undo` returns `None` if the computation for a
+/// pair of samples overflows.
+fn check_decorrelation<F: Fn(i32, i32) -> Option<i32>>(buffer: &[i32], undo: F) -> Result<()> {
+    let (fst, snd) = buffer.split_at(buffer.len() / 2);
+    for (&a, &b) in fst.iter().zip(snd) {
+        if undo(a, b).is_none() {
+            return fmt_err("invalid frame, sample overflows in inter-channel decorrelation");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn verify_check_decorrelation() {
+    let buffer = [1, i32::MIN, 1, 1];
+    assert!(check_decorrelation(&buffer, |a, b| a.checked_sub(b)).is_err());
+    assert!(check_decorrelation(&buffer, |a, b| a.checked_add(b)).is_ok());
+}
+
+/// Inter-channel decorrelation of a stereo frame, that remains to be undone.
+///
+/// The subframes of a stereo frame are decoded as they are coded, and the
+/// wasted bits shift is not applied yet either. The output stage undoes both
+/// in the same pass that writes the samples: in place for a planar block with
+/// `undo_planar()`, or while interleaving with `undo_interleaved()`. This way,
+/// interleaved output does not need an extra pass over the planar buffer.
+#[derive(Clone, Copy)]
+struct Decorrelation {
+    assignment: ChannelAssignment,
+    wasted: [u32; 2],
+}
+
+impl Decorrelation {
+    /// Undoes the decorrelation of the first two channels of the buffer, in place.
+    fn undo_planar(self, buffer: &mut [i32]) {
+        match self.assignment {
+            ChannelAssignment::LeftSideStereo => decode_left_side(buffer, self.wasted),
+            ChannelAssignment::RightSideStereo => decode_right_side(buffer, self.wasted),
+            ChannelAssignment::MidSideStereo => decode_mid_side(buffer, self.wasted),
+            ChannelAssignment::Independent(..) => unreachable!(),
+        }
+    }
+
+    /// Undoes the decorrelation of the two channels in `planar`, interleaving them into `output`.
+    fn undo_interleaved(self, planar: &[i32], output: &mut [i32]) {
+        debug_assert_eq!(planar.len(), output.len());
+        let wasted = self.wasted;
+        match self.assignment {
+            ChannelAssignment::LeftSideStereo => {
+                undo_interleaved(planar, output, |fst, snd| left_side_pair(fst, snd, wasted))
+            }
+            ChannelAssignment::RightSideStereo => {
+                undo_interleaved(planar, output, |fst, snd| right_side_pair(fst, snd, wasted))
+            }
+            ChannelAssignment::MidSideStereo => {
+                undo_interleaved(planar, output, |fst, snd| mid_side_pair(fst, snd, wasted))
+            }
+            ChannelAssignment::Independent(..) => unreachable!(),
+        }
+    }
+}
+
+/// Returns the index of the subframe that holds the side channel, if any.
+fn side_subframe(assignment: ChannelAssignment) -> Option<usize> {
+    match assignment {
+        ChannelAssignment::Independent(..) => None,
+        ChannelAssignment::LeftSideStereo => Some(1),
+        ChannelAssignment::RightSideStereo => Some(0),
+        ChannelAssignment::MidSideStereo => Some(1),
+    }
+}
+
+/// Decodes the subframes of a frame into the buffer.
+///
+/// The number of bits per sample that each subframe is coded with is stored in
+/// `widths`, in the order in which the subframes occur in the frame. For a
+/// stereo frame, the decorrelation that remains to be undone is returned.
+///
+/// Independently coded channels for which the bit in `needed` is not set are
+/// skipped, and their part of the buffer contains garbage afterwards.
+fn decode_subframes<R: ReadBytes>(bitstream: &mut Bitstream<R>,
+                                  header: &FrameHeader,
+                                  bps: u32,
+                                  buffer: &mut [i32],
+                                  widths: &mut [u32; 8],
+                                  needed: u8)
+                                  -> Result<Option<Decorrelation>> {
+    let bs = header.block_size as usize;
+
+    let wasted = match header.channel_assignment {
+        ChannelAssignment::Independent(n_ch) => {
+            for ch in 0..n_ch as usize {
+                let channel = &mut buffer[ch * bs..(ch + 1) * bs];
+                widths[ch] = if needed & (1 << ch) != 0 {
+                    try!(subframe::decode_with_width(bitstream, bps, channel))
+                } else {
+                    try!(subframe::skip(bitstream, bps, header.block_size))
+                };
+            }
+            return Ok(None)
+        }
+        ChannelAssignment::LeftSideStereo => {
+            // The side channel has one extra bit per sample. The wasted bits
+            // shift is left to the output stage.
+            let (width0, wasted0) = try!(subframe::decode_unshifted(bitstream, bps,
+                                                                    &mut buffer[..bs]));
+            let (width1, wasted1) = try!(subframe::decode_unshifted(bitstream, bps + 1,
+                                                                    &mut buffer[bs..bs * 2]));
+            widths[0] = width0;
+            widths[1] = width1;
+
+            // The output stage decodes the side channel into the right channel.
+            if cfg!(feature = "checked-arith") {
+                try!(check_decorrelation(&buffer[..bs * 2], |left, side| left.checked_sub(side)));
+            }
+            [wasted0, wasted1]
+        }
+        ChannelAssignment::RightSideStereo => {
+            // The side channel has one extra bit per sample. The wasted bits
+            // shift is left to the output stage.
+            let (width0, wasted0) = try!(subframe::decode_unshifted(bitstream, bps + 1,
+                                                                    &mut buffer[..bs]));
+            let (width1, wasted1) = try!(subframe::decode_unshifted(bitstream, bps,
+                                                                    &mut buffer[bs..bs * 2]));
+            widths[0] = width0;
+            widths[1] = width1;
+
+            // The output stage decodes the side channel into the left channel.
+            if cfg!(feature = "checked-arith") {
+                try!(check_decorrelation(&buffer[..bs * 2], |side, right| side.checked_add(right)));
+            }
+            [wasted0, wasted1]
+        }
+        ChannelAssignment::MidSideStereo => {
+            // Decode mid as the first channel, then side with one
+            // extra bitp per sample. The wasted bits shift is left to the
+            // output stage.
+            let (width0, wasted0) = try!(subframe::decode_unshifted(bitstream, bps,
+                                                                    &mut buffer[..bs]));
+            let (width1, wasted1) = try!(subframe::decode_unshifted(bitstream, bps + 1,
+                                                                    &mut buffer[bs..bs * 2]));
+            widths[0] = width0;
+            widths[1] = width1;
+
+            // The output stage decodes mid-side channel into left-right.
+            if cfg!(feature = "checked-arith") {
+                try!(check_decorrelation(&buffer[..bs * 2], |mid, side| {
+                    mid.checked_mul(2)
+                       .map(|mid| mid | (side & 1))
+                       .and_then(|mid| mid.checked_add(side).and(mid.checked_sub(side)))
+                }));
+            }
+            [wasted0, wasted1]
+        }
+    };
+
+    Ok(Some(Decorrelation {
+        assignment: header.channel_assignment,
+        wasted: wasted,
+    }))
+}
+
+/// Fills in the frame sample number for errors that carry one.
+fn with_frame_sample(err: Error, time: u64) -> Error {
+    match err {
+        Error::InvalidPartitionOrder { block_size, order, frame_sample: None } => {
+            Error::InvalidPartitionOrder {
+                block_size: block_size,
+                order: order,
+                frame_sample: Some(time),
+            }
+        }
+        other => other,
+    }
+}
+
+/// Turns a reserved value format error into `Unsupported`, if the policy says so.
+fn with_reserved_values(err: Error, policy: ReservedValues) -> Error {
+    match (policy, err) {
+        (ReservedValues::Unsupported, Error::FormatError(reason)) => {
+            match UnsupportedFeature::from_reserved_value(reason) {
+                Some(feature) => Error::Unsupported(feature),
+                None => Error::FormatError(reason),
+            }
+        }
+        (_, other) => other,
+    }
+}
+
+#[test]
+fn verify_with_reserved_values() {
+    let reserved = || Error::FormatError(error::RESERVED_SUBFRAME_HEADER);
+    let crc = || Error::FormatError("frame CRC mismatch");
+    assert_eq!(with_reserved_values(reserved(), ReservedValues::Reject), reserved());
+    assert_eq!(with_reserved_values(reserved(), ReservedValues::Unsupported),
+               Error::Unsupported(UnsupportedFeature::ReservedSubframeHeader));
+    assert_eq!(with_reserved_values(crc(), ReservedValues::Unsupported), crc());
+}
+
+#[test]
+fn verify_with_frame_sample() {
+    let err = Error::InvalidPartitionOrder {
+        block_size: 4096,
+        order: 13,
+        frame_sample: None,
+    };
+    assert_eq!(with_frame_sample(err, 8192),
+               Error::InvalidPartitionOrder {
+                   block_size: 4096,
+                   order: 13,
+                   frame_sample: Some(8192),
+               });
+
+    // Other errors pass through unchanged.
+    assert_eq!(with_frame_sample(Error::FormatError("x"), 8192),
+               Error::FormatError("x"));
+}
+
+/// Converts a number of inter-channel samples into a duration.
+///
+/// The result is rounded down to whole nanoseconds.
+///
+/// # Panics
+///
+/// Panics if `sample_rate` is 0.
+pub fn samples_to_duration(samples: u64, sample_rate: u32) -> Duration {
+    assert!(sample_rate > 0, "sample rate must be positive");
+    let rate = sample_rate as u64;
+    let nanos = (samples % rate) * 1_000_000_000 / rate;
+    Duration::new(samples / rate, nanos as u32)
+}
+
+/// Converts a duration into a number of inter-channel samples.
+///
+/// The result is rounded up to whole samples: it is the number of samples that
+/// start before the end of the duration. For a number of samples `n`, this
+/// undoes `samples_to_duration()`: `duration_to_samples(samples_to_duration(n,
+/// rate), rate) == n`.
+pub fn duration_to_samples(duration: Duration, sample_rate: u32) -> u64 {
+    let rate = sample_rate as u64;
+    let frac = (duration.subsec_nanos() as u64 * rate + 999_999_999) / 1_000_000_000;
+    duration.as_secs().saturating_mul(rate).saturating_add(frac)
+}
+
+#[test]
+fn verify_samples_to_duration() {
+    assert_eq!(samples_to_duration(0, 44_100), Duration::new(0, 0));
+    assert_eq!(samples_to_duration(44_100, 44_100), Duration::new(1, 0));
+    assert_eq!(samples_to_duration(66_150, 44_100), Duration::from_millis(1500));
+    assert_eq!(duration_to_samples(Duration::from_millis(1500), 44_100), 66_150);
+    assert_eq!(duration_to_samples(Duration::from_millis(50), 44_100), 2205);
+
+    // Round trips hold for every sample, also when the duration is inexact.
+    for &rate in &[1, 7, 8_000, 44_100, 96_000, 655_350] {
+        for n in (0..2 * rate as u64).filter(|n| n % 997 < 3) {
+            let duration = samples_to_duration(n, rate);
+            assert_eq!(duration_to_samples(duration, rate), n);
+        }
+    }
+}
+
 /// A block of raw audio samples.
+///
+/// Two blocks are equal when they start at the same sample and contain the
+/// same samples for the same number of channels. Other properties of the
+/// frame, such as its frame header fields and issues, are not compared. To
+/// compare those without comparing the samples, see `same_header()`. The
+/// `Debug` representation omits the samples themselves.
+///
+/// A block is `Send` and `Sync`, so it can be handed to another thread after
+/// decoding. To share one block between threads, see `ArcBlock`.
+#[derive(Clone)]
 pub struct Block {
     /// The sample number of the first sample in the this block.
     first_sample_number: u64,
@@ -406,26 +842,88 @@ pub struct Block {
     block_size: u32,
     /// The number of channels in the block.
     channels: u32,
+    /// The sample rate in Hz from the frame header, if it specified one.
+    sample_rate: Option<u32>,
+    /// The bits per sample from the frame header, or 0 for the empty block.
+    bits_per_sample: u32,
+    /// The frame number from the frame header, for a fixed block size stream.
+    frame_number: Option<u32>,
+    /// Whether the frame header uses the variable blocking strategy.
+    variable_block_size: bool,
+    /// How the channels of the frame were coded.
+    channel_coding: ChannelCoding,
+    /// Problems that lenient decoding tolerated in the frame.
+    issues: Vec<FrameIssue>,
     /// The decoded samples, the channels stored consecutively.
     buffer: Vec<i32>,
 }
 
 impl Block {
-    fn new(time: u64, bs: u32, buffer: Vec<i32>) -> Block {
-        Block {
+    /// Creates a block, checking the invariants that other methods rely on.
+    ///
+    /// A block that is not the empty block has at least one channel and at
+    /// least one sample per channel, at most 8 channels, its length
+    /// `bs * channels` fits in a `u32`, and its buffer holds exactly that many
+    /// samples. The frame header format guarantees all of these, but the block
+    /// size and channel count are derived from untrusted input, so a violation
+    /// results in a `FormatError` rather than a panic.
+    fn try_new(time: u64, bs: u32, channels: u32, buffer: Vec<i32>) -> Result<Block> {
+        if bs == 0 {
+            return fmt_err("invalid block, a block must contain at least one sample");
+        }
+        if channels == 0 || channels > 8 {
+            return fmt_err("invalid block, a block must have 1 to 8 channels");
+        }
+        match bs.checked_mul(channels) {
+            Some(len) if len as usize == buffer.len() => {}
+            _ => return fmt_err("invalid block, buffer size does not match block size"),
+        }
+        Ok(Block {
             first_sample_number: time,
             block_size: bs,
-            channels: buffer.len() as u32 / bs,
+            channels: channels,
+            sample_rate: None,
+            bits_per_sample: 0,
+            frame_number: None,
+            variable_block_size: false,
+            channel_coding: ChannelCoding::Independent { channels: channels as u8 },
+            issues: Vec::new(),
             buffer: buffer,
-        }
+        })
+    }
+
+    /// Creates a block like `try_new()`, but panics if the invariants do not hold.
+    #[cfg(test)]
+    fn new(time: u64, bs: u32, channels: u32, buffer: Vec<i32>) -> Block {
+        Block::try_new(time, bs, channels, buffer).unwrap()
+    }
+
+    /// Returns an empty block that holds on to `buffer`, so it can be reused.
+    ///
+    /// The buffer is cleared, but it keeps its capacity, and `into_buffer()`
+    /// returns it. This is useful to keep a buffer in place of a block, for
+    /// instance after `FrameReader::read_next_into()` returns no block.
+    pub fn empty_with_buffer(buffer: Vec<i32>) -> Block {
+        let mut buffer = buffer;
+        buffer.clear();
+        Block { buffer: buffer, ..Block::empty() }
     }
 
     /// Returns a block with 0 channels and 0 samples.
+    ///
+    /// This is the only kind of block for which `channels()` and `duration()`
+    /// are zero. Blocks returned by a `FrameReader` are never empty.
     pub fn empty() -> Block {
         Block {
             first_sample_number: 0,
             block_size: 0,
             channels: 0,
+            sample_rate: None,
+            bits_per_sample: 0,
+            frame_number: None,
+            variable_block_size: false,
+            channel_coding: ChannelCoding::Independent { channels: 0 },
+            issues: Vec::new(),
             buffer: Vec::with_capacity(0),
         }
     }
@@ -444,23 +942,143 @@ impl Block {
     /// Samples in different channels are counted as distinct samples.
     #[inline(always)]
     pub fn len(&self) -> u32 {
-        // Note: this cannot overflow, because the block size fits in 16 bits,
-        // and the number of channels is at most 8.
+        // Note: this cannot overflow, because `try_new()` checks that the
+        // product fits in a `u32`.
         self.block_size * self.channels
     }
 
+    /// Returns whether this is the empty block, with no channels or samples.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.channels == 0
+    }
+
     /// Returns the number of inter-channel samples in the block.
     ///
     /// The duration is independent of the number of channels. The returned
     /// value is also referred to as the *block size*. To get the duration of
     /// the block in seconds, divide this number by the sample rate in the
     /// streaminfo.
+    ///
+    /// The duration is at least 1 and at most 65535, unless the block is empty.
     #[inline(always)]
     pub fn duration(&self) -> u32 {
         self.block_size
     }
 
+    /// Returns the sample rate in Hz that the frame header specifies.
+    ///
+    /// Frame headers may either specify the sample rate, or refer to the
+    /// streaminfo for it, in which case this returns `None`. A rate from the
+    /// header is at least 1 and at most 655350 Hz, the range that the
+    /// streaminfo allows. The empty block has no sample rate.
+    #[inline(always)]
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    /// Returns the number of bits per sample that the frame header specifies.
+    ///
+    /// This is between 4 and 32 inclusive, unless the block is empty.
+    #[inline(always)]
+    pub fn bits_per_sample(&self) -> u32 {
+        self.bits_per_sample
+    }
+
+    /// Returns the frame number from the frame header, if it has one.
+    ///
+    /// The frame header of a fixed block size stream holds the frame number,
+    /// the frame header of a variable block size stream holds the sample number
+    /// instead, in which case this returns `None`. Do not compute the time of a
+    /// block from the frame number and the block size, because the final frame
+    /// of a fixed block size stream may be shorter than the others; `time()`
+    /// takes that into account.
+    #[inline(always)]
+    pub fn frame_number(&self) -> Option<u32> {
+        self.frame_number
+    }
+
+    /// Returns whether the frame header uses the variable blocking strategy.
+    ///
+    /// The header of a variable block size stream holds the number of the
+    /// first sample in the frame, the header of a fixed block size stream
+    /// holds the frame number, see `frame_number()`. A stream should not mix
+    /// the two. The empty block reports false.
+    #[inline(always)]
+    pub fn is_variable_block_size(&self) -> bool {
+        self.variable_block_size
+    }
+
+    /// Returns how the encoder coded the channels of the frame.
+    ///
+    /// A stereo frame can store the left and right channel as they are, or
+    /// replace one of them, or both, by the difference and the mean of the
+    /// two. This describes the frame as it was stored; the samples in the
+    /// block are left and right already. After channel selection, the block
+    /// may have fewer channels than the coding. The empty block reports 0
+    /// independent channels.
+    #[inline(always)]
+    pub fn channel_coding(&self) -> ChannelCoding {
+        self.channel_coding
+    }
+
+    /// Returns the time of the first sample in the block, since the start of the stream.
+    ///
+    /// This converts `time()` into a duration with `samples_to_duration()`.
+    /// Frame headers usually refer to the streaminfo for the sample rate, so it
+    /// must be provided.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sample_rate` is 0.
+    pub fn timestamp(&self, sample_rate: u32) -> Duration {
+        samples_to_duration(self.first_sample_number, sample_rate)
+    }
+
+    /// Returns the problems that were tolerated while decoding the frame.
+    ///
+    /// This is empty unless the frame reader is lenient, see
+    /// `FrameReader::set_lenient()`, in which case it lists every problem
+    /// found in the frame, in the order they were found.
+    pub fn issues(&self) -> &[FrameIssue] {
+        &self.issues
+    }
+
+    /// Returns the sample rate, channels, and bits per sample of the block.
+    ///
+    /// The channels are those of the block, after channel selection. If the
+    /// frame header does not specify the sample rate, it is taken from
+    /// `stream`, usually the spec of the streaminfo. Comparing the result with
+    /// the spec of the previous block tells whether the format changed.
+    pub fn spec(&self, stream: StreamSpec) -> StreamSpec {
+        StreamSpec {
+            sample_rate: self.sample_rate.unwrap_or(stream.sample_rate),
+            channels: self.channels,
+            bits_per_sample: self.bits_per_sample,
+        }
+    }
+
+    /// Returns whether the frame headers of two blocks are the same.
+    ///
+    /// This compares the time, duration, channels, sample rate, bits per
+    /// sample, frame number, blocking strategy, and channel coding, but not
+    /// the samples, so it is cheap regardless of the block size.
+    pub fn same_header(&self, other: &Block) -> bool {
+        self.first_sample_number == other.first_sample_number &&
+        self.block_size == other.block_size &&
+        self.channels == other.channels &&
+        self.sample_rate == other.sample_rate &&
+        self.bits_per_sample == other.bits_per_sample &&
+        self.frame_number == other.frame_number &&
+        self.variable_block_size == other.variable_block_size &&
+        self.channel_coding == other.channel_coding
+    }
+
     /// Returns the number of channels in the block.
+    ///
+    /// The number of channels is between 1 and 8 inclusive, unless the block
+    /// is empty. The buffer of the block holds exactly `duration()` samples for
+    /// every channel, so indexing based on these values is in bounds.
     // TODO: Should a frame know this? #channels must be constant throughout the stream anyway ...
     // TODO: Rename to `num_channels` for clarity.
     #[inline(always)]
@@ -480,6 +1098,18 @@ impl Block {
         &self.buffer[ch_usz * bsz..(ch_usz + 1) * bsz]
     }
 
+    /// Returns the (zero-based) `ch`-th channel as a slice, if it exists.
+    ///
+    /// This is like `channel()`, but returns `None` instead of panicking.
+    #[inline(always)]
+    pub fn try_channel(&self, ch: u32) -> Option<&[i32]> {
+        if ch < self.channels {
+            Some(self.channel(ch))
+        } else {
+            None
+        }
+    }
+
     /// Returns a sample in this block.
     ///
     /// The value returned is for the zero-based `ch`-th channel of the
@@ -489,13 +1119,132 @@ impl Block {
     /// # Panics
     ///
     /// Panics if `ch >= channels()` or if `sample >= len()` for the last
-    /// channel.
+    /// channel. For the other channels, an out of range `sample` is not
+    /// detected, and a sample of a later channel is returned instead. Use
+    /// `try_sample()` to check both indices.
     #[inline(always)]
     pub fn sample(&self, ch: u32, sample: u32) -> i32 {
         let bsz = self.block_size as usize;
         return self.buffer[ch as usize * bsz + sample as usize];
     }
 
+    /// Returns a sample in this block, if both indices are in range.
+    ///
+    /// This is like `sample()`, but returns `None` instead of panicking, also
+    /// when `sample >= len()` for a channel other than the last one.
+    #[inline(always)]
+    pub fn try_sample(&self, ch: u32, sample: u32) -> Option<i32> {
+        if ch < self.channels && sample < self.block_size {
+            Some(self.sample(ch, sample))
+        } else {
+            None
+        }
+    }
+
+    /// Copies all channels into a caller-owned matrix, one row per channel.
+    ///
+    /// Channel `ch` is copied to `output[ch * stride..ch * stride + len()]`,
+    /// the elements between the rows are left untouched. DSP libraries often
+    /// require their own layout, for instance rows padded to a multiple of a
+    /// vector width; this fills such a matrix in one pass per channel, without
+    /// calling `sample()` for every sample. With `stride` equal to `len()`,
+    /// the output is the buffer of the block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `stride < len()`, or if `output` is too small to hold the
+    /// last row, `(channels() - 1) * stride + len()` elements.
+    pub fn copy_planar_into(&self, output: &mut [i32], stride: usize) {
+        let bsz = self.block_size as usize;
+        assert!(stride >= bsz, "stride must be at least the block size");
+        if self.channels == 0 {
+            return
+        }
+        let len = (self.channels as usize - 1) * stride + bsz;
+        assert!(output.len() >= len, "output buffer too small for all channels");
+        for (ch, samples) in self.buffer.chunks(bsz).enumerate() {
+            output[ch * stride..ch * stride + bsz].copy_from_slice(samples);
+        }
+    }
+
+    /// Writes the samples as interleaved little-endian PCM bytes to `output`.
+    ///
+    /// Every sample takes `bits_per_sample / 8` bytes, which must be 2, 3, or
+    /// 4. This is the packed signed integer format that audio output APIs
+    /// usually expect. The samples are scaled from the bits per sample of the
+    /// frame to the full width of the container: narrower samples are shifted
+    /// left, so 16-bit audio packed as 24 bits is as loud as 24-bit audio, and
+    /// wider samples are shifted right, which discards the low bits. Samples of
+    /// a block that was not decoded from a frame, for which `bits_per_sample()`
+    /// is 0, are not scaled. Returns the number of bytes written,
+    /// `len() * bits_per_sample / 8`, where `len()` counts the samples of all
+    /// channels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits_per_sample` is not 16, 24, or 32, or if `output` is too
+    /// small to hold all samples.
+    pub fn pack_pcm_le(&self, bits_per_sample: u32, output: &mut [u8]) -> usize {
+        let bytes = packed_sample_bytes(bits_per_sample);
+        let len = self.block_size as usize * self.channels as usize * bytes;
+        assert!(output.len() >= len, "output buffer too small for the packed samples");
+        self.pack_pcm_le_range(bytes, 0, self.block_size, &mut output[..len]);
+        len
+    }
+
+    /// Writes the samples as interleaved little-endian PCM bytes to a writer.
+    ///
+    /// The format is the same as for `pack_pcm_le()`. The samples are packed
+    /// in chunks into a buffer on the stack, so this does not allocate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits_per_sample` is not 16, 24, or 32.
+    pub fn write_pcm_le<W: io::Write>(&self,
+                                      bits_per_sample: u32,
+                                      output: &mut W)
+                                      -> io::Result<()> {
+        let bytes = packed_sample_bytes(bits_per_sample);
+        let frame_len = bytes * self.channels as usize;
+        if frame_len == 0 {
+            return Ok(())
+        }
+
+        // An inter-channel sample takes at most 8 * 4 bytes, so the chunk
+        // always holds many of them.
+        let mut chunk = [0u8; 4096];
+        let samples_per_chunk = (chunk.len() / frame_len) as u32;
+        let mut start = 0;
+        while start < self.block_size {
+            let end = cmp::min(start + samples_per_chunk, self.block_size);
+            let len = (end - start) as usize * frame_len;
+            self.pack_pcm_le_range(bytes, start, end, &mut chunk[..len]);
+            try!(output.write_all(&chunk[..len]));
+            start = end;
+        }
+
+        Ok(())
+    }
+
+    /// Packs the inter-channel samples `start..end` into `output`, which must fit them exactly.
+    fn pack_pcm_le_range(&self, bytes: usize, start: u32, end: u32, output: &mut [u8]) {
+        debug_assert_eq!(output.len(), (end - start) as usize * bytes * self.channels as usize);
+        let container_bits = bytes as u32 * 8;
+        let sample_bits = match self.bits_per_sample {
+            0 => container_bits,
+            bps => bps,
+        };
+        let interleave_pcm_le = dispatch::kernels().interleave_pcm_le;
+        interleave_pcm_le(&self.buffer,
+                          self.block_size as usize,
+                          self.channels as usize,
+                          bytes,
+                          container_bits.saturating_sub(sample_bits),
+                          sample_bits.saturating_sub(container_bits),
+                          start as usize,
+                          output);
+    }
+
     /// Returns the underlying buffer that stores the samples in this block.
     ///
     /// This allows the buffer to be reused to decode the next frame. The
@@ -504,6 +1253,46 @@ impl Block {
         return self.buffer;
     }
 
+    /// Returns the number of samples that the underlying buffer can hold.
+    ///
+    /// When the buffer is reused, its capacity only ever grows, so after an
+    /// unusually large frame, it may be much bigger than the blocks that follow
+    /// need. See `shrink_buffer()`.
+    pub fn buffer_capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Releases the capacity of the underlying buffer beyond `max_len` samples.
+    ///
+    /// If the capacity exceeds `max_len`, the buffer is shrunk to hold exactly
+    /// the samples of this block. Otherwise, it is left alone, so the buffer
+    /// does not need to grow again for the next block of the same size.
+    pub fn shrink_buffer(&mut self, max_len: usize) {
+        if self.buffer.capacity() > max_len {
+            self.buffer.shrink_to_fit();
+        }
+    }
+
+    /// Converts the block into one that can be shared without copying.
+    ///
+    /// The resulting `ArcBlock` is cheap to clone, so a single decoded block
+    /// can be consumed by multiple threads, for instance for playback and for
+    /// visualization at the same time.
+    pub fn into_arc(self) -> ArcBlock {
+        ArcBlock {
+            first_sample_number: self.first_sample_number,
+            block_size: self.block_size,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: self.bits_per_sample,
+            frame_number: self.frame_number,
+            variable_block_size: self.variable_block_size,
+            channel_coding: self.channel_coding,
+            issues: self.issues,
+            buffer: Arc::new(self.buffer),
+        }
+    }
+
     /// Returns an iterator that produces left and right channel samples.
     ///
     /// This iterator can be more efficient than requesting a sample directly,
@@ -526,20 +1315,256 @@ impl Block {
             current_sample: 0,
         }
     }
+
+    /// Returns an iterator that produces left and right channel samples as `i16`.
+    ///
+    /// The samples are scaled from `bits_per_sample` bits, usually the bits
+    /// per sample of the stream, to 16 bits: narrower samples are shifted left,
+    /// and wider samples are shifted right, which discards the low bits. To
+    /// avoid the distortion that this causes for quiet signals, `dither` can
+    /// add noise before discarding bits, see `Dither`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of channels in the block is not 2, or if
+    /// `bits_per_sample` is not in the range 1 through 32.
+    pub fn stereo_samples_i16<'a>(&'a self,
+                                  bits_per_sample: u32,
+                                  dither: Dither)
+                                  -> StereoSamplesI16<'a> {
+        assert!(bits_per_sample >= 1 && bits_per_sample <= 32,
+                "bits per sample must be between 1 and 32");
+        StereoSamplesI16 {
+            inner: self.stereo_samples(),
+            shift_left: 16u32.saturating_sub(bits_per_sample),
+            shift_right: bits_per_sample.saturating_sub(16),
+            dither: dither,
+            // An arbitrary nonzero seed, so the output is deterministic.
+            rng_state: 0x9e37_79b9,
+        }
+    }
 }
 
-#[test]
-fn verify_block_sample() {
-    let block = Block {
-        first_sample_number: 0,
-        block_size: 5,
+/// Interleaves planar samples into little-endian PCM of `bytes` bytes per sample.
+///
+/// `planar` holds `channels` channels of `block_size` samples each. Every
+/// sample is shifted left by `shift_left` bits and then right by `shift_right`
+/// bits. Inter-channel samples are packed starting at index `start`, as many
+/// as fit in `output`. This is the scalar kernel, `Block` calls the one
+/// selected by `dispatch`.
+pub fn interleave_pcm_le(planar: &[i32],
+                         block_size: usize,
+                         channels: usize,
+                         bytes: usize,
+                         shift_left: u32,
+                         shift_right: u32,
+                         start: usize,
+                         output: &mut [u8]) {
+    let frame_len = bytes * channels;
+    if frame_len == 0 {
+        return
+    }
+    for (frame, i) in output.chunks_mut(frame_len).zip(start..) {
+        for (ch, packed) in frame.chunks_mut(bytes).enumerate() {
+            let sample = (planar[ch * block_size + i] << shift_left) >> shift_right;
+            for (k, byte) in packed.iter_mut().enumerate() {
+                *byte = (sample >> (8 * k)) as u8;
+            }
+        }
+    }
+}
+
+/// Returns the number of bytes per sample for packed PCM output.
+fn packed_sample_bytes(bits_per_sample: u32) -> usize {
+    match bits_per_sample {
+        16 | 24 | 32 => bits_per_sample as usize / 8,
+        _ => panic!("packed PCM must have 16, 24, or 32 bits per sample"),
+    }
+}
+
+impl PartialEq for Block {
+    fn eq(&self, other: &Block) -> bool {
+        self.first_sample_number == other.first_sample_number &&
+        self.channels == other.channels &&
+        self.buffer == other.buffer
+    }
+}
+
+impl Eq for Block {}
+
+impl fmt::Debug for Block {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("Block")
+            .field("time", &self.first_sample_number)
+            .field("duration", &self.block_size)
+            .field("channels", &self.channels)
+            .finish()
+    }
+}
+
+#[test]
+fn verify_block_sample() {
+    let block = Block {
+        first_sample_number: 0,
+        block_size: 5,
         channels: 3,
+        sample_rate: None,
+        bits_per_sample: 0,
+        frame_number: None,
+        variable_block_size: false,
+        channel_coding: ChannelCoding::Independent { channels: 3 },
+        issues: Vec::new(),
         buffer: vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47],
     };
 
     assert_eq!(block.sample(0, 2), 5);
     assert_eq!(block.sample(1, 3), 23);
     assert_eq!(block.sample(2, 4), 47);
+
+    assert_eq!(block.try_sample(1, 3), Some(23));
+    assert_eq!(block.try_sample(0, 5), None);
+    assert_eq!(block.try_sample(3, 0), None);
+    assert_eq!(block.try_channel(2), Some(&[31, 37, 41, 43, 47][..]));
+    assert_eq!(block.try_channel(3), None);
+    assert_eq!(Block::empty().try_channel(0), None);
+}
+
+#[test]
+fn verify_block_eq_and_same_header() {
+    let a = Block::new(4096, 3, 2, vec![1, 2, 3, -1, -2, -3]);
+
+    // Equality ignores the frame header fields other than time and channels.
+    let mut b = a.clone();
+    b.frame_number = Some(1);
+    assert_eq!(a, b);
+    assert!(!a.same_header(&b));
+
+    // Headers can be the same while the samples differ.
+    let c = Block::new(4096, 3, 2, vec![1, 2, 3, -1, -2, 0]);
+    assert!(a != c);
+    assert!(a.same_header(&c));
+
+    // The same samples split differently over channels are not equal.
+    let d = Block::new(4096, 6, 1, vec![1, 2, 3, -1, -2, -3]);
+    assert!(a != d);
+    assert!(!a.same_header(&d));
+}
+
+#[test]
+fn verify_block_copy_planar_into() {
+    let block = Block::new(0, 3, 2, vec![1, 2, 3, -1, -2, -3]);
+
+    // Rows padded to 4 elements; the padding is left as it was.
+    let mut output = [9; 7];
+    block.copy_planar_into(&mut output, 4);
+    assert_eq!(output, [1, 2, 3, 9, -1, -2, -3]);
+
+    let mut output = [0; 6];
+    block.copy_planar_into(&mut output, 3);
+    assert_eq!(output, [1, 2, 3, -1, -2, -3]);
+
+    // The empty block has no channels, so there is nothing to copy.
+    Block::empty().copy_planar_into(&mut [], 0);
+}
+
+#[test]
+fn verify_block_shrink_buffer() {
+    let mut buffer = Vec::with_capacity(1024);
+    buffer.extend_from_slice(&[1, 2, 3, -1, -2, -3]);
+    let mut block = Block::new(0, 3, 2, buffer);
+    assert_eq!(block.buffer_capacity(), 1024);
+
+    // Within the limit, the capacity is kept.
+    block.shrink_buffer(1024);
+    assert_eq!(block.buffer_capacity(), 1024);
+
+    block.shrink_buffer(512);
+    assert!(block.buffer_capacity() < 512);
+    assert_eq!(block.channel(1), &[-1, -2, -3]);
+}
+
+#[test]
+fn verify_block_pack_pcm_le() {
+    let block = Block::new(0, 2, 2, vec![1, -2, 0x123456, -0x123456]);
+
+    // The stereo block holds 4 samples in total, of 3 bytes each.
+    let mut output = [0u8; 16];
+    assert_eq!(block.len(), 4);
+    assert_eq!(block.pack_pcm_le(24, &mut output), block.len() as usize * 3);
+    let output = &output[..12];
+    assert_eq!(output, &[0x01, 0x00, 0x00, 0x56, 0x34, 0x12,
+                         0xfe, 0xff, 0xff, 0xaa, 0xcb, 0xed]);
+
+    let mut output = Vec::new();
+    block.write_pcm_le(16, &mut output).unwrap();
+    assert_eq!(output, vec![0x01, 0x00, 0x56, 0x34, 0xfe, 0xff, 0xaa, 0xcb]);
+
+    // Larger blocks are written in multiple chunks.
+    let samples: Vec<i32> = (0..4000).map(|i| i * 1000 - 2_000_000).collect();
+    let block = Block::new(0, 1000, 4, samples);
+    let mut packed = vec![0u8; 16_000];
+    assert_eq!(block.pack_pcm_le(32, &mut packed), 16_000);
+    let mut written = Vec::new();
+    block.write_pcm_le(32, &mut written).unwrap();
+    assert_eq!(written, packed);
+    let sample = |i: usize| {
+        let b = &packed[i * 4..i * 4 + 4];
+        (b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24) as i32
+    };
+    assert_eq!(sample(5), block.sample(1, 1));
+    assert_eq!(sample(3999), block.sample(3, 999));
+
+    let mut empty = Vec::new();
+    Block::empty().write_pcm_le(16, &mut empty).unwrap();
+    assert!(empty.is_empty());
+
+    // Samples of a decoded block are scaled to fill the container.
+    let block = Block { bits_per_sample: 16, ..Block::new(0, 1, 2, vec![0x1234, -2]) };
+    let mut output = [0u8; 6];
+    assert_eq!(block.pack_pcm_le(24, &mut output), 6);
+    assert_eq!(output, [0x00, 0x34, 0x12, 0x00, 0xfe, 0xff]);
+    let mut output = Vec::new();
+    block.write_pcm_le(32, &mut output).unwrap();
+    assert_eq!(output, vec![0x00, 0x00, 0x34, 0x12, 0x00, 0x00, 0xfe, 0xff]);
+
+    let block = Block { bits_per_sample: 24, ..Block::new(0, 1, 2, vec![0x123456, -0x123456]) };
+    let mut output = [0u8; 4];
+    assert_eq!(block.pack_pcm_le(16, &mut output), 4);
+    assert_eq!(output, [0x34, 0x12, 0xcb, 0xed]);
+}
+
+#[test]
+fn verify_block_eq_and_debug() {
+    let block = Block::new(10, 2, 2, vec![1, 2, 3, 4]);
+    assert_eq!(block, block.clone());
+    assert!(block != Block::new(12, 2, 2, vec![1, 2, 3, 4]));
+    assert!(block != Block::new(10, 2, 2, vec![1, 2, 3, 5]));
+    assert_eq!(format!("{:?}", block), "Block { time: 10, duration: 2, channels: 2 }");
+}
+
+#[test]
+fn verify_block_new_checks_invariants() {
+    use std::panic;
+
+    let block = Block::new(0, 3, 2, vec![0; 6]);
+    assert_eq!(block.len(), 6);
+    assert!(!block.is_empty());
+    assert!(Block::empty().is_empty());
+
+    assert!(panic::catch_unwind(|| Block::new(0, 0, 2, vec![])).is_err());
+    assert!(panic::catch_unwind(|| Block::new(0, 3, 0, vec![])).is_err());
+    assert!(panic::catch_unwind(|| Block::new(0, 3, 9, vec![0; 27])).is_err());
+    assert!(panic::catch_unwind(|| Block::new(0, 3, 2, vec![0; 5])).is_err());
+
+    // The checked constructor reports the same violations as format errors.
+    assert!(Block::try_new(0, 3, 2, vec![0; 6]).is_ok());
+    assert!(Block::try_new(0, 0, 2, vec![]).is_err());
+    assert!(Block::try_new(0, 3, 9, vec![0; 27]).is_err());
+    assert!(Block::try_new(0, 3, 2, vec![0; 7]).is_err());
+    match Block::try_new(0, 0x8000_0000, 2, vec![]) {
+        Err(Error::FormatError(..)) => {}
+        _ => panic!("expected format error for overflowing block length"),
+    }
 }
 
 /// An iterator over the stereo sample pairs in a block.
@@ -566,11 +1591,14 @@ impl<'a> Iterator for StereoSamples<'a> {
             // sample is less than the block duration, and the buffer size is at
             // least twice the block duration. (There is an assertion for that
             // too when the iterator is constructed.)
+            #[cfg(not(feature = "forbid-unsafe"))]
             let samples = unsafe {
                 let left = *self.buffer.get_unchecked(idx);
                 let right = *self.buffer.get_unchecked(idx + ch_offset);
                 (left, right)
             };
+            #[cfg(feature = "forbid-unsafe")]
+            let samples = (self.buffer[idx], self.buffer[idx + ch_offset]);
 
             self.current_sample += 1;
 
@@ -585,6 +1613,12 @@ fn verify_block_stereo_samples_iterator() {
         first_sample_number: 0,
         block_size: 3,
         channels: 2,
+        sample_rate: None,
+        bits_per_sample: 0,
+        frame_number: None,
+        variable_block_size: false,
+        channel_coding: ChannelCoding::Independent { channels: 2 },
+        issues: Vec::new(),
         buffer: vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47],
     };
 
@@ -596,193 +1630,3480 @@ fn verify_block_stereo_samples_iterator() {
     assert_eq!(iter.next(), None);
 }
 
-/// Reads frames from a stream and exposes decoded blocks as an iterator.
+/// How `Block::stereo_samples_i16()` discards low bits of wide samples.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Dither {
+    /// Shift right, rounding towards negative infinity, without adding noise.
+    Truncate,
+
+    /// Add triangular noise of up to one output step, then round.
+    ///
+    /// This decorrelates the quantization error from the signal, so it is
+    /// heard as a constant low noise floor rather than as distortion. The
+    /// noise comes from a fast pseudorandom generator with a fixed seed, so
+    /// the output is deterministic.
+    Triangular,
+}
+
+/// An iterator over the stereo sample pairs in a block, scaled to `i16`.
 ///
-/// TODO: for now, it is assumes that the reader starts at a frame header;
-/// no searching for a sync code is performed at the moment.
-pub struct FrameReader<R: ReadBytes> {
-    input: R,
+/// This iterator is produced by `Block::stereo_samples_i16()`.
+pub struct StereoSamplesI16<'a> {
+    inner: StereoSamples<'a>,
+    shift_left: u32,
+    shift_right: u32,
+    dither: Dither,
+    rng_state: u32,
 }
 
-/// Either a `Block` or an `Error`.
-// TODO: The option should not be part of FrameResult.
-pub type FrameResult = Result<Option<Block>>;
+impl<'a> StereoSamplesI16<'a> {
+    /// Returns the next number of a xorshift generator.
+    #[inline(always)]
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+        x
+    }
 
-/// A function to expand the length of a buffer, or replace the buffer altogether,
-/// so it can hold at least `new_len` elements. The contents of the buffer can
-/// be anything, it is assumed they will be overwritten anyway.
-///
-/// To use this function safely, the caller must overwrite all `new_len` bytes.
-fn ensure_buffer_len(mut buffer: Vec<i32>, new_len: usize) -> Vec<i32> {
-    if buffer.len() < new_len {
-        // Previous data will be overwritten, so instead of resizing the
-        // vector if it is too small, we might as well allocate a new one.
-        // NOTE: In the past we allocated a vector with Vec::with_capacity here,
-        // setting it to the right length without zeroing (and also setting the
-        // right length if the capacity was sufficient). However, the
-        // performance impact of zeroing turned out to be negligible (a 0.2%
-        // increase in decode times in an adversarial scenario, but a 0.03%
-        // increase on real-world files when the buffer is recycled). We still
-        // don't zero out previous contents of the buffer as it was passed in,
-        // zeroing only happens on resize or for new allocations.
-        if buffer.capacity() < new_len {
-            buffer = vec![0; new_len];
-        } else {
-            buffer.resize(new_len, 0);
+    #[inline(always)]
+    fn convert(&mut self, sample: i32) -> i16 {
+        if self.shift_right == 0 {
+            // Valid samples of at most 16 bits fit after the shift.
+            return (sample << self.shift_left) as i16;
         }
-    } else {
-        buffer.truncate(new_len);
+
+        let sample = sample as i64;
+        let scaled = match self.dither {
+            Dither::Truncate => sample >> self.shift_right,
+            Dither::Triangular => {
+                // The difference of two uniform values in [0, step) has a
+                // triangular distribution on (-step, step).
+                let mask = (1u64 << self.shift_right) - 1;
+                let a = (self.next_random() as u64 & mask) as i64;
+                let b = (self.next_random() as u64 & mask) as i64;
+                let half = 1i64 << (self.shift_right - 1);
+                (sample + a - b + half) >> self.shift_right
+            }
+        };
+
+        cmp::max(i16::MIN as i64, cmp::min(i16::MAX as i64, scaled)) as i16
     }
-    buffer
 }
 
-#[test]
-fn ensure_buffer_len_returns_buffer_with_new_len() {
-    for capacity in 0..10 {
-        for new_len in 0..10 {
-            let buffer = Vec::with_capacity(capacity);
-            let resized = ensure_buffer_len(buffer, new_len);
-            assert_eq!(resized.len(), new_len);
+impl<'a> Iterator for StereoSamplesI16<'a> {
+    type Item = (i16, i16);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<(i16, i16)> {
+        match self.inner.next() {
+            Some((left, right)) => Some((self.convert(left), self.convert(right))),
+            None => None,
         }
     }
 }
 
-impl<R: ReadBytes> FrameReader<R> {
-    /// Creates a new frame reader that will yield at least one element.
-    pub fn new(input: R) -> FrameReader<R> {
-        FrameReader {
-            input: input,
-        }
+#[test]
+fn verify_block_stereo_samples_i16() {
+    let block = Block::new(0, 3, 2, vec![0x123456, -0x800000, 0x7fffff,
+                                         -1, 0x0007ff, -0x000800]);
+
+    let samples: Vec<_> = block.stereo_samples_i16(24, Dither::Truncate).collect();
+    assert_eq!(samples, vec![(0x1234, -1), (-0x8000, 0x0007), (0x7fff, -0x0008)]);
+
+    // Narrow samples are shifted left, 16-bit samples are unchanged.
+    let block = Block::new(0, 2, 2, vec![0x7ff, -0x800, 1, -1]);
+    let samples: Vec<_> = block.stereo_samples_i16(12, Dither::Truncate).collect();
+    assert_eq!(samples, vec![(0x7ff0, 0x10), (-0x8000, -0x10)]);
+    let samples: Vec<_> = block.stereo_samples_i16(16, Dither::Triangular).collect();
+    assert_eq!(samples, vec![(0x7ff, 1), (-0x800, -1)]);
+
+    // Dither changes samples by at most one step from rounding, and never wraps.
+    let block = Block::new(0, 1000, 2, (0..2000).map(|i| match i % 4 {
+        0 => 0x7fffff,
+        1 => -0x800000,
+        _ => i * 97,
+    }).collect());
+    let round = |s: i32| cmp::max(-0x8000, cmp::min(0x7fff, (s + 0x80) >> 8));
+    let dithered = block.stereo_samples_i16(24, Dither::Triangular);
+    for ((l, r), (dl, dr)) in block.stereo_samples().zip(dithered) {
+        assert!((round(l) - dl as i32).abs() <= 1);
+        assert!((round(r) - dr as i32).abs() <= 1);
     }
+}
 
-    /// Decodes the next frame or returns an error if the data was invalid.
+/// A block of raw audio samples that can be shared between threads.
+///
+/// This is produced by `Block::into_arc()`. Cloning an `ArcBlock` does not copy
+/// the samples. When all but one of the clones have been dropped, the last one
+/// can be converted back into a `Block`, so its buffer can be reused to decode
+/// the next frame.
+#[derive(Clone)]
+pub struct ArcBlock {
+    first_sample_number: u64,
+    block_size: u32,
+    channels: u32,
+    sample_rate: Option<u32>,
+    bits_per_sample: u32,
+    frame_number: Option<u32>,
+    variable_block_size: bool,
+    channel_coding: ChannelCoding,
+    issues: Vec<FrameIssue>,
+    buffer: Arc<Vec<i32>>,
+}
+
+impl ArcBlock {
+    /// Returns the inter-channel sample number of the first sample in the block.
     ///
-    /// The buffer is moved into the returned block, so that the same buffer may
-    /// be reused to decode multiple blocks, avoiding a heap allocation every
-    /// time. It can be retrieved again with `block.into_buffer()`. If the
-    /// buffer is not large enough to hold all samples, a larger buffer is
-    /// allocated automatically.
+    /// See `Block::time()` for more info.
+    pub fn time(&self) -> u64 {
+        self.first_sample_number
+    }
+
+    /// Returns the total number of samples in this block.
     ///
-    /// TODO: I should really be consistent with 'read' and 'decode'.
-    pub fn read_next_or_eof(&mut self, mut buffer: Vec<i32>) -> FrameResult {
-        // The frame includes a CRC-16 at the end. It can be computed
-        // automatically while reading, by wrapping the input reader in a reader
-        // that computes the CRC. If the stream ended before the the frame
-        // header (so not in the middle of the frame header), return `None`,
-        // indicating EOF.
-        let mut crc_input = Crc16Reader::new(&mut self.input);
-        let header = match try!(read_frame_header_or_eof(&mut crc_input)) {
-            None => return Ok(None),
-            Some(h) => h,
-        };
+    /// See `Block::len()` for more info.
+    #[inline(always)]
+    pub fn len(&self) -> u32 {
+        self.block_size * self.channels
+    }
 
-        // We must allocate enough space for all channels in the block to be
-        // decoded.
-        let total_samples = header.channels() as usize * header.block_size as usize;
+    /// Returns the number of inter-channel samples in the block.
+    ///
+    /// See `Block::duration()` for more info.
+    #[inline(always)]
+    pub fn duration(&self) -> u32 {
+        self.block_size
+    }
 
-        // Ensure the buffer is the right size to hold all samples. For
-        // correctness, we must be careful to overwrite each byte in the buffer.
-        buffer = ensure_buffer_len(buffer, total_samples);
+    /// Returns the sample rate in Hz that the frame header specifies.
+    ///
+    /// See `Block::sample_rate()` for more info.
+    #[inline(always)]
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
 
-        let bps = match header.bits_per_sample {
-            Some(x) => x,
-            // TODO: if the bps is missing from the header, we must get it from
-            // the streaminfo block.
-            None => return Err(Error::Unsupported("header without bits per sample info")),
-        };
+    /// Returns the number of bits per sample that the frame header specifies.
+    ///
+    /// See `Block::bits_per_sample()` for more info.
+    #[inline(always)]
+    pub fn bits_per_sample(&self) -> u32 {
+        self.bits_per_sample
+    }
 
-        // The number of bits per sample must not exceed 32, for we decode into
-        // an i32. TODO: Turn this into an error instead of panic? Or is it
-        // enforced elsewhere?
-        debug_assert!(bps as usize <= 32);
+    /// Returns the frame number from the frame header, if it has one.
+    ///
+    /// See `Block::frame_number()` for more info.
+    #[inline(always)]
+    pub fn frame_number(&self) -> Option<u32> {
+        self.frame_number
+    }
 
-        // In the next part of the stream, nothing is byte-aligned any more,
-        // we need a bitstream. Then we can decode subframes from the bitstream.
-        {
-            let mut bitstream = Bitstream::new(&mut crc_input);
-            let bs = header.block_size as usize;
-
-            match header.channel_assignment {
-                ChannelAssignment::Independent(n_ch) => {
-                    for ch in 0..n_ch as usize {
-                        try!(subframe::decode(&mut bitstream,
-                                              bps,
-                                              &mut buffer[ch * bs..(ch + 1) * bs]));
-                    }
-                }
-                ChannelAssignment::LeftSideStereo => {
-                    // The side channel has one extra bit per sample.
-                    try!(subframe::decode(&mut bitstream, bps, &mut buffer[..bs]));
-                    try!(subframe::decode(&mut bitstream,
-                                          bps + 1,
-                                          &mut buffer[bs..bs * 2]));
-
-                    // Then decode the side channel into the right channel.
-                    decode_left_side(&mut buffer[..bs * 2]);
-                }
-                ChannelAssignment::RightSideStereo => {
-                    // The side channel has one extra bit per sample.
-                    try!(subframe::decode(&mut bitstream, bps + 1, &mut buffer[..bs]));
-                    try!(subframe::decode(&mut bitstream, bps, &mut buffer[bs..bs * 2]));
+    /// Returns whether the frame header uses the variable blocking strategy.
+    ///
+    /// See `Block::is_variable_block_size()` for more info.
+    #[inline(always)]
+    pub fn is_variable_block_size(&self) -> bool {
+        self.variable_block_size
+    }
 
-                    // Then decode the side channel into the left channel.
-                    decode_right_side(&mut buffer[..bs * 2]);
-                }
-                ChannelAssignment::MidSideStereo => {
-                    // Decode mid as the first channel, then side with one
-                    // extra bitp per sample.
-                    try!(subframe::decode(&mut bitstream, bps, &mut buffer[..bs]));
-                    try!(subframe::decode(&mut bitstream,
-                                          bps + 1,
-                                          &mut buffer[bs..bs * 2]));
-
-                    // Then decode mid-side channel into left-right.
-                    decode_mid_side(&mut buffer[..bs * 2]);
-                }
-            }
+    /// Returns how the encoder coded the channels of the frame.
+    ///
+    /// See `Block::channel_coding()` for more info.
+    #[inline(always)]
+    pub fn channel_coding(&self) -> ChannelCoding {
+        self.channel_coding
+    }
 
-            // When the bitstream goes out of scope, we can use the `input`
-            // reader again, which will be byte-aligned. The specification
-            // dictates that padding should consist of zero bits, but we do not
-            // enforce this here.
-            // TODO: It could be enforced by having a read_to_byte_aligned
-            // method on the bit reader; it'd be a simple comparison.
+    /// Returns the time of the first sample in the block, since the start of the stream.
+    ///
+    /// See `Block::timestamp()` for more info.
+    pub fn timestamp(&self, sample_rate: u32) -> Duration {
+        samples_to_duration(self.first_sample_number, sample_rate)
+    }
+
+    /// Returns the problems that were tolerated while decoding the frame.
+    ///
+    /// See `Block::issues()` for more info.
+    pub fn issues(&self) -> &[FrameIssue] {
+        &self.issues
+    }
+
+    /// Returns the sample rate, channels, and bits per sample of the block.
+    ///
+    /// See `Block::spec()` for more info.
+    pub fn spec(&self, stream: StreamSpec) -> StreamSpec {
+        StreamSpec {
+            sample_rate: self.sample_rate.unwrap_or(stream.sample_rate),
+            channels: self.channels,
+            bits_per_sample: self.bits_per_sample,
         }
+    }
 
-        // The frame footer is a 16-bit CRC.
-        let computed_crc = crc_input.crc();
-        let presumed_crc = try!(crc_input.read_be_u16());
+    /// Returns the number of channels in the block.
+    #[inline(always)]
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
 
-        // Do not verify checksum during fuzzing, otherwise malformed input from
-        // the fuzzer won't reach the actually interesting code.
-        #[cfg(not(fuzzing))]
-        {
-            if computed_crc != presumed_crc {
-                return fmt_err("frame CRC mismatch");
+    /// Returns the (zero-based) `ch`-th channel as a slice.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ch >= channels()`.
+    #[inline(always)]
+    pub fn channel(&self, ch: u32) -> &[i32] {
+        let bsz = self.block_size as usize;
+        let ch_usz = ch as usize;
+        &self.buffer[ch_usz * bsz..(ch_usz + 1) * bsz]
+    }
+
+    /// Returns a sample in this block.
+    ///
+    /// See `Block::sample()` for more info.
+    #[inline(always)]
+    pub fn sample(&self, ch: u32, sample: u32) -> i32 {
+        let bsz = self.block_size as usize;
+        self.buffer[ch as usize * bsz + sample as usize]
+    }
+
+    /// Converts back into a `Block`, if this is the only reference to the samples.
+    ///
+    /// If other clones of this block still exist, the block is returned
+    /// unchanged as error.
+    pub fn try_into_block(self) -> result::Result<Block, ArcBlock> {
+        let first_sample_number = self.first_sample_number;
+        let block_size = self.block_size;
+        let channels = self.channels;
+        let sample_rate = self.sample_rate;
+        let bits_per_sample = self.bits_per_sample;
+        let frame_number = self.frame_number;
+        let variable_block_size = self.variable_block_size;
+        let channel_coding = self.channel_coding;
+        let issues = self.issues;
+        match Arc::try_unwrap(self.buffer) {
+            Ok(buffer) => {
+                Ok(Block {
+                    first_sample_number: first_sample_number,
+                    block_size: block_size,
+                    channels: channels,
+                    sample_rate: sample_rate,
+                    bits_per_sample: bits_per_sample,
+                    frame_number: frame_number,
+                    variable_block_size: variable_block_size,
+                    channel_coding: channel_coding,
+                    issues: issues,
+                    buffer: buffer,
+                })
+            }
+            Err(buffer) => {
+                Err(ArcBlock {
+                    first_sample_number: first_sample_number,
+                    block_size: block_size,
+                    channels: channels,
+                    sample_rate: sample_rate,
+                    bits_per_sample: bits_per_sample,
+                    frame_number: frame_number,
+                    variable_block_size: variable_block_size,
+                    channel_coding: channel_coding,
+                    issues: issues,
+                    buffer: buffer,
+                })
             }
         }
+    }
+}
 
-        // Silence unused variable warnings.
-        #[cfg(fuzzing)]
-        let _ = computed_crc == presumed_crc;
+#[test]
+fn verify_arc_block() {
+    let block = Block {
+        first_sample_number: 7,
+        block_size: 5,
+        channels: 3,
+        sample_rate: None,
+        bits_per_sample: 0,
+        frame_number: None,
+        variable_block_size: false,
+        channel_coding: ChannelCoding::Independent { channels: 3 },
+        issues: Vec::new(),
+        buffer: vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47],
+    };
 
-        // TODO: constant block size should be verified if a frame number is
-        // encountered.
-        let time = match header.block_time {
-            BlockTime::FrameNumber(fnr) => header.block_size as u64 * fnr as u64,
-            BlockTime::SampleNumber(snr) => snr,
-        };
+    let arc_block = block.into_arc();
+    let clone = arc_block.clone();
+    assert_eq!(clone.time(), 7);
+    assert_eq!(clone.sample(1, 3), 23);
+    assert_eq!(clone.channel(2), &[31, 37, 41, 43, 47]);
 
-        let block = Block::new(time, header.block_size as u32, buffer);
+    // While there is a clone, the buffer cannot be recycled.
+    let arc_block = arc_block.try_into_block().err().unwrap();
+    drop(clone);
 
-        Ok(Some(block))
-    }
+    let block = arc_block.try_into_block().ok().unwrap();
+    assert_eq!(block.time(), 7);
+    assert_eq!(block.into_buffer().len(), 15);
+}
 
-    /// Destroy the frame reader, returning the wrapped reader.
-    pub fn into_inner(self) -> R {
-        self.input
-    }
+#[test]
+fn verify_blocks_and_frame_reader_are_send_and_sync() {
+    // These are compile-time checks: the test fails to build if a change makes
+    // one of the types lose a guarantee that multi-threaded players rely on.
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    assert_send::<Block>();
+    assert_sync::<Block>();
+    assert_send::<ArcBlock>();
+    assert_sync::<ArcBlock>();
+    assert_send::<FrameReader<io::Cursor<Vec<u8>>>>();
+    assert_sync::<FrameReader<io::Cursor<Vec<u8>>>>();
 }
 
-// TODO: implement Iterator<Item = FrameResult> for FrameReader, with an
+/// A block of raw audio samples, with the channels interleaved.
+///
+/// This is produced by `FrameReader::read_next_interleaved_or_eof()`. The
+/// samples are stored one inter-channel sample after the other, so the `i`-th
+/// sample of channel `ch` is at index `i * channels() + ch`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterleavedBlock {
+    time: u64,
+    duration: u32,
+    channels: u32,
+    sample_rate: Option<u32>,
+    bits_per_sample: u32,
+    samples: Vec<i32>,
+}
+
+impl InterleavedBlock {
+    /// Interleaves the samples of a planar block into `buffer`.
+    fn from_block(block: &Block, buffer: Vec<i32>) -> InterleavedBlock {
+        let channels = block.channels() as usize;
+        let mut samples = ensure_buffer_len(buffer, block.len() as usize);
+        for (i, out) in samples.chunks_mut(channels).enumerate() {
+            for (ch, sample) in out.iter_mut().enumerate() {
+                *sample = block.sample(ch as u32, i as u32);
+            }
+        }
+        InterleavedBlock {
+            time: block.time(),
+            duration: block.duration(),
+            channels: block.channels(),
+            sample_rate: block.sample_rate(),
+            bits_per_sample: block.bits_per_sample(),
+            samples: samples,
+        }
+    }
+
+    /// Returns the inter-channel sample number of the first sample in the block.
+    ///
+    /// See `Block::time()` for more info.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Returns the number of inter-channel samples in the block.
+    ///
+    /// See `Block::duration()` for more info.
+    #[inline(always)]
+    pub fn duration(&self) -> u32 {
+        self.duration
+    }
+
+    /// Returns the number of channels in the block.
+    #[inline(always)]
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    /// Returns the sample rate in Hz that the frame header specifies.
+    ///
+    /// See `Block::sample_rate()` for more info.
+    #[inline(always)]
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    /// Returns the number of bits per sample that the frame header specifies.
+    #[inline(always)]
+    pub fn bits_per_sample(&self) -> u32 {
+        self.bits_per_sample
+    }
+
+    /// Returns the interleaved samples.
+    ///
+    /// The length is `duration()` times `channels()`.
+    #[inline(always)]
+    pub fn samples(&self) -> &[i32] {
+        &self.samples
+    }
+
+    /// Returns the underlying buffer, to be reused for the next block.
+    pub fn into_buffer(self) -> Vec<i32> {
+        self.samples
+    }
+}
+
+/// How to report values that the format reserves, see `FrameReader::set_reserved_values()`.
+///
+/// The FLAC format reserves a number of values in frame headers, subframe
+/// headers, and residuals for future use. Claxon implements the format as it
+/// was with FLAC 1.3.1, where such values are invalid. A revision of the format
+/// may assign a meaning to them, so a stream that contains them is not
+/// necessarily corrupt. `Error::is_reserved_value()` identifies such errors
+/// under either policy. Reserved metadata block types are not affected, the
+/// format specifies that decoders skip unknown blocks, and Claxon does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReservedValues {
+    /// Report a reserved value as `Error::FormatError`, the default.
+    Reject,
+
+    /// Report a reserved value as `Error::Unsupported`.
+    ///
+    /// This treats the stream as valid, but using a feature that Claxon does
+    /// not implement, so an application can fall back to a newer decoder.
+    Unsupported,
+}
+
+impl Default for ReservedValues {
+    fn default() -> ReservedValues {
+        ReservedValues::Reject
+    }
+}
+
+/// A problem in a frame that a lenient `FrameReader` tolerated or reported.
+///
+/// See `FrameReader::set_lenient()`. A strict reader returns an error for the
+/// first problem it finds instead, so it never records these.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameIssue {
+    /// A bit in the frame header that the format requires to be zero was set.
+    ReservedBit,
+
+    /// The CRC-8 of the frame header did not match.
+    HeaderCrcMismatch {
+        /// The CRC computed over the header bytes.
+        computed: u8,
+        /// The CRC stored in the header.
+        stored: u8,
+    },
+
+    /// A subframe could not be decoded.
+    ///
+    /// Decoding cannot continue after this, so it is always the last issue.
+    /// The error that `read_next_or_eof()` returns has the details.
+    SubframeError,
+
+    /// The CRC-16 of the frame did not match.
+    FrameCrcMismatch {
+        /// The CRC computed over the frame bytes.
+        computed: u16,
+        /// The CRC stored in the frame footer.
+        stored: u16,
+    },
+}
+
+/// Which channels `FrameReader` decodes, see `FrameReader::set_channels()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelSelection {
+    /// Decode all channels.
+    All,
+
+    /// Decode only the channels whose bit is set, bit `i` selects channel `i`.
+    ///
+    /// The selected channels appear in the block in their original order.
+    /// Subframes of other channels are skipped without decoding them, if the
+    /// frame codes its channels independently. A stereo frame coded as
+    /// mid/side or left/side needs both subframes for either channel.
+    Mask(u8),
+
+    /// Fold all channels down to stereo.
+    ///
+    /// Mono is duplicated to both channels, and stereo is unchanged. For more
+    /// channels, the center channels are mixed into both sides at -3 dB, the
+    /// surround channels into their side at -3 dB, and the LFE channel is
+    /// dropped, without reconstructing it. The mix is scaled down such that
+    /// it never exceeds the range of the input.
+    Stereo,
+}
+
+impl Default for ChannelSelection {
+    fn default() -> ChannelSelection {
+        ChannelSelection::All
+    }
+}
+
+impl ChannelSelection {
+    /// Returns the number of channels in a block decoded from a frame with `channels` channels.
+    pub fn output_channels(&self, channels: u32) -> u32 {
+        match *self {
+            ChannelSelection::All => channels,
+            ChannelSelection::Mask(mask) => (mask as u32 & ((1 << channels) - 1)).count_ones(),
+            ChannelSelection::Stereo => 2,
+        }
+    }
+
+    /// Returns a mask of the subframes needed for a frame with `channels` channels.
+    fn needed(&self, channels: u8) -> u8 {
+        match *self {
+            ChannelSelection::All => 0xff,
+            ChannelSelection::Mask(mask) => mask,
+            // In the layouts of 6 or more channels, channel 3 is the LFE channel.
+            ChannelSelection::Stereo if channels >= 6 => !0b1000,
+            ChannelSelection::Stereo => 0xff,
+        }
+    }
+}
+
+/// Moves the channels selected by the mask to the front, returns how many there are.
+fn select_channels(buffer: &mut [i32], bs: usize, channels: u8, mask: u8) -> u32 {
+    let mut n = 0;
+    for ch in 0..channels as usize {
+        if mask & (1 << ch) != 0 {
+            if n != ch {
+                let (dst, src) = buffer.split_at_mut(ch * bs);
+                dst[n * bs..(n + 1) * bs].copy_from_slice(&src[..bs]);
+            }
+            n += 1;
+        }
+    }
+    n as u32
+}
+
+/// Mixes the channels down to stereo, into the first two channels of the buffer.
+///
+/// For a mono frame the buffer is extended to hold the second channel.
+fn downmix_stereo(buffer: &mut Vec<i32>, bs: usize, channels: u8) {
+    // The weights of the channels for the left and right output, in 16-bit
+    // fixed point. These follow the channel order that the FLAC format
+    // defines: the front channels at full weight, the centre and surround
+    // channels at 1/sqrt(2), the rear centre of 6.1 at 1/2, and the LFE channel
+    // not at all. The weights are normalized so the weights for either output
+    // sum to one, and rounded down, which ensures that the mix does not clip.
+    // They are precomputed so the decoder needs no floating point.
+    let weights: &[(i64, i64)] = match channels {
+        1 => {
+            buffer.resize(bs * 2, 0);
+            let (mono, right) = buffer.split_at_mut(bs);
+            right.copy_from_slice(mono);
+            return
+        }
+        2 => return,
+        3 => &[(38390, 0), (0, 38390), (27145, 27145)],
+        4 => &[(38390, 0), (0, 38390), (27145, 0), (0, 27145)],
+        5 => &[(27145, 0), (0, 27145), (19195, 19195), (19195, 0), (0, 19195)],
+        6 => &[(27145, 0), (0, 27145), (19195, 19195), (0, 0), (19195, 0), (0, 19195)],
+        7 => {
+            &[(22488, 0), (0, 22488), (15901, 15901), (0, 0), (11244, 11244), (15901, 0),
+              (0, 15901)]
+        }
+        _ => {
+            &[(20996, 0), (0, 20996), (14846, 14846), (0, 0), (14846, 0), (0, 14846),
+              (14846, 0), (0, 14846)]
+        }
+    };
+
+    for i in 0..bs {
+        let mut l = 0i64;
+        let mut r = 0i64;
+        for (ch, &(wl, wr)) in weights.iter().enumerate() {
+            let x = buffer[ch * bs + i] as i64;
+            l += wl * x;
+            r += wr * x;
+        }
+        buffer[i] = (l >> 16) as i32;
+        buffer[bs + i] = (r >> 16) as i32;
+    }
+}
+
+#[test]
+fn verify_select_channels_and_downmix() {
+    let mut buffer = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    assert_eq!(select_channels(&mut buffer, 2, 4, 0b1010), 2);
+    assert_eq!(&buffer[..4], &[3, 4, 7, 8]);
+    assert_eq!(ChannelSelection::Mask(0b1010).output_channels(4), 2);
+    assert_eq!(ChannelSelection::Mask(0b1010).output_channels(2), 1);
+
+    let mut buffer = vec![1, 2];
+    downmix_stereo(&mut buffer, 2, 1);
+    assert_eq!(buffer, vec![1, 2, 1, 2]);
+
+    // A 5.1 frame at full scale on every channel mixes to just below full
+    // scale, the LFE channel does not contribute.
+    let full = 0x7fffff;
+    let mut buffer = vec![full, full, full, 0, full, full];
+    downmix_stereo(&mut buffer, 1, 6);
+    assert!(buffer[0] <= full && buffer[0] > full - full / 8192);
+    assert_eq!(buffer[0], buffer[1]);
+
+    // A signal on the left surround channel ends up on the left only.
+    let mut buffer = vec![0, 0, 0, 0, 1000, 0];
+    downmix_stereo(&mut buffer, 1, 6);
+    assert_eq!(buffer[1], 0);
+    assert_eq!(buffer[0], (1000.0 * 0.7071 / (1.0 + 2.0 * 0.7071)) as i32);
+}
+
+/// Limits on the resources that decoding a single frame may use.
+///
+/// The FLAC format allows frames that are expensive to decode: large blocks,
+/// and residuals with very long unary runs. When decoding untrusted input,
+/// tighter limits bound the memory and time spent per frame. A frame that
+/// exceeds a limit results in `Error::LimitExceeded`. The default limits on
+/// the block size are the maximums allowed by the format. The default limit on
+/// the Rice quotient is lower than what the format allows, but far above what
+/// an encoder produces, see `max_rice_quotient`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameLimits {
+    /// The maximum block size (in inter-channel samples) accepted.
+    ///
+    /// This limit is independent of the maximum block size in the streaminfo.
+    pub max_block_size: u16,
+
+    /// The maximum number of samples in a frame, counted over all channels.
+    pub max_samples: u32,
+
+    /// The maximum Rice quotient (the length of a unary run) in a residual.
+    ///
+    /// The format puts no bound on the quotient, so a malicious stream can
+    /// make the decoder scan through billions of zero bits for a single
+    /// sample. An encoder picks the Rice parameter to minimize the size of a
+    /// partition, and with a partition of at most 65535 samples, increasing the
+    /// parameter pays off long before a quotient reaches 2<sup>17</sup>. The
+    /// default of 2<sup>20</sup> leaves ample margin for imperfect encoders.
+    /// Set it to `u32::max_value()` to accept every quotient.
+    pub max_rice_quotient: u32,
+}
+
+impl Default for FrameLimits {
+    fn default() -> FrameLimits {
+        FrameLimits {
+            max_block_size: 0xffff,
+            max_samples: 0xffff * 8,
+            max_rice_quotient: 1 << 20,
+        }
+    }
+}
+
+/// Reads `testsamples/wasted_bits.flac`, and returns its bytes and streaminfo.
+///
+/// This mono file has a fixed block size of 4096. Its first frame is at byte
+/// 152, the second and final frame, of 314 samples, at byte 1129. The frames
+/// are 16 bits per sample, of which the 8 least significant bits are wasted.
+#[cfg(test)]
+fn read_wasted_bits() -> (Vec<u8>, StreamInfo) {
+    use std::fs;
+    use std::io::Read;
+    use input::BufferedReader;
+    use metadata::{MetadataBlock, MetadataBlockReader};
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut data).unwrap();
+    let streaminfo = {
+        let mut reader = BufferedReader::new(io::Cursor::new(&data[4..]));
+        match MetadataBlockReader::new(&mut reader).next().unwrap().unwrap() {
+            MetadataBlock::StreamInfo(si) => si,
+            _ => panic!("expected streaminfo block"),
+        }
+    };
+    (data, streaminfo)
+}
+
+#[test]
+fn verify_frame_limits() {
+    use std::io;
+
+    // The first frame of this file starts at byte 152, it is a mono frame with
+    // a block size of 4096.
+    let (data, _) = read_wasted_bits();
+
+    let mut limits = FrameLimits::default();
+    limits.max_block_size = 1024;
+    let mut reader = FrameReader::with_limits(io::Cursor::new(&data[152..]), limits);
+    assert_eq!(reader.read_next_or_eof(Vec::new()).err().unwrap(),
+               Error::LimitExceeded { limit: "block size", value: 4096, max: 1024 });
+
+    let mut limits = FrameLimits::default();
+    limits.max_samples = 2048;
+    let mut reader = FrameReader::with_limits(io::Cursor::new(&data[152..]), limits);
+    assert_eq!(reader.read_next_or_eof(Vec::new()).err().unwrap(),
+               Error::LimitExceeded { limit: "samples per frame", value: 4096, max: 2048 });
+
+    let mut limits = FrameLimits::default();
+    limits.max_samples = 4096;
+    let mut reader = FrameReader::with_limits(io::Cursor::new(&data[152..]), limits);
+    assert!(reader.read_next_or_eof(Vec::new()).is_ok());
+
+    // Take the 6-byte header of the first frame, and follow it by a fixed
+    // subframe of order 0, whose residual has Rice parameter 0 and starts with
+    // a quotient of 2^20 + 6. The default limit rejects it.
+    let mut frame = data[152..158].to_vec();
+    frame.push(0b0001_0000);
+    frame.extend_from_slice(&vec![0; (1 << 17) + 2]);
+    frame.push(0xff);
+    let mut reader = FrameReader::new(io::Cursor::new(&frame[..]));
+    match reader.read_next_or_eof(Vec::new()) {
+        Err(Error::LimitExceeded { limit: "Rice quotient", value, max }) => {
+            assert_eq!(max, 1 << 20);
+            assert!(value > max);
+        }
+        other => panic!("expected Rice quotient limit error, got {:?}", other),
+    }
+
+    // Without a limit, decoding gets past the quotient, to the end of the data.
+    let mut limits = FrameLimits::default();
+    limits.max_rice_quotient = u32::max_value();
+    let mut reader = FrameReader::with_limits(io::Cursor::new(&frame[..]), limits);
+    match reader.read_next_or_eof(Vec::new()) {
+        Err(Error::IoError(ref err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+        other => panic!("expected end of input, got {:?}", other),
+    }
+}
+
+/// What a `FrameReader` does when frames are out of order.
+///
+/// In a variable block size stream, every frame header contains the sample
+/// number of its first sample. Some broken muxers emit frames whose sample
+/// numbers do not increase monotonically. A frame that starts before the
+/// previous frame ended is out of order, so a repeated frame is out of order
+/// too. Gaps between frames are not. Fixed block size streams are not
+/// checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameOrdering {
+    /// Return a format error for a frame that is out of order.
+    ///
+    /// This is the default. The frame is consumed entirely, so reading can
+    /// continue with the next frame.
+    Error,
+
+    /// Log a warning, and return frames in stream order regardless.
+    Warn,
+
+    /// Buffer up to `n` frames ahead, and return frames in sample order.
+    ///
+    /// Frames that are out of order by more than the window are still an
+    /// error, as are repeated frames, but reading can continue after the
+    /// error. At the end of the stream, the frames that are still buffered
+    /// are returned in order. Every buffered frame holds its own buffer.
+    Reorder(usize),
+}
+
+impl Default for FrameOrdering {
+    fn default() -> FrameOrdering {
+        FrameOrdering::Error
+    }
+}
+
+/// Reads frames from a stream and exposes decoded blocks as an iterator.
+///
+/// The reader must start at a frame header; reading does not search for a
+/// sync code. After an error halfway through a frame, or to start at an
+/// arbitrary position in a stream, `resync()` skips ahead to the next frame
+/// header, and `is_at_frame_boundary()` tells whether that is needed.
+pub struct FrameReader<R: ReadBytes> {
+    input: R,
+
+    /// The settings and state that decoding a single frame uses.
+    decoder: FrameDecoder,
+
+    /// What to do with frames that are out of order.
+    ordering: FrameOrdering,
+
+    /// The sample after the last block returned, if a block was returned.
+    next_sample: Option<u64>,
+
+    /// Frames read ahead for reordering, with whether they were variable.
+    pending: Vec<(Block, bool)>,
+}
+
+/// The part of a `FrameReader` that decodes frames, apart from its input.
+///
+/// This is separate from the reader, so frames can be decoded from a slice
+/// borrowed from the input, see `FrameReader::read_next_or_eof_sliced()`.
+struct FrameDecoder {
+    /// Whether the input is positioned at the start of a frame (or at EOF).
+    ///
+    /// This is false when reading a frame failed halfway, and true otherwise,
+    /// also when the frame was read completely but had a CRC mismatch.
+    at_frame_boundary: bool,
+
+    /// Limits on the resources used to decode a frame.
+    limits: FrameLimits,
+
+    /// The bits per sample of the subframes in the last frame decoded.
+    subframe_widths: [u32; 8],
+
+    /// The number of valid entries in `subframe_widths`.
+    num_subframes: usize,
+
+    /// The bits per sample of the last frame decoded.
+    frame_bps: u32,
+
+    /// The index of the side channel subframe in the last frame, if it had one.
+    side_subframe: Option<usize>,
+
+    /// Whether the last frame decoded had a sample number in its header.
+    is_variable_frame: bool,
+
+    /// The block size of a fixed block size stream, if known.
+    ///
+    /// This is used to convert frame numbers into sample numbers.
+    fixed_block_size: Option<u16>,
+
+    /// Whether to collect `buffer_stats` and `frame_stats`.
+    collect_stats: bool,
+
+    /// How often the buffers passed in could be reused.
+    buffer_stats: BufferStats,
+
+    /// The sizes of the frames decoded so far.
+    frame_stats: FrameStats,
+
+    /// Which channels end up in the decoded blocks.
+    channels: ChannelSelection,
+
+    /// How to report values that the format reserves.
+    reserved_values: ReservedValues,
+
+    /// Whether to decode frames despite CRC mismatches and reserved bits.
+    lenient: bool,
+
+    /// The problems found in the last frame, when lenient.
+    issues: Vec<FrameIssue>,
+
+    /// The footer of the last frame, if it was read completely.
+    last_checksum: Option<FrameChecksum>,
+
+    /// The buffer passed to the last read that found the end of the stream, or failed.
+    spare_buffer: Vec<i32>,
+
+    /// The buffer that frames are decoded into before they are interleaved.
+    planar: Vec<i32>,
+}
+
+/// An input that frames are decoded from, which knows the CRC-16 of the frame.
+trait FrameInput: ReadBytes {
+    /// Returns the CRC-16 of the frame bytes read so far.
+    fn frame_crc(&self) -> u16;
+
+    /// Returns the number of frame bytes read so far.
+    fn frame_len(&self) -> u32;
+}
+
+impl<R: ReadBytes> FrameInput for Crc16Reader<R> {
+    fn frame_crc(&self) -> u16 {
+        self.crc()
+    }
+
+    fn frame_len(&self) -> u32 {
+        self.num_read()
+    }
+}
+
+/// A slice that starts at the frame, the CRC is computed at once when needed.
+impl<'a> FrameInput for io::Cursor<&'a [u8]> {
+    fn frame_crc(&self) -> u16 {
+        crc::crc16(&self.get_ref()[..self.position() as usize])
+    }
+
+    fn frame_len(&self) -> u32 {
+        self.position() as u32
+    }
+}
+
+/// The footer of a frame, which identifies its encoded bytes.
+///
+/// Comparing these for the frames of two streams is a cheap way to detect that
+/// the audio changed, without comparing the decoded samples. The CRC is the
+/// one stored in the frame; unless the reader is lenient, it was verified to
+/// match the frame bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameChecksum {
+    /// The CRC-16 stored at the end of the frame.
+    pub crc16: u16,
+    /// The size of the frame in bytes, including header and footer.
+    pub len: u32,
+}
+
+/// The width of the samples in a subframe, and how it comes about.
+///
+/// The samples of a subframe are coded with the bits per sample of the frame,
+/// plus one for a side channel, minus the wasted bits, which are shifted in
+/// afterwards. See `FrameReader::subframe_widths()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubframeWidth {
+    /// The bits per sample from the frame header.
+    pub frame_bits_per_sample: u32,
+    /// Whether the subframe holds a side channel, which has one extra bit per sample.
+    pub is_side: bool,
+    /// The number of least significant bits that are zero in every sample.
+    pub wasted_bits: u32,
+}
+
+impl SubframeWidth {
+    /// Returns the bits per sample of the decoded subframe, including wasted bits.
+    ///
+    /// This is the bits per sample of the frame, plus one for a side channel.
+    pub fn bits_per_sample(&self) -> u32 {
+        self.frame_bits_per_sample + if self.is_side { 1 } else { 0 }
+    }
+
+    /// Returns the bits per sample that the samples are coded with.
+    ///
+    /// This is `bits_per_sample()` minus the wasted bits, the same value as
+    /// reported by `FrameReader::subframe_bits_per_sample()`.
+    pub fn coded_bits_per_sample(&self) -> u32 {
+        self.bits_per_sample() - self.wasted_bits
+    }
+}
+
+/// Statistics about the reuse of the buffers that blocks are decoded into.
+///
+/// When the buffer passed to `FrameReader::read_next_or_eof()` has enough
+/// capacity, decoding does not allocate. Applications that allocate a buffer
+/// up front, for instance based on the maximum block size in the streaminfo,
+/// can use these statistics to verify that decoding reaches a steady state
+/// without allocations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferStats {
+    /// The number of frames decoded into a buffer with sufficient capacity.
+    pub reused: u64,
+    /// The number of frames for which a new buffer had to be allocated.
+    pub allocated: u64,
+    /// The largest number of samples, for all channels together, in a frame.
+    pub max_len: usize,
+}
+
+/// The number of size classes in `FrameStats::size_histogram`.
+///
+/// A frame of 65535 samples for 8 channels of 32 bits stored verbatim takes
+/// about 2 MiB, so every frame falls in one of the classes up to 2<sup>23</sup>.
+pub const FRAME_SIZE_CLASSES: usize = 24;
+
+/// Statistics about the size of the frames decoded by a `FrameReader`.
+///
+/// At the end of the stream, these summarize how well it compresses, like
+/// `flac --test --verbose` does: the compression ratio, and how the frame sizes
+/// are distributed. Only frames that were decoded completely are counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// The number of frames decoded.
+    pub frames: u64,
+    /// The total size of the frames in bytes, including headers and footers.
+    pub compressed_bytes: u64,
+    /// The total number of inter-channel samples in the frames.
+    pub samples: u64,
+    /// The total size of the samples as PCM in bits, for all channels together.
+    ///
+    /// This uses the bits per sample of every frame, without padding to whole
+    /// bytes.
+    pub uncompressed_bits: u64,
+    /// The size of the smallest frame in bytes, or 0 if there were no frames.
+    pub min_frame_size: u32,
+    /// The size of the largest frame in bytes, or 0 if there were no frames.
+    pub max_frame_size: u32,
+    /// The number of frames per size class.
+    ///
+    /// Entry `i` counts the frames of 2<sup>i</sup> up to 2<sup>i + 1</sup>
+    /// bytes, the upper bound excluded.
+    pub size_histogram: [u64; FRAME_SIZE_CLASSES],
+}
+
+impl FrameStats {
+    /// Records a frame of `len` bytes, with `samples` samples per channel.
+    fn add_frame(&mut self, len: u32, samples: u32, channels: u32, bps: u32) {
+        self.min_frame_size = if self.frames == 0 {
+            len
+        } else {
+            cmp::min(self.min_frame_size, len)
+        };
+        self.max_frame_size = cmp::max(self.max_frame_size, len);
+        self.frames += 1;
+        self.compressed_bytes += len as u64;
+        self.samples += samples as u64;
+        self.uncompressed_bits += samples as u64 * channels as u64 * bps as u64;
+        let class = 31 - cmp::max(len, 1).leading_zeros() as usize;
+        self.size_histogram[cmp::min(class, FRAME_SIZE_CLASSES - 1)] += 1;
+    }
+
+    /// Returns the compressed size divided by the uncompressed size.
+    ///
+    /// This is the ratio that `flac` reports, so lower is better. Returns
+    /// `None` when no samples were decoded.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        if self.uncompressed_bits == 0 {
+            None
+        } else {
+            Some(self.compressed_bytes as f64 * 8.0 / self.uncompressed_bits as f64)
+        }
+    }
+}
+
+#[test]
+fn verify_frame_stats() {
+    let mut stats = FrameStats::default();
+    assert_eq!(stats.compression_ratio(), None);
+
+    stats.add_frame(3000, 4096, 2, 16);
+    stats.add_frame(1000, 4096, 2, 16);
+    stats.add_frame(12, 1, 2, 16);
+    assert_eq!(stats.frames, 3);
+    assert_eq!(stats.compressed_bytes, 4012);
+    assert_eq!(stats.samples, 8193);
+    assert_eq!(stats.uncompressed_bits, 8193 * 32);
+    assert_eq!((stats.min_frame_size, stats.max_frame_size), (12, 3000));
+    assert_eq!(stats.size_histogram[3], 1);
+    assert_eq!(stats.size_histogram[9], 1);
+    assert_eq!(stats.size_histogram[11], 1);
+    assert_eq!(stats.size_histogram.iter().sum::<u64>(), 3);
+    assert_eq!(stats.compression_ratio(), Some(4012.0 * 8.0 / (8193.0 * 32.0)));
+}
+
+/// A source of buffers to decode blocks into, shared between decoders.
+///
+/// A sample iterator reuses its buffer from one block to the next, but the
+/// buffer is freed when the iterator is dropped. With a pool, see
+/// `FlacSamples::set_buffer_pool()`, the iterator takes its buffer from the
+/// pool, and gives it back when dropped, so an application that decodes many
+/// files in a row does not allocate once the pool holds large enough buffers.
+/// `Vec<Vec<i32>>` is a pool for a single owner, wrap a pool in
+/// `Arc<Mutex<_>>` to share it.
+pub trait BufferPool {
+    /// Returns a buffer from the pool, or a new empty buffer if there is none.
+    ///
+    /// The contents of the buffer do not matter, decoding overwrites them.
+    fn take(&mut self) -> Vec<i32>;
+
+    /// Returns a buffer to the pool.
+    fn give(&mut self, buffer: Vec<i32>);
+}
+
+impl BufferPool for Vec<Vec<i32>> {
+    fn take(&mut self) -> Vec<i32> {
+        self.pop().unwrap_or_else(Vec::new)
+    }
+
+    fn give(&mut self, buffer: Vec<i32>) {
+        self.push(buffer);
+    }
+}
+
+impl<P: BufferPool> BufferPool for Arc<Mutex<P>> {
+    fn take(&mut self) -> Vec<i32> {
+        // A panic while the lock was held cannot leave the pool in a state
+        // that is worse than empty, so poisoning is ignored.
+        match self.lock() {
+            Ok(mut pool) => pool.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        }
+    }
+
+    fn give(&mut self, buffer: Vec<i32>) {
+        match self.lock() {
+            Ok(mut pool) => pool.give(buffer),
+            Err(poisoned) => poisoned.into_inner().give(buffer),
+        }
+    }
+}
+
+/// Either a `Block` or an `Error`.
+// TODO: The option should not be part of FrameResult.
+pub type FrameResult = Result<Option<Block>>;
+
+/// A function to expand the length of a buffer, or replace the buffer altogether,
+/// so it can hold at least `new_len` elements. The contents of the buffer can
+/// be anything, it is assumed they will be overwritten anyway.
+///
+/// To use this function safely, the caller must overwrite all `new_len` bytes.
+fn ensure_buffer_len(mut buffer: Vec<i32>, new_len: usize) -> Vec<i32> {
+    if buffer.len() < new_len {
+        // Previous data will be overwritten, so instead of resizing the
+        // vector if it is too small, we might as well allocate a new one.
+        // NOTE: In the past we allocated a vector with Vec::with_capacity here,
+        // setting it to the right length without zeroing (and also setting the
+        // right length if the capacity was sufficient). However, the
+        // performance impact of zeroing turned out to be negligible (a 0.2%
+        // increase in decode times in an adversarial scenario, but a 0.03%
+        // increase on real-world files when the buffer is recycled). We still
+        // don't zero out previous contents of the buffer as it was passed in,
+        // zeroing only happens on resize or for new allocations.
+        if buffer.capacity() < new_len {
+            buffer = vec![0; new_len];
+        } else {
+            buffer.resize(new_len, 0);
+        }
+    } else {
+        buffer.truncate(new_len);
+    }
+    buffer
+}
+
+#[test]
+fn ensure_buffer_len_returns_buffer_with_new_len() {
+    for capacity in 0..10 {
+        for new_len in 0..10 {
+            let buffer = Vec::with_capacity(capacity);
+            let resized = ensure_buffer_len(buffer, new_len);
+            assert_eq!(resized.len(), new_len);
+        }
+    }
+}
+
+impl<R: ReadBytes> FrameReader<R> {
+    /// Creates a new frame reader that will yield at least one element.
+    pub fn new(input: R) -> FrameReader<R> {
+        FrameReader::with_limits(input, FrameLimits::default())
+    }
+
+    /// Creates a new frame reader that rejects frames that exceed the limits.
+    pub fn with_limits(input: R, limits: FrameLimits) -> FrameReader<R> {
+        let decoder = FrameDecoder {
+            at_frame_boundary: true,
+            limits: limits,
+            subframe_widths: [0; 8],
+            num_subframes: 0,
+            frame_bps: 0,
+            side_subframe: None,
+            is_variable_frame: false,
+            fixed_block_size: None,
+            collect_stats: false,
+            buffer_stats: BufferStats::default(),
+            frame_stats: FrameStats::default(),
+            channels: ChannelSelection::default(),
+            reserved_values: ReservedValues::default(),
+            lenient: false,
+            issues: Vec::new(),
+            last_checksum: None,
+            spare_buffer: Vec::new(),
+            planar: Vec::new(),
+        };
+        FrameReader {
+            input: input,
+            decoder: decoder,
+            ordering: FrameOrdering::default(),
+            next_sample: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Sets what to do with frames that are out of order.
+    ///
+    /// See `FrameOrdering` for the options. The default is to return an error.
+    pub fn set_ordering(&mut self, ordering: FrameOrdering) {
+        self.ordering = ordering;
+    }
+
+    /// Sets the block size of a fixed block size stream.
+    ///
+    /// In a fixed block size stream, frame headers hold a frame number rather
+    /// than a sample number, and the time of a block is the frame number times
+    /// the block size of the stream. All frames but the last one have that
+    /// block size; the last one may be shorter. By default, the largest block
+    /// size of the frames read so far is used, which is correct unless the
+    /// first frame read is the last frame of the stream. The block size is
+    /// usually known from the streaminfo, where it is `max_block_size`.
+    /// `FlacReader` sets it when it creates a frame reader.
+    pub fn set_fixed_block_size(&mut self, block_size: u16) {
+        self.decoder.fixed_block_size = Some(block_size);
+    }
+
+    /// Returns whether the reader is positioned at the start of a frame.
+    ///
+    /// This is the case before any frame has been read, and after a frame was
+    /// read entirely. When `read_next_or_eof()` returns an error, the frame may
+    /// have been consumed completely (for example when the frame CRC did not
+    /// match), in which case reading can continue with the next frame. If the
+    /// error occurred halfway through the frame, the position of the next
+    /// frame is unknown, and this returns false.
+    pub fn is_at_frame_boundary(&self) -> bool {
+        self.decoder.at_frame_boundary
+    }
+
+    /// Returns the number of bits per sample of each subframe in the last frame.
+    ///
+    /// These are the widths that the samples were actually decoded with: a
+    /// side channel has one bit more than the frame, and wasted bits are
+    /// deducted. The widths are in subframe order, so for a mid-side frame
+    /// the first is the mid channel, and the second the side channel. If the
+    /// last frame could not be decoded, the slice is empty.
+    pub fn subframe_bits_per_sample(&self) -> &[u32] {
+        &self.decoder.subframe_widths[..self.decoder.num_subframes]
+    }
+
+    /// Returns how the width of each subframe in the last frame comes about.
+    ///
+    /// This breaks the widths of `subframe_bits_per_sample()` down into the
+    /// bits per sample of the frame, the extra bit of a side channel, and the
+    /// wasted bits. When debugging inter-channel decorrelation, this shows
+    /// which subframe is wider than expected, and why. The widths are in
+    /// subframe order. If the last frame could not be decoded, the result is
+    /// empty.
+    pub fn subframe_widths(&self) -> Vec<SubframeWidth> {
+        let decoder = &self.decoder;
+        let widths = &decoder.subframe_widths[..decoder.num_subframes];
+        widths.iter().enumerate().map(|(i, &coded_bps)| {
+            let is_side = decoder.side_subframe == Some(i);
+            let bps = decoder.frame_bps + if is_side { 1 } else { 0 };
+            SubframeWidth {
+                frame_bits_per_sample: decoder.frame_bps,
+                is_side: is_side,
+                wasted_bits: bps - coded_bps,
+            }
+        }).collect()
+    }
+
+    /// Sets which channels end up in the decoded blocks.
+    ///
+    /// See `ChannelSelection` for the options. The default is all channels.
+    /// When a `Mask` selects none of the channels of a frame, decoding that
+    /// frame fails with a format error.
+    pub fn set_channels(&mut self, channels: ChannelSelection) {
+        self.decoder.channels = channels;
+    }
+
+    /// Sets how to report values that the format reserves for future use.
+    ///
+    /// See `ReservedValues` for the options. The default is a format error.
+    pub fn set_reserved_values(&mut self, policy: ReservedValues) {
+        self.decoder.reserved_values = policy;
+    }
+
+    /// Sets whether to decode frames that have CRC mismatches or reserved bits set.
+    ///
+    /// By default, the first problem found in a frame is returned as an error.
+    /// A lenient reader instead records the problems as `FrameIssue`s, and
+    /// continues decoding: a frame with a CRC mismatch is returned as a block,
+    /// with the issues available from `Block::issues()`. This helps to
+    /// diagnose and repair corrupt files, as the samples may still be usable,
+    /// and the combination of problems hints at what is wrong. When a frame
+    /// cannot be decoded at all, the error is returned as usual, and
+    /// `frame_issues()` lists the problems found before it.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.decoder.lenient = lenient;
+    }
+
+    /// Returns the problems found in the last frame read by a lenient reader.
+    ///
+    /// This is the same as `Block::issues()` for a frame that was decoded, but
+    /// it is also available when reading the frame failed.
+    pub fn frame_issues(&self) -> &[FrameIssue] {
+        &self.decoder.issues
+    }
+
+    /// Sets whether to collect `buffer_stats()` and `frame_stats()`.
+    ///
+    /// Statistics are not collected by default, so decoding does not pay for
+    /// them when they are not needed. Only frames decoded after enabling this
+    /// are counted.
+    pub fn set_collect_stats(&mut self, collect_stats: bool) {
+        self.decoder.collect_stats = collect_stats;
+    }
+
+    /// Returns how often decoding could reuse the buffer that was passed in.
+    ///
+    /// The statistics cover all frames decoded by this reader so far, while
+    /// `set_collect_stats()` was enabled. Otherwise they are all zero.
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.decoder.buffer_stats
+    }
+
+    /// Returns the sizes of the frames decoded so far, see `FrameStats`.
+    ///
+    /// Read at the end of the stream, this summarizes the compression of the
+    /// entire stream. Like `buffer_stats()`, this is only collected with
+    /// `set_collect_stats()` enabled. Frames that were parsed with
+    /// `explain_next_or_eof()` are not counted.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.decoder.frame_stats
+    }
+
+    /// Returns the buffer that was passed in when the end of the stream was reached.
+    ///
+    /// When `read_next_or_eof()` returns `None`, there is no block to move the
+    /// buffer into, so the reader keeps it. This returns it, so it can be
+    /// reused, for instance to decode the next stream. The reader also keeps
+    /// the buffer when decoding a frame fails. If no read reached the end of
+    /// the stream or failed, or the buffer was taken already, the result is an
+    /// empty vector. `read_next_into()` does this automatically.
+    pub fn take_eof_buffer(&mut self) -> Vec<i32> {
+        mem::replace(&mut self.decoder.spare_buffer, Vec::new())
+    }
+
+    /// Returns the CRC-16 and size of the last frame read.
+    ///
+    /// Returns `None` before the first frame, and when reading the last frame
+    /// failed before its footer. With frame reordering, this describes the
+    /// frame read last, not necessarily the block returned last.
+    pub fn last_frame_checksum(&self) -> Option<FrameChecksum> {
+        self.decoder.last_checksum
+    }
+
+    /// Decodes the next frame or returns an error if the data was invalid.
+    ///
+    /// The buffer is moved into the returned block, so that the same buffer may
+    /// be reused to decode multiple blocks, avoiding a heap allocation every
+    /// time. It can be retrieved again with `block.into_buffer()`. If the
+    /// buffer is not large enough to hold all samples, a larger buffer is
+    /// allocated automatically.
+    ///
+    /// TODO: I should really be consistent with 'read' and 'decode'.
+    pub fn read_next_or_eof(&mut self, buffer: Vec<i32>) -> FrameResult {
+        if let FrameOrdering::Reorder(window) = self.ordering {
+            return self.read_next_reordered(buffer, window);
+        }
+
+        match try!(self.read_frame(buffer)) {
+            Some(block) => self.accept_block(block),
+            None => Ok(None),
+        }
+    }
+
+    /// Decodes the next frame, and keeps the buffer if there is no block to move it into.
+    ///
+    /// This behaves like `read_next_or_eof()`, but the buffer is borrowed. When
+    /// a block is returned, the buffer is moved into it, and `buffer` is left
+    /// empty; `block.into_buffer()` returns it. At the end of the stream, and
+    /// when decoding fails, `buffer` holds the buffer afterwards, so it is
+    /// never lost, and it can be reused after an error. With
+    /// `FrameOrdering::Reorder`, the buffer may end up in a block that is
+    /// returned later, and a fresh buffer takes its place.
+    pub fn read_next_into(&mut self, buffer: &mut Vec<i32>) -> FrameResult {
+        let result = self.read_next_or_eof(mem::replace(buffer, Vec::new()));
+        if let Ok(Some(..)) = result {
+            return result;
+        }
+        *buffer = self.take_eof_buffer();
+        result
+    }
+
+    /// Checks the order of a block read without reordering, and returns it.
+    fn accept_block(&mut self, block: Block) -> FrameResult {
+        if self.decoder.is_variable_frame {
+            if let Err(err) = self.check_order(block.time()) {
+                self.decoder.spare_buffer = block.into_buffer();
+                return Err(err);
+            }
+        }
+        self.next_sample = Some(block.time() + block.duration() as u64);
+        Ok(Some(block))
+    }
+
+    /// Decodes the next frame into a block with the channels interleaved.
+    ///
+    /// This behaves like `read_next_or_eof()`, but the samples are stored
+    /// interleaved, as most audio APIs expect them. For a stereo frame, the
+    /// inter-channel decorrelation is undone while interleaving, rather than
+    /// in place followed by a separate interleaving pass. The frame is decoded
+    /// into a planar buffer that the reader keeps, the buffer passed in holds
+    /// the interleaved samples, and can be retrieved again with
+    /// `block.into_buffer()`. With `FrameOrdering::Reorder`, blocks are decoded
+    /// as usual and interleaved afterwards.
+    pub fn read_next_interleaved_or_eof(&mut self,
+                                        buffer: Vec<i32>)
+                                        -> Result<Option<InterleavedBlock>> {
+        if let FrameOrdering::Reorder(..) = self.ordering {
+            let planar = mem::replace(&mut self.decoder.planar, Vec::new());
+            return match try!(self.read_next_or_eof(planar)) {
+                Some(block) => {
+                    let interleaved = InterleavedBlock::from_block(&block, buffer);
+                    self.decoder.planar = block.into_buffer();
+                    Ok(Some(interleaved))
+                }
+                None => {
+                    self.decoder.planar = mem::replace(&mut self.decoder.spare_buffer, buffer);
+                    Ok(None)
+                }
+            };
+        }
+
+        let result = {
+            let mut crc_input = Crc16Reader::new(&mut self.input);
+            self.decoder.read_frame_interleaved(&mut crc_input, buffer)
+        };
+        match try!(result) {
+            Some(block) => {
+                if self.decoder.is_variable_frame {
+                    // Like `accept_block()`, keep the buffer for the caller.
+                    if let Err(err) = self.check_order(block.time) {
+                        self.decoder.spare_buffer = block.into_buffer();
+                        return Err(err);
+                    }
+                }
+                self.next_sample = Some(block.time + block.duration as u64);
+                Ok(Some(block))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Parses the next frame without decoding its samples, and returns its elements.
+    ///
+    /// The frame is checked like `read_next_or_eof()` checks it, including
+    /// both CRCs, but rather than a block of samples, the result describes the
+    /// bitstream elements that make up the frame, in the order in which they
+    /// occur; see `explain::Element`. This is meant for learning and
+    /// debugging, it is slower than decoding, and it allocates. Returns `None`
+    /// at the end of the stream. The order of frames is not checked, and
+    /// frames are not reordered.
+    pub fn explain_next_or_eof(&mut self) -> Result<Option<Vec<Element>>> {
+        let mut elements = Vec::new();
+        let mut crc_input = Crc16Reader::new(&mut self.input);
+        if try!(self.decoder.explain_frame(&mut crc_input, &mut elements)) {
+            Ok(Some(elements))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads frames until the window is full, and returns the earliest one.
+    fn read_next_reordered(&mut self, mut buffer: Vec<i32>, window: usize) -> FrameResult {
+        // Only the first frame read can use the buffer that was passed in,
+        // the others need a buffer of their own.
+        while self.pending.len() <= window {
+            match try!(self.read_frame(mem::replace(&mut buffer, Vec::new()))) {
+                Some(block) => self.pending.push((block, self.decoder.is_variable_frame)),
+                None => break,
+            }
+        }
+
+        let earliest = match self.pending.iter().enumerate().min_by_key(|&(_, p)| p.0.time()) {
+            Some((i, _)) => i,
+            None => return Ok(None),
+        };
+        let (block, is_variable) = self.pending.remove(earliest);
+        if is_variable {
+            if let Err(err) = self.check_order(block.time()) {
+                self.decoder.spare_buffer = block.into_buffer();
+                return Err(err);
+            }
+        }
+        self.next_sample = Some(block.time() + block.duration() as u64);
+        Ok(Some(block))
+    }
+
+    /// Checks that a block of a variable block size stream, which starts at
+    /// `time`, does not start before the previous one ended.
+    fn check_order(&mut self, time: u64) -> Result<()> {
+        if let Some(next_sample) = self.next_sample {
+            if time < next_sample {
+                if self.ordering != FrameOrdering::Warn {
+                    return fmt_err("frame out of order, sample number decreased");
+                }
+                warn!("frame at sample {} out of order, expected sample {} or later",
+                      time, next_sample);
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the next frame in the stream, regardless of ordering.
+    fn read_frame(&mut self, buffer: Vec<i32>) -> FrameResult {
+        // The frame includes a CRC-16 at the end. It can be computed
+        // automatically while reading, by wrapping the input reader in a reader
+        // that computes the CRC.
+        let mut crc_input = Crc16Reader::new(&mut self.input);
+        self.decoder.read_frame(&mut crc_input, buffer)
+    }
+
+    /// Destroy the frame reader, returning the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
+
+/// A frame whose subframes have been decoded, but not yet passed through the output stage.
+struct DecodedFrame {
+    header: FrameHeader,
+    time: u64,
+    frame_number: Option<u32>,
+    bps: u32,
+    /// The decorrelation to undo, for a stereo frame.
+    stereo: Option<Decorrelation>,
+}
+
+impl FrameDecoder {
+    /// Decodes the subframes of the next frame into `buffer`, and checks the CRC.
+    ///
+    /// The buffer holds the channels consecutively afterwards, as they were
+    /// coded: for a stereo frame, the decorrelation remains to be undone, and
+    /// no channels have been selected yet. If `is_output` is true, the reuse
+    /// of the buffer is counted in the buffer statistics. Returns `None` at
+    /// the end of the stream.
+    fn decode_frame<I: FrameInput>(&mut self,
+                                   input: &mut I,
+                                   buffer: &mut Vec<i32>,
+                                   is_output: bool)
+                                   -> Result<Option<DecodedFrame>> {
+        // Until we have read the frame footer, an error leaves the input
+        // somewhere in the middle of the frame.
+        self.at_frame_boundary = false;
+        self.num_subframes = 0;
+        self.issues.clear();
+        self.last_checksum = None;
+
+        // If the stream ended before the the frame header (so not in the
+        // middle of the frame header), return `None`, indicating EOF.
+        let header = match read_frame_header_or_eof(input, self.lenient, &mut self.issues) {
+            Ok(None) => {
+                self.at_frame_boundary = true;
+                return Ok(None)
+            }
+            Ok(Some(h)) => h,
+            Err(err) => return Err(with_reserved_values(err, self.reserved_values)),
+        };
+        self.is_variable_frame = match header.block_time {
+            BlockTime::SampleNumber(..) => true,
+            BlockTime::FrameNumber(..) => false,
+        };
+
+        // We must allocate enough space for all channels in the block to be
+        // decoded.
+        let total_samples = header.channels() as usize * header.block_size as usize;
+
+        // Check the limits before allocating anything.
+        if header.block_size > self.limits.max_block_size {
+            return Err(Error::LimitExceeded {
+                limit: "block size",
+                value: header.block_size as u64,
+                max: self.limits.max_block_size as u64,
+            });
+        }
+        if total_samples > self.limits.max_samples as usize {
+            return Err(Error::LimitExceeded {
+                limit: "samples per frame",
+                value: total_samples as u64,
+                max: self.limits.max_samples as u64,
+            });
+        }
+
+        // Ensure the buffer is the right size to hold all samples. For
+        // correctness, we must be careful to overwrite each byte in the buffer.
+        if is_output {
+            self.count_buffer(buffer.capacity(), total_samples);
+        }
+        *buffer = ensure_buffer_len(mem::replace(buffer, Vec::new()), total_samples);
+
+        let bps = match header.bits_per_sample {
+            Some(x) => x,
+            // TODO: if the bps is missing from the header, we must get it from
+            // the streaminfo block.
+            None => return Err(Error::Unsupported(UnsupportedFeature::MissingBpsInHeader)),
+        };
+
+        // Samples are decoded into an i32, and the side channel of a stereo
+        // frame takes one bit more than the samples, so wider samples do not
+        // fit. Frame headers cannot specify more than 24 bits at the moment,
+        // but be explicit about it.
+        if bps > MAX_BITS_PER_SAMPLE {
+            return Err(Error::Unsupported(UnsupportedFeature::BitsPerSampleAbove24));
+        }
+
+        let (time, frame_number) = self.frame_time(&header);
+
+        // In the next part of the stream, nothing is byte-aligned any more,
+        // we need a bitstream. Then we can decode subframes from the bitstream.
+        let stereo;
+        {
+            let mut bitstream = Bitstream::new(&mut *input);
+            bitstream.set_max_unary(self.limits.max_rice_quotient);
+            let result = decode_subframes(&mut bitstream,
+                                          &header,
+                                          bps,
+                                          buffer,
+                                          &mut self.subframe_widths,
+                                          self.channels.needed(header.channels()));
+
+            // Errors from the subframe level do not know which frame they
+            // occurred in, so add that context here.
+            stereo = match result {
+                Ok(stereo) => stereo,
+                Err(err) => {
+                    if self.lenient {
+                        self.issues.push(FrameIssue::SubframeError);
+                    }
+                    let err = with_reserved_values(err, self.reserved_values);
+                    return Err(with_frame_sample(err, time));
+                }
+            };
+            self.num_subframes = header.channels() as usize;
+            self.frame_bps = bps;
+            self.side_subframe = side_subframe(header.channel_assignment);
+
+            // When the bitstream goes out of scope, we can use the `input`
+            // reader again, which will be byte-aligned. The specification
+            // dictates that padding should consist of zero bits, but we do not
+            // enforce this here.
+            // TODO: It could be enforced by having a read_to_byte_aligned
+            // method on the bit reader; it'd be a simple comparison.
+        }
+
+        try!(self.read_frame_footer(input, time));
+        let frame_len = input.frame_len();
+        if self.collect_stats {
+            self.frame_stats.add_frame(frame_len,
+                                       header.block_size as u32,
+                                       header.channels() as u32,
+                                       bps);
+        }
+
+        trace!("decoded frame at sample {}, {} bytes, block size {}, {} channels",
+               time, frame_len, header.block_size, header.channels());
+
+        Ok(Some(DecodedFrame {
+            header: header,
+            time: time,
+            frame_number: frame_number,
+            bps: bps,
+            stereo: stereo,
+        }))
+    }
+
+    /// Returns the time of the frame, and its frame number if it has one.
+    fn frame_time(&mut self, header: &FrameHeader) -> (u64, Option<u32>) {
+        // A frame smaller than the block size of the stream can only be the
+        // last one, but a larger frame means that the block size was wrong.
+        match header.block_time {
+            BlockTime::FrameNumber(fnr) => {
+                let block_size = match self.fixed_block_size {
+                    Some(bs) => cmp::max(bs, header.block_size),
+                    None => header.block_size,
+                };
+                self.fixed_block_size = Some(block_size);
+                (block_size as u64 * fnr as u64, Some(fnr))
+            }
+            BlockTime::SampleNumber(snr) => (snr, None),
+        }
+    }
+
+    /// Reads the CRC-16 at the end of the frame at `time`, and checks it.
+    fn read_frame_footer<I: FrameInput>(&mut self, input: &mut I, time: u64) -> Result<u16> {
+        // The frame footer is a 16-bit CRC. After it, the next frame starts.
+        let computed_crc = input.frame_crc();
+        let presumed_crc = try!(input.read_be_u16());
+        self.at_frame_boundary = true;
+        self.last_checksum = Some(FrameChecksum {
+            crc16: presumed_crc,
+            len: input.frame_len(),
+        });
+
+        // Do not verify checksum during fuzzing, otherwise malformed input from
+        // the fuzzer won't reach the actually interesting code.
+        #[cfg(not(fuzzing))]
+        {
+            if computed_crc != presumed_crc {
+                warn!("CRC mismatch in frame at sample {}, computed {:04x}, stored {:04x}",
+                      time, computed_crc, presumed_crc);
+                if !self.lenient {
+                    return fmt_err("frame CRC mismatch");
+                }
+                self.issues.push(FrameIssue::FrameCrcMismatch {
+                    computed: computed_crc,
+                    stored: presumed_crc,
+                });
+            }
+        }
+
+        // Silence unused variable warnings.
+        #[cfg(fuzzing)]
+        let _ = computed_crc == presumed_crc;
+
+        Ok(presumed_crc)
+    }
+
+    /// Parses the next frame without decoding its samples, and collects its elements.
+    ///
+    /// Returns false at the end of the stream.
+    fn explain_frame<I: FrameInput>(&mut self,
+                                    input: &mut I,
+                                    elements: &mut Vec<Element>)
+                                    -> Result<bool> {
+        self.at_frame_boundary = false;
+        self.num_subframes = 0;
+        self.issues.clear();
+        self.last_checksum = None;
+
+        let header = match read_frame_header_or_eof(input, self.lenient, &mut self.issues) {
+            Ok(None) => {
+                self.at_frame_boundary = true;
+                return Ok(false)
+            }
+            Ok(Some(h)) => h,
+            Err(err) => return Err(with_reserved_values(err, self.reserved_values)),
+        };
+        let (time, _) = self.frame_time(&header);
+
+        let (variable_block_size, number) = match header.block_time {
+            BlockTime::FrameNumber(fnr) => (false, fnr as u64),
+            BlockTime::SampleNumber(snr) => (true, snr),
+        };
+        elements.push(Element::FrameHeader {
+            variable_block_size: variable_block_size,
+            number: number,
+            block_size: header.block_size,
+            sample_rate: header.sample_rate,
+            channels: header.channel_assignment.coding(),
+            bits_per_sample: header.bits_per_sample,
+        });
+
+        let bps = match header.bits_per_sample {
+            Some(x) => x,
+            None => return Err(Error::Unsupported(UnsupportedFeature::MissingBpsInHeader)),
+        };
+        if bps > MAX_BITS_PER_SAMPLE {
+            return Err(Error::Unsupported(UnsupportedFeature::BitsPerSampleAbove24));
+        }
+
+        {
+            let mut bitstream = Bitstream::new(&mut *input);
+            bitstream.set_max_unary(self.limits.max_rice_quotient);
+            let side = side_subframe(header.channel_assignment);
+            for ch in 0..header.channels() as usize {
+                // The side channel has one extra bit per sample.
+                let sf_bps = if side == Some(ch) { bps + 1 } else { bps };
+                match subframe::walk(&mut bitstream, sf_bps, header.block_size, elements) {
+                    Ok(width) => self.subframe_widths[ch] = width,
+                    Err(err) => {
+                        if self.lenient {
+                            self.issues.push(FrameIssue::SubframeError);
+                        }
+                        let err = with_reserved_values(err, self.reserved_values);
+                        return Err(with_frame_sample(err, time));
+                    }
+                }
+            }
+            self.num_subframes = header.channels() as usize;
+            self.frame_bps = bps;
+            self.side_subframe = side;
+        }
+
+        let crc16 = try!(self.read_frame_footer(input, time));
+        elements.push(Element::FrameFooter { crc16: crc16 });
+        Ok(true)
+    }
+
+    /// Records whether a buffer of the given capacity can hold the frame.
+    fn count_buffer(&mut self, capacity: usize, total_samples: usize) {
+        if !self.collect_stats {
+            return
+        }
+        if capacity < total_samples {
+            self.buffer_stats.allocated += 1;
+        } else {
+            self.buffer_stats.reused += 1;
+        }
+        self.buffer_stats.max_len = cmp::max(self.buffer_stats.max_len, total_samples);
+    }
+
+    /// Applies the channel selection to the planar buffer, returns the number of channels left.
+    fn apply_channel_selection(&self,
+                               buffer: &mut Vec<i32>,
+                               header: &FrameHeader)
+                               -> Result<u32> {
+        let bs = header.block_size as usize;
+        let channels = match self.channels {
+            ChannelSelection::All => header.channels() as u32,
+            ChannelSelection::Mask(mask) => {
+                let n = select_channels(buffer, bs, header.channels(), mask);
+                if n == 0 {
+                    return fmt_err("frame contains none of the selected channels");
+                }
+                n
+            }
+            ChannelSelection::Stereo => {
+                downmix_stereo(buffer, bs, header.channels());
+                2
+            }
+        };
+        buffer.truncate(channels as usize * bs);
+        Ok(channels)
+    }
+
+    /// Decodes the next frame into a planar block.
+    fn read_frame<I: FrameInput>(&mut self, input: &mut I, mut buffer: Vec<i32>) -> FrameResult {
+        // Keep the buffer when there is no block to move it into, so it can be
+        // reused, see `FrameReader::read_next_into()`.
+        let frame = match self.decode_frame(input, &mut buffer, true) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                self.spare_buffer = buffer;
+                return Ok(None)
+            }
+            Err(err) => {
+                self.spare_buffer = buffer;
+                return Err(err)
+            }
+        };
+
+        let bs = frame.header.block_size as usize;
+        if let Some(stereo) = frame.stereo {
+            stereo.undo_planar(&mut buffer[..bs * 2]);
+        }
+        let channels = match self.apply_channel_selection(&mut buffer, &frame.header) {
+            Ok(channels) => channels,
+            Err(err) => {
+                self.spare_buffer = buffer;
+                return Err(err)
+            }
+        };
+
+        let mut block = try!(Block::try_new(frame.time, bs as u32, channels, buffer));
+        block.sample_rate = frame.header.sample_rate;
+        block.bits_per_sample = frame.bps;
+        block.frame_number = frame.frame_number;
+        block.variable_block_size = frame.frame_number.is_none();
+        block.channel_coding = frame.header.channel_assignment.coding();
+        block.issues = self.issues.clone();
+
+        Ok(Some(block))
+    }
+
+    /// Decodes the next frame into an interleaved block.
+    ///
+    /// The frame is decoded into the planar buffer of the decoder first. When
+    /// both channels of a stereo frame end up in the output, its decorrelation
+    /// is undone while interleaving, rather than in a pass of its own.
+    fn read_frame_interleaved<I: FrameInput>(&mut self,
+                                             input: &mut I,
+                                             buffer: Vec<i32>)
+                                             -> Result<Option<InterleavedBlock>> {
+        // Take the planar buffer, so it can be borrowed independently of the
+        // decoder, and put it back also when decoding fails.
+        let mut planar = mem::replace(&mut self.planar, Vec::new());
+        let result = self.interleave_frame(input, &mut planar, buffer);
+        self.planar = planar;
+        result
+    }
+
+    fn interleave_frame<I: FrameInput>(&mut self,
+                                       input: &mut I,
+                                       planar: &mut Vec<i32>,
+                                       buffer: Vec<i32>)
+                                       -> Result<Option<InterleavedBlock>> {
+        let frame = match try!(self.decode_frame(input, planar, false)) {
+            Some(frame) => frame,
+            None => {
+                self.spare_buffer = buffer;
+                return Ok(None)
+            }
+        };
+
+        let bs = frame.header.block_size as usize;
+        let (channels, samples) = match frame.stereo {
+            // The channel selection keeps both channels of a stereo frame
+            // as they are, unless a mask drops one of them.
+            Some(stereo) if self.channels.needed(2) & 0b11 == 0b11 => {
+                self.count_buffer(buffer.capacity(), bs * 2);
+                let mut samples = ensure_buffer_len(buffer, bs * 2);
+                stereo.undo_interleaved(&planar[..bs * 2], &mut samples);
+                (2, samples)
+            }
+            stereo => {
+                if let Some(stereo) = stereo {
+                    stereo.undo_planar(&mut planar[..bs * 2]);
+                }
+                let channels = try!(self.apply_channel_selection(planar, &frame.header));
+                let n = channels as usize;
+                self.count_buffer(buffer.capacity(), bs * n);
+                let mut samples = ensure_buffer_len(buffer, bs * n);
+                for (i, out) in samples.chunks_mut(n).enumerate() {
+                    for (ch, sample) in out.iter_mut().enumerate() {
+                        *sample = planar[ch * bs + i];
+                    }
+                }
+                (channels, samples)
+            }
+        };
+
+        let block = InterleavedBlock {
+            time: frame.time,
+            duration: bs as u32,
+            channels: channels,
+            sample_rate: frame.header.sample_rate,
+            bits_per_sample: frame.bps,
+            samples: samples,
+        };
+        Ok(Some(block))
+    }
+}
+
+/// A position in a stream that a `FrameReader` can return to.
+///
+/// This is produced by `FrameReader::snapshot()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FramePosition {
+    /// The position of the next frame header in the input.
+    ///
+    /// This is a position as returned by `SeekBytes::stream_position()`.
+    pub offset: u64,
+
+    /// The sample number at which the next frame starts.
+    ///
+    /// This is the end of the last block that was returned, or `None` if no
+    /// block had been returned yet.
+    pub next_sample: Option<u64>,
+}
+
+impl<R: SeekBytes> FrameReader<R> {
+    /// Returns the current position, to return to later with `restore()`.
+    ///
+    /// This makes it possible to loop a part of a stream, without seeking
+    /// anew or parsing metadata every iteration. Returns `None` if the reader
+    /// is not positioned at the start of a frame (see `is_at_frame_boundary()`),
+    /// or if frames were read ahead for reordering, because the position would
+    /// then not correspond to the next block returned.
+    pub fn snapshot(&self) -> Option<FramePosition> {
+        if !self.decoder.at_frame_boundary || !self.pending.is_empty() {
+            return None
+        }
+        let position = FramePosition {
+            offset: self.input.stream_position(),
+            next_sample: self.next_sample,
+        };
+        Some(position)
+    }
+
+    /// Continues reading at a position returned by `snapshot()`.
+    ///
+    /// The next block returned is the one that followed the snapshot. The
+    /// position must have been taken from a reader on the same input.
+    pub fn restore(&mut self, position: FramePosition) -> Result<()> {
+        self.pending.clear();
+        self.decoder.at_frame_boundary = false;
+        try!(self.input.seek_to(position.offset));
+        self.decoder.at_frame_boundary = true;
+        self.next_sample = position.next_sample;
+        Ok(())
+    }
+}
+
+impl FrameReader<BufferedReader<fs::File>> {
+    /// Opens the file at `filename`, and reads frames starting at byte `offset`.
+    ///
+    /// This is a convenience constructor that wraps the file in a
+    /// `BufferedReader`, with its default buffer size. The offset must be
+    /// that of a frame, usually the first one, which follows the metadata. The
+    /// positions in `snapshot()` are relative to the start of the file.
+    pub fn open<P: AsRef<path::Path>>(filename: P,
+                                      offset: u64)
+                                      -> Result<FrameReader<BufferedReader<fs::File>>> {
+        let file = try!(fs::File::open(filename));
+        let mut input = BufferedReader::new(file);
+        try!(input.seek_to(offset));
+        Ok(FrameReader::new(input))
+    }
+}
+
+impl<R: PeekBytes> FrameReader<R> {
+    /// Skips ahead to the next frame header, skipping at most `max_len` bytes.
+    ///
+    /// When `read_next_or_eof()` fails halfway through a frame, the position
+    /// of the next frame is unknown, see `is_at_frame_boundary()`. This scans
+    /// forward from where decoding stopped for a frame sync code followed by a
+    /// header with a valid CRC-8, so that decoding can continue there. The end
+    /// of the stream counts as a frame boundary too. Returns the number of
+    /// bytes skipped, or `None` if there is no frame header in range, in which
+    /// case `max_len` bytes have been skipped.
+    ///
+    /// Frame headers are short, so in corrupt data, some bytes may look like a
+    /// valid header by chance. Decoding the frame that follows then fails, and
+    /// resynchronizing again continues the search.
+    pub fn resync(&mut self, max_len: u32) -> Result<Option<u32>> {
+        let skipped = try!(::find_frame_header(&mut self.input, max_len));
+        if let Some(n) = skipped {
+            if n > 0 {
+                warn!("skipped {} bytes to find the next frame", n);
+            }
+            self.decoder.at_frame_boundary = true;
+        }
+        Ok(skipped)
+    }
+}
+
+impl<R: SliceProvider> FrameReader<R> {
+    /// Decodes the next frame from a slice of the input.
+    ///
+    /// This behaves like `read_next_or_eof()`, but rather than reading the
+    /// frame byte by byte through a reader that computes the CRC, the frame
+    /// is decoded from the slice that the input provides, and the CRC-16 is
+    /// computed over the frame bytes at once afterwards. This is faster for
+    /// inputs that are in memory already. With `FrameOrdering::Reorder`, this
+    /// falls back to `read_next_or_eof()`.
+    pub fn read_next_or_eof_sliced(&mut self, buffer: Vec<i32>) -> FrameResult {
+        if let FrameOrdering::Reorder(..) = self.ordering {
+            return self.read_next_or_eof(buffer);
+        }
+
+        let (result, consumed) = {
+            let mut slice_input = io::Cursor::new(self.input.remaining_slice());
+            let result = self.decoder.read_frame(&mut slice_input, buffer);
+            (result, slice_input.position())
+        };
+
+        // Also after an error, the input is where decoding stopped, as it is
+        // for `read_next_or_eof()`.
+        self.input.consume(consumed as usize);
+
+        match try!(result) {
+            Some(block) => self.accept_block(block),
+            None => Ok(None),
+        }
+    }
+}
+
+#[test]
+fn verify_resync() {
+    let mut data = Vec::new();
+    let mut frame_starts = Vec::new();
+    for &start in &[0, 100, 200] {
+        frame_starts.push(data.len());
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start,
+            sample_rate: Some(8_000),
+            bits_per_sample: 8,
+        };
+        let samples: Vec<i32> = (0..100).map(|i| ((start + i) * 7 % 255) as i32 - 127).collect();
+        write_frame(&spec, &[&samples], &mut data).unwrap();
+    }
+
+    // Overwrite the second frame, apart from its last bytes, with junk that
+    // contains a sync code, but no valid header.
+    for i in frame_starts[1]..frame_starts[2] - 5 {
+        data[i] = if i % 7 == 0 { 0xff } else { 0xf8 };
+    }
+
+    let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+    assert_eq!(reader.read_next_or_eof(Vec::new()).unwrap().unwrap().time(), 0);
+    assert!(reader.read_next_or_eof(Vec::new()).is_err());
+    assert!(!reader.is_at_frame_boundary());
+    let pos = reader.input.position() as usize;
+    let expected_skip = (frame_starts[2] - pos) as u32;
+    assert!(expected_skip > 0);
+
+    // Not enough to reach the next frame.
+    assert_eq!(reader.resync(expected_skip - 1).unwrap(), None);
+    assert!(!reader.is_at_frame_boundary());
+    assert_eq!(reader.resync(100).unwrap(), Some(1));
+    assert!(reader.is_at_frame_boundary());
+    assert_eq!(reader.read_next_or_eof(Vec::new()).unwrap().unwrap().time(), 200);
+
+    // At the end of the stream, there is nothing to skip.
+    assert_eq!(reader.resync(100).unwrap(), Some(0));
+    assert!(reader.read_next_or_eof(Vec::new()).unwrap().is_none());
+}
+
+#[test]
+fn verify_read_next_or_eof_sliced() {
+    let mut data = Vec::new();
+    for &(start, len) in &[(0, 300), (300, 17), (317, 4096)] {
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start,
+            sample_rate: Some(44_100),
+            bits_per_sample: 16,
+        };
+        let left: Vec<i32> = (0..len).map(|i| ((start + i) * 37 % 2001) as i32 - 1000).collect();
+        let right: Vec<i32> = left.iter().map(|&x| -x / 2).collect();
+        write_frame(&spec, &[&left, &right], &mut data).unwrap();
+    }
+    // Corrupt a sample in the second frame, so its CRC-16 does not match. The
+    // first frame has an 8-byte header, two subframes of 601 bytes, and a CRC.
+    data[1212 + 20] ^= 0x10;
+
+    let read_all = |sliced: bool| {
+        let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+        let mut results = Vec::new();
+        loop {
+            let result = if sliced {
+                reader.read_next_or_eof_sliced(Vec::new())
+            } else {
+                reader.read_next_or_eof(Vec::new())
+            };
+            match result {
+                Ok(Some(block)) => results.push(Ok(block)),
+                Ok(None) => break,
+                Err(err) => results.push(Err(err)),
+            }
+            assert!(reader.is_at_frame_boundary());
+        }
+        (results, reader.into_inner().position())
+    };
+
+    let (expected, expected_pos) = read_all(false);
+    assert_eq!(expected.len(), 3);
+    assert_eq!(expected[1], Err(Error::FormatError("frame CRC mismatch")));
+    let (actual, actual_pos) = read_all(true);
+    assert_eq!(actual, expected);
+    assert_eq!(actual_pos, expected_pos);
+    assert_eq!(actual_pos, data.len() as u64);
+}
+
+#[test]
+fn verify_read_next_into() {
+    let mut data = Vec::new();
+    for &start in &[0, 300] {
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start,
+            sample_rate: Some(44_100),
+            bits_per_sample: 16,
+        };
+        let samples: Vec<i32> = (0..300).map(|i| ((start + i) * 37 % 2001) as i32).collect();
+        write_frame(&spec, &[&samples], &mut data).unwrap();
+    }
+    // Corrupt the CRC-16 of the second frame.
+    let last = data.len() - 1;
+    data[last] ^= 0x01;
+
+    let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+    let mut buffer = Vec::with_capacity(1000);
+    let ptr = buffer.as_ptr();
+
+    // The buffer moves into the block, and can be taken out again.
+    let block = reader.read_next_into(&mut buffer).unwrap().unwrap();
+    assert_eq!(block.time(), 0);
+    assert_eq!(buffer.capacity(), 0);
+    buffer = block.into_buffer();
+    assert_eq!(buffer.as_ptr(), ptr);
+
+    // After an error and at the end of the stream, the buffer stays in place.
+    assert!(reader.read_next_into(&mut buffer).is_err());
+    assert_eq!(buffer.as_ptr(), ptr);
+    assert!(reader.read_next_into(&mut buffer).unwrap().is_none());
+    assert_eq!(buffer.as_ptr(), ptr);
+    assert_eq!(buffer.capacity(), 1000);
+    assert_eq!(reader.take_eof_buffer().capacity(), 0);
+
+    let block = Block::empty_with_buffer(buffer);
+    assert!(block.is_empty());
+    assert_eq!(block.buffer_capacity(), 1000);
+}
+
+#[test]
+fn verify_last_frame_checksum() {
+    let mut data = Vec::new();
+    let mut frame_ends = Vec::new();
+    for &(start, len) in &[(0, 20), (20, 33)] {
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start,
+            sample_rate: Some(8_000),
+            bits_per_sample: 8,
+        };
+        let samples: Vec<i32> = (0..len).map(|i| (i * 7 % 255) as i32 - 127).collect();
+        write_frame(&spec, &[&samples], &mut data).unwrap();
+        frame_ends.push(data.len());
+    }
+    let expected = |i: usize| {
+        let start = if i == 0 { 0 } else { frame_ends[i - 1] };
+        let end = frame_ends[i];
+        FrameChecksum {
+            crc16: (data[end - 2] as u16) << 8 | data[end - 1] as u16,
+            len: (end - start) as u32,
+        }
+    };
+
+    for &sliced in &[false, true] {
+        let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+        assert_eq!(reader.last_frame_checksum(), None);
+        for i in 0..2 {
+            if sliced {
+                reader.read_next_or_eof_sliced(Vec::new()).unwrap().unwrap();
+            } else {
+                reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+            }
+            assert_eq!(reader.last_frame_checksum(), Some(expected(i)));
+        }
+        assert!(reader.read_next_or_eof(Vec::new()).unwrap().is_none());
+        assert_eq!(reader.last_frame_checksum(), None);
+    }
+}
+
+#[test]
+fn verify_frame_ordering() {
+    // Build a variable block size stream where the third frame belongs
+    // between the first and the second.
+    let mut data = Vec::new();
+    for &start in &[0, 20, 10, 30] {
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start,
+            sample_rate: Some(44_100),
+            bits_per_sample: 16,
+        };
+        let samples: Vec<i32> = (0..10).map(|i| (start + i) as i32).collect();
+        write_frame(&spec, &[&samples], &mut data).unwrap();
+    }
+
+    let read_times = |data: &[u8], ordering| {
+        let mut reader = FrameReader::new(io::Cursor::new(data));
+        reader.set_ordering(ordering);
+        let mut times = Vec::new();
+        loop {
+            match reader.read_next_or_eof(Vec::new()) {
+                Ok(Some(block)) => {
+                    assert_eq!(block.channel(0)[0] as u64, block.time());
+                    times.push(Ok(block.time()));
+                }
+                Ok(None) => return times,
+                Err(err) => times.push(Err(err)),
+            }
+        }
+    };
+
+    let error = || Err(Error::FormatError("frame out of order, sample number decreased"));
+    assert_eq!(read_times(&data, FrameOrdering::Error), vec![Ok(0), Ok(20), error(), Ok(30)]);
+    assert_eq!(read_times(&data, FrameOrdering::Warn), vec![Ok(0), Ok(20), Ok(10), Ok(30)]);
+    assert_eq!(read_times(&data, FrameOrdering::Reorder(0)),
+               vec![Ok(0), Ok(20), error(), Ok(30)]);
+    assert_eq!(read_times(&data, FrameOrdering::Reorder(1)),
+               vec![Ok(0), Ok(10), Ok(20), Ok(30)]);
+
+    // A window larger than the stream holds all frames, and they are still
+    // returned when the stream ends.
+    assert_eq!(read_times(&data, FrameOrdering::Reorder(8)),
+               vec![Ok(0), Ok(10), Ok(20), Ok(30)]);
+
+    // A frame that is out of order by more than the window is an error, and
+    // so is a frame that is repeated, as it overlaps with the previous one.
+    let build = |starts: &[u64]| {
+        let mut data = Vec::new();
+        for &start in starts {
+            let spec = FrameSpec {
+                variable_block_size: true,
+                number: start,
+                sample_rate: Some(44_100),
+                bits_per_sample: 16,
+            };
+            let samples: Vec<i32> = (0..10).map(|i| (start + i) as i32).collect();
+            write_frame(&spec, &[&samples], &mut data).unwrap();
+        }
+        data
+    };
+    let late = build(&[10, 20, 30, 0, 40]);
+    assert_eq!(read_times(&late, FrameOrdering::Reorder(1)),
+               vec![Ok(10), Ok(20), error(), Ok(30), Ok(40)]);
+    assert_eq!(read_times(&late, FrameOrdering::Reorder(3)),
+               vec![Ok(0), Ok(10), Ok(20), Ok(30), Ok(40)]);
+    let repeated = build(&[0, 10, 10, 20]);
+    assert_eq!(read_times(&repeated, FrameOrdering::Error),
+               vec![Ok(0), Ok(10), error(), Ok(20)]);
+    assert_eq!(read_times(&repeated, FrameOrdering::Reorder(2)),
+               vec![Ok(0), Ok(10), error(), Ok(20)]);
+    assert_eq!(read_times(&repeated, FrameOrdering::Warn),
+               vec![Ok(0), Ok(10), Ok(10), Ok(20)]);
+}
+
+#[test]
+fn verify_buffer_stats() {
+    let mut data = Vec::new();
+    for &(start, len) in &[(0, 10), (10, 20), (30, 10)] {
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start,
+            sample_rate: Some(44_100),
+            bits_per_sample: 16,
+        };
+        let samples = vec![0; len];
+        write_frame(&spec, &[&samples, &samples], &mut data).unwrap();
+    }
+
+    // Statistics are not collected by default.
+    let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+    let mut buffer = Vec::new();
+    while let Some(block) = reader.read_next_or_eof(buffer).unwrap() {
+        buffer = block.into_buffer();
+    }
+    assert_eq!(reader.buffer_stats(), BufferStats::default());
+    assert_eq!(reader.frame_stats(), FrameStats::default());
+
+    // The first frame needs a buffer, the second a larger one, and the third
+    // fits in the buffer of the second.
+    let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+    reader.set_collect_stats(true);
+    let mut buffer = Vec::new();
+    while let Some(block) = reader.read_next_or_eof(buffer).unwrap() {
+        buffer = block.into_buffer();
+    }
+    let expected = BufferStats { reused: 1, allocated: 2, max_len: 40 };
+    assert_eq!(reader.buffer_stats(), expected);
+
+    // With a buffer allocated up front, decoding does not allocate.
+    let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+    reader.set_collect_stats(true);
+    let mut buffer = Vec::with_capacity(40);
+    while let Some(block) = reader.read_next_or_eof(buffer).unwrap() {
+        buffer = block.into_buffer();
+    }
+    assert_eq!(reader.buffer_stats().allocated, 0);
+    assert_eq!(reader.buffer_stats().reused, 3);
+}
+
+#[test]
+fn verify_channel_selection() {
+    let spec = FrameSpec {
+        variable_block_size: false,
+        number: 0,
+        sample_rate: Some(44_100),
+        bits_per_sample: 16,
+    };
+    let channels: Vec<Vec<i32>> = (0..6).map(|ch| vec![ch * 100; 4]).collect();
+    let slices: Vec<&[i32]> = channels.iter().map(|c| &c[..]).collect();
+    let mut data = Vec::new();
+    write_frame(&spec, &slices, &mut data).unwrap();
+
+    let read_with = |selection| {
+        let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+        reader.set_channels(selection);
+        reader.read_next_or_eof(Vec::new())
+    };
+
+    let block = read_with(ChannelSelection::Mask(0b100100)).unwrap().unwrap();
+    assert_eq!(block.channels(), 2);
+    assert_eq!(block.channel(0), &[200; 4]);
+    assert_eq!(block.channel(1), &[500; 4]);
+
+    // The LFE channel (300) does not contribute to the downmix.
+    let block = read_with(ChannelSelection::Stereo).unwrap().unwrap();
+    assert_eq!(block.channels(), 2);
+    let c = 0.5f64.sqrt();
+    let left = ((200.0 * c + 400.0 * c) / (1.0 + 2.0 * c)) as i32;
+    let right = ((100.0 + 200.0 * c + 500.0 * c) / (1.0 + 2.0 * c)) as i32;
+    assert!((block.sample(0, 0) - left).abs() <= 1);
+    assert!((block.sample(1, 0) - right).abs() <= 1);
+
+    assert!(read_with(ChannelSelection::Mask(0b1000000)).is_err());
+}
+
+#[test]
+fn verify_skipped_subframes_end_at_frame_footer() {
+    use std::fs;
+    use std::io::Read;
+
+    // These files are mono, so with only channel 1 selected, every subframe
+    // is skipped. If skipping did not consume the subframe exactly, the CRC
+    // would not match, instead of the frame lacking the selected channel.
+    for &(fname, offset) in &[("testsamples/pop.flac", 42), ("testsamples/wasted_bits.flac", 152)] {
+        let mut data = Vec::new();
+        fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+
+        let mut decoded = FrameReader::new(io::Cursor::new(&data[offset..]));
+        let mut n_frames = 0;
+        while let Some(_) = decoded.read_next_or_eof(Vec::new()).unwrap() {
+            n_frames += 1;
+        }
+
+        let mut skipped = FrameReader::new(io::Cursor::new(&data[offset..]));
+        skipped.set_channels(ChannelSelection::Mask(0b10));
+        for _ in 0..n_frames {
+            match skipped.read_next_or_eof(Vec::new()) {
+                Err(Error::FormatError("frame contains none of the selected channels")) => {}
+                other => panic!("unexpected result: {:?}", other.map(|b| b.map(|b| b.time()))),
+            }
+        }
+        assert!(skipped.read_next_or_eof(Vec::new()).unwrap().is_none());
+    }
+}
+
+#[test]
+fn verify_subframe_bits_per_sample() {
+    use std::fs;
+    use std::io::Read;
+
+    // The first frame of this file is a mono frame of 16 bits per sample, of
+    // which the 8 least significant bits are wasted.
+    let (data, _) = read_wasted_bits();
+    let mut reader = FrameReader::new(io::Cursor::new(&data[152..]));
+    assert_eq!(reader.subframe_bits_per_sample(), &[]);
+    reader.read_next_or_eof(Vec::new()).unwrap();
+    assert_eq!(reader.subframe_bits_per_sample(), &[8]);
+
+    // The frame in this file is a 24-bit stereo frame with a side channel,
+    // which has one bit more.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/non_subset.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut reader = FrameReader::new(io::Cursor::new(&data[42..]));
+    reader.read_next_or_eof(Vec::new()).unwrap();
+    assert_eq!(reader.subframe_bits_per_sample(), &[24, 25]);
+
+    // After a failure, there is nothing to report.
+    let mut reader = FrameReader::new(io::Cursor::new(&data[42..100]));
+    assert!(reader.read_next_or_eof(Vec::new()).is_err());
+    assert_eq!(reader.subframe_bits_per_sample(), &[]);
+}
+
+#[test]
+fn verify_subframe_widths() {
+    use std::fs;
+    use std::io::Read;
+
+    let (data, _) = read_wasted_bits();
+    let mut reader = FrameReader::new(io::Cursor::new(&data[152..]));
+    assert_eq!(reader.subframe_widths(), vec![]);
+    reader.read_next_or_eof(Vec::new()).unwrap();
+    let mono = SubframeWidth { frame_bits_per_sample: 16, is_side: false, wasted_bits: 8 };
+    assert_eq!(reader.subframe_widths(), vec![mono]);
+    assert_eq!(mono.bits_per_sample(), 16);
+    assert_eq!(mono.coded_bits_per_sample(), 8);
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/non_subset.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut reader = FrameReader::new(io::Cursor::new(&data[42..]));
+    reader.read_next_or_eof(Vec::new()).unwrap();
+    let widths = reader.subframe_widths();
+    let coded: Vec<u32> = widths.iter().map(|w| w.coded_bits_per_sample()).collect();
+    assert_eq!(&coded[..], reader.subframe_bits_per_sample());
+    assert_eq!(widths.iter().filter(|w| w.is_side).count(), 1);
+    assert!(widths.iter().all(|w| w.frame_bits_per_sample == 24 && w.wasted_bits == 0));
+}
+
+#[test]
+fn verify_explain_next_or_eof() {
+    use std::fs;
+    use std::io::Read;
+    use explain::SubframeKind;
+
+    // A verbatim frame is explained sample by sample.
+    let spec = FrameSpec {
+        variable_block_size: true,
+        number: 7,
+        sample_rate: Some(8_000),
+        bits_per_sample: 8,
+    };
+    let mut data = Vec::new();
+    write_frame(&spec, &[&[3, -4], &[-128, 127]], &mut data).unwrap();
+    let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+    let elements = reader.explain_next_or_eof().unwrap().unwrap();
+    let crc16 = reader.last_frame_checksum().unwrap().crc16;
+    let verbatim = Element::SubframeHeader {
+        kind: SubframeKind::Verbatim,
+        wasted_bits: 0,
+        bits_per_sample: 8,
+    };
+    assert_eq!(elements, vec![
+        Element::FrameHeader {
+            variable_block_size: true,
+            number: 7,
+            block_size: 2,
+            sample_rate: Some(8_000),
+            channels: ChannelCoding::Independent { channels: 2 },
+            bits_per_sample: Some(8),
+        },
+        verbatim, Element::Sample { value: 3 }, Element::Sample { value: -4 },
+        verbatim, Element::Sample { value: -128 }, Element::Sample { value: 127 },
+        Element::FrameFooter { crc16: crc16 },
+    ]);
+    assert_eq!(reader.explain_next_or_eof(), Ok(None));
+
+    // For frames of real encoders, explaining reads exactly the frames that
+    // decoding reads, and the warm-up samples and partitions fill the blocks.
+    let files = [("testsamples/wasted_bits.flac", 152), ("testsamples/non_subset.flac", 42)];
+    for &(fname, offset) in &files {
+        let mut data = Vec::new();
+        fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+        let mut decoder = FrameReader::new(io::Cursor::new(&data[offset..]));
+        let mut explainer = FrameReader::new(io::Cursor::new(&data[offset..]));
+        while let Some(block) = decoder.read_next_or_eof(Vec::new()).unwrap() {
+            let elements = explainer.explain_next_or_eof().unwrap().unwrap();
+            assert_eq!(explainer.last_frame_checksum(), decoder.last_frame_checksum());
+            assert_eq!(explainer.subframe_bits_per_sample(),
+                       decoder.subframe_bits_per_sample());
+
+            match elements[0] {
+                Element::FrameHeader { block_size, .. } => {
+                    assert_eq!(block_size as u32, block.duration());
+                }
+                ref other => panic!("unexpected first element: {:?}", other),
+            }
+            let mut subframes = 0;
+            let mut samples = block.duration();
+            for element in &elements[1..] {
+                match *element {
+                    Element::SubframeHeader { kind, .. } => {
+                        assert_eq!(samples, block.duration());
+                        subframes += 1;
+                        // A constant subframe stores one sample for the block.
+                        samples = match kind {
+                            SubframeKind::Constant => block.duration() - 1,
+                            _ => 0,
+                        };
+                    }
+                    Element::Sample { .. } => samples += 1,
+                    Element::RicePartition { samples: n, .. } => samples += n as u32,
+                    Element::FrameFooter { .. } => assert_eq!(samples, block.duration()),
+                    _ => {}
+                }
+            }
+            assert_eq!(subframes, block.channels());
+        }
+        assert_eq!(explainer.explain_next_or_eof(), Ok(None));
+    }
+}
+
+// TODO: implement Iterator<Item = FrameResult> for FrameReader, with an
 // accurate size hint.
+
+/// Finds and decodes the last frame in the tail end of a stream.
+///
+/// This is useful when only the final bytes of a stream are available, for
+/// instance during a progressive download, or to determine the duration of a
+/// stream that does not specify the number of samples in its streaminfo. The
+/// end of the stream in samples is `block.time() + block.duration()`.
+///
+/// The tail is scanned backwards for a frame sync code, and the first
+/// candidate position from the end where a frame can be decoded with valid
+/// checksums, and which matches the number of channels in the streaminfo, is
+/// taken to be the start of the last frame. The tail may contain trailing data
+/// after the last frame, such as an ID3v1 tag. The tail must contain the final
+/// frame in its entirety, so it should be at least `max_frame_size` bytes long
+/// if the streaminfo specifies it.
+///
+/// Returns a format error if no valid frame could be found.
+pub fn decode_last_frame(tail: &[u8], streaminfo: &StreamInfo) -> Result<Block> {
+    // A frame starts with the 14-bit sync code 11111111111110, followed by a
+    // reserved bit that must be 0, so the second byte is 0xf8 or 0xf9. Try all
+    // candidate positions, starting from the end.
+    let mut buffer = Vec::new();
+    for i in (0..tail.len().saturating_sub(1)).rev() {
+        if tail[i] != 0xff || tail[i + 1] & 0b1111_1110 != 0b1111_1000 {
+            continue
+        }
+
+        // In a fixed block size stream, the final frame may be shorter than
+        // the others, so the reader must know the block size of the stream.
+        let mut reader = FrameReader::new(io::Cursor::new(&tail[i..]));
+        reader.set_fixed_block_size(streaminfo.max_block_size);
+        // On error, the reader puts the buffer back, to try the next candidate.
+        if let Ok(Some(block)) = reader.read_next_into(&mut buffer) {
+            if block.channels() == streaminfo.channels &&
+               block.duration() <= streaminfo.max_block_size as u32 {
+                return Ok(block)
+            }
+            buffer = block.into_buffer();
+        }
+    }
+
+    fmt_err("no valid frame found in stream tail")
+}
+
+#[test]
+fn verify_decode_last_frame() {
+    use std::io;
+
+    // This file has its first frame at byte 152, and its second and final frame
+    // at byte 1129.
+    let (data, streaminfo) = read_wasted_bits();
+
+    let mut reader = FrameReader::new(io::Cursor::new(&data[1129..]));
+    let expected = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+
+    // The last frame is shorter than the others, but its time must still be
+    // the end of the stream minus its duration. The frame reader by itself
+    // cannot know this, as it does not know the block size of the stream.
+    let expected_time = streaminfo.samples.unwrap() - expected.duration() as u64;
+
+    // Whether the tail starts at the start of the stream, at the start of the
+    // last frame, or halfway a frame, the last frame should be found.
+    for &start in &[0, 152, 600, 1129] {
+        let block = decode_last_frame(&data[start..], &streaminfo).unwrap();
+        assert_eq!(block.time(), expected_time);
+        assert_eq!(block.duration(), expected.duration());
+        assert_eq!(block.into_buffer(), expected.buffer);
+    }
+
+    // Trailing garbage after the last frame should be ignored.
+    let mut with_trailer = data.clone();
+    with_trailer.extend_from_slice(b"TAG\xff\xf8 trailing data");
+    let block = decode_last_frame(&with_trailer[152..], &streaminfo).unwrap();
+    assert_eq!(block.time(), expected_time);
+
+    // If the last frame is incomplete, we cannot decode it.
+    assert!(decode_last_frame(&data[1129..data.len() - 1], &streaminfo).is_err());
+}
+
+/// Decodes exactly one frame from the reader, without reading beyond its end.
+///
+/// This is useful for protocols and containers that interleave FLAC frames with
+/// other data: after this returns, the reader is positioned right after the
+/// frame. Returns the block, and the number of bytes that the frame occupied.
+/// The buffer is used for the block, like in `FrameReader::read_next_or_eof()`.
+///
+/// The input is read byte by byte, see `UnbufferedReader`. To avoid a system
+/// call per byte, pass a `&mut` to a reader that is buffered itself.
+///
+/// A frame by itself does not contain enough information to determine its
+/// time in a fixed block size stream if it is the final, shorter frame. If the
+/// streaminfo is provided, it is used for that, and it is checked that the
+/// frame matches it. If the input ends before the frame starts, an `IoError`
+/// with kind `UnexpectedEof` is returned.
+pub fn decode_single<R: io::Read>(input: R,
+                                  streaminfo: Option<&StreamInfo>,
+                                  buffer: Vec<i32>)
+                                  -> Result<(Block, u64)> {
+    // See also `decode_last_frame()`. In a fixed block size stream, the frame
+    // may be the final one, which may be shorter than the others.
+    let mut reader = FrameReader::new(UnbufferedReader::new(input));
+    if let Some(si) = streaminfo {
+        reader.set_fixed_block_size(si.max_block_size);
+    }
+    let block = match try!(reader.read_next_or_eof(buffer)) {
+        Some(block) => block,
+        None => {
+            let err = io::Error::new(io::ErrorKind::UnexpectedEof, "expected a frame");
+            return Err(Error::IoError(err))
+        }
+    };
+
+    if let Some(si) = streaminfo {
+        if block.channels() != si.channels || block.duration() > si.max_block_size as u32 {
+            return fmt_err("frame does not match the streaminfo");
+        }
+    }
+
+    let len = reader.into_inner().position();
+    Ok((block, len))
+}
+
+#[test]
+fn verify_decode_single() {
+
+    // This file has its first frame at byte 152, and its second and final frame
+    // at byte 1129.
+    let (data, streaminfo) = read_wasted_bits();
+    let mut reader = FrameReader::new(io::Cursor::new(&data[152..]));
+    let first = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    let last = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+
+    // The reader must be left right after the frame, so the next frame can be
+    // decoded from where it is.
+    let mut input = &data[152..];
+    let (block, len) = decode_single(&mut input, None, Vec::new()).unwrap();
+    assert_eq!(len, 1129 - 152);
+    assert_eq!(input.len(), data.len() - 1129);
+    assert_eq!(block, first);
+
+    let (block, len) = decode_single(&mut input, None, Vec::new()).unwrap();
+    assert_eq!(len, (data.len() - 1129) as u64);
+    assert_eq!(block.into_buffer(), last.buffer);
+    assert!(input.is_empty());
+
+    // With the streaminfo, the time of the final, shorter frame is known.
+    let (block, _) = decode_single(&data[1129..], Some(&streaminfo), Vec::new()).unwrap();
+    assert_eq!(block.time() + block.duration() as u64, streaminfo.samples.unwrap());
+
+    match decode_single(&mut input, None, Vec::new()) {
+        Err(Error::IoError(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+        other => panic!("expected end of input, got {:?}", other),
+    }
+}
+
+#[test]
+fn verify_frame_reader_snapshot_restore() {
+    use input::BufferedReader;
+
+    // This file has its first frame at byte 152, and its second and final frame
+    // at byte 1129.
+    let (data, _) = read_wasted_bits();
+
+    let mut reader = FrameReader::new(BufferedReader::new(io::Cursor::new(&data[152..])));
+    let start = reader.snapshot().unwrap();
+    assert_eq!(start, FramePosition { offset: 0, next_sample: None });
+    let first = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    let loop_start = reader.snapshot().unwrap();
+    assert_eq!(loop_start, FramePosition { offset: 1129 - 152, next_sample: Some(4096) });
+    let second = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert!(reader.read_next_or_eof(Vec::new()).unwrap().is_none());
+
+    // Loop the final frame a few times, then go back to the start.
+    for _ in 0..3 {
+        reader.restore(loop_start).unwrap();
+        assert_eq!(reader.read_next_or_eof(Vec::new()).unwrap().unwrap(), second);
+        assert!(reader.read_next_or_eof(Vec::new()).unwrap().is_none());
+    }
+    reader.restore(start).unwrap();
+    assert_eq!(reader.read_next_or_eof(Vec::new()).unwrap().unwrap(), first);
+
+    // Halfway a frame, there is no position to return to.
+    let mut input = BufferedReader::new(io::Cursor::new(&data[152..160]));
+    let mut reader = FrameReader::new(&mut input);
+    assert!(reader.read_next_or_eof(Vec::new()).is_err());
+    assert_eq!(reader.snapshot(), None);
+    reader.restore(start).unwrap();
+    assert_eq!(reader.snapshot(), Some(start));
+}
+
+#[test]
+fn verify_frame_reader_time_of_short_final_frame() {
+
+    // This file has a fixed block size of 4096. The first frame is at byte
+    // 152, the second and final frame, of 314 samples, at byte 1129.
+    let (data, _) = read_wasted_bits();
+
+    let mut reader = FrameReader::new(io::Cursor::new(&data[152..]));
+    let first = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    let last = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert_eq!((first.frame_number(), first.time()), (Some(0), 0));
+    assert_eq!((last.frame_number(), last.time()), (Some(1), 4096));
+    assert_eq!(last.duration(), 314);
+    assert_eq!(last.timestamp(44_100), samples_to_duration(4096, 44_100));
+    assert_eq!(last.clone().into_arc().timestamp(44_100), last.timestamp(44_100));
+
+    // Starting at the final frame, the block size of the stream is unknown,
+    // unless it is provided.
+    let mut reader = FrameReader::new(io::Cursor::new(&data[1129..]));
+    assert_eq!(reader.read_next_or_eof(Vec::new()).unwrap().unwrap().time(), 314);
+    let mut reader = FrameReader::new(io::Cursor::new(&data[1129..]));
+    reader.set_fixed_block_size(4096);
+    assert_eq!(reader.read_next_or_eof(Vec::new()).unwrap().unwrap().time(), 4096);
+}
+
+/// Returns whether the bytes start with a valid frame header.
+///
+/// The header must start with the sync code, contain no reserved values, and
+/// end with a matching CRC-8. The bytes after the header are not inspected.
+pub fn is_frame_header(bytes: &[u8]) -> bool {
+    let mut input = io::Cursor::new(bytes);
+    match read_frame_header_or_eof(&mut input, false, &mut Vec::new()) {
+        Ok(Some(..)) => true,
+        _ => false,
+    }
+}
+
+/// Derives a streaminfo from the header of the first frame of a stream.
+///
+/// This is used for streams that consist of frames only, without a stream
+/// header and metadata blocks. Such streams can only be decoded if the frame
+/// headers specify the sample rate and bits per sample, otherwise
+/// `Error::Unsupported` is returned. The block size of the first frame is
+/// taken to be the minimum and maximum block size. The frame sizes, number of
+/// samples, and MD5 signature are unknown.
+pub fn streaminfo_from_frame_header<R: ReadBytes>(input: &mut R) -> Result<StreamInfo> {
+    let header = match try!(read_frame_header_or_eof(input, false, &mut Vec::new())) {
+        Some(header) => header,
+        None => return fmt_err("stream contains no frames"),
+    };
+    let sample_rate = match header.sample_rate {
+        Some(sr) => sr,
+        None => return Err(Error::Unsupported(UnsupportedFeature::MissingSampleRateInHeader)),
+    };
+    let bits_per_sample = match header.bits_per_sample {
+        Some(bps) => bps,
+        None => return Err(Error::Unsupported(UnsupportedFeature::MissingBpsInHeader)),
+    };
+
+    Ok(StreamInfo {
+        min_block_size: header.block_size,
+        max_block_size: header.block_size,
+        min_frame_size: None,
+        max_frame_size: None,
+        sample_rate: sample_rate,
+        channels: header.channels() as u32,
+        bits_per_sample: bits_per_sample,
+        samples: None,
+        md5sum: [0; 16],
+    })
+}
+
+#[test]
+fn verify_reserved_values() {
+    let spec = FrameSpec {
+        variable_block_size: false,
+        number: 0,
+        sample_rate: Some(44_100),
+        bits_per_sample: 16,
+    };
+    let mut data = Vec::new();
+    write_frame(&spec, &[&[0; 16]], &mut data).unwrap();
+
+    // Set the reserved bit that follows the sync code.
+    data[1] |= 0b10;
+
+    let read_frame = |policy| {
+        let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+        reader.set_reserved_values(policy);
+        reader.read_next_or_eof(Vec::new()).err().unwrap()
+    };
+
+    let rejected = read_frame(ReservedValues::Reject);
+    assert_eq!(rejected, Error::FormatError(error::RESERVED_FRAME_HEADER));
+    assert!(rejected.is_reserved_value());
+
+    let unsupported = read_frame(ReservedValues::Unsupported);
+    assert_eq!(unsupported, Error::Unsupported(UnsupportedFeature::ReservedFrameHeader));
+    assert!(unsupported.is_reserved_value());
+}
+
+#[test]
+fn verify_lenient_frame_issues() {
+    let spec = FrameSpec {
+        variable_block_size: false,
+        number: 0,
+        sample_rate: Some(44_100),
+        bits_per_sample: 16,
+    };
+    let samples: Vec<i32> = (0..16).collect();
+    let mut data = Vec::new();
+    write_frame(&spec, &[&samples], &mut data).unwrap();
+    let frame_len = data.len();
+    data.extend_from_slice(&data.clone());
+
+    // Set the reserved bit at the end of the fourth header byte of the first
+    // frame, without fixing either CRC. In the second frame, corrupt the CRC-8
+    // at byte 6, and make the subframe type reserved.
+    data[3] |= 1;
+    data[frame_len + 6] ^= 0xff;
+    data[frame_len + 7] = 0b0_000010_0;
+
+    let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+    assert_eq!(reader.read_next_or_eof(Vec::new()).err().unwrap(),
+               Error::FormatError(error::RESERVED_FRAME_HEADER));
+    assert!(reader.frame_issues().is_empty());
+
+    let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+    reader.set_lenient(true);
+    let block = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert_eq!(block.channel(0), &samples[..]);
+    assert_eq!(block.issues().len(), 3);
+    assert_eq!(block.issues()[0], FrameIssue::ReservedBit);
+    match (block.issues()[1], block.issues()[2]) {
+        (FrameIssue::HeaderCrcMismatch { computed, stored },
+         FrameIssue::FrameCrcMismatch { .. }) => {
+            assert_eq!(stored, data[6]);
+            assert_eq!(computed, crc::crc8(&data[..6]));
+        }
+        other => panic!("unexpected issues: {:?}", other),
+    }
+    assert_eq!(reader.frame_issues(), block.issues());
+
+    // The subframe error is returned, the issues include what came before.
+    assert_eq!(reader.read_next_or_eof(Vec::new()).err().unwrap(),
+               Error::FormatError(error::RESERVED_SUBFRAME_HEADER));
+    assert_eq!(reader.frame_issues().len(), 2);
+    assert_eq!(reader.frame_issues()[1], FrameIssue::SubframeError);
+}
+
+/// Describes the header of a frame to be written with `write_frame()`.
+///
+/// The block size and number of channels of the frame are determined by the
+/// samples passed to `write_frame()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameSpec {
+    /// Whether the frame is part of a variable block size stream.
+    pub variable_block_size: bool,
+
+    /// The frame number, or for a variable block size stream, the sample number
+    /// of the first sample in the frame.
+    ///
+    /// A frame number must fit in 31 bits, a sample number in 36 bits.
+    pub number: u64,
+
+    /// The sample rate in Hz, or `None` to refer to the streaminfo block.
+    pub sample_rate: Option<u32>,
+
+    /// The number of bits per sample, between 1 and 32.
+    ///
+    /// If the value cannot be expressed in a frame header (anything other than
+    /// 8, 12, 16, 20, or 24), the header refers to the streaminfo block.
+    pub bits_per_sample: u32,
+}
+
+/// Accumulates bits most significant bit first, for writing subframes.
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// The bits not yet written to `bytes`, in the least significant bits.
+    data: u64,
+    /// The number of valid bits in `data`, always less than 8.
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new(bytes: Vec<u8>) -> BitWriter {
+        BitWriter {
+            bytes: bytes,
+            data: 0,
+            bits: 0,
+        }
+    }
+
+    /// Writes the `n` least significant bits of `value`, where `n <= 32`.
+    fn write_bits(&mut self, value: u32, n: u32) {
+        debug_assert!(n <= 32);
+        let mask = (1u64 << n) - 1;
+        self.data = (self.data << n) | (value as u64 & mask);
+        self.bits += n;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.data >> self.bits) as u8);
+        }
+        self.data &= (1 << self.bits) - 1;
+    }
+
+    /// Pads with zero bits up to the next byte boundary, and returns the bytes.
+    fn into_bytes(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            let pad = 8 - self.bits;
+            self.write_bits(0, pad);
+        }
+        self.bytes
+    }
+}
+
+/// Appends an integer in the "UTF-8" coding used for frame and sample numbers.
+///
+/// This is the inverse of `read_var_length_int()`, for integers up to 36 bits.
+fn write_var_length_int(value: u64, bytes: &mut Vec<u8>) {
+    debug_assert!(value < 1 << 36);
+
+    if value < 0x80 {
+        bytes.push(value as u8);
+        return
+    }
+
+    // Every continuation byte holds 6 bits, and a first byte for n bytes in
+    // total holds 7 - n bits. Find the smallest n for which the value fits.
+    let mut n = 2;
+    while value >= 1 << (5 * n + 1) {
+        n += 1;
+    }
+
+    let mark = (0xff00u16 >> n) as u8;
+    bytes.push(mark | (value >> (6 * (n - 1))) as u8);
+    for i in (0..n - 1).rev() {
+        bytes.push(0b1000_0000 | ((value >> (6 * i)) as u8 & 0b0011_1111));
+    }
+}
+
+#[test]
+fn verify_write_var_length_int() {
+    use input::BufferedReader;
+
+    let values = [0, 0x24, 0x7f, 0x80, 0xa2, 0x7ff, 0x800, 0x20ac, 0x010348,
+                  0x7fff_ffff, 0xf_ffff_ffff];
+    let mut bytes = Vec::new();
+    for &value in &values {
+        write_var_length_int(value, &mut bytes);
+    }
+
+    // The first values are the ones from `verify_read_var_length_int()`.
+    assert_eq!(&bytes[1..10], &[0x24, 0x7f, 0xc2, 0x80, 0xc2, 0xa2, 0xdf, 0xbf, 0xe0]);
+
+    let mut reader = BufferedReader::new(io::Cursor::new(bytes));
+    for &value in &values {
+        assert_eq!(read_var_length_int(&mut reader).unwrap(), value);
+    }
+}
+
+/// Writes a frame with verbatim subframes.
+///
+/// `channels` contains the samples of each channel, all of the same length,
+/// which is the block size of the frame. Channels are coded independently,
+/// without inter-channel decorrelation, and no compression is applied. The
+/// frame header CRC-8 and frame CRC-16 are computed, so the frame can be read
+/// back with a `FrameReader`. This is not an encoder, it is intended for
+/// constructing test vectors, and for tools that need to rewrite frames.
+///
+/// Returns `Error::Unsupported` if the sample rate cannot be expressed in a
+/// frame header.
+///
+/// # Panics
+///
+/// Panics if there are no channels or more than 8, if the channels differ in
+/// length, if the block size is not between 1 and 65535, if the number does
+/// not fit in 31 (fixed block size) or 36 (variable block size) bits, if
+/// `bits_per_sample` is not between 1 and 32, or if a sample does not fit in
+/// `bits_per_sample` bits.
+pub fn write_frame<W: io::Write>(spec: &FrameSpec,
+                                 channels: &[&[i32]],
+                                 output: &mut W)
+                                 -> Result<()> {
+    assert!(channels.len() >= 1 && channels.len() <= 8,
+            "a frame must contain between 1 and 8 channels");
+    let block_size = channels[0].len();
+    assert!(block_size >= 1 && block_size <= 0xffff,
+            "block size must be between 1 and 65535");
+    assert!(channels.iter().all(|ch| ch.len() == block_size),
+            "all channels must have the same length");
+    assert!(spec.bits_per_sample >= 1 && spec.bits_per_sample <= 32,
+            "bits per sample must be between 1 and 32");
+    if spec.variable_block_size {
+        assert!(spec.number < 1 << 36, "sample number must fit in 36 bits");
+    } else {
+        assert!(spec.number <= 0x7fff_ffff, "frame number must fit in 31 bits");
+    }
+
+    // Pick the shortest block size and sample rate codes. Some codes indicate
+    // that the value follows after the frame or sample number.
+    let bs_code: u8 = match block_size {
+        192 => 0b0001,
+        576 => 0b0010,
+        1152 => 0b0011,
+        2304 => 0b0100,
+        4608 => 0b0101,
+        256 => 0b1000,
+        512 => 0b1001,
+        1024 => 0b1010,
+        2048 => 0b1011,
+        4096 => 0b1100,
+        8192 => 0b1101,
+        16384 => 0b1110,
+        32768 => 0b1111,
+        n if n <= 0x100 => 0b0110,
+        _ => 0b0111,
+    };
+
+    let sr_code = match spec.sample_rate {
+        None => 0b0000,
+        Some(88_200) => 0b0001,
+        Some(176_400) => 0b0010,
+        Some(192_000) => 0b0011,
+        Some(8_000) => 0b0100,
+        Some(16_000) => 0b0101,
+        Some(22_050) => 0b0110,
+        Some(24_000) => 0b0111,
+        Some(32_000) => 0b1000,
+        Some(44_100) => 0b1001,
+        Some(48_000) => 0b1010,
+        Some(96_000) => 0b1011,
+        Some(sr) if !metadata::is_valid_sample_rate(sr) => return fmt_err("invalid sample rate"),
+        Some(sr) if sr % 1000 == 0 && sr / 1000 <= 0xff => 0b1100,
+        Some(sr) if sr <= 0xffff => 0b1101,
+        Some(sr) if sr % 10 == 0 && sr / 10 <= 0xffff => 0b1110,
+        Some(_) => return Err(Error::Unsupported(UnsupportedFeature::SampleRateNotCodable)),
+    };
+
+    let bps_code = match spec.bits_per_sample {
+        8 => 0b001,
+        12 => 0b010,
+        16 => 0b100,
+        20 => 0b101,
+        24 => 0b110,
+        _ => 0b000,
+    };
+
+    let mut bytes = Vec::new();
+    let blocking_bit = if spec.variable_block_size { 1 } else { 0 };
+    bytes.push(0xff);
+    bytes.push(0b1111_1000 | blocking_bit);
+    bytes.push((bs_code << 4) | sr_code);
+    bytes.push((((channels.len() - 1) as u8) << 4) | (bps_code << 1));
+    write_var_length_int(spec.number, &mut bytes);
+
+    match bs_code {
+        0b0110 => bytes.push((block_size - 1) as u8),
+        0b0111 => {
+            bytes.push(((block_size - 1) >> 8) as u8);
+            bytes.push((block_size - 1) as u8);
+        }
+        _ => {}
+    }
+
+    match (sr_code, spec.sample_rate) {
+        (0b1100, Some(sr)) => bytes.push((sr / 1000) as u8),
+        (0b1101, Some(sr)) => {
+            bytes.push((sr >> 8) as u8);
+            bytes.push(sr as u8);
+        }
+        (0b1110, Some(sr)) => {
+            bytes.push((sr / 10 >> 8) as u8);
+            bytes.push((sr / 10) as u8);
+        }
+        _ => {}
+    }
+
+    let header_crc = crc::crc8(&bytes);
+    bytes.push(header_crc);
+
+    // Each subframe has a header with a zero padding bit, the 6-bit type, and a
+    // flag for wasted bits, followed by the samples as-is.
+    let bps = spec.bits_per_sample;
+    let mut writer = BitWriter::new(bytes);
+    for samples in channels {
+        writer.write_bits(0b0_000001_0, 8);
+        for &sample in samples.iter() {
+            if bps < 32 {
+                assert!(sample >= -(1 << (bps - 1)) && sample < 1 << (bps - 1),
+                        "sample does not fit in bits per sample");
+            }
+            writer.write_bits(sample as u32, bps);
+        }
+    }
+
+    // The subframes are padded to a byte boundary, followed by the CRC-16.
+    let mut bytes = writer.into_bytes();
+    let frame_crc = crc::crc16(&bytes);
+    bytes.push((frame_crc >> 8) as u8);
+    bytes.push(frame_crc as u8);
+
+    try!(output.write_all(&bytes));
+    Ok(())
+}
+
+#[test]
+fn verify_write_frame() {
+    let left: Vec<i32> = (0..1000).map(|i| (i * 37) % 2001 - 1000).collect();
+    let right: Vec<i32> = (0..1000).map(|i| 500 - (i * 13) % 1001).collect();
+
+    let specs = [
+        // Fixed block size with a common sample rate.
+        (FrameSpec {
+            variable_block_size: false,
+            number: 3,
+            sample_rate: Some(44_100),
+            bits_per_sample: 16,
+        }, 1000, 3000),
+        // Variable block size with a sample number that needs 7 bytes, and a
+        // sample rate that is stored in tens of Hz.
+        (FrameSpec {
+            variable_block_size: true,
+            number: 0xf_ffff_0000,
+            sample_rate: Some(655_350),
+            bits_per_sample: 12,
+        }, 1000, 0xf_ffff_0000),
+        // A sample rate that is stored in kHz.
+        (FrameSpec {
+            variable_block_size: true,
+            number: 0,
+            sample_rate: Some(100_000),
+            bits_per_sample: 16,
+        }, 1000, 0),
+        // A block size that fits in 8 bits, with a 16-bit sample rate.
+        (FrameSpec {
+            variable_block_size: false,
+            number: 2,
+            sample_rate: Some(200),
+            bits_per_sample: 24,
+        }, 77, 154),
+        // A block size with a predefined code.
+        (FrameSpec {
+            variable_block_size: false,
+            number: 0,
+            sample_rate: None,
+            bits_per_sample: 20,
+        }, 576, 0),
+    ];
+
+    for &(ref spec, block_size, time) in &specs {
+        let mut left = left.clone();
+        left.resize(block_size, 17);
+        let mut right = right.clone();
+        right.resize(block_size, -17);
+
+        let mut data = Vec::new();
+        write_frame(spec, &[&left, &right], &mut data).unwrap();
+
+        let mut reader = FrameReader::new(io::Cursor::new(data));
+        let block = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+        assert_eq!(block.time(), time);
+        assert_eq!(block.duration(), block_size as u32);
+        assert_eq!(block.sample_rate(), spec.sample_rate);
+        assert_eq!(block.bits_per_sample(), spec.bits_per_sample);
+
+        // The spec takes the rate from the stream only if the header lacks it.
+        let stream = StreamSpec { sample_rate: 8_000, channels: 2, bits_per_sample: 16 };
+        let block_spec = block.spec(stream);
+        assert_eq!(block_spec.sample_rate, spec.sample_rate.unwrap_or(8_000));
+        assert_eq!((block_spec.channels, block_spec.bits_per_sample), (2, spec.bits_per_sample));
+
+        assert_eq!(block.channel(0), &left[..]);
+        assert_eq!(block.channel(1), &right[..]);
+        assert!(reader.read_next_or_eof(block.into_buffer()).unwrap().is_none());
+    }
+
+    let spec = FrameSpec {
+        variable_block_size: false,
+        number: 0,
+        sample_rate: Some(700_001),
+        bits_per_sample: 16,
+    };
+    assert!(write_frame(&spec, &[&left], &mut Vec::new()).is_err());
+}
+
+#[test]
+fn verify_decode_stereo_wasted_bits() {
+    // A left-side stereo frame of 16 samples at 16 bits per sample, with
+    // verbatim subframes. The left channel has 2 wasted bits, the side channel
+    // has 3, so both are coded with 14 bits per sample.
+    let left: Vec<i32> = (0..16).map(|i| (i * 37 % 64 - 32) * 4).collect();
+    let right: Vec<i32> = (0..16).map(|i| left[i as usize] - (i - 8) * 8).collect();
+
+    let mut bytes = vec![0xff, 0xf8, 0b0110_1001, 0b1000_1000, 0x00, 15];
+    let header_crc = crc::crc8(&bytes);
+    bytes.push(header_crc);
+    let mut writer = BitWriter::new(bytes);
+    writer.write_bits(0b0_000001_1, 8);
+    writer.write_bits(0b01, 2);
+    for &l in &left {
+        writer.write_bits((l >> 2) as u32, 14);
+    }
+    writer.write_bits(0b0_000001_1, 8);
+    writer.write_bits(0b001, 3);
+    for (&l, &r) in left.iter().zip(&right) {
+        writer.write_bits(((l - r) >> 3) as u32, 14);
+    }
+    let mut bytes = writer.into_bytes();
+    let frame_crc = crc::crc16(&bytes);
+    bytes.push((frame_crc >> 8) as u8);
+    bytes.push(frame_crc as u8);
+
+    let mut reader = FrameReader::new(io::Cursor::new(bytes));
+    let block = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert_eq!(block.channel(0), &left[..]);
+    assert_eq!(block.channel(1), &right[..]);
+    assert_eq!(block.channel_coding(), ChannelCoding::LeftSide);
+    assert_eq!(block.bits_per_sample(), 16);
+    assert!(!block.is_variable_block_size());
+}
+
+#[test]
+fn verify_block_frame_header_info() {
+    let samples: Vec<i32> = (0..32).map(|i| i * 3 - 48).collect();
+    let mut data = Vec::new();
+    let mut spec = FrameSpec {
+        variable_block_size: true,
+        number: 0,
+        sample_rate: None,
+        bits_per_sample: 12,
+    };
+    write_frame(&spec, &[&samples, &samples, &samples], &mut data).unwrap();
+    spec.number = 32;
+    write_frame(&spec, &[&samples], &mut data).unwrap();
+
+    let mut reader = FrameReader::new(io::Cursor::new(data));
+    let block = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert!(block.is_variable_block_size());
+    assert_eq!(block.channel_coding(), ChannelCoding::Independent { channels: 3 });
+    assert_eq!(block.bits_per_sample(), 12);
+
+    let block = reader.read_next_or_eof(block.into_buffer()).unwrap().unwrap();
+    assert_eq!(block.time(), 32);
+    assert_eq!(block.channel_coding(), ChannelCoding::Independent { channels: 1 });
+
+    // The header information survives a round trip through `ArcBlock`.
+    let arc_block = block.into_arc();
+    assert!(arc_block.is_variable_block_size());
+    assert_eq!(arc_block.channel_coding(), ChannelCoding::Independent { channels: 1 });
+}
+
+#[test]
+fn verify_frame_reader_open() {
+
+    // This file has its first frame at byte 152.
+    let (data, _) = read_wasted_bits();
+    let mut expected = FrameReader::new(io::Cursor::new(&data[152..]));
+
+    let mut reader = FrameReader::open("testsamples/wasted_bits.flac", 152).unwrap();
+    assert_eq!(reader.snapshot().unwrap().offset, 152);
+    let mut n = 0;
+    while let Some(block) = reader.read_next_or_eof(Vec::new()).unwrap() {
+        let expected_block = expected.read_next_or_eof(Vec::new()).unwrap().unwrap();
+        assert_eq!(block.time(), expected_block.time());
+        for ch in 0..block.channels() {
+            assert_eq!(block.channel(ch), expected_block.channel(ch));
+        }
+        n += 1;
+    }
+    assert!(n > 0);
+    assert!(expected.read_next_or_eof(Vec::new()).unwrap().is_none());
+    assert_eq!(reader.snapshot().unwrap().offset, data.len() as u64);
+}
+
+#[test]
+fn verify_read_next_interleaved_or_eof() {
+    // A left-side stereo frame with wasted bits, like the one above, followed
+    // by a frame with independent channels.
+    let left: Vec<i32> = (0..16).map(|i| (i * 37 % 64 - 32) * 4).collect();
+    let right: Vec<i32> = (0..16).map(|i| left[i as usize] - (i - 8) * 8).collect();
+    let mut bytes = vec![0xff, 0xf8, 0b0110_1001, 0b1000_1000, 0x00, 15];
+    let header_crc = crc::crc8(&bytes);
+    bytes.push(header_crc);
+    let mut writer = BitWriter::new(bytes);
+    writer.write_bits(0b0_000001_1, 8);
+    writer.write_bits(0b01, 2);
+    for &l in &left {
+        writer.write_bits((l >> 2) as u32, 14);
+    }
+    writer.write_bits(0b0_000001_1, 8);
+    writer.write_bits(0b001, 3);
+    for (&l, &r) in left.iter().zip(&right) {
+        writer.write_bits(((l - r) >> 3) as u32, 14);
+    }
+    let mut data = writer.into_bytes();
+    let frame_crc = crc::crc16(&data);
+    data.push((frame_crc >> 8) as u8);
+    data.push(frame_crc as u8);
+    let spec = FrameSpec {
+        variable_block_size: false,
+        number: 1,
+        sample_rate: None,
+        bits_per_sample: 16,
+    };
+    write_frame(&spec, &[&right, &left], &mut data).unwrap();
+
+    let selections = [ChannelSelection::All,
+                      ChannelSelection::Mask(0b10),
+                      ChannelSelection::Stereo];
+    for &selection in &selections {
+        for &ordering in &[FrameOrdering::Error, FrameOrdering::Reorder(1)] {
+            let mut planar = FrameReader::new(io::Cursor::new(&data[..]));
+            let mut interleaved = FrameReader::new(io::Cursor::new(&data[..]));
+            planar.set_channels(selection);
+            interleaved.set_channels(selection);
+            interleaved.set_ordering(ordering);
+
+            let mut buffer = Vec::new();
+            while let Some(block) = planar.read_next_or_eof(Vec::new()).unwrap() {
+                let iblock = interleaved.read_next_interleaved_or_eof(buffer).unwrap().unwrap();
+                assert_eq!(iblock.time(), block.time());
+                assert_eq!(iblock.duration(), block.duration());
+                assert_eq!(iblock.channels(), block.channels());
+                assert_eq!(iblock.bits_per_sample(), block.bits_per_sample());
+                let expected: Vec<i32> = (0..block.duration())
+                    .flat_map(|i| (0..block.channels()).map(move |ch| (ch, i)))
+                    .map(|(ch, i)| block.sample(ch, i))
+                    .collect();
+                assert_eq!(iblock.samples(), &expected[..]);
+                buffer = iblock.into_buffer();
+            }
+            assert!(interleaved.read_next_interleaved_or_eof(buffer).unwrap().is_none());
+            assert!(!interleaved.take_eof_buffer().is_empty());
+        }
+    }
+}
+
+#[test]
+fn verify_read_next_interleaved_keeps_buffer_on_order_error() {
+    // A variable block size stream where the second frame goes back in time.
+    let mut data = Vec::new();
+    for &start in &[20, 10] {
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start,
+            sample_rate: Some(44_100),
+            bits_per_sample: 16,
+        };
+        let samples: Vec<i32> = (0..10).map(|i| (start + i) as i32).collect();
+        write_frame(&spec, &[&samples], &mut data).unwrap();
+    }
+
+    let mut reader = FrameReader::new(io::Cursor::new(&data[..]));
+    let block = reader.read_next_interleaved_or_eof(Vec::with_capacity(1000)).unwrap().unwrap();
+    assert_eq!(block.time(), 20);
+    assert!(reader.read_next_interleaved_or_eof(block.into_buffer()).is_err());
+
+    // The buffer that was passed in can be reused.
+    assert!(reader.take_eof_buffer().capacity() >= 1000);
+}
+
+/// Rewrites the header of an encoded frame to make it part of a variable block size stream.
+///
+/// `frame` must contain exactly one frame, including its CRC-16 footer. The
+/// frame or sample number in the header is replaced with `sample_number`, and
+/// the blocking strategy bit is set, so a frame can be moved to a different
+/// position in a new stream without decoding it. The other header fields and
+/// the subframes are kept as-is, and both CRCs are recomputed. The CRCs of the
+/// input are not checked.
+///
+/// Returns a format error if the frame header is malformed or the frame is
+/// truncated.
+///
+/// # Panics
+///
+/// Panics if `sample_number` does not fit in 36 bits.
+pub fn renumber_frame(frame: &[u8], sample_number: u64) -> Result<Vec<u8>> {
+    assert!(sample_number < 1 << 36, "sample number must fit in 36 bits");
+
+    if frame.len() < 4 || frame[0] != 0xff || frame[1] & 0b1111_1110 != 0b1111_1000 {
+        return fmt_err("frame sync code missing");
+    }
+
+    // The length of the coded number follows from the leading ones of its
+    // first byte, see `read_var_length_int()`.
+    let num_len = match frame.get(4) {
+        Some(&b) if b & 0b1000_0000 == 0 => 1,
+        Some(&b) if b != 0xff && (!b).leading_zeros() >= 2 => (!b).leading_zeros() as usize,
+        _ => return fmt_err("invalid variable-length integer"),
+    };
+
+    // The block size and sample rate codes determine how many bytes follow the
+    // number, before the CRC-8.
+    let extra_len = match frame[2] >> 4 {
+        0b0110 => 1,
+        0b0111 => 2,
+        _ => 0,
+    } + match frame[2] & 0b1111 {
+        0b1100 => 1,
+        0b1101 | 0b1110 => 2,
+        _ => 0,
+    };
+
+    let header_end = 4 + num_len + extra_len;
+    if frame.len() < header_end + 1 + 2 {
+        return fmt_err("frame is truncated");
+    }
+
+    let mut bytes = Vec::with_capacity(frame.len() + 6);
+    bytes.push(0xff);
+    bytes.push(0b1111_1001);
+    bytes.extend_from_slice(&frame[2..4]);
+    write_var_length_int(sample_number, &mut bytes);
+    bytes.extend_from_slice(&frame[4 + num_len..header_end]);
+    let header_crc = crc::crc8(&bytes);
+    bytes.push(header_crc);
+
+    // Skip the old CRC-8, and replace the CRC-16 at the end.
+    bytes.extend_from_slice(&frame[header_end + 1..frame.len() - 2]);
+    let frame_crc = crc::crc16(&bytes);
+    bytes.push((frame_crc >> 8) as u8);
+    bytes.push(frame_crc as u8);
+
+    Ok(bytes)
+}
+
+#[test]
+fn verify_renumber_frame() {
+    let samples: Vec<i32> = (0..300).map(|i| (i * 37) % 201 - 100).collect();
+    let spec = FrameSpec {
+        variable_block_size: false,
+        number: 7,
+        sample_rate: Some(200),
+        bits_per_sample: 16,
+    };
+    let mut data = Vec::new();
+    write_frame(&spec, &[&samples], &mut data).unwrap();
+
+    // A number that needs more bytes than the original one.
+    for &number in &[0, 0x7ff, 0xf_ffff_ffff] {
+        let renumbered = renumber_frame(&data, number).unwrap();
+        let mut reader = FrameReader::new(io::Cursor::new(renumbered));
+        let block = reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+        assert_eq!(block.time(), number);
+        assert_eq!(block.frame_number(), None);
+        assert_eq!(block.sample_rate(), Some(200));
+        assert_eq!(block.channel(0), &samples[..]);
+    }
+
+    assert!(renumber_frame(&data[..8], 0).is_err());
+    assert!(renumber_frame(&data[1..], 0).is_err());
+}
+
+#[test]
+fn verify_frame_header_sample_rate_zero() {
+    // A frame with a block size of 576, frame number 0, and a 16-bit sample
+    // rate, which is at bytes 5 and 6. Set it to zero and fix the header CRC.
+    let spec = FrameSpec {
+        variable_block_size: false,
+        number: 0,
+        sample_rate: Some(200),
+        bits_per_sample: 16,
+    };
+    let mut data = Vec::new();
+    write_frame(&spec, &[&[0; 576]], &mut data).unwrap();
+    data[5] = 0;
+    data[6] = 0;
+    data[7] = crc::crc8(&data[..7]);
+
+    let mut reader = FrameReader::new(io::Cursor::new(data));
+    assert_eq!(reader.read_next_or_eof(Vec::new()).err().unwrap(),
+               Error::FormatError("invalid frame header, sample rate is zero"));
+}
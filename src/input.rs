@@ -13,6 +13,8 @@
 //! internally to read the bitstream.
 
 use std::cmp;
+use std::error;
+use std::fmt;
 use std::io;
 
 /// Similar to `std::io::BufRead`, but more performant.
@@ -33,6 +35,12 @@ pub struct BufferedReader<R: io::Read> {
 
     /// The number of bytes of the buffer which have meaningful content.
     num_valid: u32,
+
+    /// The total number of bytes read from the inner reader so far.
+    num_read: u64,
+
+    /// The number of bytes that may be read from the inner reader in total.
+    bound: u64,
 }
 
 impl<R: io::Read> BufferedReader<R> {
@@ -49,13 +57,129 @@ impl<R: io::Read> BufferedReader<R> {
         #[cfg(fuzzing)]
         const CAPACITY: usize = 31;
 
-        let buf = vec![0; CAPACITY].into_boxed_slice();
+        BufferedReader::with_capacity(inner, CAPACITY)
+    }
+
+    /// Wrap the reader in a new buffered reader with a buffer of the given size.
+    ///
+    /// The default buffer of `new()` is 2048 bytes. When the input is known to
+    /// be smaller than that, for instance when only the streaminfo of many
+    /// small files is read, a smaller buffer saves memory. A larger buffer
+    /// reduces the number of reads from the inner reader.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn with_capacity(inner: R, capacity: usize) -> BufferedReader<R> {
+        BufferedReader::with_buffer(inner, vec![0; capacity])
+    }
+
+    /// Wrap the reader in a new buffered reader that uses the given buffer.
+    ///
+    /// The buffer is used up to its capacity, its contents are irrelevant.
+    /// Together with `into_parts()`, this allows reusing one allocation for
+    /// many inputs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity of `buffer` is 0.
+    pub fn with_buffer(inner: R, buffer: Vec<u8>) -> BufferedReader<R> {
+        let mut buffer = buffer;
+        assert!(buffer.capacity() > 0, "buffer capacity must be at least 1 byte");
+        // Fill up to the capacity, so converting into a boxed slice does not
+        // need to reallocate.
+        let capacity = buffer.capacity();
+        buffer.resize(capacity, 0);
+        let buf = buffer.into_boxed_slice();
         BufferedReader {
             inner: inner,
             buf: buf,
             pos: 0,
             num_valid: 0,
+            num_read: 0,
+            bound: u64::MAX,
+        }
+    }
+
+    /// Limits the number of bytes read from the inner reader to `bound`.
+    ///
+    /// The bound is relative to where the inner reader was positioned when the
+    /// buffered reader was constructed, like `position()`. Nothing past it is
+    /// ever read from the inner reader. Reading up to the bound ends the input
+    /// like the inner reader reaching its end would, but a read that needs
+    /// bytes past the bound fails with an error of kind `UnexpectedEof`,
+    /// that converts into `Error::BoundExceeded`.
+    pub fn set_bound(&mut self, bound: u64) {
+        self.bound = bound;
+    }
+
+    /// Returns the bound set with `set_bound()`, if any.
+    pub fn bound(&self) -> Option<u64> {
+        if self.bound == u64::MAX { None } else { Some(self.bound) }
+    }
+
+    /// Returns the number of bytes consumed since the reader was constructed.
+    ///
+    /// This is the position in the stream, relative to where the inner reader
+    /// was positioned when the buffered reader was constructed. Bytes that have
+    /// been read into the buffer but not consumed yet are not counted.
+    pub fn position(&self) -> u64 {
+        self.num_read - (self.num_valid - self.pos) as u64
+    }
+
+    /// Returns the size of the buffer, the maximum number of bytes to peek at.
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns the bytes in the buffer that have not been consumed yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos as usize..self.num_valid as usize]
+    }
+
+    /// Returns the next `n` bytes without consuming them.
+    ///
+    /// Fewer bytes are returned if the stream ends before. The number of bytes
+    /// that can be peeked at is limited by the size of the buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` exceeds the size of the buffer.
+    pub fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+        assert!(n <= self.buf.len(), "cannot peek beyond the buffer size");
+
+        if ((self.num_valid - self.pos) as usize) < n {
+            // Move the unconsumed bytes to the front of the buffer, to make
+            // room for more after them.
+            let len = (self.num_valid - self.pos) as usize;
+            for i in 0..len {
+                self.buf[i] = self.buf[self.pos as usize + i];
+            }
+            self.pos = 0;
+            self.num_valid = len as u32;
+
+            while (self.num_valid as usize) < n {
+                let end = self.limit_read(self.buf.len());
+                let num_new = try!(self.inner.read(&mut self.buf[self.num_valid as usize..end]));
+                if num_new == 0 {
+                    break
+                }
+                self.num_valid += num_new as u32;
+                self.num_read += num_new as u64;
+            }
         }
+
+        let end = cmp::min(self.pos as usize + n, self.num_valid as usize);
+        Ok(&self.buf[self.pos as usize..end])
+    }
+
+    /// Returns a mutable reference to the wrapped reader.
+    ///
+    /// Reading from the wrapped reader directly interferes with the buffering.
+    /// It is only safe to use the reader in a way that leaves its position
+    /// unchanged, for example to seek and afterwards seek back.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
     }
 
     /// Destroys the buffered reader, returning the wrapped reader.
@@ -64,9 +188,70 @@ impl<R: io::Read> BufferedReader<R> {
     pub fn into_inner(self) -> R {
         self.inner
     }
+
+    /// Destroys the buffered reader, returning the wrapped reader and the buffer.
+    ///
+    /// Anything in the buffer will be lost.
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        (self.inner, self.buf.into_vec())
+    }
+
+    /// Replaces the buffer contents with fresh bytes from the inner reader.
+    ///
+    /// Returns the number of bytes read, which is 0 at the end of the input.
+    /// This happens once per buffer, so it is kept out of line, which keeps
+    /// the inlined fast path of `read_u8()` small.
+    #[cold]
+    #[inline(never)]
+    fn refill(&mut self) -> io::Result<u32> {
+        self.pos = 0;
+        self.num_valid = 0;
+        let end = self.limit_read(self.buf.len());
+        self.num_valid = try!(self.inner.read(&mut self.buf[..end])) as u32;
+        self.num_read += self.num_valid as u64;
+        Ok(self.num_valid)
+    }
+
+    /// Returns the end of the buffer to read into, such that the read stops at the bound.
+    ///
+    /// `end` is the end of the buffer without a bound, the read starts at
+    /// `num_valid`.
+    fn limit_read(&self, end: usize) -> usize {
+        let left = self.bound.saturating_sub(self.num_read);
+        cmp::min(end as u64, self.num_valid as u64 + left) as usize
+    }
+
+    /// Builds the error for running out of input, which depends on the bound.
+    #[cold]
+    fn eof_error(&self, msg: &'static str) -> io::Error {
+        if self.num_read >= self.bound {
+            io::Error::new(io::ErrorKind::UnexpectedEof, BoundExceeded { bound: self.bound })
+        } else {
+            io::Error::new(io::ErrorKind::UnexpectedEof, msg)
+        }
+    }
 }
 
 
+/// The payload of the `io::Error` for reading past the bound of a `BufferedReader`.
+#[derive(Debug)]
+pub struct BoundExceeded {
+    /// The bound, the number of bytes that may be read.
+    pub bound: u64,
+}
+
+impl fmt::Display for BoundExceeded {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Expected more bytes than the bound of {} bytes.", self.bound)
+    }
+}
+
+impl error::Error for BoundExceeded {
+    fn description(&self) -> &str {
+        "read past the bound of the stream"
+    }
+}
+
 /// Provides convenience methods to make input less cumbersome.
 pub trait ReadBytes {
     /// Reads a single byte, failing on EOF.
@@ -117,6 +302,13 @@ pub trait ReadBytes {
         Ok(b0 << 24 | b1 << 16 | b2 << 8 | b3)
     }
 
+    /// Reads eight bytes and interprets them as a big-endian 64-bit unsigned integer.
+    fn read_be_u64(&mut self) -> io::Result<u64> {
+        let hi = try!(self.read_be_u32()) as u64;
+        let lo = try!(self.read_be_u32()) as u64;
+        Ok(hi << 32 | lo)
+    }
+
     /// Reads four bytes and interprets them as a little-endian 32-bit unsigned integer.
     fn read_le_u32(&mut self) -> io::Result<u32> {
         let b0 = try!(self.read_u8()) as u32;
@@ -133,19 +325,19 @@ impl<R: io::Read> ReadBytes for BufferedReader<R>
     fn read_u8(&mut self) -> io::Result<u8> {
         if self.pos == self.num_valid {
             // The buffer was depleted, replenish it first.
-            self.pos = 0;
-            self.num_valid = try!(self.inner.read(&mut self.buf)) as u32;
-
-            if self.num_valid == 0 {
-                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                          "Expected one more byte."))
+            if try!(self.refill()) == 0 {
+                return Err(self.eof_error("Expected one more byte."))
             }
         }
 
         // At this point there is at least one more byte in the buffer, we
         // checked that above. However, when using regular indexing, the
-        // compiler still inserts a bounds check here. It is safe to avoid it.
+        // compiler still inserts a bounds check here. It is safe to avoid it,
+        // unless unsafe code is ruled out.
+        #[cfg(not(feature = "forbid-unsafe"))]
         let byte = unsafe { *self.buf.get_unchecked(self.pos as usize) };
+        #[cfg(feature = "forbid-unsafe")]
+        let byte = self.buf[self.pos as usize];
         self.pos += 1;
         Ok(byte)
     }
@@ -153,10 +345,7 @@ impl<R: io::Read> ReadBytes for BufferedReader<R>
     fn read_u8_or_eof(&mut self) -> io::Result<Option<u8>> {
         if self.pos == self.num_valid {
             // The buffer was depleted, try to replenish it first.
-            self.pos = 0;
-            self.num_valid = try!(self.inner.read(&mut self.buf)) as u32;
-
-            if self.num_valid == 0 {
+            if try!(self.refill()) == 0 {
                 return Ok(None);
             }
         }
@@ -177,11 +366,8 @@ impl<R: io::Read> ReadBytes for BufferedReader<R>
 
             if bytes_left > 0 {
                 // Replenish the buffer if there is more to be read.
-                self.pos = 0;
-                self.num_valid = try!(self.inner.read(&mut self.buf)) as u32;
-                if self.num_valid == 0 {
-                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                              "Expected more bytes."))
+                if try!(self.refill()) == 0 {
+                    return Err(self.eof_error("Expected more bytes."))
                 }
             }
         }
@@ -198,12 +384,8 @@ impl<R: io::Read> ReadBytes for BufferedReader<R>
 
             if amount > 0 {
                 // If there is more to skip, refill the buffer first.
-                self.pos = 0;
-                self.num_valid = try!(self.inner.read(&mut self.buf)) as u32;
-
-                if self.num_valid == 0 {
-                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
-                                              "Expected more bytes."))
+                if try!(self.refill()) == 0 {
+                    return Err(self.eof_error("Expected more bytes."))
                 }
             }
         }
@@ -231,15 +413,22 @@ impl<'r, R: ReadBytes> ReadBytes for &'r mut R {
     }
 }
 
+/// Builds the error for reading past the end of a cursor.
+#[cold]
+fn cursor_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected eof")
+}
+
 impl<T: AsRef<[u8]>> ReadBytes for io::Cursor<T> {
 
+    #[inline(always)]
     fn read_u8(&mut self) -> io::Result<u8> {
         let pos = self.position();
         if pos < self.get_ref().as_ref().len() as u64 {
             self.set_position(pos + 1);
             Ok(self.get_ref().as_ref()[pos as usize])
         } else {
-            Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected eof"))
+            Err(cursor_eof())
         }
     }
 
@@ -262,7 +451,7 @@ impl<T: AsRef<[u8]>> ReadBytes for io::Cursor<T> {
             self.set_position(pos + buffer.len() as u64);
             Ok(())
         } else {
-            Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected eof"))
+            Err(cursor_eof())
         }
     }
 
@@ -272,11 +461,252 @@ impl<T: AsRef<[u8]>> ReadBytes for io::Cursor<T> {
             self.set_position(pos + amount as u64);
             Ok(())
         } else {
-            Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected eof"))
+            Err(cursor_eof())
         }
     }
 }
 
+/// A `ReadBytes` that can return to a position it was at before.
+///
+/// Positions are only meaningful to the reader that returned them. For a
+/// `BufferedReader`, they are relative to where the inner reader was when the
+/// buffered reader was constructed, see `BufferedReader::position()`.
+pub trait SeekBytes: ReadBytes {
+    /// Returns the position of the next byte to be read.
+    fn stream_position(&self) -> u64;
+
+    /// Continues reading at a position returned by `stream_position()`.
+    fn seek_to(&mut self, pos: u64) -> io::Result<()>;
+}
+
+impl<R: io::Read + io::Seek> BufferedReader<R> {
+    /// Returns the length of the input, relative to where the reader started.
+    ///
+    /// The length is capped at the bound, if any. The position of the inner
+    /// reader is restored afterwards.
+    pub fn stream_len(&mut self) -> io::Result<u64> {
+        let inner_pos = try!(self.inner.seek(io::SeekFrom::Current(0)));
+        let end = try!(self.inner.seek(io::SeekFrom::End(0)));
+        try!(self.inner.seek(io::SeekFrom::Start(inner_pos)));
+        Ok(cmp::min(end - inner_pos + self.num_read, self.bound))
+    }
+}
+
+impl<R: io::Read + io::Seek> SeekBytes for BufferedReader<R> {
+    fn stream_position(&self) -> u64 {
+        self.position()
+    }
+
+    fn seek_to(&mut self, pos: u64) -> io::Result<()> {
+        // The inner reader is at `num_read`, seek relative to that, so the
+        // position remains relative to where we started. Anything in the
+        // buffer is discarded.
+        let delta = pos as i64 - self.num_read as i64;
+        try!(self.inner.seek(io::SeekFrom::Current(delta)));
+        self.pos = 0;
+        self.num_valid = 0;
+        self.num_read = pos;
+        Ok(())
+    }
+}
+
+impl<'r, R: SeekBytes> SeekBytes for &'r mut R {
+    fn stream_position(&self) -> u64 {
+        (**self).stream_position()
+    }
+
+    fn seek_to(&mut self, pos: u64) -> io::Result<()> {
+        (*self).seek_to(pos)
+    }
+}
+
+impl<T: AsRef<[u8]>> SeekBytes for io::Cursor<T> {
+    fn stream_position(&self) -> u64 {
+        self.position()
+    }
+
+    fn seek_to(&mut self, pos: u64) -> io::Result<()> {
+        self.set_position(pos);
+        Ok(())
+    }
+}
+
+/// A `ReadBytes` that holds all of its remaining input in memory.
+///
+/// Such a reader can lend the bytes that follow as a slice, for instance when
+/// the input is a memory-mapped file or a file read into a buffer entirely.
+/// `FrameReader::read_next_or_eof_sliced()` decodes frames from this slice,
+/// which avoids the per-byte overhead of reading through `ReadBytes`.
+pub trait SliceProvider: ReadBytes {
+    /// Returns all bytes from the current position up to the end of the input.
+    fn remaining_slice(&self) -> &[u8];
+
+    /// Advances the position by `amount` bytes.
+    ///
+    /// The amount must not exceed the length of `remaining_slice()`.
+    fn consume(&mut self, amount: usize);
+}
+
+impl<'r, R: SliceProvider> SliceProvider for &'r mut R {
+    fn remaining_slice(&self) -> &[u8] {
+        (**self).remaining_slice()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        (*self).consume(amount)
+    }
+}
+
+impl<T: AsRef<[u8]>> SliceProvider for io::Cursor<T> {
+    fn remaining_slice(&self) -> &[u8] {
+        let data = self.get_ref().as_ref();
+        let pos = cmp::min(self.position(), data.len() as u64) as usize;
+        &data[pos..]
+    }
+
+    fn consume(&mut self, amount: usize) {
+        debug_assert!(amount <= self.remaining_slice().len());
+        let pos = self.position();
+        self.set_position(pos + amount as u64);
+    }
+}
+
+/// A `ReadBytes` that can look at the bytes that follow without consuming them.
+pub trait PeekBytes: ReadBytes {
+    /// Returns the next `n` bytes without consuming them.
+    ///
+    /// Fewer bytes are returned if the input ends before, or if the reader
+    /// cannot look that far ahead.
+    fn peek_bytes(&mut self, n: usize) -> io::Result<&[u8]>;
+}
+
+impl<R: io::Read> PeekBytes for BufferedReader<R> {
+    fn peek_bytes(&mut self, n: usize) -> io::Result<&[u8]> {
+        // A buffered reader can peek at most one buffer ahead.
+        let n = cmp::min(n, self.capacity());
+        self.peek(n)
+    }
+}
+
+impl<'r, R: PeekBytes> PeekBytes for &'r mut R {
+    fn peek_bytes(&mut self, n: usize) -> io::Result<&[u8]> {
+        (**self).peek_bytes(n)
+    }
+}
+
+impl<T: AsRef<[u8]>> PeekBytes for io::Cursor<T> {
+    fn peek_bytes(&mut self, n: usize) -> io::Result<&[u8]> {
+        let remaining = SliceProvider::remaining_slice(&*self);
+        Ok(&remaining[..cmp::min(n, remaining.len())])
+    }
+}
+
+/// A reader that reads exactly the bytes that are consumed, without buffering.
+///
+/// Unlike `BufferedReader`, this never reads from the inner reader beyond the
+/// last byte consumed, so the inner reader can be used for something else
+/// afterwards. Every byte is a separate call to `read()` on the inner reader,
+/// so to avoid a system call per byte, the inner reader should be buffered
+/// itself, for example by passing a `&mut io::BufReader`.
+pub struct UnbufferedReader<R: io::Read> {
+    inner: R,
+    num_read: u64,
+}
+
+impl<R: io::Read> UnbufferedReader<R> {
+    /// Wraps the reader.
+    pub fn new(inner: R) -> UnbufferedReader<R> {
+        UnbufferedReader {
+            inner: inner,
+            num_read: 0,
+        }
+    }
+
+    /// Returns the number of bytes consumed since the reader was constructed.
+    pub fn position(&self) -> u64 {
+        self.num_read
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: io::Read> ReadBytes for UnbufferedReader<R> {
+    fn read_u8(&mut self) -> io::Result<u8> {
+        match try!(self.read_u8_or_eof()) {
+            Some(byte) => Ok(byte),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Expected one more byte.")),
+        }
+    }
+
+    fn read_u8_or_eof(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        loop {
+            match self.inner.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(..) => {
+                    self.num_read += 1;
+                    return Ok(Some(byte[0]))
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn read_into(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        let mut from = 0;
+        while from < buffer.len() {
+            match self.inner.read(&mut buffer[from..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                              "Expected more bytes."))
+                }
+                Ok(n) => {
+                    from += n;
+                    self.num_read += n as u64;
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, amount: u32) -> io::Result<()> {
+        let mut limited = io::Read::take(&mut self.inner, amount as u64);
+        let skipped = try!(io::copy(&mut limited, &mut io::sink()));
+        self.num_read += skipped;
+        if skipped < amount as u64 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Expected more bytes."))
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn verify_unbuffered_reader() {
+    let data = [2u8, 3, 5, 7, 11, 13, 17, 19, 23];
+    let mut inner = &data[..];
+    {
+        let mut reader = UnbufferedReader::new(&mut inner);
+        let mut buf = [0u8; 3];
+        assert_eq!(reader.read_u8().unwrap(), 2);
+        reader.read_into(&mut buf).unwrap();
+        assert_eq!(buf, [3, 5, 7]);
+        reader.skip(2).unwrap();
+        assert_eq!(reader.position(), 6);
+    }
+    // Nothing beyond the consumed bytes must have been read.
+    assert_eq!(inner, &[17, 19, 23]);
+
+    let mut reader = UnbufferedReader::new(&data[7..]);
+    assert!(reader.skip(3).is_err());
+    assert_eq!(reader.read_u8_or_eof().unwrap(), None);
+}
+
 #[test]
 fn verify_read_into_buffered_reader() {
     let mut reader = BufferedReader::new(io::Cursor::new(vec![2u8, 3, 5, 7, 11, 13, 17, 19, 23]));
@@ -290,6 +720,112 @@ fn verify_read_into_buffered_reader() {
     assert_eq!(&buf2[..], &[7u8, 11, 13, 17, 19]);
 }
 
+#[test]
+fn verify_buffered_reader_position() {
+    let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+    let mut reader = BufferedReader::new(io::Cursor::new(data));
+    assert_eq!(reader.position(), 0);
+    reader.read_u8().unwrap();
+    assert_eq!(reader.position(), 1);
+    assert_eq!(reader.buffer()[0], 1);
+    reader.skip(2500).unwrap();
+    assert_eq!(reader.position(), 2501);
+    let mut buf = [0u8; 2000];
+    reader.read_into(&mut buf).unwrap();
+    assert_eq!(reader.position(), 4501);
+    assert_eq!(buf[0], (2501 % 256) as u8);
+    reader.skip(499).unwrap();
+    assert_eq!(reader.read_u8_or_eof().unwrap(), None);
+    assert_eq!(reader.position(), 5000);
+}
+
+#[test]
+fn verify_buffered_reader_seek_to() {
+    let data: Vec<u8> = (0..100).collect();
+    // Start at an offset, positions are relative to it.
+    let mut cursor = io::Cursor::new(&data[..]);
+    cursor.set_position(10);
+    let mut reader = BufferedReader::with_capacity(cursor, 16);
+    reader.skip(5).unwrap();
+    let pos = reader.stream_position();
+    assert_eq!(pos, 5);
+    assert_eq!(reader.read_u8().unwrap(), 15);
+
+    // Seek back into the buffer, and beyond it.
+    reader.skip(40).unwrap();
+    reader.seek_to(pos).unwrap();
+    assert_eq!(reader.position(), 5);
+    assert_eq!(reader.read_u8().unwrap(), 15);
+    reader.seek_to(80).unwrap();
+    assert_eq!(reader.read_u8().unwrap(), 90);
+    assert_eq!(reader.stream_position(), 81);
+}
+
+#[test]
+fn verify_buffered_reader_with_buffer_reuses_allocation() {
+    let data = [2u8, 3, 5, 7, 11, 13, 17, 19, 23];
+    let buffer = Vec::with_capacity(4);
+    let ptr = buffer.as_ptr();
+    let mut reader = BufferedReader::with_buffer(io::Cursor::new(&data[..]), buffer);
+    assert_eq!(reader.read_u8().unwrap(), 2);
+    assert_eq!(reader.peek(4).unwrap(), &[3, 5, 7, 11]);
+    let (_, buffer) = reader.into_parts();
+    assert_eq!(buffer.as_ptr(), ptr);
+    assert_eq!(buffer.len(), 4);
+}
+
+#[test]
+fn verify_buffered_reader_stops_at_bound() {
+    let data = [2u8, 3, 5, 7, 11, 13, 17, 19, 23];
+    let mut reader = BufferedReader::with_capacity(io::Cursor::new(&data[..]), 4);
+    reader.set_bound(6);
+    assert_eq!(reader.bound(), Some(6));
+    assert_eq!(reader.peek(4).unwrap(), &[2, 3, 5, 7]);
+    reader.skip(3).unwrap();
+    assert_eq!(reader.peek(4).unwrap(), &[7, 11, 13]);
+    let mut buf = [0u8; 3];
+    reader.read_into(&mut buf).unwrap();
+    assert_eq!(reader.read_u8_or_eof().unwrap(), None);
+    let err = reader.read_u8().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(err.get_ref().unwrap().is::<BoundExceeded>());
+    assert_eq!(reader.into_inner().position(), 6);
+}
+
+#[test]
+fn verify_buffered_reader_with_capacity() {
+    // With a tiny buffer, reads span many refills.
+    let data: Vec<u8> = (0..100).collect();
+    let mut reader = BufferedReader::with_capacity(io::Cursor::new(&data[..]), 3);
+    let mut buffer = [0u8; 50];
+    reader.read_into(&mut buffer).unwrap();
+    assert_eq!(&buffer[..], &data[..50]);
+    reader.skip(10).unwrap();
+    assert_eq!(reader.read_u8().unwrap(), 60);
+    assert_eq!(reader.position(), 61);
+    assert_eq!(reader.buffer().len(), 2);
+}
+
+#[test]
+fn verify_buffered_reader_peek() {
+    let data: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+    let mut reader = BufferedReader::new(io::Cursor::new(data));
+    assert_eq!(reader.peek(3).unwrap(), &[0, 1, 2]);
+    assert_eq!(reader.position(), 0);
+    assert_eq!(reader.read_u8().unwrap(), 0);
+
+    // Peeking across the end of the buffer moves the remainder to the front.
+    reader.skip(2040).unwrap();
+    assert_eq!(reader.peek(16).unwrap().len(), 16);
+    assert_eq!(reader.position(), 2041);
+    assert_eq!(reader.read_u8().unwrap(), (2041 % 256) as u8);
+
+    // Near the end of the stream, fewer bytes are available.
+    reader.skip(950).unwrap();
+    assert_eq!(reader.peek(16).unwrap(), &[176, 177, 178, 179, 180, 181, 182, 183]);
+    assert_eq!(reader.position(), 2992);
+}
+
 #[test]
 fn verify_read_into_cursor() {
     let mut cursor = io::Cursor::new(vec![2u8, 3, 5, 7, 11, 13, 17, 19, 23]);
@@ -375,6 +911,13 @@ fn verify_read_be_u32_cursor() {
     assert!(cursor.read_be_u32().is_err());
 }
 
+#[test]
+fn verify_read_be_u64_cursor() {
+    let mut cursor = io::Cursor::new(vec![0u8, 0, 0, 2, 0x80, 0x01, 0xff, 0xe9, 0]);
+    assert_eq!(cursor.read_be_u64().ok(), Some(0x2_8001_ffe9));
+    assert!(cursor.read_be_u64().is_err());
+}
+
 #[test]
 fn verify_read_le_u32_buffered_reader() {
     let mut reader = BufferedReader::new(io::Cursor::new(vec![2u8, 0, 0, 0, 0xe9, 0xff, 0x01, 0x80, 0]));
@@ -419,6 +962,8 @@ pub struct Bitstream<R: ReadBytes> {
     data: u8,
     /// The number of bits of `data` that have not been consumed.
     bits_left: u32,
+    /// The longest unary run that `read_unary()` reads in full.
+    max_unary: u32,
 }
 
 impl<R: ReadBytes> Bitstream<R> {
@@ -428,9 +973,35 @@ impl<R: ReadBytes> Bitstream<R> {
             reader: reader,
             data: 0,
             bits_left: 0,
+            max_unary: u32::max_value(),
         }
     }
 
+    /// Limits the length of unary runs read by `read_unary()`.
+    ///
+    /// Once a run is known to be longer than `max`, `read_unary()` stops
+    /// reading, and returns a value greater than `max`. The position of the
+    /// bitstream is then somewhere in the middle of the run. This bounds the
+    /// amount of input consumed for a single value in ill-formed streams.
+    pub fn set_max_unary(&mut self, max: u32) {
+        self.max_unary = max;
+    }
+
+    /// Returns a mutable reference to the wrapped reader.
+    ///
+    /// When the bitstream is at a byte boundary, the reader is positioned at
+    /// the next bit. Reading from the reader directly is only safe there,
+    /// otherwise it interferes with the bits that were read already.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Returns the limit set with `set_max_unary()`, 2<sup>32</sup> - 1 by default.
+    #[inline(always)]
+    pub fn max_unary(&self) -> u32 {
+        self.max_unary
+    }
+
     /// Generates a bitmask with 1s in the `bits` most significant bits.
     #[inline(always)]
     fn mask_u8(bits: u32) -> u8 {
@@ -497,13 +1068,19 @@ impl<R: ReadBytes> Bitstream<R> {
             loop {
                 let fresh_byte = try!(self.reader.read_u8());
                 let zeros = fresh_byte.leading_zeros();
-                n = n + zeros;
+                n = n.saturating_add(zeros);
                 if zeros < 8 {
                     // We consumed the zeros, plus the one following it.
                     self.bits_left = 8 - (zeros + 1);
                     self.data = shift_left(fresh_byte, zeros + 1);
                     break;
                 }
+                if n > self.max_unary {
+                    // The run is too long, the caller will reject it.
+                    self.bits_left = 0;
+                    self.data = 0;
+                    break;
+                }
             }
         }
 
@@ -642,6 +1219,56 @@ impl<R: ReadBytes> Bitstream<R> {
     }
 }
 
+impl<R: PeekBytes> Bitstream<R> {
+    /// Returns the next `bits` bits without consuming them.
+    ///
+    /// Returns `None` if the input ends before. This lets a scanner test
+    /// whether a pattern occurs at the current position, for instance a frame
+    /// sync code, without having to undo the read when it does not.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` is more than 32.
+    pub fn peek_bits(&mut self, bits: u32) -> io::Result<Option<u32>> {
+        assert!(bits <= 32, "cannot peek more than 32 bits");
+
+        // Start with the bits left in the buffer byte, and append whole bytes
+        // from the reader until there are enough.
+        let mut acc = shift_right(self.data, 8 - self.bits_left) as u64;
+        let mut num_bits = self.bits_left;
+        if num_bits < bits {
+            let num_bytes = ((bits - num_bits + 7) / 8) as usize;
+            let bytes = try!(self.reader.peek_bytes(num_bytes));
+            if bytes.len() < num_bytes {
+                return Ok(None)
+            }
+            for &byte in bytes {
+                acc = acc << 8 | byte as u64;
+            }
+            num_bits += 8 * num_bytes as u32;
+        }
+
+        let mask = (1u64 << bits) - 1;
+        Ok(Some(((acc >> (num_bits - bits)) & mask) as u32))
+    }
+
+    /// Consumes the next `bits` bits if they are equal to `pattern`.
+    ///
+    /// Returns whether the bits matched. If they did not, or if the input ends
+    /// before, nothing is consumed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` is more than 32.
+    pub fn try_match(&mut self, pattern: u32, bits: u32) -> io::Result<bool> {
+        if try!(self.peek_bits(bits)) != Some(pattern) {
+            return Ok(false)
+        }
+        try!(self.read_leq_u32(bits));
+        Ok(true)
+    }
+}
+
 #[test]
 fn verify_read_bit() {
     let data = io::Cursor::new(vec![0b1010_0100, 0b1110_0001]);
@@ -668,6 +1295,43 @@ fn verify_read_bit() {
     assert!(bits.read_bit().is_err());
 }
 
+#[test]
+fn verify_read_unary_stops_at_max() {
+    let data = io::Cursor::new(vec![0b0000_0000, 0b0000_0000, 0b0000_0000, 0b0000_0001]);
+    let mut bits = Bitstream::new(BufferedReader::new(data));
+    bits.set_max_unary(10);
+    // The run is 31 zeros long, but reading stops after 16 zeros.
+    assert_eq!(bits.read_unary().unwrap(), 16);
+}
+
+#[test]
+fn verify_peek_bits_and_try_match() {
+    let data = io::Cursor::new(vec![0b1111_1111, 0b1111_1000, 0b0110_1001, 0b1100_0011]);
+    let mut bits = Bitstream::new(BufferedReader::new(data));
+
+    assert_eq!(bits.peek_bits(0).unwrap(), Some(0));
+    assert_eq!(bits.peek_bits(14).unwrap(), Some(0x3ffe));
+    assert_eq!(bits.peek_bits(32).unwrap(), Some(0xfff8_69c3));
+    assert_eq!(bits.try_match(0x3fff, 14).unwrap(), false);
+    assert_eq!(bits.try_match(0x3ffe, 14).unwrap(), true);
+
+    // Peeking works from the middle of a byte, and does not consume.
+    assert_eq!(bits.peek_bits(3).unwrap(), Some(0b000));
+    assert_eq!(bits.peek_bits(9).unwrap(), Some(0b0_0011_0100));
+    assert_eq!(bits.read_leq_u8(5).unwrap(), 0b0_0011);
+    assert_eq!(bits.peek_bits(13).unwrap(), Some(0b0_1001_1100_0011));
+    assert_eq!(bits.peek_bits(14).unwrap(), None);
+    assert_eq!(bits.try_match(0, 14).unwrap(), false);
+    assert_eq!(bits.try_match(0b0100, 4).unwrap(), true);
+    assert_eq!(bits.read_leq_u16(9).unwrap(), 0b1_1100_0011);
+    assert_eq!(bits.peek_bits(1).unwrap(), None);
+
+    // A cursor can peek too.
+    let mut bits = Bitstream::new(io::Cursor::new(vec![0xff, 0xf8]));
+    assert_eq!(bits.try_match(0xfff8, 16).unwrap(), true);
+    assert_eq!(bits.peek_bits(1).unwrap(), None);
+}
+
 #[test]
 fn verify_read_unary() {
     let data = io::Cursor::new(vec![
@@ -775,3 +1439,130 @@ fn verify_read_mixed() {
     assert_eq!(bits.read_leq_u32(17).unwrap(), minus | (-08489_i16 as u16 as u32));
     assert_eq!(bits.read_leq_u32(17).unwrap(), minus | (-08698_i16 as u16 as u32));
 }
+
+/// Writes bits most significant first, to produce input for `Bitstream` tests.
+#[cfg(test)]
+struct TestBitWriter {
+    bytes: Vec<u8>,
+    acc: u8,
+    n: u32,
+}
+
+#[cfg(test)]
+impl TestBitWriter {
+    fn new() -> TestBitWriter {
+        TestBitWriter { bytes: Vec::new(), acc: 0, n: 0 }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        self.acc = (self.acc << 1) | (bit as u8);
+        self.n += 1;
+        if self.n == 8 {
+            self.bytes.push(self.acc);
+            self.acc = 0;
+            self.n = 0;
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, bits: u32) {
+        for i in 0..bits {
+            self.write_bit((value >> (bits - 1 - i)) & 1 == 1);
+        }
+    }
+
+    fn write_unary(&mut self, q: u32) {
+        for _ in 0..q {
+            self.write_bit(false);
+        }
+        self.write_bit(true);
+    }
+
+    /// Returns the bytes written, with the last byte padded with zeros.
+    fn into_bytes(mut self) -> Vec<u8> {
+        while self.n != 0 {
+            self.write_bit(false);
+        }
+        self.bytes
+    }
+}
+
+#[test]
+fn verify_bitstream_round_trips_random_sequences() {
+    // A sequence of reads of random kinds and widths, with random values,
+    // must return exactly what was written. The generator is a fixed xorshift
+    // so failures are reproducible; the seed is reported on failure.
+    #[derive(Debug)]
+    enum Op {
+        Bit(bool),
+        Unary(u32),
+        LeqU8(u32, u8),
+        GtU8LeqU16(u32, u32),
+        LeqU16(u32, u16),
+        LeqU32(u32, u32),
+    }
+
+    let mut state = 0x2545f491_u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+
+    // Masks a random value to `bits` bits, bits may be 32.
+    fn truncate(x: u32, bits: u32) -> u32 {
+        if bits == 32 { x } else { x & ((1 << bits) - 1) }
+    }
+
+    for seed in 0..500 {
+        let len = next() % 200;
+        let mut ops = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            let x = next();
+            let op = match next() % 6 {
+                0 => Op::Bit(x & 1 == 1),
+                // Mostly short quotients, sometimes one that spans many bytes.
+                1 => Op::Unary(if x & 0xf == 0 { x >> 22 } else { x % 12 }),
+                2 => { let n = next() % 9; Op::LeqU8(n, truncate(x, n) as u8) }
+                3 => { let n = 9 + next() % 8; Op::GtU8LeqU16(n, truncate(x, n)) }
+                4 => { let n = next() % 17; Op::LeqU16(n, truncate(x, n) as u16) }
+                _ => { let n = next() % 33; Op::LeqU32(n, truncate(x, n)) }
+            };
+            ops.push(op);
+        }
+
+        let mut writer = TestBitWriter::new();
+        for op in &ops {
+            match *op {
+                Op::Bit(b) => writer.write_bit(b),
+                Op::Unary(q) => writer.write_unary(q),
+                Op::LeqU8(n, v) => writer.write_bits(v as u32, n),
+                Op::GtU8LeqU16(n, v) => writer.write_bits(v, n),
+                Op::LeqU16(n, v) => writer.write_bits(v as u32, n),
+                Op::LeqU32(n, v) => writer.write_bits(v, n),
+            }
+        }
+        let bytes = writer.into_bytes();
+
+        let mut bits = Bitstream::new(BufferedReader::new(io::Cursor::new(bytes)));
+        for op in &ops {
+            let ok = match *op {
+                Op::Bit(b) => bits.read_bit().unwrap() == b,
+                Op::Unary(q) => bits.read_unary().unwrap() == q,
+                Op::LeqU8(n, v) => bits.read_leq_u8(n).unwrap() == v,
+                Op::GtU8LeqU16(n, v) => bits.read_gt_u8_leq_u16(n).unwrap() == v,
+                Op::LeqU16(n, v) => bits.read_leq_u16(n).unwrap() == v,
+                Op::LeqU32(n, v) => bits.read_leq_u32(n).unwrap() == v,
+            };
+            assert!(ok, "mismatch at {:?} for sequence {}", op, seed);
+        }
+
+        // Only the padding of the last byte may remain.
+        let mut padding = 0;
+        while let Ok(b) = bits.read_bit() {
+            assert!(!b, "nonzero padding for sequence {}", seed);
+            padding += 1;
+        }
+        assert!(padding < 8, "too much data left for sequence {}", seed);
+    }
+}
@@ -53,6 +53,14 @@
 //! # }
 //! ```
 //!
+//! This works for streams with a multiple of 8 bits per sample. With the `hound`
+//! feature enabled, the `wav` module converts the streaminfo into a
+//! `hound::WavSpec`, and writes blocks of any bit depth.
+//!
+//! Applications built on [Symphonia](https://github.com/pdeljanov/Symphonia)
+//! can decode FLAC with Claxon by enabling the `symphonia-core` feature, and
+//! registering the decoder and format reader in the `symphonia` module.
+//!
 //! Retrieving the artist metadata:
 //!
 //! ```
@@ -63,36 +71,123 @@
 //! }
 //! ```
 //!
+//! Parsing of tags is enabled by the `full-metadata` feature, which is on by
+//! default. A decoder that needs only the streaminfo and the audio can disable
+//! default features, to leave the parser out of the binary. Tags then always
+//! appear empty.
+//!
+//! The decoding core uses integer arithmetic only, and no 64-bit division, so
+//! it is suitable for microcontrollers without a floating point unit. On
+//! 32-bit targets, LPC prediction uses 32x32 to 64-bit multiply-accumulates,
+//! see the `dispatch` module.
+//!
 //! For more examples, see the [examples](https://github.com/ruuda/claxon/tree/master/examples)
 //! directory in the crate.
 
 #![warn(missing_docs)]
+#![cfg_attr(feature = "forbid-unsafe", forbid(unsafe_code))]
+
+// With the `log` feature enabled, Claxon emits debug and trace events at
+// significant points during decoding. Without it, the logging macros expand to
+// nothing, so logging has no cost.
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } }
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } }
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! warn {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } }
+}
+
+// With the `serde` feature enabled, the elements of the `explain` module can
+// be serialized.
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
+use std::cmp;
 use std::fs;
 use std::io;
 use std::mem;
+use std::ops::Range;
 use std::path;
+use std::time::Duration;
 use error::fmt_err;
-use frame::FrameReader;
-use input::{BufferedReader, ReadBytes};
-use metadata::{MetadataBlock, MetadataBlockReader, StreamInfo, VorbisComment};
+use frame::{BufferStats, ChannelSelection, FrameIssue, FrameLimits, FrameOrdering, FrameReader};
+use frame::{BufferPool, FrameStats, ReservedValues};
+use input::{Bitstream, BufferedReader, PeekBytes, ReadBytes, SeekBytes};
+use explain::Element;
+use metadata::{DurationEstimate, EncoderInfo, MetadataBlock, MetadataLimits, StreamInfo};
+use metadata::{CueSheet, LengthConflict, MetadataWarning, Picture, SeekTable, StreamSpec};
+use metadata::VorbisComment;
+use meter::Levels;
+use resample::{ResampledSamples, Resampler};
 
 mod crc;
+pub mod dispatch;
 mod error;
+pub mod explain;
 pub mod frame;
 pub mod input;
 pub mod metadata;
+pub mod meter;
+#[cfg(feature = "mp4")]
+pub mod mp4;
+pub mod resample;
+pub mod rice;
+#[cfg(feature = "scan")]
+pub mod scan;
+pub mod split;
 pub mod subframe;
+#[cfg(feature = "symphonia-core")]
+pub mod symphonia;
+#[cfg(feature = "hound")]
+pub mod wav;
 
-pub use error::{Error, Result};
-pub use frame::Block;
+pub use dispatch::{SimdLevel, simd_level};
+pub use error::{Error, Result, UnsupportedFeature};
+pub use frame::{ArcBlock, Block};
+
+/// The largest number of bits per sample that Claxon can decode.
+///
+/// The FLAC format allows up to 32 bits per sample, but frame headers can
+/// only specify up to 24 bits, and Claxon does not support taking the bits per
+/// sample from the streaminfo. Wider streams are rejected with
+/// `Error::Unsupported` when the reader is constructed, unless it is for
+/// reading metadata only.
+pub const MAX_BITS_PER_SAMPLE: u32 = 24;
 
 /// A FLAC decoder that can decode the stream from the underlying reader.
 ///
+/// A `FlacReader<R>` is `Send` if `R` is, and `Sync` if `R` is, so a reader
+/// can be moved to a decoding thread. The same holds for the sample iterators.
+///
 /// TODO: Add an example.
 pub struct FlacReader<R: io::Read> {
     streaminfo: StreamInfo,
+    /// The streaminfo block header and body as stored in the stream, if any.
+    streaminfo_bytes: Option<[u8; 38]>,
     vorbis_comment: Option<VorbisComment>,
+    seek_table: Option<SeekTable>,
+    pictures: Vec<Picture>,
+    cue_sheet: Option<CueSheet>,
+    metadata_warnings: Vec<MetadataWarning>,
+    /// The position of the first frame, if all metadata blocks were read.
+    first_frame_offset: Option<u64>,
+    /// The number of bytes skipped between the metadata and the first frame.
+    junk_len: u32,
+    frame_settings: FrameSettings,
+    length_check: LengthCheck,
     input: FlacReaderState<BufferedReader<R>>,
 }
 
@@ -103,6 +198,22 @@ enum FlacReaderState<T> {
     MetadataOnly(T),
 }
 
+/// The metadata of a stream, read without decoding audio, for a later upgrade.
+///
+/// A player typically inspects a file first, to show its tags and duration,
+/// and then plays it. `FlacProbe` reads only the streaminfo and the Vorbis
+/// comment, like a metadata-only `FlacReader`, but it keeps the input open.
+/// `into_full_reader()` then continues from where the probe stopped, so the
+/// file is not opened again, and the metadata is not read twice.
+pub struct FlacProbe<R: io::Read> {
+    input: BufferedReader<R>,
+    /// The options for the full reader.
+    options: FlacReaderOptions,
+    streaminfo: StreamInfo,
+    streaminfo_bytes: [u8; 38],
+    blocks: MetadataBlocks,
+}
+
 /// Controls what metadata `FlacReader` reads when constructed.
 ///
 /// The FLAC format contains a number of metadata blocks before the start of
@@ -114,11 +225,14 @@ enum FlacReaderState<T> {
 /// A few use cases:
 ///
 /// * To read only the streaminfo, as quickly as possible, set `metadata_only`
-///   to true and `read_vorbis_comment` to false. The resulting reader cannot be
-///   used to read audio data.
+///   to true, and `read_vorbis_comment` and `read_seek_table` to false. The
+///   resulting reader cannot be used to read audio data.
 /// * To read only the streaminfo and tags, set `metadata_only` and
-///   `read_vorbis_comment` both to true. The resulting reader cannot be used to
-///   read audio data.
+///   `read_vorbis_comment` both to true, and `read_seek_table` to false. The
+///   resulting reader cannot be used to read audio data.
+/// * To extract cover art, set `read_pictures` to true, and possibly
+///   `metadata_only` too. Pictures can be anywhere in the metadata, so all
+///   metadata blocks are read.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct FlacReaderOptions {
     /// When true, return a reader as soon as all desired metadata has been read.
@@ -137,15 +251,113 @@ pub struct FlacReaderOptions {
     /// `FlacReader::tags()` and other tag-related methods will not return tag
     /// data.
     ///
+    /// Without the `full-metadata` feature, Vorbis comments are never parsed,
+    /// and this option has no effect.
+    ///
     /// Defaults to true.
     pub read_vorbis_comment: bool,
+
+    /// When true, read metadata blocks at least until a seek table block is found.
+    ///
+    /// When false, the `FlacReader` will be constructed without a seek table,
+    /// even if the stream contains one, and `FlacReader::seek_table()` returns
+    /// `None`.
+    ///
+    /// Defaults to true.
+    pub read_seek_table: bool,
+
+    /// When true, read all picture blocks, including their image data.
+    ///
+    /// Pictures, such as cover art, are often hundreds of kilobytes, and a
+    /// single picture block can be up to 16 MiB. When false, picture blocks
+    /// are skipped without reading them into memory, and
+    /// `FlacReader::pictures()` returns an empty slice. To bound the total
+    /// size of the pictures, set `metadata_limits`.
+    ///
+    /// Without the `full-metadata` feature, pictures are never parsed, and
+    /// this option has no effect.
+    ///
+    /// Defaults to false.
+    pub read_pictures: bool,
+
+    /// When true, accept metadata with minor spec violations, rather than failing.
+    ///
+    /// Real-world files sometimes contain metadata that is technically invalid,
+    /// but harmless. With this option, a wrong flag that marks the last
+    /// metadata block is tolerated: a frame may follow a block that is not
+    /// marked as the last one, and more metadata may follow the block that
+    /// is. These problems are recorded in `FlacReader::metadata_warnings()`.
+    /// Also, Vorbis comment field names that contain invalid bytes have those
+    /// bytes replaced by `_`, and comments without a `=` are dropped. Values
+    /// and vendor strings that are not valid UTF-8, for instance because they
+    /// were written in a legacy encoding, have the invalid sequences replaced
+    /// by U+FFFD. A warning is logged for every such comment. See also
+    /// `metadata::read_metadata_block_ext()`.
+    ///
+    /// Defaults to false.
+    pub lenient_metadata: bool,
+
+    /// When true, reject metadata that other decoders tolerate.
+    ///
+    /// A seek table whose points are out of order or duplicated, or a second
+    /// seek table, violates the spec. Such seek tables cannot be trusted, but
+    /// the audio data is fine, so by default the seek table is dropped, and
+    /// the problem is recorded in `FlacReader::metadata_warnings()`. With
+    /// `lenient_metadata`, only the offending points are dropped. Similarly,
+    /// only the first of several cue sheets is used by default. With this
+    /// option, such streams fail to open with `Error::FormatError` instead.
+    ///
+    /// Defaults to false.
+    pub strict_metadata: bool,
+
+    /// What to do when metadata blocks or frames disagree about the length of the stream.
+    ///
+    /// The seek table and cue sheet are checked against the streaminfo when
+    /// the reader is constructed, see `metadata::length_conflicts()`. The
+    /// total number of samples in the streaminfo is checked against the frames
+    /// when a sample iterator reaches the end of the stream.
+    ///
+    /// Defaults to `LengthCheck::Warn`.
+    pub length_check: LengthCheck,
+
+    /// Limits on the number of metadata blocks and bytes of metadata to read.
+    ///
+    /// A malicious file can contain a long chain of tiny metadata blocks, to
+    /// stall the construction of the reader. The limits bound the metadata
+    /// that is read before the reader is returned, exceeding them results in
+    /// `Error::LimitExceeded`. Blocks that are not read, because all desired
+    /// metadata was read before them, do not count.
+    ///
+    /// Defaults to no limits, see `MetadataLimits`.
+    pub metadata_limits: MetadataLimits,
+
+    /// The maximum number of bytes of junk to skip before the first frame.
+    ///
+    /// Some writers leave bytes between the metadata and the first frame that
+    /// are neither padding nor a frame. Such streams fail to decode with
+    /// "frame sync code missing". When this is nonzero, the reader scans
+    /// forward from the end of the metadata for a valid frame header, and
+    /// skips at most this many bytes to find it. If there is no frame header
+    /// in range, constructing the reader fails. The number of bytes skipped is
+    /// available as `FlacReader::junk_len()`. Scanning does not happen when
+    /// `metadata_only` is set.
+    ///
+    /// Defaults to 0, no junk is skipped.
+    pub max_junk_len: u32,
 }
 
 impl Default for FlacReaderOptions {
     fn default() -> FlacReaderOptions {
         FlacReaderOptions {
             read_vorbis_comment: true,
+            read_seek_table: true,
+            read_pictures: false,
             metadata_only: false,
+            lenient_metadata: false,
+            strict_metadata: false,
+            length_check: LengthCheck::Warn,
+            metadata_limits: MetadataLimits::default(),
+            max_junk_len: 0,
         }
     }
 }
@@ -159,9 +371,167 @@ impl FlacReaderOptions {
             return true
         }
 
-        // Should be the or of all read_* fields, of which vorbis_comment is the
-        // only one at the moment.
-        self.read_vorbis_comment
+        // This is the or of all read_* fields. Without the full-metadata
+        // feature, the Vorbis comment and pictures cannot be read, so there is
+        // no point looking for them. There can be any number of pictures, so
+        // `read_pictures` remains set until the last block.
+        let full_metadata = cfg!(feature = "full-metadata");
+        (self.read_vorbis_comment && full_metadata) || self.read_seek_table ||
+            (self.read_pictures && full_metadata)
+    }
+}
+
+/// Information about a stream without header, for `FlacReader::new_headerless()`.
+///
+/// A stream that consists of frames only lacks the streaminfo block. The
+/// sample rate, number of channels, and bits per sample are taken from the
+/// header of the first frame, but some properties of the stream cannot be
+/// determined from a frame header. If they are known from elsewhere, for
+/// instance from the container that carries the frames, they can be provided
+/// here.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct HeaderlessHints {
+    /// The maximum block size of the stream.
+    ///
+    /// When `None`, the block size of the first frame is assumed.
+    pub max_block_size: Option<u16>,
+
+    /// The total number of inter-channel samples in the stream, if known.
+    pub samples: Option<u64>,
+}
+
+/// What `FlacReader` does when the length of the stream is inconsistent.
+///
+/// The streaminfo, the seek table, and the cue sheet can disagree about the
+/// length of the stream, and so can the frames themselves. The streaminfo
+/// takes precedence, see `metadata::LengthConflict` for more details.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LengthCheck {
+    /// Do not check the length.
+    Ignore,
+    /// Record conflicts, but decode the stream as usual.
+    ///
+    /// Conflicts between metadata blocks are recorded in
+    /// `FlacReader::metadata_warnings()`. A conflict with the decoded frames is
+    /// available as `FlacSamples::length_conflict()` at the end of the stream.
+    Warn,
+    /// Fail with `Error::FormatError` on a conflict.
+    ///
+    /// Conflicts between metadata blocks fail the construction of the reader.
+    /// A conflict with the decoded frames is yielded as an error by the sample
+    /// iterator at the end of the stream.
+    Fail,
+}
+
+/// What `FlacReader::seek_ext()` does when bisection does not find the target frame.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SeekFallback {
+    /// Stop at the closest frame before the target sample found so far.
+    ///
+    /// This bounds the number of bytes read, but the caller may have to skip
+    /// many samples to reach the target. See `SeekReport::samples_to_skip()`.
+    Closest,
+    /// Decode frames from the closest frame before the target sample onwards.
+    ///
+    /// This continues until the frame that contains the target sample, so the
+    /// seek is always exact, but it may read a large part of the stream.
+    ReadForward,
+    /// Fail with `Error::LimitExceeded`.
+    Fail,
+}
+
+/// Tuning knobs for `FlacReader::seek_ext()`.
+///
+/// Seeking without a seek table bisects the audio data: every probe jumps to
+/// an estimated position, scans forward to the next frame header, and decodes
+/// the frame to learn its sample number. On a local disk, probes are cheap,
+/// and scanning far is fine. On a high-latency network mount, every probe is
+/// a round trip, so fewer probes with a fallback may be preferable.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SeekOptions {
+    /// The maximum number of bytes to scan for a frame header per probe.
+    ///
+    /// This should exceed the largest frame of the stream, otherwise a probe
+    /// that lands at the start of a large frame finds nothing, and the seek
+    /// resorts to the fallback.
+    ///
+    /// Defaults to 64 KiB.
+    pub max_scan_len: u32,
+
+    /// The maximum number of probes before resorting to the fallback.
+    ///
+    /// Defaults to 32.
+    pub max_probes: u32,
+
+    /// What to do when the probes do not find the frame that contains the target.
+    ///
+    /// Defaults to `SeekFallback::ReadForward`.
+    pub fallback: SeekFallback,
+}
+
+impl Default for SeekOptions {
+    fn default() -> SeekOptions {
+        SeekOptions {
+            max_scan_len: 64 * 1024,
+            max_probes: 32,
+            fallback: SeekFallback::ReadForward,
+        }
+    }
+}
+
+/// The outcome of a seek, see `FlacReader::seek_ext()`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SeekReport {
+    /// The inter-channel sample that was sought.
+    pub target: u64,
+
+    /// The first sample of the frame that the reader is positioned at.
+    pub frame_sample: u64,
+
+    /// Whether the frame that the reader is positioned at contains the target.
+    ///
+    /// This is false if the fallback stopped at an earlier frame, or if the
+    /// target lies outside of the stream.
+    pub is_exact: bool,
+
+    /// The number of probes that were made.
+    pub probes: u32,
+
+    /// The total number of bytes scanned for frame headers.
+    pub bytes_scanned: u64,
+}
+
+impl SeekReport {
+    /// Returns the number of inter-channel samples to skip to reach the target.
+    pub fn samples_to_skip(&self) -> u64 {
+        self.target.saturating_sub(self.frame_sample)
+    }
+}
+
+/// The heap memory held by a reader, in bytes.
+///
+/// Sizes are capacities of allocations, not the part of them that is in use.
+/// `FlacReader::memory_usage()` reports the input buffer and the metadata,
+/// the sample iterators report the buffer that they decode blocks into. A
+/// server that keeps many readers around can use this to decide which ones
+/// to drop, or when to shrink the block buffer with
+/// `FlacSamples::shrink_block_buffer()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The buffer that the input is read into.
+    pub input_buffer: usize,
+
+    /// The buffer that blocks are decoded into.
+    pub block_buffer: usize,
+
+    /// The metadata that is kept: the vendor string and the tags.
+    pub metadata: usize,
+}
+
+impl MemoryUsage {
+    /// Returns the sum of all parts.
+    pub fn total(&self) -> usize {
+        self.input_buffer + self.block_buffer + self.metadata
     }
 }
 
@@ -173,14 +543,124 @@ pub struct FlacSamples<R: ReadBytes> {
     channel: u32,
 
     /// If reading ever failed, this flag is set, so that the iterator knows not
-    /// to return any new values.
+    /// to return any new values, until `resume()` is called.
     has_failed: bool,
+
+    /// The number of frames that were skipped with `resume()` or `resync()`.
+    skipped_frames: u64,
+
+    /// The number of bytes that `resync()` skipped to find a frame header.
+    skipped_bytes: u64,
+
+    /// The total number of samples according to the streaminfo, if known.
+    total_samples: Option<u64>,
+
+    /// Whether to check the total against the frames at the end of the stream.
+    length_check: LengthCheck,
+
+    /// The sample after the last block that was decoded, if any.
+    decoded_samples: Option<u64>,
+
+    /// The conflict found at the end of the stream, if any.
+    length_conflict: Option<LengthConflict>,
+
+    /// Where to take the block buffer from, and return it to when dropped.
+    pool: Option<Box<BufferPool + Send + Sync>>,
 }
 
 /// An iterator that yields samples read from a `FlacReader`.
 pub struct FlacIntoSamples<R: ReadBytes> {
     // This works because `ReadBytes` is implemented for both `&mut R` and `R`.
     inner: FlacSamples<R>,
+
+    /// The number of channels of the stream, from the streaminfo.
+    channels: u32,
+}
+
+/// What `FlacIntoSamples::take_exact()` does when the stream ends early.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShortStream {
+    /// Yield silence (samples with value 0) until the requested length.
+    PadWithSilence,
+    /// Yield an error of kind `UnexpectedEof`.
+    Error,
+}
+
+/// An iterator that yields an exact number of samples, see `FlacIntoSamples::take_exact()`.
+pub struct TakeExact<R: ReadBytes> {
+    inner: FlacIntoSamples<R>,
+
+    /// What to do when the stream ends before `remaining` reaches 0.
+    short_stream: ShortStream,
+
+    /// The number of samples still to yield, counted in interleaved samples.
+    remaining: u64,
+
+    /// Whether the stream ended, after which only silence is yielded.
+    is_padding: bool,
+}
+
+/// An iterator that skips corrupt frames, see `FlacSamples::lenient()`.
+pub struct LenientSamples<R: PeekBytes> {
+    inner: FlacSamples<R>,
+
+    /// The maximum number of bytes to skip to find the next frame header.
+    max_resync_len: u32,
+}
+
+/// An iterator that yields the samples of a time span, see `FlacReader::take_duration()`.
+pub struct TakeDuration<R: ReadBytes> {
+    inner: FlacSamples<R>,
+
+    /// The number of samples still to yield, counted in interleaved samples.
+    remaining: u64,
+}
+
+/// A reader that yields interleaved chunks of a fixed size, see `FlacReader::chunks()`.
+pub struct FlacChunks<R: ReadBytes> {
+    frame_reader: FrameReader<R>,
+    block: Block,
+
+    /// The index of the first inter-channel sample in `block` not yet yielded.
+    pos: u32,
+
+    /// The number of inter-channel samples in a full chunk.
+    frames_per_chunk: u32,
+
+    /// The spec of the stream, with the channels after channel selection.
+    spec: StreamSpec,
+
+    /// If reading ever failed, the iterator yields no new values.
+    has_failed: bool,
+}
+
+/// Interleaved samples, yielded by `FlacChunks`.
+pub struct Chunk {
+    samples: Vec<i32>,
+    frames: u32,
+    bits_per_sample: u32,
+    is_partial: bool,
+}
+
+/// A reader that yields the blocks that overlap with a range, see `FlacReader::blocks_between()`.
+pub struct BlocksBetween<R: ReadBytes> {
+    frame_reader: FrameReader<R>,
+
+    /// The first sample of the range.
+    start: u64,
+
+    /// The sample after the last sample of the range.
+    end: u64,
+
+    /// Set after the block that reaches the end of the range, or an error.
+    is_done: bool,
+}
+
+/// A block that overlaps with a range of samples, yielded by `BlocksBetween`.
+pub struct RangeBlock {
+    block: Block,
+    trim_start: u32,
+    trim_end: u32,
 }
 
 fn read_stream_header<R: ReadBytes>(input: &mut R) -> Result<()> {
@@ -204,6 +684,435 @@ fn read_stream_header<R: ReadBytes>(input: &mut R) -> Result<()> {
     }
 }
 
+/// Returns whether another metadata block follows the block with the given `is_last` flag.
+///
+/// Some encoders in the wild set the `is_last` flag of metadata blocks
+/// incorrectly: either audio data follows a block that is not marked as the
+/// last one, or more metadata follows the block that is. Audio data always
+/// starts with a frame sync code, and the first byte of that is not a valid
+/// metadata block header, so in lenient mode we tell the two apart by peeking
+/// ahead, and record a warning when the flag is wrong.
+fn has_next_metadata_block<R: io::Read>(input: &mut BufferedReader<R>,
+                                        is_last: bool,
+                                        lenient: bool,
+                                        warnings: &mut Vec<MetadataWarning>)
+                                        -> Result<bool> {
+    if !lenient {
+        return Ok(!is_last)
+    }
+
+    let bytes = try!(input.peek(2));
+    let is_frame_sync = bytes.len() == 2 && bytes[0] == 0xff && bytes[1] & 0xfe == 0xf8;
+
+    if is_last {
+        // Only continue if the next byte is a header of a block type that can
+        // appear after streaminfo. Anything else, including the end of the
+        // stream, is left to the frame reader to deal with.
+        let block_type = match bytes.first() {
+            Some(&byte) => byte & 0x7f,
+            None => return Ok(false),
+        };
+        let is_plausible = !is_frame_sync && block_type >= 1 && block_type <= 6;
+        if is_plausible {
+            warn!("metadata block of type {} follows block marked as last", block_type);
+            warnings.push(MetadataWarning::BlockAfterLast { block_type: block_type });
+        }
+        Ok(is_plausible)
+    } else {
+        if is_frame_sync {
+            warn!("frame follows metadata block not marked as last");
+            warnings.push(MetadataWarning::LastBlockNotMarked);
+        }
+        Ok(!is_frame_sync)
+    }
+}
+
+/// Reads the stream header and the streaminfo block at the start of a stream.
+///
+/// Returns the streaminfo, its raw bytes, and the state to read the other
+/// metadata blocks with.
+fn read_metadata_start<R: io::Read>(input: &mut BufferedReader<R>,
+                                    options: &FlacReaderOptions)
+                                    -> Result<(StreamInfo, [u8; 38], MetadataBlocks)> {
+    // A flac stream first of all starts with a stream header.
+    try!(read_stream_header(input));
+
+    // Next are one or more metadata blocks. The flac specification dictates
+    // that the streaminfo block is the first block. Keep its raw bytes; the
+    // header can be reconstructed exactly from its fields. Any other block is
+    // read as usual, and rejected below.
+    let header = try!(metadata::read_metadata_block_header(input));
+    let len = 4 + header.length as u64;
+    try!(options.metadata_limits.check(1, len));
+    let mut streaminfo_bytes = [0u8; 38];
+    let streaminfo_block = if header.block_type == 0 && header.length == 34 {
+        streaminfo_bytes[0] = if header.is_last { 0x80 } else { 0x00 };
+        streaminfo_bytes[3] = 34;
+        try!(input.read_into(&mut streaminfo_bytes[4..]));
+        let mut body = io::Cursor::new(&streaminfo_bytes[4..]);
+        try!(metadata::read_metadata_block(&mut body, 0, 34))
+    } else {
+        try!(metadata::read_metadata_block(input, header.block_type, header.length))
+    };
+    let streaminfo = match streaminfo_block {
+        MetadataBlock::StreamInfo(info) => info,
+        _ => return fmt_err("streaminfo block missing"),
+    };
+
+    let blocks = MetadataBlocks {
+        vorbis_comment: None,
+        seek_table: None,
+        pictures: Vec::new(),
+        cue_sheet: None,
+        warnings: Vec::new(),
+        n_seek_tables: 0,
+        n_blocks: 1,
+        len: len,
+        is_last: header.is_last,
+    };
+    Ok((streaminfo, streaminfo_bytes, blocks))
+}
+
+/// The metadata blocks after the streaminfo, read while constructing a `FlacReader`.
+///
+/// Reading can stop early and resume later, see `FlacProbe`, so this also
+/// tracks how much metadata was read, to enforce the limits over both parts.
+struct MetadataBlocks {
+    vorbis_comment: Option<VorbisComment>,
+    seek_table: Option<SeekTable>,
+    pictures: Vec<Picture>,
+    cue_sheet: Option<CueSheet>,
+    warnings: Vec<MetadataWarning>,
+    /// The number of seek table blocks read.
+    n_seek_tables: u32,
+    /// The number of metadata blocks read, including the streaminfo block.
+    n_blocks: u32,
+    /// The number of bytes of metadata read, including the block headers.
+    len: u64,
+    /// Whether the last block that was read is marked as the last one.
+    is_last: bool,
+}
+
+impl MetadataBlocks {
+    /// Reads metadata blocks until the last one, or until the desired blocks have been read.
+    ///
+    /// Which blocks to parse, and the limits, are taken from `options`. When
+    /// to stop early is taken from `desired`. Returns whether reading stopped
+    /// after the last metadata block, rather than early.
+    fn read<R: io::Read>(&mut self,
+                         input: &mut BufferedReader<R>,
+                         options: &FlacReaderOptions,
+                         desired: FlacReaderOptions)
+                         -> Result<bool> {
+        let mut desired = desired;
+        while try!(has_next_metadata_block(input,
+                                           self.is_last,
+                                           options.lenient_metadata,
+                                           &mut self.warnings)) {
+            let header = try!(metadata::read_metadata_block_header(input));
+            self.is_last = header.is_last;
+            self.n_blocks += 1;
+            self.len += 4 + header.length as u64;
+            try!(options.metadata_limits.check(self.n_blocks, self.len));
+            let block = if header.block_type == 6 && !options.read_pictures {
+                // Skip pictures without reading the image data into memory.
+                try!(input.skip(header.length));
+                MetadataBlock::Padding { length: header.length }
+            } else {
+                // Unless strict, read the seek table leniently, so points out
+                // of order do not fail the stream. They are dealt with below.
+                let is_lenient_seek_table = header.block_type == 3 && !options.strict_metadata;
+                try!(metadata::read_metadata_block_ext(input,
+                                                       header.block_type,
+                                                       header.length,
+                                                       options.lenient_metadata ||
+                                                           is_lenient_seek_table))
+            };
+            match block {
+                MetadataBlock::VorbisComment(vc) => {
+                    // The Vorbis comment block need not be present, but
+                    // when it is, it must be unique.
+                    if self.vorbis_comment.is_some() {
+                        return fmt_err("encountered second Vorbis comment block")
+                    } else {
+                        self.vorbis_comment = Some(vc);
+                    }
+
+                    // We have one, no new one is desired.
+                    desired.read_vorbis_comment = false;
+                }
+                MetadataBlock::SeekTable(st) => {
+                    try!(self.add_seek_table(st, header.length, options));
+                    desired.read_seek_table = false;
+                }
+                MetadataBlock::Picture(picture) => self.pictures.push(picture),
+                MetadataBlock::CueSheet(..) if self.cue_sheet.is_some() => {
+                    if options.strict_metadata {
+                        return fmt_err("encountered second cue sheet block")
+                    }
+                    warn!("ignoring second cue sheet block");
+                    self.warnings.push(MetadataWarning::SecondCueSheet);
+                }
+                MetadataBlock::CueSheet(cs) => self.cue_sheet = Some(cs),
+                MetadataBlock::StreamInfo(..) => {
+                    return fmt_err("encountered second streaminfo block")
+                }
+                // Other blocks are currently not handled.
+                _block => {}
+            }
+
+            // Early-out reading metadata once all desired blocks have been
+            // collected.
+            if !desired.has_desired_blocks() {
+                return Ok(false)
+            }
+        }
+        Ok(true)
+    }
+
+    /// Stores a seek table block of `length` bytes, or drops it if it cannot be trusted.
+    ///
+    /// Unless `strict_metadata` is set, the seek table was read leniently, so
+    /// points out of order have been dropped already.
+    fn add_seek_table(&mut self,
+                      seek_table: SeekTable,
+                      length: u32,
+                      options: &FlacReaderOptions)
+                      -> Result<()> {
+        self.n_seek_tables += 1;
+        if self.n_seek_tables > 1 {
+            if options.strict_metadata {
+                return fmt_err("encountered second seek table block")
+            }
+            warn!("dropping seek tables, the stream has more than one");
+            self.warnings.push(MetadataWarning::SecondSeekTable);
+            self.seek_table = None;
+            return Ok(())
+        }
+
+        // Every seek point takes 18 bytes, if there are fewer points than
+        // that, some were out of order.
+        if seek_table.seekpoints().len() as u32 != length / 18 {
+            self.warnings.push(MetadataWarning::UnorderedSeekTable);
+            if !options.lenient_metadata {
+                warn!("dropping seek table, its points are not in ascending order");
+                return Ok(())
+            }
+        }
+        self.seek_table = Some(seek_table);
+        Ok(())
+    }
+}
+
+/// The maximum size of a frame header in bytes.
+///
+/// A frame header is at most 16 bytes long: 4 bytes of fixed fields, up to 7
+/// bytes of frame or sample number, 2 bytes of block size, 2 bytes of sample
+/// rate, and the CRC-8.
+const MAX_FRAME_HEADER_LEN: usize = 16;
+
+/// Skips bytes up to the next valid frame header, skipping at most `max_len` bytes.
+///
+/// Returns the number of bytes skipped. Scanning stops at the first valid
+/// frame header, or at the end of the stream. If there is no frame header in
+/// range, `None` is returned, and `max_len` bytes have been skipped.
+fn find_frame_header<R: PeekBytes>(input: &mut R, max_len: u32) -> Result<Option<u32>> {
+    // The scanner moves a byte at a time, so the bitstream is always at a
+    // byte boundary, and the input can be peeked at directly.
+    let mut bits = Bitstream::new(input);
+    let mut skipped = 0;
+    loop {
+        // First test for the 14-bit sync code and the reserved bit that
+        // follows it, which rules out most positions cheaply. Fewer than two
+        // bytes cannot hold a frame, the frame reader treats them as the end
+        // of the stream.
+        match try!(bits.peek_bits(15)) {
+            None => return Ok(Some(skipped)),
+            Some(0b111_1111_1111_1100) => {
+                let bytes = try!(bits.get_mut().peek_bytes(MAX_FRAME_HEADER_LEN));
+                if frame::is_frame_header(bytes) {
+                    return Ok(Some(skipped))
+                }
+            }
+            Some(..) => {}
+        }
+        if skipped == max_len {
+            return Ok(None)
+        }
+        try!(bits.read_leq_u8(8));
+        skipped += 1;
+    }
+}
+
+/// Skips bytes that are neither metadata nor a frame, before the first frame.
+///
+/// Returns the number of bytes skipped. If no frame header is found in the
+/// first `max_len` bytes, a format error is returned.
+fn skip_leading_junk<R: io::Read>(input: &mut BufferedReader<R>, max_len: u32) -> Result<u32> {
+    let skipped = match try!(find_frame_header(input, max_len)) {
+        Some(n) => n,
+        None => return fmt_err("frame sync code missing after skipping junk"),
+    };
+    if skipped > 0 {
+        warn!("skipped {} bytes of junk before the first frame", skipped);
+    }
+    Ok(skipped)
+}
+
+/// Describes which parts of the FLAC format this build of Claxon can decode.
+///
+/// Claxon returns `Error::Unsupported` when it encounters a feature of the
+/// format that it does not implement. These features are valid, but they are
+/// not produced by common encoders. Applications that have a fallback decoder
+/// can use this to decide up front where to route a file, rather than relying
+/// on an error halfway through decoding.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Capabilities {
+    /// The maximum number of bits per sample that can be decoded, `MAX_BITS_PER_SAMPLE`.
+    pub max_bits_per_sample: u32,
+
+    /// Whether frames can refer to the streaminfo block for the bits per sample.
+    pub bits_per_sample_from_streaminfo: bool,
+
+    /// Whether residual partitions with an escape code (unencoded binary) are supported.
+    pub escaped_partitions: bool,
+
+    /// Whether LPC subframes with a negative coefficient shift are supported.
+    pub negative_qlp_shift: bool,
+
+    /// Whether the `mp4` module is available.
+    pub mp4: bool,
+
+    /// Whether decode events are logged through the `log` crate.
+    pub log: bool,
+
+    /// Whether SIMD implementations of decoding routines are active.
+    ///
+    /// There are no SIMD implementations yet, so this is false, also when
+    /// `simd_level()` detects instruction set extensions.
+    pub simd: bool,
+}
+
+/// Returns which parts of the FLAC format this build of Claxon can decode.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        max_bits_per_sample: MAX_BITS_PER_SAMPLE,
+        bits_per_sample_from_streaminfo: false,
+        escaped_partitions: false,
+        negative_qlp_shift: false,
+        mp4: cfg!(feature = "mp4"),
+        log: cfg!(feature = "log"),
+        simd: dispatch::kernels().level != SimdLevel::Scalar,
+    }
+}
+
+/// The settings of a `FlacReader` that apply to the frame readers it creates.
+#[derive(Clone, Copy, Default)]
+struct FrameSettings {
+    fixed_block_size: u16,
+    limits: FrameLimits,
+    ordering: FrameOrdering,
+    channels: ChannelSelection,
+    reserved_values: ReservedValues,
+    lenient: bool,
+    collect_stats: bool,
+}
+
+impl FrameSettings {
+    /// Returns the default settings for the stream.
+    fn new(streaminfo: &StreamInfo) -> FrameSettings {
+        // In a fixed block size stream, all frames but the last one have the
+        // maximum block size.
+        FrameSettings {
+            fixed_block_size: streaminfo.max_block_size,
+            ..FrameSettings::default()
+        }
+    }
+
+    /// Creates a frame reader with these settings.
+    fn new_frame_reader<R: ReadBytes>(&self, input: R) -> FrameReader<R> {
+        let mut frame_reader = FrameReader::with_limits(input, self.limits);
+        frame_reader.set_fixed_block_size(self.fixed_block_size);
+        frame_reader.set_ordering(self.ordering);
+        frame_reader.set_channels(self.channels);
+        frame_reader.set_reserved_values(self.reserved_values);
+        frame_reader.set_lenient(self.lenient);
+        frame_reader.set_collect_stats(self.collect_stats);
+        frame_reader
+    }
+}
+
+/// Reads only the streaminfo block of the FLAC file at the given path.
+///
+/// This is the cheapest way to get the streaminfo of a file: it performs a
+/// single read of 42 bytes (the stream header, the metadata block header, and
+/// the streaminfo block itself) into a buffer on the stack. No `FlacReader` is
+/// constructed and no buffer is allocated on the heap. This makes it suitable
+/// for scanning large collections of files.
+///
+/// The FLAC specification dictates that the streaminfo block is the first
+/// metadata block in the stream, so no other metadata is read. To read tags as
+/// well, use `FlacReader::open_ext()` with `metadata_only` set instead.
+pub fn read_streaminfo<P: AsRef<path::Path>>(filename: P) -> Result<StreamInfo> {
+    use std::io::Read;
+
+    // The stream header is 4 bytes, the metadata block header 4 bytes, and the
+    // streaminfo block has a fixed size of 34 bytes.
+    let mut bytes = [0u8; 4 + 4 + 34];
+    let mut file = try!(fs::File::open(filename));
+    try!(file.read_exact(&mut bytes));
+
+    parse_streaminfo(&bytes)
+}
+
+/// Parses the stream header and streaminfo block at the start of a stream.
+fn parse_streaminfo(bytes: &[u8]) -> Result<StreamInfo> {
+    let mut input = io::Cursor::new(bytes);
+    try!(read_stream_header(&mut input));
+    match try!(metadata::read_metadata_block_with_header(&mut input)) {
+        MetadataBlock::StreamInfo(streaminfo) => Ok(streaminfo),
+        _ => fmt_err("streaminfo block missing"),
+    }
+}
+
+/// A reader that yields the bytes consumed by `probe()`, followed by the rest of the stream.
+pub type Probed<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+/// Reads the streaminfo from the start of a stream, without losing any bytes.
+///
+/// This reads the first 42 bytes of the stream, like `read_streaminfo()`, and
+/// parses them. Regardless of whether that succeeds, it returns a reader that
+/// replays those bytes, and then continues with the original reader. This
+/// allows a layer that decides between decoders to sniff the format of a
+/// stream that does not implement `Seek`, and to hand the stream to whichever
+/// decoder can handle it, for instance `FlacReader::new()`.
+pub fn probe<R: io::Read>(mut reader: R) -> (Result<StreamInfo>, Probed<R>) {
+    use std::io::Read;
+
+    let mut bytes = vec![0u8; 4 + 4 + 34];
+    let mut len = 0;
+    let mut read_error = None;
+    while len < bytes.len() {
+        match reader.read(&mut bytes[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                read_error = Some(err);
+                break
+            }
+        }
+    }
+    bytes.truncate(len);
+
+    // A stream that is too short fails to parse with an unexpected EOF.
+    let result = match read_error {
+        Some(err) => Err(Error::IoError(err)),
+        None => parse_streaminfo(&bytes),
+    };
+    (result, io::Cursor::new(bytes).chain(reader))
+}
+
 impl<R: io::Read> FlacReader<R> {
     /// Create a reader that reads the FLAC format.
     ///
@@ -218,6 +1127,21 @@ impl<R: io::Read> FlacReader<R> {
         FlacReader::new_ext(reader, FlacReaderOptions::default())
     }
 
+    /// Create a reader for a FLAC stream of `len` bytes embedded in a larger stream.
+    ///
+    /// The stream must start at the current position of `reader`. The reader
+    /// never reads past the `len` bytes, so data that follows the stream, for
+    /// instance in an archive or bundle, is left alone, and the end of the
+    /// stream at `len` bytes is treated as the end of the audio data. The
+    /// bound is enforced by the internal buffer, so skipping and seeking stay
+    /// as efficient as without a bound. A frame or metadata block that extends
+    /// past the bound results in `Error::BoundExceeded`.
+    pub fn new_bounded(reader: R, len: u64) -> Result<FlacReader<R>> {
+        let mut buf_reader = BufferedReader::new(reader);
+        buf_reader.set_bound(len);
+        FlacReader::new_with_buffer(buf_reader, FlacReaderOptions::default())
+    }
+
     /// Create a reader that reads the FLAC format, with reader options.
     ///
     /// The header and metadata blocks are read immediately, but only as much as
@@ -228,63 +1152,109 @@ impl<R: io::Read> FlacReader<R> {
     /// small damaged or malicous file could cause gigabytes of memory
     /// to be allocated. `Error::Unsupported` is returned in that case.
     pub fn new_ext(reader: R, options: FlacReaderOptions) -> Result<FlacReader<R>> {
-        let mut buf_reader = BufferedReader::new(reader);
-        let mut opts_current = options;
-
-        // A flac stream first of all starts with a stream header.
-        try!(read_stream_header(&mut buf_reader));
-
-        // Start a new scope, because the input reader must be available again
-        // for the frame reader next.
-        let (streaminfo, vorbis_comment) = {
-            // Next are one or more metadata blocks. The flac specification
-            // dictates that the streaminfo block is the first block. The metadata
-            // block reader will yield at least one element, so the unwrap is safe.
-            let mut metadata_iter = MetadataBlockReader::new(&mut buf_reader);
-            let streaminfo_block = try!(metadata_iter.next().unwrap());
-            let streaminfo = match streaminfo_block {
-                MetadataBlock::StreamInfo(info) => info,
-                _ => return fmt_err("streaminfo block missing"),
-            };
+        FlacReader::new_with_buffer(BufferedReader::new(reader), options)
+    }
 
-            let mut vorbis_comment = None;
-
-            // There might be more metadata blocks, read and store them.
-            for block_result in metadata_iter {
-                match try!(block_result) {
-                    MetadataBlock::VorbisComment(vc) => {
-                        // The Vorbis comment block need not be present, but
-                        // when it is, it must be unique.
-                        if vorbis_comment.is_some() {
-                            return fmt_err("encountered second Vorbis comment block")
-                        } else {
-                            vorbis_comment = Some(vc);
-                        }
+    /// Create a reader with reader options, and an input buffer of the given size.
+    ///
+    /// This is like `new_ext()`, with a hint for the size of the buffer that
+    /// holds data read from `reader`. The default is 2048 bytes. When reading
+    /// only the metadata of many small inputs, a buffer that fits just the
+    /// metadata of interest reduces the size of the buffer allocated per
+    /// input. For the streaminfo alone, 42 bytes suffice. The buffer is also
+    /// used to decode frames, so for decoding audio, the default is a good
+    /// choice. Capacities smaller than a frame header (16 bytes) are rounded
+    /// up to 16 bytes.
+    pub fn new_ext_with_capacity(reader: R,
+                                 options: FlacReaderOptions,
+                                 capacity: usize)
+                                 -> Result<FlacReader<R>> {
+        let capacity = cmp::max(capacity, MAX_FRAME_HEADER_LEN);
+        FlacReader::new_with_buffer(BufferedReader::with_capacity(reader, capacity), options)
+    }
 
-                        // We have one, no new one is desired.
-                        opts_current.read_vorbis_comment = false;
-                    }
-                    MetadataBlock::StreamInfo(..) => {
-                        return fmt_err("encountered second streaminfo block")
-                    }
-                    // Other blocks are currently not handled.
-                    _block => {}
-                }
+    /// Create a reader with reader options, that uses the given input buffer.
+    ///
+    /// This is like `new_ext_with_capacity()`, but the buffer is provided by
+    /// the caller, and its capacity determines the size of the buffer. Its
+    /// contents are irrelevant. Use `into_parts()` to get the buffer back.
+    /// When scanning many inputs, reusing one buffer avoids allocating a new
+    /// buffer per input. A buffer with a capacity smaller than 16 bytes is
+    /// grown to 16 bytes.
+    pub fn new_ext_with_buffer(reader: R,
+                               options: FlacReaderOptions,
+                               buffer: Vec<u8>)
+                               -> Result<FlacReader<R>> {
+        let mut buffer = buffer;
+        let len = buffer.len();
+        if buffer.capacity() < MAX_FRAME_HEADER_LEN {
+            buffer.reserve_exact(MAX_FRAME_HEADER_LEN - len);
+        }
+        FlacReader::new_with_buffer(BufferedReader::with_buffer(reader, buffer), options)
+    }
 
-                // Early-out reading metadata once all desired blocks have been
-                // collected.
-                if !opts_current.has_desired_blocks() {
-                    break
-                }
-            }
+    fn new_with_buffer(mut buf_reader: BufferedReader<R>,
+                       options: FlacReaderOptions)
+                       -> Result<FlacReader<R>> {
+        let (streaminfo, streaminfo_bytes, mut blocks) =
+            try!(read_metadata_start(&mut buf_reader, &options));
+        let is_complete = try!(blocks.read(&mut buf_reader, &options, options));
+        FlacReader::with_metadata(buf_reader,
+                                  options,
+                                  streaminfo,
+                                  streaminfo_bytes,
+                                  blocks,
+                                  is_complete)
+    }
+
+    /// Finishes constructing a reader, once the metadata blocks have been read.
+    fn with_metadata(mut buf_reader: BufferedReader<R>,
+                     options: FlacReaderOptions,
+                     streaminfo: StreamInfo,
+                     streaminfo_bytes: [u8; 38],
+                     blocks: MetadataBlocks,
+                     is_complete: bool)
+                     -> Result<FlacReader<R>> {
+        let mut blocks = blocks;
+
+        // TODO: Rather than discarding afterwards, never parse it in the
+        // first place; treat it like padding in the MetadataBlockReader.
+        if !options.read_vorbis_comment {
+            blocks.vorbis_comment = None;
+        }
+        if !options.read_seek_table {
+            blocks.seek_table = None;
+        }
 
-            // TODO: Rather than discarding afterwards, never parse it in the
-            // first place; treat it like padding in the MetadataBlockReader.
-            if !options.read_vorbis_comment {
-                vorbis_comment = None;
+        if options.length_check != LengthCheck::Ignore {
+            let conflicts = metadata::length_conflicts(&streaminfo,
+                                                       blocks.seek_table.as_ref(),
+                                                       blocks.cue_sheet.as_ref());
+            if !conflicts.is_empty() && options.length_check == LengthCheck::Fail {
+                return fmt_err("metadata blocks disagree about the length of the stream")
             }
+            for conflict in conflicts {
+                blocks.warnings.push(MetadataWarning::LengthConflict(conflict));
+            }
+        }
 
-            (streaminfo, vorbis_comment)
+        // Reject streams that the frame decoder cannot handle up front, rather
+        // than failing on the first frame.
+        if !options.metadata_only && streaminfo.bits_per_sample > MAX_BITS_PER_SAMPLE {
+            return Err(Error::Unsupported(UnsupportedFeature::BitsPerSampleAbove24));
+        }
+
+        let skip_junk = is_complete && !options.metadata_only && options.max_junk_len > 0;
+        let junk_len = if skip_junk {
+            try!(skip_leading_junk(&mut buf_reader, options.max_junk_len))
+        } else {
+            0
+        };
+
+        let first_frame_offset = if is_complete {
+            Some(buf_reader.position())
+        } else {
+            None
         };
 
         // Even if we might have read all metadata blocks, only set the state to
@@ -299,13 +1269,63 @@ impl<R: io::Read> FlacReader<R> {
         // The flac reader will contain the reader that will read frames.
         let flac_reader = FlacReader {
             streaminfo: streaminfo,
-            vorbis_comment: vorbis_comment,
+            streaminfo_bytes: Some(streaminfo_bytes),
+            vorbis_comment: blocks.vorbis_comment,
+            seek_table: blocks.seek_table,
+            pictures: blocks.pictures,
+            cue_sheet: blocks.cue_sheet,
+            metadata_warnings: blocks.warnings,
+            first_frame_offset: first_frame_offset,
+            junk_len: junk_len,
+            frame_settings: FrameSettings::new(&streaminfo),
+            length_check: options.length_check,
             input: state,
         };
 
         Ok(flac_reader)
     }
 
+    /// Create a reader for a stream that consists of frames only.
+    ///
+    /// Some tools and containers carry bare FLAC frames, without the `fLaC`
+    /// stream header and metadata blocks. This constructor derives the
+    /// streaminfo from the header of the first frame, and the hints. The first
+    /// frame is not consumed, so all samples can be read afterwards.
+    ///
+    /// The frame headers of such a stream must specify the sample rate and bits
+    /// per sample, as there is no streaminfo block to refer to. If the first
+    /// frame header does not, `Error::Unsupported` is returned.
+    pub fn new_headerless(reader: R, hints: HeaderlessHints) -> Result<FlacReader<R>> {
+        let mut buf_reader = BufferedReader::new(reader);
+        let mut streaminfo = {
+            let header = try!(buf_reader.peek(MAX_FRAME_HEADER_LEN));
+            try!(frame::streaminfo_from_frame_header(&mut io::Cursor::new(header)))
+        };
+
+        if let Some(max_block_size) = hints.max_block_size {
+            streaminfo.max_block_size = max_block_size;
+            streaminfo.min_block_size = cmp::min(streaminfo.min_block_size, max_block_size);
+        }
+        streaminfo.samples = hints.samples;
+
+        let flac_reader = FlacReader {
+            streaminfo: streaminfo,
+            streaminfo_bytes: None,
+            vorbis_comment: None,
+            seek_table: None,
+            pictures: Vec::new(),
+            cue_sheet: None,
+            metadata_warnings: Vec::new(),
+            first_frame_offset: Some(0),
+            junk_len: 0,
+            frame_settings: FrameSettings::new(&streaminfo),
+            length_check: LengthCheck::Warn,
+            input: FlacReaderState::Full(buf_reader),
+        };
+
+        Ok(flac_reader)
+    }
+
     /// Returns the streaminfo metadata.
     ///
     /// This contains information like the sample rate and number of channels.
@@ -313,6 +1333,44 @@ impl<R: io::Read> FlacReader<R> {
         self.streaminfo
     }
 
+    /// Returns the position in bytes where the first frame starts.
+    ///
+    /// The position is relative to the start of the stream, the position of
+    /// the reader when the `FlacReader` was constructed. An application can
+    /// store it together with the streaminfo, and on a later play seek the
+    /// reader directly to this position and decode with a `FrameReader`,
+    /// skipping the metadata entirely.
+    ///
+    /// Returns `None` if not all metadata blocks were read, which can happen
+    /// when `FlacReaderOptions::metadata_only` is set. For a reader created with
+    /// `new_headerless()`, this is `Some(0)`.
+    pub fn first_frame_offset(&self) -> Option<u64> {
+        self.first_frame_offset
+    }
+
+    /// Returns the number of bytes of junk that were skipped before the first frame.
+    ///
+    /// This is nonzero only if `FlacReaderOptions::max_junk_len` allowed
+    /// skipping, and the stream needed it. A nonzero value indicates a faulty
+    /// writer. The first frame offset points past the junk.
+    pub fn junk_len(&self) -> u32 {
+        self.junk_len
+    }
+
+    /// Returns the streaminfo block exactly as it was stored in the stream.
+    ///
+    /// The 38 bytes consist of the 4-byte metadata block header, followed by
+    /// the 34-byte body. This is useful to compare streams with a fast byte
+    /// comparison, or to emit the block verbatim when remuxing, without the
+    /// normalization that reserializing `streaminfo()` might introduce. Note
+    /// that the header includes the flag that marks the last metadata block.
+    ///
+    /// Returns `None` for a reader created with `new_headerless()`, as such a
+    /// stream has no streaminfo block.
+    pub fn streaminfo_bytes(&self) -> Option<&[u8]> {
+        self.streaminfo_bytes.as_ref().map(|bytes| &bytes[..])
+    }
+
     /// Returns the vendor string of the Vorbis comment block, if present.
     ///
     /// This string usually contains the name and version of the program that
@@ -322,6 +1380,70 @@ impl<R: io::Read> FlacReader<R> {
         self.vorbis_comment.as_ref().map(|vc| &vc.vendor[..])
     }
 
+    /// Returns the seek table, if the stream has one and it was read.
+    ///
+    /// The seek table lists the offsets of some frames, relative to the first
+    /// frame, so applications can jump close to a sample without searching
+    /// for it. See `SeekTable::point_at_or_before()`. It is `None` when
+    /// `FlacReaderOptions::read_seek_table` is false, and with `metadata_only`,
+    /// also when reading stopped before the seek table.
+    pub fn seek_table(&self) -> Option<&SeekTable> {
+        self.seek_table.as_ref()
+    }
+
+    /// Returns the pictures embedded in the stream, such as cover art.
+    ///
+    /// The pictures are in stream order. Use `Picture::is_front_cover()` to
+    /// find the cover of the album. This is empty unless
+    /// `FlacReaderOptions::read_pictures` is true.
+    pub fn pictures(&self) -> &[Picture] {
+        &self.pictures
+    }
+
+    /// Returns the cue sheet, if the stream has one and it was read.
+    ///
+    /// A stream that holds a rip of a whole CD usually has a cue sheet that
+    /// lists the track offsets. With `FlacReaderOptions::metadata_only`, this
+    /// is `None` when reading stopped before the cue sheet. Without the
+    /// `full-metadata` feature, cue sheets are never parsed, and this is
+    /// always `None`.
+    pub fn cue_sheet(&self) -> Option<&CueSheet> {
+        self.cue_sheet.as_ref()
+    }
+
+    /// Returns the duration of a track in the cue sheet.
+    ///
+    /// This is the time that a CD player would play the track for, see
+    /// `CueSheet::track_samples()`. Returns `None` if there is no cue sheet,
+    /// or if it has no track with the given number.
+    pub fn track_duration(&self, track_number: u8) -> Option<Duration> {
+        let samples = match self.cue_sheet {
+            Some(ref cue_sheet) => cue_sheet.track_samples(track_number),
+            None => None,
+        };
+        samples.map(|n| frame::samples_to_duration(n, self.streaminfo.sample_rate))
+    }
+
+    /// Returns the problems in the metadata that were tolerated, in stream order.
+    ///
+    /// Some problems, such as a wrong flag that marks the last metadata block,
+    /// are only tolerated when `FlacReaderOptions::lenient_metadata` is set.
+    /// Others, such as an invalid seek table, are tolerated unless
+    /// `FlacReaderOptions::strict_metadata` is set. A file with warnings
+    /// decodes fine, but it was likely written by a faulty tool.
+    pub fn metadata_warnings(&self) -> &[MetadataWarning] {
+        &self.metadata_warnings
+    }
+
+    /// Returns information about the encoder of the stream, based on heuristics.
+    ///
+    /// This combines the vendor string, the block size, and tags that record
+    /// encoder settings. See `EncoderInfo` for the caveats. If the Vorbis
+    /// comment block was not read, only the block size is taken into account.
+    pub fn encoder_info(&self) -> EncoderInfo {
+        EncoderInfo::new(&self.streaminfo, self.vorbis_comment.as_ref())
+    }
+
     /// Returns name-value pairs of Vorbis comments, such as `("ARTIST", "Queen")`.
     ///
     /// The name is supposed to be interpreted case-insensitively, and is
@@ -333,10 +1455,7 @@ impl<R: io::Read> FlacReader<R> {
     ///
     /// See <https://www.xiph.org/vorbis/doc/v-comment.html> for more details.
     pub fn tags<'a>(&'a self) -> metadata::Tags<'a> {
-        match self.vorbis_comment.as_ref() {
-            Some(vc) => metadata::Tags::new(&vc.comments[..]),
-            None => metadata::Tags::new(&[]),
-        }
+        metadata::Tags::new(self.vorbis_comment.as_ref())
     }
 
     /// Look up a Vorbis comment such as `ARTIST` in a case-insensitive way.
@@ -352,10 +1471,62 @@ impl<R: io::Read> FlacReader<R> {
     /// See also `tags()` for access to the raw tags.
     /// See <https://www.xiph.org/vorbis/doc/v-comment.html> for more details.
     pub fn get_tag<'a>(&'a self, tag_name: &'a str) -> metadata::GetTag<'a> {
-        match self.vorbis_comment.as_ref() {
-            Some(vc) => metadata::GetTag::new(&vc.comments[..], tag_name),
-            None => metadata::GetTag::new(&[], tag_name),
-        }
+        metadata::GetTag::new(self.vorbis_comment.as_ref(), tag_name)
+    }
+
+    /// Sets the limits on the resources that decoding a single frame may use.
+    ///
+    /// The limits apply to frame readers and sample iterators that are created
+    /// afterwards. By default, the limits allow any valid stream. See
+    /// `FrameLimits` for more details.
+    pub fn set_frame_limits(&mut self, limits: FrameLimits) {
+        self.frame_settings.limits = limits;
+    }
+
+    /// Sets what to do with frames that are out of order.
+    ///
+    /// This applies to frame readers and sample iterators that are created
+    /// afterwards. By default, a frame that is out of order is an error. See
+    /// `FrameOrdering` for more details.
+    pub fn set_frame_ordering(&mut self, ordering: FrameOrdering) {
+        self.frame_settings.ordering = ordering;
+    }
+
+    /// Sets which channels are decoded, to select a subset or mix down to stereo.
+    ///
+    /// This applies to frame readers and sample iterators that are created
+    /// afterwards. By default, all channels are decoded. Note that the number
+    /// of channels in the streaminfo is that of the stream, not the number of
+    /// channels produced. See `ChannelSelection` for more details.
+    pub fn set_channel_selection(&mut self, selection: ChannelSelection) {
+        self.frame_settings.channels = selection;
+    }
+
+    /// Sets how to report values that the format reserves for future use.
+    ///
+    /// This applies to frame readers and sample iterators that are created
+    /// afterwards. By default, a reserved value is a format error. See
+    /// `ReservedValues` for more details.
+    pub fn set_reserved_values(&mut self, policy: ReservedValues) {
+        self.frame_settings.reserved_values = policy;
+    }
+
+    /// Sets whether to decode frames that have CRC mismatches or reserved bits set.
+    ///
+    /// This applies to frame readers and sample iterators that are created
+    /// afterwards. By default, such a frame is an error. See
+    /// `FrameReader::set_lenient()` for more details.
+    pub fn set_lenient_frames(&mut self, lenient: bool) {
+        self.frame_settings.lenient = lenient;
+    }
+
+    /// Sets whether to collect buffer and frame size statistics.
+    ///
+    /// This applies to frame readers and sample iterators that are created
+    /// afterwards. By default, no statistics are collected. See
+    /// `FrameReader::set_collect_stats()` for more details.
+    pub fn set_collect_stats(&mut self, collect_stats: bool) {
+        self.frame_settings.collect_stats = collect_stats;
     }
 
     /// Returns an iterator that decodes a single frame on every iteration.
@@ -365,11 +1536,15 @@ impl<R: io::Read> FlacReader<R> {
     /// happens. The representation of the decoded audio is somewhat specific to
     /// the FLAC format. For a higher-level interface, see `samples()`.
     pub fn blocks<'r>(&'r mut self) -> FrameReader<&'r mut BufferedReader<R>> {
+        let settings = self.frame_settings;
         match self.input {
-            FlacReaderState::Full(ref mut inp) => FrameReader::new(inp),
-            FlacReaderState::MetadataOnly(..) =>
+            FlacReaderState::Full(ref mut inp) => {
+                settings.new_frame_reader(inp)
+            }
+            FlacReaderState::MetadataOnly(..) => {
                 panic!("FlacReaderOptions::metadata_only must be false \
-                       to be able to use FlacReader::blocks()"),
+                       to be able to use FlacReader::blocks()")
+            }
         }
     }
 
@@ -391,17 +1566,30 @@ impl<R: io::Read> FlacReader<R> {
     /// be decoded. Additionally, there is a cost to every iteration returning a
     /// `Result`. When a block has been decoded, iterating the samples in that
     /// block can never fail, but a match on every sample is required
-    /// nonetheless. For more control over when decoding happens, and less error
-    /// handling overhead, use `blocks()`.
+    /// nonetheless. To avoid that, but still receive samples one by one, use
+    /// `FlacSamples::for_each_sample()`. For more control over when decoding
+    /// happens, use `blocks()`.
+    ///
+    /// A stream that ends right after the metadata has no frames. That is not
+    /// an error: the iterator yields no samples, and `blocks()` returns `None`
+    /// on the first read.
     pub fn samples<'r>(&'r mut self) -> FlacSamples<&'r mut BufferedReader<R>> {
+        let settings = self.frame_settings;
         match self.input {
             FlacReaderState::Full(ref mut inp) => {
                 FlacSamples {
-                    frame_reader: frame::FrameReader::new(inp),
+                    frame_reader: settings.new_frame_reader(inp),
                     block: Block::empty(),
                     sample: 0,
                     channel: 0,
                     has_failed: false,
+                    skipped_frames: 0,
+                    skipped_bytes: 0,
+                    total_samples: self.streaminfo.samples,
+                    length_check: self.length_check,
+                    decoded_samples: None,
+                    length_conflict: None,
+                    pool: None,
                 }
             }
             FlacReaderState::MetadataOnly(..) => {
@@ -411,20 +1599,152 @@ impl<R: io::Read> FlacReader<R> {
         }
     }
 
+    /// Returns an iterator over all samples, fed through a resampler.
+    ///
+    /// The iterator decodes blocks, passes the channels of every block to the
+    /// resampler, and yields the resampled output interleaved, like `samples()`
+    /// does. At the end of the stream, the resampler is flushed. A block whose
+    /// format differs from the streaminfo is an error. Claxon does not
+    /// implement resampling itself, see the `resample` module for more details.
+    pub fn samples_resampled<'r, S: Resampler>(&'r mut self,
+                                               resampler: S)
+                                               -> ResampledSamples<&'r mut BufferedReader<R>, S> {
+        let mut spec = self.streaminfo.spec();
+        spec.channels = self.frame_settings.channels.output_channels(spec.channels);
+        let settings = self.frame_settings;
+        match self.input {
+            FlacReaderState::Full(ref mut inp) => {
+                let frame_reader = settings.new_frame_reader(inp);
+                ResampledSamples::new(frame_reader, resampler, spec)
+            }
+            FlacReaderState::MetadataOnly(..) => {
+                panic!("FlacReaderOptions::metadata_only must be false \
+                       to be able to use FlacReader::samples_resampled()")
+            }
+        }
+    }
+
+    /// Returns an iterator over the peak and RMS level of windows of the stream.
+    ///
+    /// Every window covers `window` of audio, rounded up to whole samples,
+    /// except for the final window, which can be shorter. This is useful for
+    /// level meters and silence detection. The levels are computed from the
+    /// decoded blocks directly, without interleaving the samples. The
+    /// configured limits and channel selection apply as for `samples()`, and
+    /// the iterator is streaming in the same way.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn levels<'r>(&'r mut self, window: Duration) -> Levels<&'r mut BufferedReader<R>> {
+        let window_len = frame::duration_to_samples(window, self.streaminfo.sample_rate);
+        assert!(window_len > 0, "window must not be empty");
+        let window_len = cmp::min(window_len, u32::max_value() as u64) as u32;
+        let channels = self.frame_settings.channels.output_channels(self.streaminfo.channels);
+        let settings = self.frame_settings;
+        match self.input {
+            FlacReaderState::Full(ref mut inp) => {
+                let frame_reader = settings.new_frame_reader(inp);
+                Levels::new(frame_reader, window_len, channels)
+            }
+            FlacReaderState::MetadataOnly(..) => {
+                panic!("FlacReaderOptions::metadata_only must be false \
+                       to be able to use FlacReader::levels()")
+            }
+        }
+    }
+
+    /// Returns an iterator over interleaved chunks of `frames_per_chunk` samples.
+    ///
+    /// Every chunk holds `frames_per_chunk` inter-channel samples, regardless
+    /// of the block boundaries in the stream, except for the final chunk, which
+    /// can be shorter and is flagged by `Chunk::is_partial()`. This matches the
+    /// fixed buffer size that audio output callbacks usually ask for. The
+    /// configured limits and channel selection apply as for `samples()`, and
+    /// the iterator is streaming in the same way.
+    ///
+    /// Chunks hold `i32` samples; `Chunk::samples_i16_into()` and
+    /// `Chunk::samples_f32_into()` convert them for outputs that expect `i16`
+    /// or `f32` samples. An empty stream yields no chunks at all. To avoid
+    /// allocating a buffer for every chunk, use `FlacChunks::read_next_or_eof()`
+    /// instead of iterating.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `frames_per_chunk` is 0.
+    pub fn chunks<'r>(&'r mut self,
+                      frames_per_chunk: u32)
+                      -> FlacChunks<&'r mut BufferedReader<R>> {
+        assert!(frames_per_chunk > 0, "chunks must contain at least one sample");
+        let mut spec = self.streaminfo.spec();
+        spec.channels = self.frame_settings.channels.output_channels(spec.channels);
+        let settings = self.frame_settings;
+        match self.input {
+            FlacReaderState::Full(ref mut inp) => {
+                FlacChunks {
+                    frame_reader: settings.new_frame_reader(inp),
+                    block: Block::empty(),
+                    pos: 0,
+                    frames_per_chunk: frames_per_chunk,
+                    spec: spec,
+                    has_failed: false,
+                }
+            }
+            FlacReaderState::MetadataOnly(..) => {
+                panic!("FlacReaderOptions::metadata_only must be false \
+                       to be able to use FlacReader::chunks()")
+            }
+        }
+    }
+
+    /// Returns an iterator over the samples of the next `duration` of audio.
+    ///
+    /// The iterator yields interleaved samples like `samples()` does, and
+    /// stops after the inter-channel sample at which `duration` is reached,
+    /// counted from the current position. When the cut lands inside a block,
+    /// that block is decoded in full, but the samples after the cut are not
+    /// yielded, and no further input is read. When the stream is shorter, the
+    /// iterator ends with the stream, which works whether or not the total
+    /// number of samples is known from the streaminfo.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `metadata_only` was set when the reader was constructed,
+    /// like `samples()`.
+    pub fn take_duration<'r>(&'r mut self,
+                             duration: Duration)
+                             -> TakeDuration<&'r mut BufferedReader<R>> {
+        let channels = self.frame_settings.channels.output_channels(self.streaminfo.channels);
+        let frames = frame::duration_to_samples(duration, self.streaminfo.sample_rate);
+        TakeDuration {
+            inner: self.samples(),
+            remaining: frames.saturating_mul(channels as u64),
+        }
+    }
+
     /// Same as `samples`, but takes ownership of the `FlacReader`.
     ///
     /// See `samples()` for more info.
     pub fn into_samples(self) -> FlacIntoSamples<BufferedReader<R>> {
+        let settings = self.frame_settings;
         match self.input {
             FlacReaderState::Full(inp) => {
                 FlacIntoSamples {
                     inner: FlacSamples {
-                        frame_reader: frame::FrameReader::new(inp),
+                        frame_reader: settings.new_frame_reader(inp),
                         block: Block::empty(),
                         sample: 0,
                         channel: 0,
                         has_failed: false,
-                    }
+                        skipped_frames: 0,
+                        skipped_bytes: 0,
+                        total_samples: self.streaminfo.samples,
+                        length_check: self.length_check,
+                        decoded_samples: None,
+                        length_conflict: None,
+                        pool: None,
+                    },
+                    channels: settings.channels.output_channels(self.streaminfo.channels),
                 }
             }
             FlacReaderState::MetadataOnly(..) => {
@@ -434,6 +1754,65 @@ impl<R: io::Read> FlacReader<R> {
         }
     }
 
+    /// Computes a checksum of the remaining audio data, without decoding it.
+    ///
+    /// The checksum is a 64-bit FNV-1a hash of the encoded frames, from the
+    /// current position to the end of the stream. Because the metadata is not
+    /// included, it stays the same when only tags or pictures are changed,
+    /// which makes it useful for library managers to detect that the audio of
+    /// a file changed, at the cost of reading the file, but not decoding it.
+    /// FNV-1a processes one byte at a time, so it is simple rather than fast:
+    /// it is much cheaper than decoding, but slower than reading from a fast
+    /// disk.
+    /// Like the frame CRCs, it identifies the encoded bytes, so re-encoding the
+    /// same samples produces a different checksum; use the MD5 signature in
+    /// the streaminfo to compare decoded audio. The hash is not cryptographic.
+    ///
+    /// Use `FrameReader::last_frame_checksum()` for the CRC-16 of every frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `metadata_only` was set when the reader was constructed.
+    pub fn audio_checksum(self) -> Result<u64> {
+        let mut input = match self.input {
+            FlacReaderState::Full(inp) => inp,
+            FlacReaderState::MetadataOnly(..) => {
+                panic!("FlacReaderOptions::metadata_only must be false \
+                       to be able to use FlacReader::audio_checksum()")
+            }
+        };
+        let mut state = crc::FNV1A_64_INIT;
+        loop {
+            // Peeking a single byte fills the buffer when it is empty.
+            if try!(input.peek(1)).is_empty() {
+                return Ok(state)
+            }
+            let len = {
+                let buffer = input.buffer();
+                state = crc::fnv1a_64_update(state, buffer);
+                buffer.len()
+            };
+            try!(input.skip(len as u32));
+        }
+    }
+
+    /// Returns the heap memory held by the reader, see `MemoryUsage`.
+    ///
+    /// The reader itself does not hold a block buffer, so `block_buffer` is 0.
+    /// Use `FlacSamples::memory_usage()` for the buffer of a sample iterator.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let input = match self.input {
+            FlacReaderState::Full(ref inp) => inp,
+            FlacReaderState::MetadataOnly(ref inp) => inp,
+        };
+        MemoryUsage {
+            input_buffer: input.capacity(),
+            block_buffer: 0,
+            metadata: self.vorbis_comment.as_ref().map_or(0, |vc| vc.heap_size()) +
+                self.pictures.iter().map(|p| p.heap_size()).sum::<usize>(),
+        }
+    }
+
     /// Destroys the FLAC reader and returns the underlying reader.
     ///
     /// Because the reader employs buffering internally, anything in the buffer
@@ -444,89 +1823,1450 @@ impl<R: io::Read> FlacReader<R> {
             FlacReaderState::MetadataOnly(inp) => inp.into_inner(),
         }
     }
-}
 
-impl FlacReader<fs::File> {
-    /// Attempts to create a reader that reads from the specified file.
+    /// Destroys the FLAC reader and returns the underlying reader and buffer.
     ///
-    /// This is a convenience constructor that opens a `File`, and constructs a
-    /// `FlacReader` from it. There is no need to wrap the file in a
-    /// `BufReader`, as the `FlacReader` employs buffering already.
-    pub fn open<P: AsRef<path::Path>>(filename: P) -> Result<FlacReader<fs::File>> {
-        let file = try!(fs::File::open(filename));
-        FlacReader::new(file)
+    /// The buffer can be passed to `new_ext_with_buffer()` to read another
+    /// input without allocating a new buffer. Anything in the buffer will be
+    /// lost.
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        match self.input {
+            FlacReaderState::Full(inp) => inp.into_parts(),
+            FlacReaderState::MetadataOnly(inp) => inp.into_parts(),
+        }
     }
+}
 
-    /// Attemps to create a reader that reads from the specified file.
+impl<R: io::Read + io::Seek> FlacReader<R> {
+    /// Estimates the duration of the stream, also when the streaminfo lacks it.
     ///
-    /// This is a convenience constructor that opens a `File`, and constructs a
-    /// `FlacReader` from it. There is no need to wrap the file in a
-    /// `BufReader`, as the `FlacReader` employs buffering already.
-    pub fn open_ext<P: AsRef<path::Path>>(filename: P,
-                                          options: FlacReaderOptions)
-                                          -> Result<FlacReader<fs::File>> {
-        let file = try!(fs::File::open(filename));
-        FlacReader::new_ext(file, options)
-    }
-}
+    /// If the streaminfo specifies the number of samples, that number is
+    /// returned as an exact estimate. Otherwise, the length of the stream is
+    /// determined by seeking, and the first and final frame are decoded. If
+    /// that succeeds, the estimate is exact too. (For a truncated stream, this
+    /// is the duration up to the last complete frame.) If no frame can be
+    /// decoded from the tail, the duration is estimated from the length of the
+    /// stream and the frame sizes in the streaminfo, see
+    /// `StreamInfo::estimate_duration()`. For a stream without frames, the
+    /// estimate is exactly zero.
+    ///
+    /// The position of the reader is restored afterwards, so it is possible to
+    /// call this method before or during decoding.
+    pub fn estimate_duration(&mut self) -> Result<DurationEstimate> {
+        if self.streaminfo.samples.is_some() {
+            return Ok(self.streaminfo.estimate_duration(0));
+        }
 
-impl<R: ReadBytes> Iterator for FlacSamples<R> {
-    type Item = Result<i32>;
+        let input = match self.input {
+            FlacReaderState::Full(ref mut inp) => inp,
+            FlacReaderState::MetadataOnly(ref mut inp) => inp,
+        };
 
-    fn next(&mut self) -> Option<Result<i32>> {
-        // If the previous read failed, end iteration.
-        if self.has_failed {
-            return None;
-        }
+        // The position of the inner reader is ahead of the buffered reader,
+        // by the number of bytes that are still in the buffer.
+        let stream_len = try!(input.stream_len());
+        let inner_pos = try!(input.get_mut().seek(io::SeekFrom::Current(0)));
+        let stream_pos = input.position();
+        let end_pos = stream_pos + input.buffer().len() as u64;
+
+        let result = estimate_duration_from_tail(input.get_mut(),
+                                                 &self.streaminfo,
+                                                 self.first_frame_offset,
+                                                 inner_pos,
+                                                 end_pos,
+                                                 stream_len);
 
-        // Iterate the samples channel interleaved, so first increment the
-        // channel.
-        self.channel += 1;
+        // Restore the position regardless of whether estimation succeeded.
+        try!(input.get_mut().seek(io::SeekFrom::Start(inner_pos)));
+        result
+    }
+
+    /// Returns a reader that yields the blocks that overlap with a range of samples.
+    ///
+    /// The range is `start..end`, in inter-channel samples relative to the
+    /// start of the stream. Every block is yielded with the number of samples
+    /// that lie outside of the range at either end, see `RangeBlock`, so
+    /// typically only the first and last block are trimmed. Reading stops
+    /// after the block that contains the end of the range, the block after it
+    /// is not decoded.
+    ///
+    /// The reader is first positioned at the frame that contains `start` with
+    /// `seek()`, so the blocks before the range are not decoded, and an error
+    /// is returned if seeking fails. To avoid allocating a buffer for every
+    /// block, use `BlocksBetween::read_next_or_eof()` instead of iterating.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` is greater than `end`, or if `metadata_only` was set
+    /// when the reader was constructed.
+    pub fn blocks_between<'r>(&'r mut self,
+                              start: u64,
+                              end: u64)
+                              -> Result<BlocksBetween<&'r mut BufferedReader<R>>> {
+        assert!(start <= end, "the range must not end before it starts");
+        if start < end {
+            try!(self.seek(start));
+        }
+        let settings = self.frame_settings;
+        match self.input {
+            FlacReaderState::Full(ref mut inp) => {
+                Ok(BlocksBetween {
+                    frame_reader: settings.new_frame_reader(inp),
+                    start: start,
+                    end: end,
+                    is_done: start == end,
+                })
+            }
+            FlacReaderState::MetadataOnly(..) => {
+                panic!("FlacReaderOptions::metadata_only must be false \
+                       to be able to use FlacReader::blocks_between()")
+            }
+        }
+    }
 
-        // If that was the last channel, increment the sample number.
-        if self.channel >= self.block.channels() {
-            self.channel = 0;
-            self.sample += 1;
+    /// Positions the reader at the frame that contains the given sample.
+    ///
+    /// This is `seek_ext()` with the default `SeekOptions`.
+    pub fn seek(&mut self, sample: u64) -> Result<SeekReport> {
+        self.seek_ext(sample, SeekOptions::default())
+    }
 
-            // If that was the last sample in the block, decode the next block.
-            if self.sample >= self.block.duration() {
-                self.sample = 0;
+    /// Positions the reader at the start of a track in the cue sheet.
+    ///
+    /// A single file that holds a whole CD usually has a cue sheet that lists
+    /// its tracks. This seeks to the start of the track with the given number,
+    /// after its pregap, see `metadata::CueSheetTrack::start()`. Like after
+    /// `seek()`, the report tells how many samples to skip to reach the start
+    /// exactly.
+    /// Returns `None` if there is no cue sheet, or if it has no track with the
+    /// given number; the reader is not moved in that case.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `FlacReaderOptions::metadata_only` was set.
+    pub fn seek_to_track(&mut self, track_number: u8) -> Result<Option<SeekReport>> {
+        let start = match self.cue_sheet.as_ref().and_then(|cs| cs.track(track_number)) {
+            Some(track) => track.start(),
+            None => return Ok(None),
+        };
+        self.seek(start).map(Some)
+    }
 
-                // Replace the current block with an empty one so that we may
-                // reuse the current buffer to decode again.
-                let current_block = mem::replace(&mut self.block, Block::empty());
+    /// Returns information about the encoder, also based on the first frames.
+    ///
+    /// This is like `encoder_info()`, and additionally takes into account the
+    /// predictors used in the subframes of up to `max_frames` frames from the
+    /// start of the audio data, see `EncoderInfo::add_subframe()`. The
+    /// position of the reader is restored afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `FlacReaderOptions::metadata_only` was set.
+    pub fn inspect_encoder(&mut self, max_frames: u32) -> Result<EncoderInfo> {
+        let mut info = EncoderInfo::new(&self.streaminfo, self.vorbis_comment.as_ref());
+        let input = match self.input {
+            FlacReaderState::Full(ref mut inp) => inp,
+            FlacReaderState::MetadataOnly(..) => {
+                panic!("FlacReaderOptions::metadata_only must be false \
+                       to be able to use FlacReader::inspect_encoder()")
+            }
+        };
+        let audio_start = self.first_frame_offset.expect("a full reader knows its first frame");
+        let pos = input.position();
 
-                match self.frame_reader.read_next_or_eof(current_block.into_buffer()) {
-                    Ok(Some(next_block)) => {
-                        self.block = next_block;
-                    }
-                    Ok(None) => {
-                        // The stream ended with EOF.
-                        // TODO: If a number of samples was specified in the
-                        // streaminfo metadata block, verify that we did not
-                        // read more or less samples.
-                        return None;
+        try!(input.seek_to(audio_start));
+        let result = {
+            let mut frame_reader = self.frame_settings.new_frame_reader(&mut *input);
+            let mut result = Ok(());
+            for _ in 0..max_frames {
+                match frame_reader.explain_next_or_eof() {
+                    Ok(Some(elements)) => {
+                        for element in elements {
+                            if let Element::SubframeHeader { kind, .. } = element {
+                                info.add_subframe(kind);
+                            }
+                        }
                     }
-                    Err(error) => {
-                        self.has_failed = true;
-                        return Some(Err(error));
+                    Ok(None) => break,
+                    Err(err) => {
+                        result = Err(err);
+                        break
                     }
                 }
             }
-        }
+            result
+        };
+
+        // Restore the position regardless of whether inspection succeeded.
+        try!(input.seek_to(pos));
+        result.map(|()| info)
+    }
+
+    /// Positions the reader at the frame that contains the given sample.
+    ///
+    /// The audio data is bisected, guided by the streaminfo, see
+    /// `StreamInfo::estimate_offset_for_sample()`; a seek table is not used.
+    /// Afterwards, `blocks()` and `samples()` continue at the start of the
+    /// frame, so the report tells how many samples to skip to reach the
+    /// target. If the reader could not be positioned exactly, because the
+    /// options limited the search, the reader is positioned at an earlier
+    /// frame, or an error is returned, depending on `options.fallback`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `FlacReaderOptions::metadata_only` was set.
+    pub fn seek_ext(&mut self, sample: u64, options: SeekOptions) -> Result<SeekReport> {
+        let input = match self.input {
+            FlacReaderState::Full(ref mut inp) => inp,
+            FlacReaderState::MetadataOnly(..) => {
+                panic!("FlacReaderOptions::metadata_only must be false \
+                       to be able to use FlacReader::seek()")
+            }
+        };
+        let audio_start = self.first_frame_offset.expect("a full reader knows its first frame");
+
+        // Determine the length of the stream, relative to where the buffered
+        // reader started.
+        let stream_len = try!(input.stream_len());
 
-        Some(Ok(self.block.sample(self.channel, self.sample)))
+        let seeker = Seeker {
+            settings: self.frame_settings,
+            audio_start: audio_start,
+            audio_end: cmp::max(audio_start, stream_len),
+            options: options,
+        };
+        seeker.seek(input, &self.streaminfo, sample)
     }
 }
 
-impl<R: ReadBytes> Iterator for FlacIntoSamples<R> {
-    type Item = Result<i32>;
+/// The position and extent of a frame, as `(offset, first sample, duration)`.
+type FramePos = (u64, u64, u32);
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next()
+/// Implements `FlacReader::seek_ext()`.
+struct Seeker {
+    settings: FrameSettings,
+    /// The offset of the first frame.
+    audio_start: u64,
+    /// The offset of the end of the stream.
+    audio_end: u64,
+    options: SeekOptions,
+}
+
+impl Seeker {
+    /// Decodes the first frame that starts at most `max_len` bytes after `pos`.
+    ///
+    /// Returns the frame, if there is one in range, and the number of bytes
+    /// scanned. A frame header whose frame fails to decode is a false sync
+    /// code in the middle of a frame, and scanning continues after it.
+    fn probe<R: io::Read + io::Seek>(&self,
+                                     input: &mut BufferedReader<R>,
+                                     pos: u64,
+                                     max_len: u32,
+                                     buffer: &mut Vec<i32>)
+                                     -> Result<(Option<FramePos>, u32)> {
+        try!(input.seek_to(pos));
+        let mut scanned = 0;
+        loop {
+            match try!(find_frame_header(input, max_len - scanned)) {
+                Some(skipped) => scanned += skipped,
+                None => return Ok((None, max_len)),
+            }
+            let offset = input.position();
+            let result = self.settings.new_frame_reader(&mut *input).read_next_into(buffer);
+            match result {
+                Ok(Some(block)) => {
+                    let frame = (offset, block.time(), block.duration());
+                    *buffer = block.into_buffer();
+                    return Ok((Some(frame), scanned))
+                }
+                Ok(None) => return Ok((None, scanned)),
+                Err(..) if scanned < max_len => {
+                    try!(input.seek_to(offset + 1));
+                    scanned += 1;
+                }
+                Err(..) => return Ok((None, scanned)),
+            }
+        }
     }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.inner.size_hint()
+    fn seek<R: io::Read + io::Seek>(&self,
+                                    input: &mut BufferedReader<R>,
+                                    streaminfo: &StreamInfo,
+                                    target: u64)
+                                    -> Result<SeekReport> {
+        let mut buffer = Vec::new();
+        let mut report = SeekReport {
+            target: target,
+            frame_sample: 0,
+            is_exact: false,
+            probes: 0,
+            bytes_scanned: 0,
+        };
+        let contains_target = |frame: FramePos| {
+            frame.1 <= target && target < frame.1 + frame.2 as u64
+        };
+
+        // The first frame is the initial lower bound. A stream without frames
+        // has nothing to seek in, but unlike in a probe, a first frame that
+        // fails to decode is an error, there is no earlier frame to fall back to.
+        try!(input.seek_to(self.audio_start));
+        let first = try!(self.settings.new_frame_reader(&mut *input).read_next_into(&mut buffer));
+        let mut lower = match first {
+            Some(block) => {
+                let frame = (self.audio_start, block.time(), block.duration());
+                buffer = block.into_buffer();
+                frame
+            }
+            None => {
+                try!(input.seek_to(self.audio_start));
+                return Ok(report)
+            }
+        };
+
+        // Any frame that starts at or after `upper` starts after the target.
+        // Positions in the estimate are relative to the first frame.
+        let mut upper = self.audio_end;
+        let audio_len = self.audio_end - self.audio_start;
+        let mut estimate = streaminfo.estimate_offset_for_sample(target, audio_len);
+
+        let mut is_limited = false;
+        while !contains_target(lower) && upper > lower.0 + 1 {
+            if report.probes == self.options.max_probes {
+                is_limited = true;
+                break
+            }
+            report.probes += 1;
+
+            let pos = self.audio_start + estimate.offset;
+            let pos = cmp::min(cmp::max(pos, lower.0 + 1), upper - 1);
+            let max_len = cmp::min(self.options.max_scan_len as u64, upper - pos) as u32;
+            let (found, scanned) = try!(self.probe(input, pos, max_len, &mut buffer));
+            report.bytes_scanned += scanned as u64;
+
+            match found {
+                Some(frame) if frame.0 < upper => {
+                    // No frame starts between `pos` and the frame found, so
+                    // if it starts after the target, neither does any frame
+                    // after `pos`.
+                    if frame.1 <= target {
+                        lower = frame;
+                    } else {
+                        upper = pos;
+                    }
+                    estimate.refine(frame.1, frame.0 - self.audio_start);
+                }
+                // There is no frame between `pos` and `upper`.
+                _ if max_len as u64 == upper - pos => upper = pos,
+                _ => {
+                    is_limited = true;
+                    break
+                }
+            }
+        }
+
+        if is_limited {
+            match self.options.fallback {
+                SeekFallback::Closest => {}
+                SeekFallback::ReadForward => {
+                    lower = try!(self.read_forward(input, lower, target, &mut buffer));
+                }
+                SeekFallback::Fail if report.probes == self.options.max_probes => {
+                    return Err(Error::LimitExceeded {
+                        limit: "seek probes",
+                        value: report.probes as u64 + 1,
+                        max: self.options.max_probes as u64,
+                    })
+                }
+                SeekFallback::Fail => {
+                    return Err(Error::LimitExceeded {
+                        limit: "seek scan length",
+                        value: self.options.max_scan_len as u64 + 1,
+                        max: self.options.max_scan_len as u64,
+                    })
+                }
+            }
+        }
+
+        try!(input.seek_to(lower.0));
+        report.frame_sample = lower.1;
+        report.is_exact = contains_target(lower);
+        Ok(report)
+    }
+
+    /// Decodes frames from `frame` onwards, until the one that contains the target.
+    ///
+    /// Returns the last frame decoded, which precedes the target if the stream
+    /// ends before it.
+    fn read_forward<R: io::Read + io::Seek>(&self,
+                                            input: &mut BufferedReader<R>,
+                                            frame: FramePos,
+                                            target: u64,
+                                            buffer: &mut Vec<i32>)
+                                            -> Result<FramePos> {
+        let mut frame = frame;
+        let mut offset = frame.0;
+        try!(input.seek_to(offset));
+        loop {
+            let result = self.settings.new_frame_reader(&mut *input).read_next_into(buffer);
+            let block = match try!(result) {
+                Some(block) => block,
+                None => return Ok(frame),
+            };
+            frame = (offset, block.time(), block.duration());
+            *buffer = block.into_buffer();
+            if frame.1 + frame.2 as u64 > target {
+                return Ok(frame)
+            }
+            offset = input.position();
+        }
+    }
+}
+
+/// Implements `FlacReader::estimate_duration()`.
+///
+/// `inner_pos` is the absolute position of the reader, which corresponds to
+/// position `end_pos` relative to the start of the FLAC stream, which is
+/// `stream_len` bytes long.
+fn estimate_duration_from_tail<R: io::Read + io::Seek>(reader: &mut R,
+                                                       streaminfo: &StreamInfo,
+                                                       first_frame_offset: Option<u64>,
+                                                       inner_pos: u64,
+                                                       end_pos: u64,
+                                                       stream_len: u64)
+                                                       -> Result<DurationEstimate> {
+    use std::io::Read;
+
+    // If not all metadata blocks were read, the first frame starts at least
+    // after the stream header and streaminfo block.
+    let audio_start = first_frame_offset.unwrap_or(4 + 38);
+    let audio_len = stream_len.saturating_sub(audio_start);
+
+    // The tail must include the entire final frame, and allow for trailing
+    // data such as an ID3v1 tag. If the maximum frame size is not known, use
+    // the size of a frame with verbatim subframes as an upper bound.
+    let max_frame_size = match streaminfo.max_frame_size {
+        Some(size) => size as u64,
+        None => {
+            let bits = (streaminfo.bits_per_sample as u64 + 1) * streaminfo.max_block_size as u64;
+            18 + streaminfo.channels as u64 * (2 + (bits + 7) / 8)
+        }
+    };
+    let tail_len = cmp::min(audio_len, max_frame_size + 128);
+
+    let mut tail = Vec::with_capacity(tail_len as usize);
+    try!(reader.seek(io::SeekFrom::Start(inner_pos - end_pos + stream_len - tail_len)));
+    try!(reader.by_ref().take(tail_len).read_to_end(&mut tail));
+
+    match frame::decode_last_frame(&tail, streaminfo) {
+        Ok(block) => {
+            // Streams usually start at sample 0, but one that was cut from a
+            // longer stream need not, so take the time of the first frame
+            // into account too.
+            let start_time = match first_frame_offset {
+                Some(offset) => {
+                    try!(reader.seek(io::SeekFrom::Start(inner_pos - end_pos + offset)));
+                    let mut frame_reader = FrameReader::new(BufferedReader::new(reader));
+                    match frame_reader.read_next_or_eof(Vec::new()) {
+                        Ok(Some(first)) => first.time(),
+                        _ => 0,
+                    }
+                }
+                None => 0,
+            };
+            let samples = (block.time() + block.duration() as u64).saturating_sub(start_time);
+            Ok(DurationEstimate {
+                samples: samples,
+                min_samples: samples,
+                max_samples: Some(samples),
+            })
+        }
+        Err(..) => {
+            let mut estimate = streaminfo.estimate_duration(audio_len);
+            // If the start of the audio data is not known exactly, the length
+            // may be overestimated, so the lower bound is not reliable.
+            if first_frame_offset.is_none() {
+                estimate.min_samples = cmp::min(estimate.min_samples, 1);
+            }
+            Ok(estimate)
+        }
+    }
+}
+
+impl FlacReader<fs::File> {
+    /// Attempts to create a reader that reads from the specified file.
+    ///
+    /// This is a convenience constructor that opens a `File`, and constructs a
+    /// `FlacReader` from it. There is no need to wrap the file in a
+    /// `BufReader`, as the `FlacReader` employs buffering already.
+    pub fn open<P: AsRef<path::Path>>(filename: P) -> Result<FlacReader<fs::File>> {
+        let file = try!(fs::File::open(filename));
+        FlacReader::new(file)
+    }
+
+    /// Attemps to create a reader that reads from the specified file.
+    ///
+    /// This is a convenience constructor that opens a `File`, and constructs a
+    /// `FlacReader` from it. There is no need to wrap the file in a
+    /// `BufReader`, as the `FlacReader` employs buffering already.
+    pub fn open_ext<P: AsRef<path::Path>>(filename: P,
+                                          options: FlacReaderOptions)
+                                          -> Result<FlacReader<fs::File>> {
+        let file = try!(fs::File::open(filename));
+        FlacReader::new_ext(file, options)
+    }
+}
+
+impl<R: io::Read> FlacProbe<R> {
+    /// Reads the streaminfo and the Vorbis comment from the start of a stream.
+    ///
+    /// Metadata blocks after the Vorbis comment are not read until
+    /// `into_full_reader()` is called.
+    pub fn new(reader: R) -> Result<FlacProbe<R>> {
+        FlacProbe::new_ext(reader, FlacReaderOptions::default())
+    }
+
+    /// Reads the streaminfo and the Vorbis comment, with options for the full reader.
+    ///
+    /// The probe itself always stops after the Vorbis comment, but the blocks
+    /// that it reads are parsed according to `options`, and the metadata
+    /// limits apply to the probe and the upgrade together. The options apply
+    /// to the reader returned by `into_full_reader()`, as if it was
+    /// constructed with `FlacReader::new_ext()`. Because that reader decodes
+    /// audio, `metadata_only` is ignored.
+    pub fn new_ext(reader: R, options: FlacReaderOptions) -> Result<FlacProbe<R>> {
+        let options = FlacReaderOptions { metadata_only: false, ..options };
+        let desired = FlacReaderOptions {
+            metadata_only: true,
+            read_vorbis_comment: true,
+            read_seek_table: false,
+            read_pictures: false,
+            ..options
+        };
+        let mut input = BufferedReader::new(reader);
+        let (streaminfo, streaminfo_bytes, mut blocks) =
+            try!(read_metadata_start(&mut input, &options));
+        try!(blocks.read(&mut input, &options, desired));
+        let probe = FlacProbe {
+            input: input,
+            options: options,
+            streaminfo: streaminfo,
+            streaminfo_bytes: streaminfo_bytes,
+            blocks: blocks,
+        };
+        Ok(probe)
+    }
+
+    /// Returns the streaminfo metadata.
+    pub fn streaminfo(&self) -> StreamInfo {
+        self.streaminfo
+    }
+
+    /// Returns name-value pairs of Vorbis comments, such as `("ARTIST", "Queen")`.
+    ///
+    /// See `FlacReader::tags()` for more details.
+    pub fn tags<'a>(&'a self) -> metadata::Tags<'a> {
+        metadata::Tags::new(self.blocks.vorbis_comment.as_ref())
+    }
+
+    /// Look up a Vorbis comment such as `ARTIST` in a case-insensitive way.
+    ///
+    /// See `FlacReader::get_tag()` for more details.
+    pub fn get_tag<'a>(&'a self, tag_name: &'a str) -> metadata::GetTag<'a> {
+        metadata::GetTag::new(self.blocks.vorbis_comment.as_ref(), tag_name)
+    }
+
+    /// Continues reading the stream, and returns a reader that can decode audio.
+    ///
+    /// The remaining metadata blocks are read in the same way as by
+    /// `FlacReader::new_ext()` with the options of the probe, and the buffered
+    /// input of the probe is reused, so no data is read twice. The resulting
+    /// reader is the same as one constructed by `FlacReader::new_ext()`.
+    ///
+    /// Like `FlacReader::new()`, this returns `Error::Unsupported` for streams
+    /// with more than 24 bits per sample.
+    pub fn into_full_reader(self) -> Result<FlacReader<R>> {
+        let mut input = self.input;
+        let mut blocks = self.blocks;
+        let is_complete = try!(blocks.read(&mut input, &self.options, self.options));
+        FlacReader::with_metadata(input,
+                                  self.options,
+                                  self.streaminfo,
+                                  self.streaminfo_bytes,
+                                  blocks,
+                                  is_complete)
+    }
+}
+
+impl FlacProbe<fs::File> {
+    /// Opens the file at `filename`, and reads its streaminfo and Vorbis comment.
+    pub fn open<P: AsRef<path::Path>>(filename: P) -> Result<FlacProbe<fs::File>> {
+        let file = try!(fs::File::open(filename));
+        FlacProbe::new(file)
+    }
+}
+
+impl<R: ReadBytes> FlacSamples<R> {
+    /// Continues iteration after an error, if the error is recoverable.
+    ///
+    /// After the iterator yields an error, it normally returns `None`
+    /// afterwards. Some errors affect only a single frame though: when a frame
+    /// was read completely, but its CRC did not match, the samples of that
+    /// frame are lost, but the next frame can still be decoded. In that case
+    /// this method skips the corrupt frame, so that iteration continues with
+    /// the next frame, and returns true.
+    ///
+    /// If the error left the input in the middle of a frame, the position of
+    /// the next frame is unknown, so iteration cannot continue, and this
+    /// returns false. `resync()` can continue in that case, at the cost of
+    /// skipping data. It also returns false when the error was the length
+    /// check at the end of the stream. Calling this method when no error
+    /// occurred has no effect and returns true.
+    pub fn resume(&mut self) -> bool {
+        if !self.has_failed {
+            return true
+        }
+
+        // See `resync()`, after a length conflict there is no frame to skip.
+        if self.length_conflict.is_some() {
+            return false
+        }
+
+        if self.frame_reader.is_at_frame_boundary() {
+            debug!("resuming decoding after skipping a corrupt frame");
+            self.has_failed = false;
+            self.skipped_frames += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the number of corrupt frames that were skipped by `resume()` or `resync()`.
+    pub fn skipped_frames(&self) -> u64 {
+        self.skipped_frames
+    }
+
+    /// Returns the number of bytes that `resync()` skipped to find the next frame.
+    ///
+    /// This does not include the bytes of frames that were decoded, but whose
+    /// CRC did not match.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// Returns whether the frames disagree with the streaminfo about the length of the stream.
+    ///
+    /// This is known only once the iterator reached the end of the stream. See
+    /// `FlacReaderOptions::length_check` for more info.
+    pub fn length_conflict(&self) -> Option<LengthConflict> {
+        self.length_conflict
+    }
+
+    /// Returns the problems found in the last frame, with lenient frames enabled.
+    ///
+    /// After an error, these are the problems of the frame that `resume()`
+    /// would skip. See `FrameReader::frame_issues()` for more info.
+    pub fn frame_issues(&self) -> &[FrameIssue] {
+        self.frame_reader.frame_issues()
+    }
+
+    /// Returns how often decoding could reuse the block buffer.
+    ///
+    /// This is collected only when enabled with `FlacReader::set_collect_stats()`.
+    /// See `FrameReader::buffer_stats()` for more info.
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.frame_reader.buffer_stats()
+    }
+
+    /// Returns the sizes of the frames decoded so far.
+    ///
+    /// Once the iterator is exhausted, this summarizes the compression of the
+    /// entire stream. This is collected only when enabled with
+    /// `FlacReader::set_collect_stats()`. See `FrameReader::frame_stats()` for
+    /// more info.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_reader.frame_stats()
+    }
+
+    /// Returns the heap memory held by the block buffer, see `MemoryUsage`.
+    ///
+    /// Only `block_buffer` is nonzero, the input buffer and the metadata are
+    /// held by the `FlacReader`, see `FlacReader::memory_usage()`.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            block_buffer: self.block.buffer_capacity() * mem::size_of::<i32>(),
+            ..MemoryUsage::default()
+        }
+    }
+
+    /// Releases block buffer capacity beyond `max_len` samples.
+    ///
+    /// The block buffer grows to fit the largest frame decoded so far, and is
+    /// reused for all frames after it. Call this after an unusually large
+    /// frame, with for instance the maximum block size in the streaminfo times
+    /// the number of channels, to not hold on to the excess memory. The
+    /// samples of the current block are kept. See `Block::shrink_buffer()`.
+    pub fn shrink_block_buffer(&mut self, max_len: usize) {
+        self.block.shrink_buffer(max_len)
+    }
+
+    /// Takes block buffers from the pool, and returns them to it when dropped.
+    ///
+    /// The iterator still reuses its buffer from one block to the next, the
+    /// pool only avoids allocating a new buffer for every iterator. Buffers
+    /// need not have the right size, a buffer that is too small is grown once.
+    /// See `BufferPool` for more info.
+    pub fn set_buffer_pool<P: BufferPool + Send + Sync + 'static>(&mut self, pool: P) {
+        self.pool = Some(Box::new(pool));
+    }
+
+    /// Calls `f` for every remaining sample, and checks for errors only once.
+    ///
+    /// The samples are interleaved in the same way as for the iterator, and
+    /// iteration continues where the iterator stopped, if it was used before.
+    /// The closure receives plain samples, so there is no match on a `Result`
+    /// for every sample, which makes this faster than iterating. A decoding
+    /// error stops iteration and is returned; afterwards, `resume()` works as
+    /// it does after the iterator yields an error. If the iterator failed
+    /// before, and `resume()` was not called, no samples are produced.
+    pub fn for_each_sample<F: FnMut(i32)>(&mut self, mut f: F) -> Result<()> {
+        if self.has_failed {
+            return Ok(())
+        }
+
+        // First finish the block that the iterator was in the middle of. The
+        // sample and channel are those of the last sample that it yielded.
+        if self.block.duration() > 0 {
+            for ch in self.channel + 1..self.block.channels() {
+                f(self.block.sample(ch, self.sample));
+            }
+            for_each_block_sample(&self.block, self.sample + 1, &mut f);
+        }
+
+        loop {
+            let mut buffer = self.take_buffer();
+            match self.frame_reader.read_next_into(&mut buffer) {
+                Ok(Some(block)) => {
+                    for_each_block_sample(&block, 0, &mut f);
+                    self.decoded_samples = Some(block.time() + block.duration() as u64);
+                    self.block = block;
+                }
+                // At the end of the stream, the current block is empty, so the
+                // iterator ends too.
+                Ok(None) => {
+                    self.block = Block::empty_with_buffer(buffer);
+                    return self.check_length()
+                }
+                Err(error) => {
+                    self.block = Block::empty_with_buffer(buffer);
+                    self.has_failed = true;
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Checks the total number of samples against the frames, at the end of the stream.
+    ///
+    /// If no block was decoded, it is unknown where the frames end, so there is
+    /// nothing to check.
+    fn check_length(&mut self) -> Result<()> {
+        let (total_samples, decoded_samples) = match (self.total_samples, self.decoded_samples) {
+            (Some(total), Some(decoded)) => (total, decoded),
+            _ => return Ok(()),
+        };
+        let is_checked = self.length_check != LengthCheck::Ignore;
+        if !is_checked || total_samples == decoded_samples || self.length_conflict.is_some() {
+            return Ok(())
+        }
+
+        warn!("stream ends at sample {}, but the streaminfo has {} samples",
+              decoded_samples, total_samples);
+        self.length_conflict = Some(LengthConflict::DecodedLengthMismatch {
+            total_samples: total_samples,
+            decoded_samples: decoded_samples,
+        });
+        if self.length_check == LengthCheck::Fail {
+            self.has_failed = true;
+            return fmt_err("number of samples in frames does not match streaminfo")
+        }
+        Ok(())
+    }
+
+    /// Takes the buffer of the current block, leaving an empty block.
+    fn take_buffer(&mut self) -> Vec<i32> {
+        // Replace the current block with an empty one so that we may reuse the
+        // current buffer to decode again. Before the first block, there is no
+        // buffer yet, so take one from the pool, if there is one.
+        let mut buffer = mem::replace(&mut self.block, Block::empty()).into_buffer();
+        if buffer.capacity() == 0 {
+            if let Some(ref mut pool) = self.pool {
+                buffer = pool.take();
+            }
+        }
+        buffer
+    }
+
+    /// Decodes the next block, and returns its first sample.
+    #[inline(never)]
+    fn next_block(&mut self) -> Option<Result<i32>> {
+        self.channel = 0;
+        self.sample = 0;
+
+        // When there is no next block, keep the buffer in an empty block, so
+        // it is reused after `resume()`, and returned to the pool.
+        let mut buffer = self.take_buffer();
+        match self.frame_reader.read_next_into(&mut buffer) {
+            Ok(Some(next_block)) => {
+                self.decoded_samples = Some(next_block.time() + next_block.duration() as u64);
+                self.block = next_block;
+            }
+            Ok(None) => {
+                // The stream ended with EOF.
+                self.block = Block::empty_with_buffer(buffer);
+                return match self.check_length() {
+                    Ok(()) => None,
+                    Err(error) => Some(Err(error)),
+                };
+            }
+            Err(error) => {
+                self.block = Block::empty_with_buffer(buffer);
+                self.has_failed = true;
+                return Some(Err(error));
+            }
+        }
+
+        Some(Ok(self.block.sample(0, 0)))
+    }
+}
+
+impl<R: PeekBytes> FlacSamples<R> {
+    /// Continues iteration after any error, skipping ahead to the next frame if needed.
+    ///
+    /// This is a lossy alternative to `resume()`, for damaged streams such as
+    /// partially downloaded files or broadcast captures. When the error left
+    /// the input in the middle of a frame, this scans forward for the next
+    /// valid frame header, skipping at most `max_len` bytes, and iteration
+    /// continues with that frame. The samples in between are lost, and the
+    /// bytes skipped are counted in `skipped_bytes()`. Returns false if there
+    /// is no frame header in range, or if reading the input failed; calling
+    /// this method again continues the search. It also returns false when the
+    /// error was the length check at the end of the stream, because no frame
+    /// was lost then. Calling it when no error occurred has no effect and
+    /// returns true.
+    ///
+    /// See `FrameReader::resync()` for more info.
+    pub fn resync(&mut self, max_len: u32) -> bool {
+        if !self.has_failed {
+            return true
+        }
+
+        // A length conflict is only found after the last frame, so the error
+        // is not in a frame, and there is nothing to skip.
+        if self.length_conflict.is_some() {
+            return false
+        }
+
+        match self.frame_reader.resync(max_len) {
+            Ok(Some(skipped)) => {
+                self.has_failed = false;
+                self.skipped_frames += 1;
+                self.skipped_bytes += skipped as u64;
+                true
+            }
+            Ok(None) => {
+                self.skipped_bytes += max_len as u64;
+                false
+            }
+            Err(..) => false,
+        }
+    }
+}
+
+/// Calls `f` for the samples of the block from inter-channel sample `from` on, interleaved.
+#[inline]
+fn for_each_block_sample<F: FnMut(i32)>(block: &Block, from: u32, f: &mut F) {
+    let from = from as usize;
+    // Like the iterator, give mono and stereo a fast path, which walks the
+    // channels as slices, without indexing every sample.
+    match block.channels() {
+        1 => {
+            for &sample in &block.channel(0)[from..] {
+                f(sample);
+            }
+        }
+        2 => {
+            let left = &block.channel(0)[from..];
+            let right = &block.channel(1)[from..];
+            for (&l, &r) in left.iter().zip(right) {
+                f(l);
+                f(r);
+            }
+        }
+        channels => {
+            for i in from as u32..block.duration() {
+                for ch in 0..channels {
+                    f(block.sample(ch, i));
+                }
+            }
+        }
+    }
+}
+
+impl<R: ReadBytes> FlacIntoSamples<R> {
+    /// Continues iteration after an error, if the error is recoverable.
+    ///
+    /// See `FlacSamples::resume()` for more info.
+    pub fn resume(&mut self) -> bool {
+        self.inner.resume()
+    }
+
+    /// Calls `f` for every remaining sample, and checks for errors only once.
+    ///
+    /// See `FlacSamples::for_each_sample()` for more info.
+    pub fn for_each_sample<F: FnMut(i32)>(&mut self, f: F) -> Result<()> {
+        self.inner.for_each_sample(f)
+    }
+
+    /// Returns the number of corrupt frames that were skipped by `resume()` or `resync()`.
+    pub fn skipped_frames(&self) -> u64 {
+        self.inner.skipped_frames()
+    }
+
+    /// Returns the number of bytes that `resync()` skipped to find the next frame.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.inner.skipped_bytes()
+    }
+
+    /// Returns whether the frames disagree with the streaminfo about the length of the stream.
+    ///
+    /// See `FlacSamples::length_conflict()` for more info.
+    pub fn length_conflict(&self) -> Option<LengthConflict> {
+        self.inner.length_conflict()
+    }
+
+    /// Returns the problems found in the last frame, with lenient frames enabled.
+    ///
+    /// See `FlacSamples::frame_issues()` for more info.
+    pub fn frame_issues(&self) -> &[FrameIssue] {
+        self.inner.frame_issues()
+    }
+
+    /// Returns how often decoding could reuse the block buffer.
+    ///
+    /// See `FrameReader::buffer_stats()` for more info.
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.inner.buffer_stats()
+    }
+
+    /// Returns the sizes of the frames decoded so far.
+    ///
+    /// See `FlacSamples::frame_stats()` for more info.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.inner.frame_stats()
+    }
+
+    /// Returns the heap memory held by the block buffer, see `MemoryUsage`.
+    ///
+    /// See `FlacSamples::memory_usage()` for more info.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.inner.memory_usage()
+    }
+
+    /// Releases block buffer capacity beyond `max_len` samples.
+    ///
+    /// See `FlacSamples::shrink_block_buffer()` for more info.
+    pub fn shrink_block_buffer(&mut self, max_len: usize) {
+        self.inner.shrink_block_buffer(max_len)
+    }
+
+    /// Takes block buffers from the pool, and returns them to it when dropped.
+    ///
+    /// See `FlacSamples::set_buffer_pool()` for more info.
+    pub fn set_buffer_pool<P: BufferPool + Send + Sync + 'static>(&mut self, pool: P) {
+        self.inner.set_buffer_pool(pool)
+    }
+
+    /// Yields exactly `samples` inter-channel samples, interleaved as usual.
+    ///
+    /// If the stream is longer, the remaining samples are not decoded. If the
+    /// stream is shorter, `short_stream` determines what happens: it is either
+    /// padded with silence, or the iterator yields an error of kind
+    /// `io::ErrorKind::UnexpectedEof` after the last sample. This is useful to
+    /// fill a buffer of a fixed size. A decoding error ends iteration, as for
+    /// `FlacSamples`.
+    pub fn take_exact(self, samples: u64, short_stream: ShortStream) -> TakeExact<R> {
+        let remaining = samples * self.channels as u64;
+        TakeExact {
+            inner: self,
+            short_stream: short_stream,
+            remaining: remaining,
+            is_padding: false,
+        }
+    }
+}
+
+impl<R: PeekBytes> FlacIntoSamples<R> {
+    /// Continues iteration after any error, skipping ahead to the next frame if needed.
+    ///
+    /// See `FlacSamples::resync()` for more info.
+    pub fn resync(&mut self, max_len: u32) -> bool {
+        self.inner.resync(max_len)
+    }
+}
+
+impl<R: PeekBytes> FlacSamples<R> {
+    /// Returns an iterator that skips corrupt frames, rather than ending at the first one.
+    ///
+    /// When a frame cannot be decoded because of an error confined to that
+    /// frame (see `Error::is_frame_local()`), such as a CRC mismatch or an
+    /// invalid subframe, the iterator calls `resync()` with `max_resync_len`,
+    /// and continues with the next frame. The samples of the corrupt frame are
+    /// lost. Errors of the underlying reader, a failed length check at the end
+    /// of the stream (see `LengthCheck::Fail`), and data in which no frame
+    /// header is found within `max_resync_len` bytes, still end iteration with
+    /// an error. Use `skipped_frames()` and `skipped_bytes()`
+    /// to find out how much data was lost.
+    pub fn lenient(self, max_resync_len: u32) -> LenientSamples<R> {
+        LenientSamples {
+            inner: self,
+            max_resync_len: max_resync_len,
+        }
+    }
+}
+
+impl<R: PeekBytes> LenientSamples<R> {
+    /// Returns the number of corrupt frames that were skipped.
+    pub fn skipped_frames(&self) -> u64 {
+        self.inner.skipped_frames()
+    }
+
+    /// Returns the number of bytes that were skipped to find the next frame.
+    ///
+    /// See `FlacSamples::skipped_bytes()` for more info.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.inner.skipped_bytes()
+    }
+
+    /// Returns the underlying iterator.
+    pub fn into_inner(self) -> FlacSamples<R> {
+        self.inner
+    }
+}
+
+impl<R: PeekBytes> Iterator for LenientSamples<R> {
+    type Item = Result<i32>;
+
+    fn next(&mut self) -> Option<Result<i32>> {
+        loop {
+            match self.inner.next() {
+                Some(Err(err)) => {
+                    // `resync()` refuses to continue after the length check
+                    // failed at the end of the stream, there is no frame to
+                    // skip then.
+                    if !err.is_frame_local() || !self.inner.resync(self.max_resync_len) {
+                        return Some(Err(err))
+                    }
+                }
+                other => return other,
+            }
+        }
+    }
+}
+
+impl<R: ReadBytes> Drop for FlacSamples<R> {
+    fn drop(&mut self) {
+        if let Some(ref mut pool) = self.pool {
+            // Also at the end of the stream and after an error, the current
+            // block holds the buffer, see `next_block()`.
+            let buffer = mem::replace(&mut self.block, Block::empty()).into_buffer();
+            if buffer.capacity() > 0 {
+                pool.give(buffer);
+            }
+        }
+    }
+}
+
+impl<R: ReadBytes> Iterator for FlacSamples<R> {
+    type Item = Result<i32>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Result<i32>> {
+        // If the previous read failed, end iteration.
+        if self.has_failed {
+            return None;
+        }
+
+        // Mono and stereo are by far the most common, so they get a fast path
+        // where the channel index is a constant, which avoids the generic
+        // channel arithmetic.
+        match self.block.channels() {
+            1 => {
+                self.sample += 1;
+                if self.sample < self.block.duration() {
+                    return Some(Ok(self.block.sample(0, self.sample)));
+                }
+            }
+            2 => {
+                if self.channel == 0 {
+                    self.channel = 1;
+                    return Some(Ok(self.block.sample(1, self.sample)));
+                }
+                self.channel = 0;
+                self.sample += 1;
+                if self.sample < self.block.duration() {
+                    return Some(Ok(self.block.sample(0, self.sample)));
+                }
+            }
+            _ => {
+                // Iterate the samples channel interleaved, so first increment
+                // the channel. If that was the last channel, increment the
+                // sample number.
+                self.channel += 1;
+                if self.channel < self.block.channels() {
+                    return Some(Ok(self.block.sample(self.channel, self.sample)));
+                }
+                self.channel = 0;
+                self.sample += 1;
+                if self.sample < self.block.duration() {
+                    return Some(Ok(self.block.sample(0, self.sample)));
+                }
+            }
+        }
+
+        // That was the last sample in the block, decode the next block.
+        self.next_block()
+    }
+}
+
+impl<R: ReadBytes> Iterator for FlacIntoSamples<R> {
+    type Item = Result<i32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<R: ReadBytes> Iterator for TakeExact<R> {
+    type Item = Result<i32>;
+
+    fn next(&mut self) -> Option<Result<i32>> {
+        if self.remaining == 0 {
+            return None
+        }
+
+        if self.is_padding {
+            self.remaining -= 1;
+            return Some(Ok(0))
+        }
+
+        match self.inner.next() {
+            Some(Ok(sample)) => {
+                self.remaining -= 1;
+                Some(Ok(sample))
+            }
+            Some(Err(err)) => {
+                self.remaining = 0;
+                Some(Err(err))
+            }
+            None => {
+                // If the inner iterator failed before, it returns None now,
+                // but then we should not pretend that the stream ended.
+                if self.inner.inner.has_failed {
+                    self.remaining = 0;
+                    return None
+                }
+                match self.short_stream {
+                    ShortStream::PadWithSilence => {
+                        self.is_padding = true;
+                        self.remaining -= 1;
+                        Some(Ok(0))
+                    }
+                    ShortStream::Error => {
+                        self.remaining = 0;
+                        let msg = "stream ended before the requested number of samples";
+                        Some(Err(Error::IoError(io::Error::new(io::ErrorKind::UnexpectedEof, msg))))
+                    }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Decoding may fail, in which case fewer samples are yielded.
+        let remaining = cmp::min(self.remaining, usize::max_value() as u64) as usize;
+        (0, Some(remaining))
+    }
+}
+
+impl<R: ReadBytes> Iterator for TakeDuration<R> {
+    type Item = Result<i32>;
+
+    fn next(&mut self) -> Option<Result<i32>> {
+        // Do not call into the inner iterator after the last sample, because
+        // that would decode the next block.
+        if self.remaining == 0 {
+            return None
+        }
+
+        match self.inner.next() {
+            Some(Ok(sample)) => {
+                self.remaining -= 1;
+                Some(Ok(sample))
+            }
+            Some(Err(err)) => {
+                self.remaining = 0;
+                Some(Err(err))
+            }
+            None => {
+                self.remaining = 0;
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // The stream may end before the duration is reached.
+        let remaining = cmp::min(self.remaining, usize::max_value() as u64) as usize;
+        (0, Some(remaining))
+    }
+}
+
+impl Chunk {
+    /// Returns the interleaved samples.
+    ///
+    /// The length is `frames()` times the number of channels.
+    #[inline(always)]
+    pub fn samples(&self) -> &[i32] {
+        &self.samples
+    }
+
+    /// Returns the number of inter-channel samples in this chunk.
+    #[inline(always)]
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Returns whether the chunk is shorter than requested.
+    ///
+    /// Only the final chunk of a stream can be partial.
+    #[inline(always)]
+    pub fn is_partial(&self) -> bool {
+        self.is_partial
+    }
+
+    /// Appends the interleaved samples to `output`, scaled to `i16`.
+    ///
+    /// Samples are shifted so that full scale maps to full scale: narrower
+    /// samples are shifted left, and the low bits of wider samples are
+    /// truncated. Use `Block::stereo_samples_i16()` to dither instead.
+    pub fn samples_i16_into(&self, output: &mut Vec<i16>) {
+        output.reserve(self.samples.len());
+        if self.bits_per_sample <= 16 {
+            let shift = 16 - self.bits_per_sample;
+            output.extend(self.samples.iter().map(|&s| (s << shift) as i16));
+        } else {
+            let shift = self.bits_per_sample - 16;
+            output.extend(self.samples.iter().map(|&s| (s >> shift) as i16));
+        }
+    }
+
+    /// Appends the interleaved samples to `output`, scaled to `f32`.
+    ///
+    /// Samples are divided by 2 to the power `bits_per_sample - 1`, so they
+    /// lie in the range [-1.0, 1.0).
+    pub fn samples_f32_into(&self, output: &mut Vec<f32>) {
+        let scale = 1.0 / (1u64 << (self.bits_per_sample - 1)) as f32;
+        output.reserve(self.samples.len());
+        output.extend(self.samples.iter().map(|&s| s as f32 * scale));
+    }
+
+    /// Returns the underlying buffer, to be reused for the next chunk.
+    pub fn into_buffer(self) -> Vec<i32> {
+        self.samples
+    }
+}
+
+impl<R: ReadBytes> FlacChunks<R> {
+    /// Returns the format of the samples in every chunk.
+    ///
+    /// This is the spec of the streaminfo, with the number of channels after
+    /// channel selection. Every block must match it, see `read_next_or_eof()`.
+    pub fn spec(&self) -> StreamSpec {
+        self.spec
+    }
+
+    /// Reads the next chunk, or returns `None` at the end of the stream.
+    ///
+    /// The buffer is used to store the interleaved samples, so it can be the
+    /// buffer of a previous chunk, to avoid allocating. Blocks are decoded as
+    /// needed; a chunk can span several blocks, and a block can span several
+    /// chunks. If decoding fails, the samples of the partially filled chunk are
+    /// lost. A block whose spec differs from `spec()` is an error, because
+    /// chunks do not track changes in format.
+    pub fn read_next_or_eof(&mut self, buffer: Vec<i32>) -> Result<Option<Chunk>> {
+        let mut samples = buffer;
+        samples.clear();
+        let channels = self.spec.channels;
+        samples.reserve(self.frames_per_chunk as usize * channels as usize);
+
+        let mut frames = 0;
+        while frames < self.frames_per_chunk {
+            if self.pos == self.block.duration() {
+                // Replace the current block with an empty one so that we may
+                // reuse the current buffer to decode again.
+                let current_block = mem::replace(&mut self.block, Block::empty());
+                self.pos = 0;
+                match try!(self.frame_reader.read_next_or_eof(current_block.into_buffer())) {
+                    Some(next_block) => {
+                        if next_block.spec(self.spec) != self.spec {
+                            return fmt_err("block format differs from the streaminfo");
+                        }
+                        self.block = next_block;
+                        continue
+                    }
+                    None => break,
+                }
+            }
+
+            let n = cmp::min(self.frames_per_chunk - frames, self.block.duration() - self.pos);
+            for i in self.pos..self.pos + n {
+                for ch in 0..channels {
+                    samples.push(self.block.sample(ch, i));
+                }
+            }
+            self.pos += n;
+            frames += n;
+        }
+
+        if frames == 0 {
+            return Ok(None)
+        }
+
+        Ok(Some(Chunk {
+            samples: samples,
+            frames: frames,
+            bits_per_sample: self.spec.bits_per_sample,
+            is_partial: frames < self.frames_per_chunk,
+        }))
+    }
+}
+
+impl RangeBlock {
+    /// Returns the decoded block, including the samples outside of the range.
+    #[inline(always)]
+    pub fn block(&self) -> &Block {
+        &self.block
+    }
+
+    /// Returns the number of inter-channel samples at the start of the block
+    /// that lie before the range.
+    #[inline(always)]
+    pub fn trim_start(&self) -> u32 {
+        self.trim_start
+    }
+
+    /// Returns the number of inter-channel samples at the end of the block
+    /// that lie after the range.
+    #[inline(always)]
+    pub fn trim_end(&self) -> u32 {
+        self.trim_end
+    }
+
+    /// Returns the indices of the samples in the block that lie in the range.
+    ///
+    /// These are sample indices as passed to `Block::sample()`, or indices
+    /// into the slices returned by `Block::channel()`.
+    pub fn range(&self) -> Range<u32> {
+        self.trim_start..self.block.duration() - self.trim_end
+    }
+
+    /// Returns the block, for instance to reuse its buffer.
+    pub fn into_block(self) -> Block {
+        self.block
+    }
+}
+
+impl<R: ReadBytes> BlocksBetween<R> {
+    /// Reads the next block that overlaps with the range, or returns `None` after the range.
+    ///
+    /// The buffer is used to decode into, also for the blocks that are
+    /// skipped, so it can be the buffer of a previous block, to avoid
+    /// allocating. `None` is also returned when the stream ends before the
+    /// end of the range.
+    pub fn read_next_or_eof(&mut self, buffer: Vec<i32>) -> Result<Option<RangeBlock>> {
+        let mut buffer = buffer;
+        while !self.is_done {
+            let block = match try!(self.frame_reader.read_next_or_eof(buffer)) {
+                Some(block) => block,
+                None => break,
+            };
+            let block_start = block.time();
+            let block_end = block_start + block.duration() as u64;
+
+            if block_end <= self.start {
+                buffer = block.into_buffer();
+                continue
+            }
+
+            // A block could start after the end of the range in a stream with
+            // gaps, or when the reader was past the range already.
+            if block_end >= self.end {
+                self.is_done = true;
+                if block_start >= self.end {
+                    return Ok(None)
+                }
+            }
+
+            let trim_start = self.start.saturating_sub(block_start) as u32;
+            let trim_end = block_end.saturating_sub(self.end) as u32;
+            return Ok(Some(RangeBlock {
+                block: block,
+                trim_start: trim_start,
+                trim_end: trim_end,
+            }))
+        }
+        self.is_done = true;
+        Ok(None)
+    }
+}
+
+impl<R: ReadBytes> Iterator for BlocksBetween<R> {
+    type Item = Result<RangeBlock>;
+
+    fn next(&mut self) -> Option<Result<RangeBlock>> {
+        match self.read_next_or_eof(Vec::new()) {
+            Ok(Some(block)) => Some(Ok(block)),
+            Ok(None) => None,
+            Err(err) => {
+                // If reading failed, end iteration.
+                self.is_done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<R: ReadBytes> Iterator for FlacChunks<R> {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Result<Chunk>> {
+        // If the previous read failed, end iteration.
+        if self.has_failed {
+            return None
+        }
+
+        match self.read_next_or_eof(Vec::new()) {
+            Ok(Some(chunk)) => Some(Ok(chunk)),
+            Ok(None) => None,
+            Err(err) => {
+                self.has_failed = true;
+                Some(Err(err))
+            }
+        }
     }
 }
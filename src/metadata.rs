@@ -7,20 +7,40 @@
 
 //! The `metadata` module deals with metadata at the beginning of a FLAC stream.
 
-use error::{Error, Result, fmt_err};
-use input::ReadBytes;
+use error::{Error, Result, UnsupportedFeature, fmt_err};
+use explain::SubframeKind;
+use frame;
+use input::{BufferedReader, ReadBytes};
+use std::cmp;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::iter;
+use std::mem;
+use std::path;
 use std::str;
 use std::slice;
+use std::time::Duration;
 
-#[derive(Clone, Copy)]
-struct MetadataBlockHeader {
-    is_last: bool,
-    block_type: u8,
-    length: u32,
+#[cfg(feature = "full-metadata")]
+mod full;
+
+#[cfg(feature = "full-metadata")]
+pub use self::full::write_picture_data_to;
+
+/// The header that precedes every metadata block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetadataBlockHeader {
+    /// Whether this is the last metadata block before the audio data.
+    pub is_last: bool,
+    /// The type of the metadata block, see `read_metadata_block()`.
+    pub block_type: u8,
+    /// The length of the block body in bytes, excluding the header.
+    pub length: u32,
 }
 
 /// The streaminfo metadata block, with important information about the stream.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct StreamInfo {
     // TODO: "size" would better be called "duration" for clarity.
     /// The minimum block size (in inter-channel samples) used in the stream.
@@ -53,8 +73,370 @@ pub struct StreamInfo {
     pub md5sum: [u8; 16],
 }
 
+/// Formats the stream properties on one line, for display to a user.
+///
+/// The format is `44100 Hz, 2 channels, 16 bits per sample, 441000 samples`.
+/// When the number of samples is not known, the last part reads `unknown
+/// duration` instead.
+impl fmt::Display for StreamInfo {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(formatter,
+                    "{} Hz, {} channel{}, {} bits per sample, ",
+                    self.sample_rate,
+                    self.channels,
+                    if self.channels == 1 { "" } else { "s" },
+                    self.bits_per_sample));
+        match self.samples {
+            Some(samples) => write!(formatter, "{} samples", samples),
+            None => formatter.write_str("unknown duration"),
+        }
+    }
+}
+
+#[test]
+fn verify_streaminfo_display() {
+    let mut streaminfo = StreamInfo {
+        min_block_size: 4096,
+        max_block_size: 4096,
+        min_frame_size: None,
+        max_frame_size: None,
+        sample_rate: 44100,
+        channels: 2,
+        bits_per_sample: 16,
+        samples: Some(441000),
+        md5sum: [0; 16],
+    };
+    assert_eq!(format!("{}", streaminfo),
+               "44100 Hz, 2 channels, 16 bits per sample, 441000 samples");
+    streaminfo.channels = 1;
+    streaminfo.samples = None;
+    assert_eq!(format!("{}", streaminfo),
+               "44100 Hz, 1 channel, 16 bits per sample, unknown duration");
+}
+
+/// The sample rate, number of channels, and bits per sample of audio.
+///
+/// This is what consumers of decoded audio usually need to know about its
+/// format. It is available for the stream as a whole from `StreamInfo::spec()`,
+/// and for every block from `Block::spec()`, so a change in format can be
+/// detected with a single comparison.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StreamSpec {
+    /// The sample rate in Hz.
+    pub sample_rate: u32,
+    /// The number of channels.
+    pub channels: u32,
+    /// The number of bits per sample.
+    pub bits_per_sample: u32,
+}
+
+/// An estimate of the duration of a stream, with bounds on the error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DurationEstimate {
+    /// The best estimate of the number of inter-channel samples in the stream.
+    pub samples: u64,
+    /// A lower bound on the number of inter-channel samples.
+    pub min_samples: u64,
+    /// An upper bound on the number of inter-channel samples, if one is known.
+    pub max_samples: Option<u64>,
+}
+
+impl DurationEstimate {
+    fn exact(samples: u64) -> DurationEstimate {
+        DurationEstimate {
+            samples: samples,
+            min_samples: samples,
+            max_samples: Some(samples),
+        }
+    }
+
+    /// Returns whether the estimate is known to be the exact duration.
+    pub fn is_exact(&self) -> bool {
+        self.max_samples == Some(self.min_samples)
+    }
+}
+
+impl StreamInfo {
+    /// Returns the sample rate, number of channels, and bits per sample.
+    pub fn spec(&self) -> StreamSpec {
+        StreamSpec {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            bits_per_sample: self.bits_per_sample,
+        }
+    }
+
+    /// Returns the duration of the stream, if the streaminfo specifies it.
+    ///
+    /// The FLAC format uses 0 total samples to indicate that the number of
+    /// samples is unknown, so a streaminfo block can never declare an empty
+    /// stream: for a stream without frames, this returns `None`, like it does
+    /// for any stream of unknown length. Use `FlacReader::estimate_duration()`
+    /// to determine the duration in that case; it is exactly zero for a stream
+    /// without frames.
+    pub fn duration(&self) -> Option<Duration> {
+        match self.samples {
+            Some(samples) if self.sample_rate > 0 => {
+                Some(frame::samples_to_duration(samples, self.sample_rate))
+            }
+            _ => None,
+        }
+    }
+
+    /// Estimates the duration of the stream from the size of its audio data.
+    ///
+    /// `audio_len` is the number of bytes of audio data, from the start of the
+    /// first frame to the end of the stream. If the streaminfo specifies the
+    /// number of samples, the estimate is exact. Otherwise the bounds are
+    /// derived from the minimum and maximum frame size, and the estimate
+    /// assumes that frames are halfway between the two. When the frame sizes
+    /// are not known either, the estimate assumes a typical compression ratio,
+    /// and the bounds are not informative.
+    pub fn estimate_duration(&self, audio_len: u64) -> DurationEstimate {
+        if let Some(samples) = self.samples {
+            return DurationEstimate::exact(samples);
+        }
+        if audio_len == 0 {
+            return DurationEstimate::exact(0);
+        }
+
+        let min_block_size = self.min_block_size as u64;
+        let max_block_size = self.max_block_size as u64;
+
+        // Every frame but the last contains at least `min_block_size` samples,
+        // and the last frame contains at least one sample.
+        let min_samples = match self.max_frame_size {
+            Some(max_fs) => {
+                let min_frames = (audio_len + max_fs as u64 - 1) / max_fs as u64;
+                (min_frames - 1) * min_block_size + 1
+            }
+            None => 1,
+        };
+        let max_samples = self.min_frame_size.map(|min_fs| {
+            (audio_len / min_fs as u64) * max_block_size
+        });
+
+        let estimate = match (self.min_frame_size, self.max_frame_size) {
+            (Some(min_fs), Some(max_fs)) => {
+                audio_len * 2 * max_block_size / (min_fs as u64 + max_fs as u64)
+            }
+            (Some(fs), None) | (None, Some(fs)) => audio_len * max_block_size / fs as u64,
+            (None, None) => {
+                // Music typically compresses to about 60% of its original size.
+                let bits_per_sample = self.channels as u64 * self.bits_per_sample as u64;
+                audio_len * 8 * 5 / (bits_per_sample * 3)
+            }
+        };
+
+        DurationEstimate {
+            samples: cmp::min(cmp::max(estimate, min_samples),
+                              max_samples.unwrap_or(u64::max_value())),
+            min_samples: min_samples,
+            max_samples: max_samples,
+        }
+    }
+
+    /// Returns the sample width of a PCM container for this stream.
+    ///
+    /// See `container_bits_per_sample()` for more info.
+    pub fn container_bits_per_sample(&self) -> u32 {
+        container_bits_per_sample(self.bits_per_sample)
+    }
+
+    /// Returns the shift that scales samples of this stream to the container.
+    ///
+    /// See `left_justify_shift()` for more info.
+    pub fn left_justify_shift(&self) -> u32 {
+        left_justify_shift(self.bits_per_sample, self.container_bits_per_sample())
+    }
+}
+
+#[test]
+fn verify_estimate_duration() {
+    let mut streaminfo = StreamInfo {
+        min_block_size: 4096,
+        max_block_size: 4096,
+        min_frame_size: Some(1000),
+        max_frame_size: Some(3000),
+        sample_rate: 44100,
+        channels: 2,
+        bits_per_sample: 16,
+        samples: None,
+        md5sum: [0; 16],
+    };
+
+    let estimate = streaminfo.estimate_duration(200_000);
+    assert_eq!(estimate.min_samples, 66 * 4096 + 1);
+    assert_eq!(estimate.max_samples, Some(200 * 4096));
+    assert_eq!(estimate.samples, 100 * 4096);
+    assert!(!estimate.is_exact());
+
+    streaminfo.min_frame_size = None;
+    streaminfo.max_frame_size = None;
+    let estimate = streaminfo.estimate_duration(200_000);
+    assert_eq!(estimate.max_samples, None);
+    assert!(estimate.min_samples <= estimate.samples);
+
+    // Without audio data, the stream is empty, whatever the frame sizes.
+    assert_eq!(streaminfo.estimate_duration(0), DurationEstimate::exact(0));
+    assert_eq!(streaminfo.duration(), None);
+
+    streaminfo.samples = Some(12345);
+    let estimate = streaminfo.estimate_duration(200_000);
+    assert_eq!(estimate, DurationEstimate::exact(12345));
+    assert!(estimate.is_exact());
+    assert_eq!(streaminfo.duration(), Some(Duration::new(0, 279_931_972)));
+}
+
+/// An estimate of the position of a sample in the audio data, with bounds.
+///
+/// The offsets are in bytes, relative to the start of the first frame. The
+/// estimate is the position of the frame that contains the sample. When
+/// searching for the frame, for instance by bisection, the estimate can be
+/// narrowed down with every frame found along the way, using `refine()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OffsetEstimate {
+    /// The best estimate of the offset of the frame that contains the sample.
+    pub offset: u64,
+    /// A lower bound on the offset.
+    pub min_offset: u64,
+    /// An upper bound on the offset.
+    pub max_offset: u64,
+
+    /// The sample that the estimate is for.
+    sample: u64,
+    /// A known position at or before the sample, as `(sample, offset)`.
+    lower: (u64, u64),
+    /// A known position after the sample, as `(sample, offset)`.
+    upper: (u64, u64),
+}
+
+impl OffsetEstimate {
+    /// Narrows down the estimate, given the position of a frame.
+    ///
+    /// `frame_sample` is the first sample of a frame that starts at byte
+    /// `frame_offset`. If the frame starts at or before the sample that the
+    /// estimate is for, the sample cannot be located before the frame,
+    /// otherwise it must be located before it. The new estimate interpolates
+    /// between the closest known positions around the sample.
+    pub fn refine(&mut self, frame_sample: u64, frame_offset: u64) {
+        if frame_sample <= self.sample {
+            if frame_sample >= self.lower.0 {
+                self.lower = (frame_sample, frame_offset);
+            }
+            self.min_offset = cmp::max(self.min_offset, frame_offset);
+            self.max_offset = cmp::max(self.max_offset, self.min_offset);
+        } else {
+            if frame_sample < self.upper.0 {
+                self.upper = (frame_sample, frame_offset);
+            }
+            self.max_offset = cmp::min(self.max_offset, frame_offset);
+            self.min_offset = cmp::min(self.min_offset, self.max_offset);
+        }
+        self.interpolate();
+    }
+
+    /// Sets `offset` by linear interpolation between `lower` and `upper`.
+    fn interpolate(&mut self) {
+        let (lower_sample, lower_offset) = self.lower;
+        let (upper_sample, upper_offset) = self.upper;
+        let offset = if upper_sample > lower_sample && upper_offset > lower_offset {
+            // Use floating point arithmetic, because the product of a 36-bit
+            // sample number and a byte offset could overflow a u64.
+            let fraction = (self.sample - lower_sample) as f64 /
+                           (upper_sample - lower_sample) as f64;
+            lower_offset + (fraction * (upper_offset - lower_offset) as f64) as u64
+        } else {
+            lower_offset
+        };
+        self.offset = cmp::min(cmp::max(offset, self.min_offset), self.max_offset);
+    }
+}
+
+impl StreamInfo {
+    /// Estimates the position of the frame that contains a sample.
+    ///
+    /// `audio_len` is the number of bytes of audio data, from the start of the
+    /// first frame to the end of the stream. The estimate assumes a constant
+    /// bitrate, and the bounds are derived from the minimum and maximum block
+    /// and frame size, when these are known. This is a suitable initial guess
+    /// for seeking, see `OffsetEstimate::refine()` to narrow it down.
+    pub fn estimate_offset_for_sample(&self, sample: u64, audio_len: u64) -> OffsetEstimate {
+        let samples = self.estimate_duration(audio_len).samples;
+        let sample = cmp::min(sample, samples.saturating_sub(1));
+
+        // The frame that contains the sample is preceded by at least
+        // `sample / max_block_size` frames, and at most
+        // `sample / min_block_size` frames.
+        let min_frames = sample / cmp::max(1, self.max_block_size as u64);
+        let max_frames = sample / cmp::max(1, self.min_block_size as u64);
+        let min_offset = match self.min_frame_size {
+            Some(min_fs) => min_frames.saturating_mul(min_fs as u64),
+            None => 0,
+        };
+        let max_offset = match self.max_frame_size {
+            Some(max_fs) => max_frames.saturating_mul(max_fs as u64),
+            None => audio_len,
+        };
+        let max_offset = cmp::min(max_offset, audio_len);
+
+        let mut estimate = OffsetEstimate {
+            offset: 0,
+            min_offset: cmp::min(min_offset, max_offset),
+            max_offset: max_offset,
+            sample: sample,
+            lower: (0, 0),
+            upper: (samples, audio_len),
+        };
+        estimate.interpolate();
+        estimate
+    }
+}
+
+#[test]
+fn verify_estimate_offset_for_sample() {
+    let mut streaminfo = StreamInfo {
+        min_block_size: 4096,
+        max_block_size: 4096,
+        min_frame_size: Some(1000),
+        max_frame_size: Some(3000),
+        sample_rate: 44100,
+        channels: 2,
+        bits_per_sample: 16,
+        samples: Some(100 * 4096),
+        md5sum: [0; 16],
+    };
+
+    let estimate = streaminfo.estimate_offset_for_sample(50 * 4096, 200_000);
+    assert_eq!(estimate.offset, 100_000);
+    assert_eq!(estimate.min_offset, 50_000);
+    assert_eq!(estimate.max_offset, 150_000);
+
+    // The start of the stream is known exactly.
+    let estimate = streaminfo.estimate_offset_for_sample(0, 200_000);
+    assert_eq!((estimate.offset, estimate.min_offset, estimate.max_offset), (0, 0, 0));
+
+    // Suppose that the first half of the stream compresses better than the
+    // second half, and we find a frame at 50_000 at sample 40 * 4096.
+    let mut estimate = streaminfo.estimate_offset_for_sample(60 * 4096, 200_000);
+    estimate.refine(40 * 4096, 50_000);
+    assert_eq!(estimate.min_offset, 60_000);
+    assert_eq!(estimate.offset, 50_000 + 150_000 / 3);
+    estimate.refine(70 * 4096, 110_000);
+    assert_eq!(estimate.max_offset, 110_000);
+    assert_eq!(estimate.offset, 50_000 + 60_000 * 2 / 3);
+
+    // Without frame sizes, the bounds are the entire audio data.
+    streaminfo.min_frame_size = None;
+    streaminfo.max_frame_size = None;
+    let estimate = streaminfo.estimate_offset_for_sample(50 * 4096, 200_000);
+    assert_eq!(estimate.min_offset, 0);
+    assert_eq!(estimate.max_offset, 200_000);
+    assert_eq!(estimate.offset, 100_000);
+}
+
 /// A seek point in the seek table.
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct SeekPoint {
     /// Sample number of the first sample in the target frame, or 2<sup>64</sup> - 1 for a placeholder.
     pub sample: u64,
@@ -66,13 +448,44 @@ pub struct SeekPoint {
 }
 
 /// A seek table to aid seeking in the stream.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct SeekTable {
     /// The seek points, sorted in ascending order by sample number.
-    #[allow(dead_code)] // TODO: Implement seeking.
     seekpoints: Vec<SeekPoint>,
 }
 
+impl SeekTable {
+    /// Returns the seek points, sorted in ascending order by sample number.
+    ///
+    /// Placeholder points, with sample number 2<sup>64</sup> - 1, come last.
+    pub fn seekpoints(&self) -> &[SeekPoint] {
+        &self.seekpoints
+    }
+
+    /// Returns the last seek point at or before the given sample, if any.
+    ///
+    /// Placeholder points are never returned. The frame that the point refers
+    /// to starts at `point.offset` bytes after the first frame. Decoding from
+    /// there reaches the sample after `sample - point.sample` samples, unless
+    /// a later frame is closer.
+    pub fn point_at_or_before(&self, sample: u64) -> Option<SeekPoint> {
+        let n_before = self.seekpoints
+            .iter()
+            .take_while(|p| p.sample <= sample && p.sample != u64::max_value())
+            .count();
+        if n_before == 0 {
+            None
+        } else {
+            Some(self.seekpoints[n_before - 1])
+        }
+    }
+}
+
 /// Vorbis comments, also known as FLAC tags (e.g. artist, title, etc.).
+///
+/// Two Vorbis comments are equal when they have the same vendor string, and
+/// the same comments in the same order.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct VorbisComment {
     /// The “vendor string”, chosen by the encoder vendor.
     ///
@@ -81,12 +494,56 @@ pub struct VorbisComment {
     /// or `Lavf57.25.100`.
     pub vendor: String,
 
-    /// Name-value pairs of Vorbis comments, such as `ARTIST=Queen`.
+    /// The comments in `"NAME=value"` format, stored consecutively.
+    ///
+    /// Storing all comments in a single string, rather than a string per
+    /// comment, saves an allocation per comment. This matters when scanning
+    /// the tags of many files.
+    comments: String,
+
+    /// For every comment, the index of its `'='`, and the index of its end.
+    ///
+    /// A comment starts where the previous comment ends.
+    entries: Vec<(usize, usize)>,
+}
+
+impl VorbisComment {
+    /// Creates a Vorbis comment block without comments.
+    pub fn new(vendor: String) -> VorbisComment {
+        VorbisComment {
+            vendor: vendor,
+            comments: String::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Appends a `NAME=value` comment.
     ///
-    /// This struct stores a raw low-level representation of tags. Use
-    /// `FlacReader::tags()` for a friendlier iterator. The tuple consists of
-    /// the string in `"NAME=value"` format, and the index of the `'='` into
-    /// that string.
+    /// # Panics
+    ///
+    /// Panics if the name is not a valid field name: it must consist of ASCII
+    /// bytes 0x20 through 0x7d, `'='` excluded.
+    pub fn push(&mut self, name: &str, value: &str) {
+        assert!(name.bytes().all(|x| x >= 0x20 && x <= 0x7d && x != b'='),
+                "Vorbis comment field name contains invalid byte");
+        self.comments.push_str(name);
+        let sep_index = self.comments.len();
+        self.comments.push('=');
+        self.comments.push_str(value);
+        self.entries.push((sep_index, self.comments.len()));
+    }
+
+    /// Returns the number of comments.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether there are no comments.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns name-value pairs of the comments, such as `("ARTIST", "Queen")`.
     ///
     /// The name is supposed to be interpreted case-insensitively, and is
     /// guaranteed to consist of ASCII characters. Claxon does not normalize
@@ -97,10 +554,198 @@ pub struct VorbisComment {
     /// be present on a collaboration track.
     ///
     /// See <https://www.xiph.org/vorbis/doc/v-comment.html> for more details.
-    pub comments: Vec<(String, usize)>,
+    pub fn tags<'a>(&'a self) -> Tags<'a> {
+        Tags::new(Some(self))
+    }
+
+    /// Returns the number of bytes that this Vorbis comment occupies in a file.
+    ///
+    /// This includes the 4-byte metadata block header, so it can be compared
+    /// directly against the space taken by existing blocks and padding, to
+    /// find out whether an edited comment fits in place. No allocation or
+    /// serialization is done. The result may exceed what a metadata block can
+    /// hold, in which case writing the block fails.
+    pub fn serialized_len(&self) -> u64 {
+        // Block header, vendor length, vendor, comment count, and then for
+        // every comment its length followed by the comment itself.
+        4 + 4 + self.vendor.len() as u64 + 4 + 4 * self.entries.len() as u64 +
+            self.comments.len() as u64
+    }
+
+    /// Returns the number of bytes of heap memory allocated for the comments.
+    ///
+    /// This counts the capacity of the allocations, not only the part in use.
+    pub fn heap_size(&self) -> usize {
+        self.vendor.capacity() + self.comments.capacity() +
+            self.entries.capacity() * mem::size_of::<(usize, usize)>()
+    }
+}
+
+#[test]
+fn verify_serialized_len() {
+    let mut vorbis_comment = VorbisComment::new("claxon".to_string());
+    let mut expected = Vec::new();
+    write_metadata_block(&mut expected, true, &MetadataBlock::VorbisComment(vorbis_comment.clone()))
+        .unwrap();
+    assert_eq!(vorbis_comment.serialized_len(), expected.len() as u64);
+
+    vorbis_comment.push("ARTIST", "Queen");
+    vorbis_comment.push("TITLE", "Don’t Stop Me Now");
+    vorbis_comment.push("COMMENT", "");
+    let mut output = Vec::new();
+    write_metadata_block(&mut output, true, &MetadataBlock::VorbisComment(vorbis_comment.clone()))
+        .unwrap();
+    assert_eq!(vorbis_comment.serialized_len(), output.len() as u64);
+}
+
+/// An index point of a track in a cue sheet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CueSheetIndex {
+    /// Offset in inter-channel samples, relative to the offset of the track.
+    pub offset: u64,
+    /// The index point number, such as 0 for the pregap and 1 for the track start.
+    pub number: u8,
+}
+
+/// A track in a cue sheet.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CueSheetTrack {
+    /// Offset in inter-channel samples, relative to the start of the audio data.
+    pub offset: u64,
+    /// The track number, 170 or 255 for the lead-out track.
+    pub number: u8,
+    /// The International Standard Recording Code, if the track has one.
+    pub isrc: Option<String>,
+    /// Whether the track contains audio, as opposed to data.
+    pub is_audio: bool,
+    /// Whether the audio was recorded with pre-emphasis.
+    pub pre_emphasis: bool,
+    /// The index points of the track, empty for the lead-out track.
+    pub indices: Vec<CueSheetIndex>,
+}
+
+impl CueSheetTrack {
+    /// Returns whether this is the lead-out track, which marks the end of the audio.
+    pub fn is_lead_out(&self) -> bool {
+        self.number == 170 || self.number == 255
+    }
+
+    /// Returns the offset of the track proper, after its pregap, in inter-channel samples.
+    ///
+    /// This is where index point 1 is, or the offset of the track if it has no
+    /// index point 1, as is the case for the lead-out track.
+    pub fn start(&self) -> u64 {
+        match self.indices.iter().find(|index| index.number == 1) {
+            Some(index) => self.offset + index.offset,
+            None => self.offset,
+        }
+    }
+}
+
+/// The track layout of a CD, or of the medium that the audio came from.
+///
+/// This is the content of a CUESHEET metadata block. Rip verification tools
+/// use it to reconstruct the table of contents of the original CD. The last
+/// track is always the lead-out track.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CueSheet {
+    /// The media catalog number, empty if there is none.
+    pub media_catalog_number: String,
+    /// The number of lead-in samples, 0 if the cue sheet does not describe a CD.
+    pub lead_in: u64,
+    /// Whether the cue sheet describes a CD.
+    pub is_cd: bool,
+    /// The tracks, in order, including the lead-out track.
+    pub tracks: Vec<CueSheetTrack>,
+}
+
+impl CueSheet {
+    /// Returns the number of inter-channel samples from the start of a track to the next.
+    ///
+    /// Returns `None` for the lead-out track, and for an index out of range.
+    pub fn track_len(&self, index: usize) -> Option<u64> {
+        match (self.tracks.get(index), self.tracks.get(index + 1)) {
+            (Some(track), Some(next)) => Some(next.offset.saturating_sub(track.offset)),
+            _ => None,
+        }
+    }
+
+    /// Returns the track with the given track number, if there is one.
+    ///
+    /// The lead-out track is not a track of the medium, so it is never returned.
+    pub fn track(&self, number: u8) -> Option<&CueSheetTrack> {
+        self.tracks.iter().find(|track| track.number == number && !track.is_lead_out())
+    }
+
+    /// Returns the number of inter-channel samples that a player plays for a track.
+    ///
+    /// This is the distance between the start of the track, and the start of
+    /// the next track or the lead-out, see `CueSheetTrack::start()`. Unlike
+    /// `track_len()`, this excludes the pregap of the track, and includes the
+    /// pregap of the next track, like a CD player does. Returns `None` if there
+    /// is no track with the given track number.
+    pub fn track_samples(&self, number: u8) -> Option<u64> {
+        let index = match self.tracks.iter().position(|t| t.number == number && !t.is_lead_out()) {
+            Some(i) => i,
+            None => return None,
+        };
+        match self.tracks.get(index + 1) {
+            Some(next) => Some(next.start().saturating_sub(self.tracks[index].start())),
+            None => None,
+        }
+    }
+}
+
+/// An image embedded in the stream, such as cover art.
+///
+/// This is the content of a PICTURE metadata block. A stream can contain any
+/// number of pictures, but at most one of type 1 and one of type 2.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Picture {
+    /// What the picture depicts, as an ID3v2 APIC type, such as 3 for the front cover.
+    pub picture_type: u32,
+    /// The MIME type of the image data, such as `image/jpeg`.
+    ///
+    /// The type `-->` indicates that the data is a URL that refers to the
+    /// image, rather than the image itself.
+    pub mime_type: String,
+    /// A description of the picture, possibly empty.
+    pub description: String,
+    /// The width of the image in pixels, 0 if unknown.
+    pub width: u32,
+    /// The height of the image in pixels, 0 if unknown.
+    pub height: u32,
+    /// The color depth of the image in bits per pixel, 0 if unknown.
+    pub depth: u32,
+    /// The number of colors for an indexed-color image, 0 otherwise.
+    pub colors: u32,
+    /// The image data, in the format indicated by the MIME type.
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// Returns whether the picture is the front cover of the album.
+    pub fn is_front_cover(&self) -> bool {
+        self.picture_type == 3
+    }
+
+    /// Writes the image data to `sink`, returns the number of bytes written.
+    ///
+    /// To copy the image data of a picture block from the input without
+    /// holding it in memory, see `MetadataBlockReader::write_picture_to()`.
+    pub fn write_to<W: io::Write>(&self, sink: &mut W) -> io::Result<u64> {
+        try!(sink.write_all(&self.data));
+        Ok(self.data.len() as u64)
+    }
+
+    /// Returns the number of bytes of heap memory allocated for the picture.
+    pub fn heap_size(&self) -> usize {
+        self.mime_type.capacity() + self.description.capacity() + self.data.capacity()
+    }
 }
 
 /// A metadata about the flac stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum MetadataBlock {
     /// A stream info block.
     StreamInfo(StreamInfo),
@@ -121,9 +766,9 @@ pub enum MetadataBlock {
     /// A Vorbis comment block, also known as FLAC tags.
     VorbisComment(VorbisComment),
     /// A CUE sheet block.
-    CueSheet, // TODO
+    CueSheet(CueSheet),
     /// A picture block.
-    Picture, // TODO
+    Picture(Picture),
     /// A block with a reserved block type, not supported by this library.
     Reserved,
 }
@@ -132,16 +777,29 @@ pub enum MetadataBlock {
 ///
 /// See `FlacReader::tags()` for more details.
 pub struct Tags<'a> {
-    /// The underlying iterator.
-    iter: slice::Iter<'a, (String, usize)>,
+    /// The comments, stored consecutively.
+    comments: &'a str,
+    /// The underlying iterator over the `(separator, end)` indices.
+    iter: slice::Iter<'a, (usize, usize)>,
+    /// The index in `comments` where the next comment starts.
+    start: usize,
 }
 
 impl<'a> Tags<'a> {
-    /// Returns a new `Tags` iterator.
+    /// Returns a new `Tags` iterator over the comments, if there are any.
     #[inline]
-    pub fn new(comments: &'a [(String, usize)]) -> Tags<'a> {
-        Tags {
-            iter: comments.iter(),
+    pub fn new(vorbis_comment: Option<&'a VorbisComment>) -> Tags<'a> {
+        match vorbis_comment {
+            Some(vc) => Tags {
+                comments: &vc.comments,
+                iter: vc.entries.iter(),
+                start: 0,
+            },
+            None => Tags {
+                comments: "",
+                iter: [].iter(),
+                start: 0,
+            },
         }
     }
 }
@@ -151,9 +809,15 @@ impl<'a> Iterator for Tags<'a> {
 
     #[inline]
     fn next(&mut self) -> Option<(&'a str, &'a str)> {
-        return self.iter.next().map(|&(ref comment, sep_idx)| {
-            (&comment[..sep_idx], &comment[sep_idx+1..])
-        })
+        match self.iter.next() {
+            Some(&(sep_idx, end)) => {
+                let comment = &self.comments[self.start..end];
+                let sep_idx = sep_idx - self.start;
+                self.start = end;
+                Some((&comment[..sep_idx], &comment[sep_idx + 1..]))
+            }
+            None => None,
+        }
     }
     
     #[inline]
@@ -169,21 +833,18 @@ impl<'a> ExactSizeIterator for Tags<'a> {}
 /// See `FlacReader::get_tag()` for more details.
 pub struct GetTag<'a> {
     /// The Vorbis comments to search through.
-    vorbis_comments: &'a [(String, usize)],
+    tags: Tags<'a>,
     /// The tag to look for.
     needle: &'a str,
-    /// The index of the (name, value) pair that should be inspected next.
-    index: usize,
 }
 
 impl<'a> GetTag<'a> {
-    /// Returns a new `GetTag` iterator.
+    /// Returns a new `GetTag` iterator over the comments, if there are any.
     #[inline]
-    pub fn new(vorbis_comments: &'a [(String, usize)], needle: &'a str) -> GetTag<'a> {
+    pub fn new(vorbis_comment: Option<&'a VorbisComment>, needle: &'a str) -> GetTag<'a> {
         GetTag {
-            vorbis_comments: vorbis_comments,
+            tags: Tags::new(vorbis_comment),
             needle: needle,
-            index: 0,
         }
     }
 }
@@ -197,12 +858,9 @@ impl<'a> Iterator for GetTag<'a> {
         #[allow(unused_imports)]
         use std::ascii::AsciiExt;
 
-        while self.index < self.vorbis_comments.len() {
-            let (ref comment, sep_idx) = self.vorbis_comments[self.index];
-            self.index += 1;
-
-            if comment[..sep_idx].eq_ignore_ascii_case(self.needle) {
-                return Some(&comment[sep_idx + 1..])
+        while let Some((name, value)) = self.tags.next() {
+            if name.eq_ignore_ascii_case(self.needle) {
+                return Some(value)
             }
         }
 
@@ -210,8 +868,224 @@ impl<'a> Iterator for GetTag<'a> {
     }
 }
 
+/// The program that encoded a stream, as far as it can be identified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoder {
+    /// The reference encoder, libFLAC, or a frontend such as `flac`.
+    LibFlac,
+    /// The FFmpeg or Libav FLAC encoder.
+    Ffmpeg,
+    /// An encoder that could not be identified from the vendor string.
+    Unknown,
+}
+
+/// Information about the encoder of a stream, based on heuristics.
+///
+/// The information is derived from the vendor string, from the block size,
+/// from tags that some frontends write to record their settings, and
+/// optionally from the predictors used in the first frames, see
+/// `add_subframe()`. None of this is authoritative: any encoder can write any
+/// vendor string, and the metadata may have been rewritten by a tagger. It is
+/// useful for flagging files for closer inspection, not for drawing
+/// conclusions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncoderInfo {
+    /// The encoder, as identified from the vendor string.
+    pub encoder: Encoder,
+
+    /// The version of the encoder, if it could be extracted from the vendor string.
+    pub version: Option<String>,
+
+    /// The range of compression levels consistent with the stream, inclusive.
+    ///
+    /// This is only known for libFLAC, whose presets use a block size of 1152
+    /// for levels 0 through 2, and 4096 for levels 3 through 8. A different
+    /// block size indicates that the block size was set explicitly. The
+    /// presets also limit the LPC order: to 0 for levels 0 through 2, 6 for
+    /// level 3, 8 for levels 4 through 7, and 12 for level 8, so observed LPC
+    /// orders narrow the range further.
+    pub levels: Option<(u32, u32)>,
+
+    /// The encoder settings recorded in an `ENCODER_OPTIONS` or
+    /// `ENCODERSETTINGS` tag, if present.
+    pub settings: Option<String>,
+
+    /// The apodization functions from the settings, such as `tukey(0.5)`.
+    ///
+    /// This is the argument of the `-A` or `--apodization` option of `flac`,
+    /// if the settings include it.
+    pub apodization: Option<String>,
+
+    /// The highest LPC order among the subframes passed to `add_subframe()`.
+    ///
+    /// This is 0 if none of them was an LPC subframe, and `None` if no
+    /// subframes were inspected.
+    pub max_lpc_order: Option<u32>,
+}
+
+impl EncoderInfo {
+    /// Derives encoder information from the streaminfo and Vorbis comment.
+    pub fn new(streaminfo: &StreamInfo, vorbis_comment: Option<&VorbisComment>) -> EncoderInfo {
+        let vendor = vorbis_comment.map(|vc| &vc.vendor[..]).unwrap_or("");
+
+        // libFLAC writes "reference libFLAC 1.3.2 20170101", FFmpeg writes
+        // "Lavf57.25.100" when muxing, or "Lavc57.107.100" for raw output.
+        let (encoder, version) = if vendor.starts_with("reference libFLAC ") {
+            let version = vendor["reference libFLAC ".len()..].split(' ').next();
+            (Encoder::LibFlac, version)
+        } else if vendor.starts_with("Lavf") || vendor.starts_with("Lavc") {
+            (Encoder::Ffmpeg, Some(&vendor[4..]))
+        } else {
+            (Encoder::Unknown, None)
+        };
+
+        let is_fixed_block_size = streaminfo.min_block_size == streaminfo.max_block_size;
+        let levels = match (encoder, is_fixed_block_size, streaminfo.max_block_size) {
+            (Encoder::LibFlac, true, 1152) => Some((0, 2)),
+            (Encoder::LibFlac, true, 4096) => Some((3, 8)),
+            _ => None,
+        };
+
+        let settings = GetTag::new(vorbis_comment, "ENCODER_OPTIONS")
+            .chain(GetTag::new(vorbis_comment, "ENCODERSETTINGS"))
+            .next();
+
+        EncoderInfo {
+            encoder: encoder,
+            version: version.and_then(|v| if v.is_empty() { None } else { Some(v.to_string()) }),
+            levels: levels,
+            settings: settings.map(|s| s.to_string()),
+            apodization: settings.and_then(parse_apodization).map(|a| a.to_string()),
+            max_lpc_order: None,
+        }
+    }
+
+    /// Takes the type of a subframe of the stream into account.
+    ///
+    /// The subframe headers can be obtained with
+    /// `FrameReader::explain_next_or_eof()`, or all at once with
+    /// `FlacReader::inspect_encoder()`. Only LPC subframes affect the result.
+    pub fn add_subframe(&mut self, kind: SubframeKind) {
+        let order = match kind {
+            SubframeKind::Lpc { order } => order as u32,
+            _ => 0,
+        };
+        let max_order = cmp::max(self.max_lpc_order.unwrap_or(0), order);
+        self.max_lpc_order = Some(max_order);
+
+        // The lowest libFLAC preset that allows the LPC order.
+        let min_level = if max_order == 0 {
+            0
+        } else if max_order <= 6 {
+            3
+        } else if max_order <= 8 {
+            4
+        } else if max_order <= 12 {
+            8
+        } else {
+            // No preset uses an order this high, so it was set explicitly.
+            self.levels = None;
+            return
+        };
+        self.levels = match self.levels {
+            Some((lo, hi)) if min_level <= hi => Some((cmp::max(lo, min_level), hi)),
+            _ => None,
+        };
+    }
+}
+
+/// Extracts the argument of `-A` or `--apodization` from encoder settings.
+fn parse_apodization(settings: &str) -> Option<&str> {
+    let mut args = settings.split(' ').filter(|arg| !arg.is_empty());
+    while let Some(arg) = args.next() {
+        if arg == "-A" || arg == "--apodization" {
+            return args.next().map(|a| a.trim_matches('"'));
+        }
+        if arg.starts_with("--apodization=") {
+            return Some(arg["--apodization=".len()..].trim_matches('"'));
+        }
+    }
+    None
+}
+
+#[test]
+fn verify_encoder_info() {
+    let streaminfo = StreamInfo {
+        min_block_size: 4096,
+        max_block_size: 4096,
+        min_frame_size: None,
+        max_frame_size: None,
+        sample_rate: 44100,
+        channels: 2,
+        bits_per_sample: 16,
+        samples: None,
+        md5sum: [0; 16],
+    };
+    let mut vorbis_comment = VorbisComment::new("reference libFLAC 1.3.2 20170101".to_string());
+    vorbis_comment.push("ENCODER_OPTIONS", "-8");
+
+    let info = EncoderInfo::new(&streaminfo, Some(&vorbis_comment));
+    assert_eq!(info.encoder, Encoder::LibFlac);
+    assert_eq!(info.version, Some("1.3.2".to_string()));
+    assert_eq!(info.levels, Some((3, 8)));
+    assert_eq!(info.settings, Some("-8".to_string()));
+
+    let vorbis_comment = VorbisComment::new("Lavf57.25.100".to_string());
+    let info = EncoderInfo::new(&streaminfo, Some(&vorbis_comment));
+    assert_eq!(info.encoder, Encoder::Ffmpeg);
+    assert_eq!(info.version, Some("57.25.100".to_string()));
+    assert_eq!(info.levels, None);
+    assert_eq!(info.settings, None);
+
+    let info = EncoderInfo::new(&streaminfo, None);
+    assert_eq!(info.encoder, Encoder::Unknown);
+    assert_eq!(info.version, None);
+    assert_eq!(info.max_lpc_order, None);
+}
+
+#[test]
+fn verify_encoder_info_subframes_and_apodization() {
+    let streaminfo = StreamInfo {
+        min_block_size: 4096,
+        max_block_size: 4096,
+        min_frame_size: None,
+        max_frame_size: None,
+        sample_rate: 44100,
+        channels: 2,
+        bits_per_sample: 16,
+        samples: None,
+        md5sum: [0; 16],
+    };
+    let mut vorbis_comment = VorbisComment::new("reference libFLAC 1.3.2 20170101".to_string());
+    vorbis_comment.push("ENCODER_OPTIONS", "-8 -A \"tukey(0.5);partial_tukey(2)\"");
+
+    let mut info = EncoderInfo::new(&streaminfo, Some(&vorbis_comment));
+    assert_eq!(info.apodization, Some("tukey(0.5);partial_tukey(2)".to_string()));
+    info.add_subframe(SubframeKind::Fixed { order: 2 });
+    assert_eq!(info.max_lpc_order, Some(0));
+    assert_eq!(info.levels, Some((3, 8)));
+    info.add_subframe(SubframeKind::Lpc { order: 8 });
+    assert_eq!(info.levels, Some((4, 8)));
+    info.add_subframe(SubframeKind::Lpc { order: 12 });
+    assert_eq!(info.max_lpc_order, Some(12));
+    assert_eq!(info.levels, Some((8, 8)));
+    info.add_subframe(SubframeKind::Lpc { order: 32 });
+    assert_eq!(info.levels, None);
+
+    // A block size of 1152 means a level without LPC, so LPC contradicts it.
+    let streaminfo = StreamInfo { min_block_size: 1152, max_block_size: 1152, .. streaminfo };
+    let mut vorbis_comment = VorbisComment::new("reference libFLAC 1.3.2 20170101".to_string());
+    vorbis_comment.push("ENCODERSETTINGS", "--apodization=tukey(0.5)");
+    let mut info = EncoderInfo::new(&streaminfo, Some(&vorbis_comment));
+    assert_eq!(info.apodization, Some("tukey(0.5)".to_string()));
+    assert_eq!(info.levels, Some((0, 2)));
+    info.add_subframe(SubframeKind::Lpc { order: 4 });
+    assert_eq!(info.levels, None);
+}
+
+/// Reads the 4-byte header of a metadata block.
 #[inline]
-fn read_metadata_block_header<R: ReadBytes>(input: &mut R) -> Result<MetadataBlockHeader> {
+pub fn read_metadata_block_header<R: ReadBytes>(input: &mut R) -> Result<MetadataBlockHeader> {
     let byte = try!(input.read_u8());
 
     // The first bit specifies whether this is the last block, the next 7 bits
@@ -257,11 +1131,34 @@ pub fn read_metadata_block_with_header<R: ReadBytes>(input: &mut R)
 /// used to decode a single metadata block. For instance, the MP4 format sports
 /// a “FLAC Specific Box” which contains the block type and the raw data. This
 /// function can be used to decode that raw data.
+///
+/// Without the `full-metadata` feature, Vorbis comment, cue sheet, and picture
+/// blocks are not parsed, they are skipped and returned as padding.
 #[inline]
 pub fn read_metadata_block<R: ReadBytes>(input: &mut R,
                                          block_type: u8,
                                          length: u32)
                                          -> Result<MetadataBlock> {
+    read_metadata_block_ext(input, block_type, length, false)
+}
+
+/// Read a single metadata block, optionally accepting minor spec violations.
+///
+/// With `lenient` set to false, this is the same as `read_metadata_block()`.
+/// With `lenient` set to true, a Vorbis comment whose field name contains
+/// bytes outside of the range allowed by the Vorbis spec has those bytes
+/// replaced by `_`, a comment that lacks a `=` is dropped, and invalid UTF-8
+/// in a value or the vendor string is replaced by U+FFFD, rather than failing
+/// to read the block. A warning is logged for every such comment. Likewise, a
+/// picture with invalid UTF-8 in its description has it replaced, and its
+/// MIME type has invalid bytes removed, surrounding whitespace trimmed, and
+/// is converted to lowercase.
+pub fn read_metadata_block_ext<R: ReadBytes>(input: &mut R,
+                                             block_type: u8,
+                                             length: u32,
+                                             lenient: bool)
+                                             -> Result<MetadataBlock> {
+    debug!("reading metadata block of type {}, {} bytes", block_type, length);
     match block_type {
         0 => {
             // The streaminfo block has a fixed size of 34 bytes.
@@ -284,21 +1181,39 @@ pub fn read_metadata_block<R: ReadBytes>(input: &mut R,
             })
         }
         3 => {
-            // TODO: implement seektable reading. For now, pretend it is padding.
-            try!(input.skip(length));
-            Ok(MetadataBlock::Padding { length: length })
+            let seek_table = try!(read_seek_table_block(input, length, lenient));
+            Ok(MetadataBlock::SeekTable(seek_table))
         }
+        #[cfg(feature = "full-metadata")]
         4 => {
-            let vorbis_comment = try!(read_vorbis_comment_block(input, length));
+            let vorbis_comment = try!(full::read_vorbis_comment_block(input, length, lenient));
             Ok(MetadataBlock::VorbisComment(vorbis_comment))
         }
+        #[cfg(not(feature = "full-metadata"))]
+        4 => {
+            // Without the `full-metadata` feature, Vorbis comments are not
+            // parsed. Like other unparsed blocks, pretend it is padding.
+            let _ = lenient;
+            try!(input.skip(length));
+            Ok(MetadataBlock::Padding { length: length })
+        }
+        #[cfg(feature = "full-metadata")]
+        5 => {
+            let cue_sheet = try!(full::read_cue_sheet_block(input, length));
+            Ok(MetadataBlock::CueSheet(cue_sheet))
+        }
+        #[cfg(not(feature = "full-metadata"))]
         5 => {
-            // TODO: implement CUE sheet reading. For now, pretend it is padding.
             try!(input.skip(length));
             Ok(MetadataBlock::Padding { length: length })
         }
+        #[cfg(feature = "full-metadata")]
+        6 => {
+            let picture = try!(full::read_picture_block(input, length, lenient));
+            Ok(MetadataBlock::Picture(picture))
+        }
+        #[cfg(not(feature = "full-metadata"))]
         6 => {
-            // TODO: implement picture reading. For now, pretend it is padding.
             try!(input.skip(length));
             Ok(MetadataBlock::Padding { length: length })
         }
@@ -318,6 +1233,97 @@ pub fn read_metadata_block<R: ReadBytes>(input: &mut R,
     }
 }
 
+/// The maximum sample rate in Hz of a FLAC stream.
+///
+/// The streaminfo has room for larger rates, but frame headers can code at
+/// most 0xffff tens of Hz, so the format limits the sample rate to this.
+pub const MAX_SAMPLE_RATE: u32 = 655_350;
+
+/// Returns whether the sample rate is one that a FLAC stream can have.
+///
+/// A sample rate of 0 is invalid, as is a rate above `MAX_SAMPLE_RATE`. This
+/// applies both to the streaminfo and to rates specified in frame headers.
+pub fn is_valid_sample_rate(sample_rate: u32) -> bool {
+    sample_rate > 0 && sample_rate <= MAX_SAMPLE_RATE
+}
+
+#[test]
+fn verify_is_valid_sample_rate() {
+    assert!(!is_valid_sample_rate(0));
+    assert!(is_valid_sample_rate(1));
+    assert!(is_valid_sample_rate(44_100));
+    assert!(is_valid_sample_rate(655_350));
+    assert!(!is_valid_sample_rate(655_351));
+    assert!(!is_valid_sample_rate(0xf_ffff));
+}
+
+/// Returns the sample width of a PCM container that holds samples of `bits_per_sample` bits.
+///
+/// FLAC supports any width from 4 to 32 bits, but formats such as WAV and most
+/// audio APIs store samples in 16, 24, or 32 bits. This returns the smallest
+/// of those widths that fits the samples. Widths of 8 bits and less map to 16
+/// bits too, because 8-bit WAV samples are unsigned.
+///
+/// Samples that are stored unscaled in a wider container sound quieter, so
+/// they should be shifted left by `left_justify_shift()`. WAV files with an
+/// extensible format header can record the original width as the number of
+/// valid bits.
+///
+/// ```
+/// use claxon::metadata::{container_bits_per_sample, left_justify_shift};
+///
+/// for &(bits, container, shift) in &[(8, 16, 8), (12, 16, 4), (16, 16, 0),
+///                                    (20, 24, 4), (24, 24, 0), (28, 32, 4)] {
+///     assert_eq!(container_bits_per_sample(bits), container);
+///     assert_eq!(left_justify_shift(bits, container), shift);
+/// }
+/// ```
+///
+/// # Panics
+///
+/// Panics if `bits_per_sample` is greater than 32.
+pub fn container_bits_per_sample(bits_per_sample: u32) -> u32 {
+    assert!(bits_per_sample <= 32, "bits per sample must be at most 32");
+    if bits_per_sample <= 16 {
+        16
+    } else if bits_per_sample <= 24 {
+        24
+    } else {
+        32
+    }
+}
+
+/// Returns the left shift that scales samples to the full container width.
+///
+/// Shifting samples left by this amount preserves their relative loudness:
+/// full scale in the stream maps to full scale in the container, and the low
+/// bits are zero. Because the shift is exact, it can be undone by shifting
+/// right.
+///
+/// # Panics
+///
+/// Panics if `container_bits` is smaller than `bits_per_sample`.
+pub fn left_justify_shift(bits_per_sample: u32, container_bits: u32) -> u32 {
+    assert!(container_bits >= bits_per_sample, "container too narrow for the samples");
+    container_bits - bits_per_sample
+}
+
+#[test]
+fn verify_container_bits_per_sample() {
+    for bits in 4..33 {
+        let container = container_bits_per_sample(bits);
+        assert!(container == 16 || container == 24 || container == 32);
+        assert!(container >= bits);
+        // The container is the smallest one that fits.
+        assert!(container == 16 || container - 8 < bits);
+
+        // The most negative sample maps to the most negative container value.
+        let min = -1i64 << (bits - 1);
+        let shifted = min << left_justify_shift(bits, container);
+        assert_eq!(shifted, -1i64 << (container - 1));
+    }
+}
+
 fn read_streaminfo_block<R: ReadBytes>(input: &mut R) -> Result<StreamInfo> {
     let min_block_size = try!(input.read_be_u16());
     let max_block_size = try!(input.read_be_u16());
@@ -367,9 +1373,7 @@ fn read_streaminfo_block<R: ReadBytes>(input: &mut R) -> Result<StreamInfo> {
         return fmt_err("inconsistent bounds, min frame size > max frame size");
     }
 
-    // A sample rate of 0 is invalid, and the maximum sample rate is limited by
-    // the structure of the frame headers to 655350 Hz.
-    if sample_rate == 0 || sample_rate > 655350 {
+    if !is_valid_sample_rate(sample_rate) {
         return fmt_err("invalid sample rate");
     }
 
@@ -399,117 +1403,24 @@ fn read_streaminfo_block<R: ReadBytes>(input: &mut R) -> Result<StreamInfo> {
     Ok(stream_info)
 }
 
-fn read_vorbis_comment_block<R: ReadBytes>(input: &mut R, length: u32) -> Result<VorbisComment> {
-    if length < 8 {
-        // We expect at a minimum a 32-bit vendor string length, and a 32-bit
-        // comment count.
-        return fmt_err("Vorbis comment block is too short")
-    }
-
-    // Fail if the length of the Vorbis comment block is larger than 1 MiB. This
-    // block is full of length-prefixed strings for which we allocate memory up
-    // front. If there were no limit on these, a maliciously crafted file could
-    // cause OOM by claiming to contain large strings. But at least the strings
-    // cannot be longer than the size of the Vorbis comment block, and by
-    // limiting the size of that block, we can mitigate such DoS attacks.
-    //
-    // The typical size of a the Vorbis comment block is 1 KiB; on a corpus of
-    // real-world flac files, the 0.05 and 0.95 quantiles were 792 and 1257
-    // bytes respectively, with even the 0.99 quantile below 2 KiB. The only
-    // reason for having a large Vorbis comment block is when cover art is
-    // incorrectly embedded there, but the Vorbis comment block is not the right
-    // place for that anyway.
-    if length > 10 * 1024 * 1024 {
-        let msg = "Vorbis comment blocks larger than 10 MiB are not supported";
-        return Err(Error::Unsupported(msg))
-    }
-
-    // The Vorbis comment block starts with a length-prefixed "vendor string".
-    // It cannot be larger than the block length - 8, because there are the
-    // 32-bit vendor string length, and comment count.
-    let vendor_len = try!(input.read_le_u32());
-    if vendor_len > length - 8 { return fmt_err("vendor string too long") }
-    let mut vendor_bytes = Vec::with_capacity(vendor_len as usize);
-
-    // We can safely set the lenght of the vector here; the uninitialized memory
-    // is not exposed. If `read_into` succeeds, it will have overwritten all
-    // bytes. If not, an error is returned and the memory is never exposed.
-    unsafe { vendor_bytes.set_len(vendor_len as usize); }
-    try!(input.read_into(&mut vendor_bytes));
-    let vendor = try!(String::from_utf8(vendor_bytes));
-
-    // Next up is the number of comments. Because every comment is at least 4
-    // bytes to indicate its length, there cannot be more comments than the
-    // length of the block divided by 4. This is only an upper bound to ensure
-    // that we don't allocate a big vector, to protect against DoS attacks.
-    let mut comments_len = try!(input.read_le_u32());
-    if comments_len >= length / 4 {
-        return fmt_err("too many entries for Vorbis comment block")
-    }
-    let mut comments = Vec::with_capacity(comments_len as usize);
-
-    let mut bytes_left = length - 8 - vendor_len;
-
-    // For every comment, there is a length-prefixed string of the form
-    // "NAME=value".
-    while bytes_left >= 4 && comments.len() < comments_len as usize {
-        let comment_len = try!(input.read_le_u32());
-        bytes_left -= 4;
-
-        if comment_len > bytes_left {
-            return fmt_err("Vorbis comment too long for Vorbis comment block")
-        }
-
-        // Some older versions of libflac allowed writing zero-length Vorbis
-        // comments. ALthough such files are invalid, they do occur in the wild,
-        // so we skip over the empty comment.
-        if comment_len == 0 {
-            // Does not overflow because `comments_len > comments.len() >= 0`.
-            comments_len -= 1;
-            continue;
-        }
-
-        // For the same reason as above, setting the length is safe here.
-        let mut comment_bytes = Vec::with_capacity(comment_len as usize);
-        unsafe { comment_bytes.set_len(comment_len as usize); }
-        try!(input.read_into(&mut comment_bytes));
-
-        bytes_left -= comment_len;
-
-        if let Some(sep_index) = comment_bytes.iter().position(|&x| x == b'=') {
-            {
-                let name_bytes = &comment_bytes[..sep_index];
-
-                // According to the Vorbis spec, the field name may consist of ascii
-                // bytes 0x20 through 0x7d, 0x3d (`=`) excluded. Verifying this has
-                // the advantage that if the check passes, the result is valid
-                // UTF-8, so the conversion to string will not fail.
-                if name_bytes.iter().any(|&x| x < 0x20 || x > 0x7d) {
-                    return fmt_err("Vorbis comment field name contains invalid byte")
-                }
-            }
-
-            let comment = try!(String::from_utf8(comment_bytes));
-            comments.push((comment, sep_index));
-        } else {
-            return fmt_err("Vorbis comment does not contain '='")
-        }
-    }
-
-    if bytes_left != 0 {
-        return fmt_err("Vorbis comment block has excess data")
-    }
-
-    if comments.len() != comments_len as usize {
-        return fmt_err("Vorbis comment block contains wrong number of entries")
-    }
-
-    let vorbis_comment = VorbisComment {
-        vendor: vendor,
-        comments: comments,
-    };
+/// Allocates a buffer of `len` bytes, to be filled entirely by `read_into()`.
+///
+/// The buffer is left uninitialized, which is safe because the memory is never
+/// exposed: `read_into()` either overwrites all bytes, or returns an error, in
+/// which case the buffer is dropped without being read.
+#[cfg(not(feature = "forbid-unsafe"))]
+fn new_read_buffer(len: usize) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(len);
+    unsafe { buffer.set_len(len); }
+    buffer
+}
 
-    Ok(vorbis_comment)
+/// Allocates a buffer of `len` bytes, to be filled entirely by `read_into()`.
+///
+/// Without unsafe code, the buffer has to be zeroed first.
+#[cfg(feature = "forbid-unsafe")]
+fn new_read_buffer(len: usize) -> Vec<u8> {
+    vec![0u8; len]
 }
 
 fn read_padding_block<R: ReadBytes>(input: &mut R, length: u32) -> Result<()> {
@@ -521,28 +1432,113 @@ fn read_padding_block<R: ReadBytes>(input: &mut R, length: u32) -> Result<()> {
     Ok(try!(input.skip(length)))
 }
 
-fn read_application_block<R: ReadBytes>(input: &mut R, length: u32) -> Result<(u32, Vec<u8>)> {
-    if length < 4 {
-        return fmt_err("application block length must be at least 4 bytes")
+fn read_seek_table_block<R: ReadBytes>(input: &mut R,
+                                       length: u32,
+                                       lenient: bool)
+                                       -> Result<SeekTable> {
+    // Every seek point takes 18 bytes: a 64-bit sample number, a 64-bit
+    // offset, and a 16-bit number of samples.
+    if length % 18 != 0 {
+        return fmt_err("invalid seek table metadata block length")
     }
 
-    // Reject large application blocks to avoid memory-based denial-
-    // of-service attacks. See also the more elaborate motivation in
-    // `read_vorbis_comment_block()`.
-    if length > 10 * 1024 * 1024 {
-        let msg = "application blocks larger than 10 MiB are not supported";
-        return Err(Error::Unsupported(msg))
+    // The block length is at most 2^24 - 1 bytes, but the stream need not be
+    // that long. Grow the table as points are read, rather than allocating
+    // for all of them up front.
+    let n_points = length / 18;
+    let mut seekpoints: Vec<SeekPoint> = Vec::with_capacity(cmp::min(n_points, 1024) as usize);
+    for _ in 0..n_points {
+        let sample = try!(input.read_be_u64());
+        let offset = try!(input.read_be_u64());
+        let samples = try!(input.read_be_u16());
+
+        // Points must be sorted by sample number and unique, except for
+        // placeholders, which all have the maximum sample number, so they
+        // come last.
+        let is_ordered = match seekpoints.last() {
+            Some(prev) => prev.sample < sample || sample == u64::max_value(),
+            None => true,
+        };
+        if !is_ordered {
+            if !lenient {
+                return fmt_err("seek points are not in ascending order")
+            }
+            warn!("dropping seek point at sample {}, it is out of order", sample);
+            continue
+        }
+
+        seekpoints.push(SeekPoint {
+            sample: sample,
+            offset: offset,
+            samples: samples,
+        });
+    }
+
+    Ok(SeekTable { seekpoints: seekpoints })
+}
+
+/// Returns whether a seek table block of `length` bytes, read leniently, kept all its points.
+///
+/// When reading leniently, points out of order are dropped, so if the table
+/// lacks points, the block was invalid.
+fn is_complete_seek_table(seek_table: &SeekTable, length: u32) -> bool {
+    seek_table.seekpoints.len() as u32 == length / 18
+}
+
+#[test]
+fn verify_read_seek_table_block() {
+    let point = |sample: u64, offset: u64, samples: u16| {
+        SeekPoint { sample: sample, offset: offset, samples: samples }
+    };
+    let placeholder = point(u64::max_value(), 0, 0);
+    let table = SeekTable {
+        seekpoints: vec![point(0, 0, 4096), point(8192, 900, 4096), placeholder],
+    };
+    let data = serialize_seek_table(&table).unwrap();
+    let mut input = io::Cursor::new(&data[..]);
+    assert_eq!(read_seek_table_block(&mut input, 54, false).unwrap(), table);
+
+    assert_eq!(table.point_at_or_before(0), Some(point(0, 0, 4096)));
+    assert_eq!(table.point_at_or_before(8191), Some(point(0, 0, 4096)));
+    assert_eq!(table.point_at_or_before(u64::max_value()), Some(point(8192, 900, 4096)));
+
+    // Swap the first two points. Only when lenient, the second is dropped.
+    let mut swapped = data.clone();
+    swapped[..36].copy_from_slice(&[&data[18..36], &data[..18]].concat());
+    let mut input = io::Cursor::new(&swapped[..]);
+    assert!(read_seek_table_block(&mut input, 54, false).is_err());
+    let mut input = io::Cursor::new(&swapped[..]);
+    let lenient_table = read_seek_table_block(&mut input, 54, true).unwrap();
+    assert_eq!(lenient_table.seekpoints(), &[point(8192, 900, 4096), placeholder]);
+    assert_eq!(lenient_table.point_at_or_before(8191), None);
+
+    // A point after a placeholder is out of order, as is a duplicate.
+    for bytes in &[[&data[36..54], &data[..18]].concat(), [&data[..18], &data[..18]].concat()] {
+        let mut input = io::Cursor::new(&bytes[..]);
+        assert!(read_seek_table_block(&mut input, 36, false).is_err());
+    }
+
+    // The length must be a multiple of the size of a seek point.
+    let mut input = io::Cursor::new(&data[..]);
+    assert!(read_seek_table_block(&mut input, 53, false).is_err());
+}
+
+fn read_application_block<R: ReadBytes>(input: &mut R, length: u32) -> Result<(u32, Vec<u8>)> {
+    if length < 4 {
+        return fmt_err("application block length must be at least 4 bytes")
+    }
+
+    // Reject large application blocks to avoid memory-based denial-
+    // of-service attacks. See also the more elaborate motivation in
+    // `read_vorbis_comment_block()`.
+    if length > 10 * 1024 * 1024 {
+        return Err(Error::Unsupported(UnsupportedFeature::TooLargeMetadata))
     }
 
     let id = try!(input.read_be_u32());
 
     // Four bytes of the block have been used for the ID, the rest is payload.
-    // Create a vector of uninitialized memory, and read the block into it. The
-    // uninitialized memory is never exposed: read_into will either fill the
-    // buffer completely, or return an err, in which case the memory is not
-    // exposed.
-    let mut data = Vec::with_capacity(length as usize - 4);
-    unsafe { data.set_len(length as usize - 4); }
+    let mut data = new_read_buffer(length as usize - 4);
     try!(input.read_into(&mut data));
 
     Ok((id, data))
@@ -578,6 +1574,30 @@ impl<R: ReadBytes> MetadataBlockReader<R> {
         self.done = header.is_last;
         Ok(block)
     }
+
+    /// Returns the underlying input.
+    ///
+    /// After the final block has been read, the input is positioned at the
+    /// first frame, so it can be used to construct a `FrameReader`.
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
+
+impl MetadataBlockReader<BufferedReader<fs::File>> {
+    /// Opens the file at `filename`, and reads its stream header.
+    ///
+    /// This is a convenience constructor that wraps the file in a
+    /// `BufferedReader`, with its default buffer size. The first block that
+    /// the reader yields is the streaminfo block. An error is returned if the
+    /// file does not start with the FLAC stream header.
+    pub fn open<P: AsRef<path::Path>>(filename: P)
+                                      -> Result<MetadataBlockReader<BufferedReader<fs::File>>> {
+        let file = try!(fs::File::open(filename));
+        let mut input = BufferedReader::new(file);
+        try!(::read_stream_header(&mut input));
+        Ok(MetadataBlockReader::new(input))
+    }
 }
 
 impl<R: ReadBytes> Iterator for MetadataBlockReader<R> {
@@ -607,3 +1627,1336 @@ impl<R: ReadBytes> Iterator for MetadataBlockReader<R> {
         if self.done { (0, Some(0)) } else { (1, None) }
     }
 }
+
+/// Limits on the metadata that `MetadataBlockReader::collect_all()` reads.
+///
+/// A stream can contain any number of metadata blocks. When reading untrusted
+/// input, limits bound the time spent reading metadata, in addition to the
+/// size limits on individual blocks. Exceeding a limit results in
+/// `Error::LimitExceeded`. The default limits do not restrict anything. The
+/// same limits apply to `FlacReader`, see `FlacReaderOptions::metadata_limits`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetadataLimits {
+    /// The maximum number of metadata blocks, including the streaminfo block.
+    pub max_blocks: u32,
+
+    /// The maximum number of bytes of metadata, including block headers.
+    pub max_len: u64,
+}
+
+impl Default for MetadataLimits {
+    fn default() -> MetadataLimits {
+        MetadataLimits {
+            max_blocks: u32::max_value(),
+            max_len: u64::max_value(),
+        }
+    }
+}
+
+impl MetadataLimits {
+    /// Checks the number of blocks and bytes of metadata read so far against the limits.
+    ///
+    /// Call this after reading the header of a block, before reading the block
+    /// itself, with the totals including that block.
+    pub fn check(&self, n_blocks: u32, len: u64) -> Result<()> {
+        if n_blocks > self.max_blocks {
+            return Err(Error::LimitExceeded {
+                limit: "metadata blocks",
+                value: n_blocks as u64,
+                max: self.max_blocks as u64,
+            });
+        }
+        if len > self.max_len {
+            return Err(Error::LimitExceeded {
+                limit: "metadata length",
+                value: len,
+                max: self.max_len,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The metadata of a stream, collected by `MetadataBlockReader::collect_all()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    /// The streaminfo block, which every stream has.
+    pub streaminfo: StreamInfo,
+
+    /// The Vorbis comment block, if the stream has one.
+    pub vorbis_comment: Option<VorbisComment>,
+
+    /// The seek table, if the stream has one and Claxon could read it.
+    pub seek_table: Option<SeekTable>,
+
+    /// The application blocks, as (application ID, data) pairs in stream order.
+    pub applications: Vec<(u32, Vec<u8>)>,
+
+    /// The pictures, in stream order.
+    ///
+    /// Without the `full-metadata` feature, pictures are not parsed, and this
+    /// is always empty.
+    pub pictures: Vec<Picture>,
+
+    /// The cue sheet, if the stream has one.
+    ///
+    /// Without the `full-metadata` feature, cue sheets are not parsed, and
+    /// this is always `None`.
+    pub cue_sheet: Option<CueSheet>,
+}
+
+impl<R: ReadBytes> MetadataBlockReader<R> {
+    /// Reads all remaining metadata blocks, and collects them into a `Metadata`.
+    ///
+    /// This must be called on a reader positioned at the streaminfo block,
+    /// right after the stream header. The same checks are done as when
+    /// constructing a `FlacReader`: the streaminfo block must come first, and
+    /// there may be at most one of it, and of the Vorbis comment block. A seek
+    /// table with points out of order cannot be trusted, and neither can two
+    /// seek tables, so in that case there is no seek table, and a warning is
+    /// logged. If there is more than one cue sheet, the first one is kept, and
+    /// a warning is logged. Padding and blocks that Claxon does not
+    /// parse are skipped. Afterwards, the input is positioned at the first
+    /// frame.
+    pub fn collect_all(&mut self, limits: MetadataLimits) -> Result<Metadata> {
+        let mut n_blocks = 0u32;
+        let mut len = 0u64;
+        let mut streaminfo = None;
+        let mut vorbis_comment = None;
+        let mut seek_table = None;
+        let mut n_seek_tables = 0u32;
+        let mut applications = Vec::new();
+        let mut pictures = Vec::new();
+        let mut cue_sheet = None;
+
+        while !self.done {
+            // If reading fails halfway, we don't know where we are in the
+            // stream, so no more attempts to read will be made.
+            self.done = true;
+            let header = try!(read_metadata_block_header(&mut self.input));
+
+            // Check the limits before reading the block itself.
+            n_blocks += 1;
+            len += 4 + header.length as u64;
+            try!(limits.check(n_blocks, len));
+
+            // Read the seek table leniently, so points out of order do not
+            // fail the stream. Such a table is dropped below.
+            let block = try!(read_metadata_block_ext(&mut self.input,
+                                                     header.block_type,
+                                                     header.length,
+                                                     header.block_type == 3));
+            self.done = header.is_last;
+
+            match block {
+                MetadataBlock::StreamInfo(si) => {
+                    if streaminfo.is_some() {
+                        return fmt_err("encountered second streaminfo block")
+                    }
+                    streaminfo = Some(si);
+                }
+                // Any other block than the streaminfo is out of place first.
+                _ if streaminfo.is_none() => return fmt_err("streaminfo block missing"),
+                MetadataBlock::VorbisComment(vc) => {
+                    if vorbis_comment.is_some() {
+                        return fmt_err("encountered second Vorbis comment block")
+                    }
+                    vorbis_comment = Some(vc);
+                }
+                MetadataBlock::SeekTable(st) => {
+                    n_seek_tables += 1;
+                    if n_seek_tables > 1 {
+                        warn!("dropping seek tables, the stream has more than one");
+                        seek_table = None;
+                    } else if !is_complete_seek_table(&st, header.length) {
+                        warn!("dropping seek table, its points are not in ascending order");
+                    } else {
+                        seek_table = Some(st);
+                    }
+                }
+                MetadataBlock::Application { id, data } => applications.push((id, data)),
+                MetadataBlock::Padding { .. } => {}
+                MetadataBlock::CueSheet(..) if cue_sheet.is_some() => {
+                    warn!("ignoring second cue sheet block");
+                }
+                MetadataBlock::CueSheet(cs) => cue_sheet = Some(cs),
+                MetadataBlock::Picture(picture) => pictures.push(picture),
+                MetadataBlock::Reserved => {}
+            }
+        }
+
+        match streaminfo {
+            Some(si) => {
+                Ok(Metadata {
+                    streaminfo: si,
+                    vorbis_comment: vorbis_comment,
+                    seek_table: seek_table,
+                    applications: applications,
+                    pictures: pictures,
+                    cue_sheet: cue_sheet,
+                })
+            }
+            None => fmt_err("streaminfo block missing"),
+        }
+    }
+}
+
+/// A problem in the metadata that a `FlacReader` tolerated.
+///
+/// See `FlacReaderOptions::lenient_metadata`, `FlacReaderOptions::strict_metadata`,
+/// and `FlacReader::metadata_warnings()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetadataWarning {
+    /// A frame follows a metadata block that is not marked as the last one.
+    LastBlockNotMarked,
+    /// A metadata block follows the block that is marked as the last one.
+    BlockAfterLast {
+        /// The type of the block that follows.
+        block_type: u8,
+    },
+    /// The seek table has points that are out of order, or duplicated.
+    ///
+    /// In lenient mode, only those points are dropped. Otherwise, the seek
+    /// table is dropped altogether.
+    UnorderedSeekTable,
+    /// The stream has more than one seek table, all of them are dropped.
+    SecondSeekTable,
+    /// The stream has more than one cue sheet, only the first one is used.
+    SecondCueSheet,
+    /// Metadata blocks disagree about the length of the stream.
+    LengthConflict(LengthConflict),
+}
+
+/// A disagreement between metadata blocks about the length of the stream.
+///
+/// When blocks disagree, the streaminfo takes precedence: Claxon uses the total
+/// number of samples from the streaminfo for durations and seeking, the seek
+/// table only as a hint of where frames are, and the cue sheet only for track
+/// offsets. A conflict usually means that the file was edited by a tool that
+/// did not update all blocks, so the other blocks should not be trusted
+/// either. The samples that are decoded are those of the frames, regardless of
+/// what the metadata says. See `FlacReaderOptions::length_check` for how a
+/// `FlacReader` reports conflicts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LengthConflict {
+    /// A seek point refers to a frame that ends after the stream ends.
+    SeekPointPastEnd {
+        /// The total number of samples according to the streaminfo.
+        total_samples: u64,
+        /// The seek point that lies past the end.
+        seek_point: SeekPoint,
+    },
+    /// The lead-out track of the cue sheet is not at the end of the stream.
+    LeadOutMismatch {
+        /// The total number of samples according to the streaminfo.
+        total_samples: u64,
+        /// The offset of the lead-out track, in inter-channel samples.
+        lead_out: u64,
+    },
+    /// The frames of the stream end at a different sample than the streaminfo says.
+    ///
+    /// This is found only after decoding up to the end of the stream. A
+    /// truncated stream, for instance a partial download, ends early.
+    DecodedLengthMismatch {
+        /// The total number of samples according to the streaminfo.
+        total_samples: u64,
+        /// The sample after the last sample of the last frame.
+        decoded_samples: u64,
+    },
+}
+
+impl Metadata {
+    /// Checks that the blocks agree on the length of the stream.
+    ///
+    /// See `length_conflicts()` for more info.
+    pub fn length_conflicts(&self) -> Vec<LengthConflict> {
+        length_conflicts(&self.streaminfo, self.seek_table.as_ref(), self.cue_sheet.as_ref())
+    }
+}
+
+/// Checks that the seek table and cue sheet agree with the streaminfo on the length of the stream.
+///
+/// Returns all conflicts, in stream order, and logs a warning for each of
+/// them. When the streaminfo does not know the total number of samples, there
+/// is nothing to compare to. See `LengthConflict` for which block takes
+/// precedence. `FlacReader` does this check when it is constructed, see
+/// `FlacReaderOptions::length_check`.
+pub fn length_conflicts(streaminfo: &StreamInfo,
+                        seek_table: Option<&SeekTable>,
+                        cue_sheet: Option<&CueSheet>)
+                        -> Vec<LengthConflict> {
+    let mut conflicts = Vec::new();
+    let total_samples = match streaminfo.samples {
+        Some(n) => n,
+        None => return conflicts,
+    };
+    if let Some(seek_table) = seek_table {
+        let points = seek_table.seekpoints().iter().filter(|p| p.sample != u64::max_value());
+        for &point in points {
+            let end = point.sample.saturating_add(point.samples as u64);
+            if end > total_samples {
+                warn!("seek point at sample {} ends at sample {}, past the end at sample {}",
+                      point.sample, end, total_samples);
+                conflicts.push(LengthConflict::SeekPointPastEnd {
+                    total_samples: total_samples,
+                    seek_point: point,
+                });
+            }
+        }
+    }
+    if let Some(lead_out) = cue_sheet.and_then(|cs| cs.tracks.last()) {
+        if lead_out.offset != total_samples {
+            warn!("cue sheet lead-out at sample {}, but the stream ends at sample {}",
+                  lead_out.offset, total_samples);
+            conflicts.push(LengthConflict::LeadOutMismatch {
+                total_samples: total_samples,
+                lead_out: lead_out.offset,
+            });
+        }
+    }
+    conflicts
+}
+
+#[test]
+#[cfg(feature = "full-metadata")]
+fn verify_collect_all() {
+    use std::fs;
+    use std::io::Read;
+
+    let read_file = |fname: &str| {
+        let mut data = Vec::new();
+        fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+        data
+    };
+
+    // This file has its first frame at byte 152.
+    let data = read_file("testsamples/wasted_bits.flac");
+    let mut input = io::Cursor::new(&data[4..]);
+    let metadata = MetadataBlockReader::new(&mut input).collect_all(MetadataLimits::default())
+        .unwrap();
+    assert_eq!(input.position(), 152 - 4);
+    assert_eq!(metadata.streaminfo.sample_rate, 44_100);
+    let seekpoints = metadata.seek_table.as_ref().map(|st| st.seekpoints().to_vec());
+    assert_eq!(seekpoints, Some(vec![SeekPoint { sample: 0, offset: 0, samples: 4096 }]));
+
+    let limits = MetadataLimits { max_blocks: 1, max_len: u64::max_value() };
+    let result = MetadataBlockReader::new(io::Cursor::new(&data[4..])).collect_all(limits);
+    assert_eq!(result, Err(Error::LimitExceeded { limit: "metadata blocks", value: 2, max: 1 }));
+
+    let limits = MetadataLimits { max_blocks: 100, max_len: 100 };
+    match MetadataBlockReader::new(io::Cursor::new(&data[4..])).collect_all(limits) {
+        Err(Error::LimitExceeded { limit: "metadata length", .. }) => {}
+        other => panic!("expected metadata length limit error, got {:?}", other),
+    }
+
+    // This file has only a streaminfo block, append two Vorbis comment blocks.
+    let mut data = read_file("testsamples/pop.flac")[4..42].to_vec();
+    data[0] &= 0x7f;
+    let block = MetadataBlock::VorbisComment(VorbisComment::new("claxon".to_string()));
+    write_metadata_block(&mut data, false, &block).unwrap();
+    write_metadata_block(&mut data, true, &block).unwrap();
+    let result = MetadataBlockReader::new(io::Cursor::new(&data[..]))
+        .collect_all(MetadataLimits::default());
+    assert_eq!(result, Err(Error::FormatError("encountered second Vorbis comment block")));
+
+    // Without the streaminfo block, the Vorbis comment block is out of place.
+    let result = MetadataBlockReader::new(io::Cursor::new(&data[38..]))
+        .collect_all(MetadataLimits::default());
+    assert_eq!(result, Err(Error::FormatError("streaminfo block missing")));
+}
+
+#[test]
+#[cfg(feature = "full-metadata")]
+fn verify_metadata_block_reader_open() {
+    use input::SeekBytes;
+
+    let mut reader = MetadataBlockReader::open("testsamples/wasted_bits.flac").unwrap();
+    let metadata = reader.collect_all(MetadataLimits::default()).unwrap();
+    assert_eq!(metadata.streaminfo.sample_rate, 44_100);
+    assert_eq!(reader.into_inner().stream_position(), 152);
+
+    match MetadataBlockReader::open("Cargo.toml") {
+        Err(Error::FormatError("invalid stream header")) => {}
+        Err(err) => panic!("expected invalid stream header, got {:?}", err),
+        Ok(..) => panic!("expected invalid stream header"),
+    }
+}
+
+#[test]
+fn verify_length_conflicts() {
+    let point = |sample: u64, samples: u16| {
+        SeekPoint { sample: sample, offset: sample / 4, samples: samples }
+    };
+    let mut metadata = Metadata {
+        streaminfo: StreamInfo {
+            min_block_size: 4096,
+            max_block_size: 4096,
+            min_frame_size: None,
+            max_frame_size: None,
+            sample_rate: 44_100,
+            channels: 2,
+            bits_per_sample: 16,
+            samples: Some(10_000),
+            md5sum: [0; 16],
+        },
+        vorbis_comment: None,
+        seek_table: Some(SeekTable {
+            seekpoints: vec![point(0, 4096), point(8192, 1808), point(u64::max_value(), 0)],
+        }),
+        applications: Vec::new(),
+        pictures: Vec::new(),
+        cue_sheet: Some(CueSheet {
+            media_catalog_number: String::new(),
+            lead_in: 0,
+            is_cd: false,
+            tracks: vec![CueSheetTrack {
+                offset: 10_000,
+                number: 255,
+                isrc: None,
+                is_audio: true,
+                pre_emphasis: false,
+                indices: Vec::new(),
+            }],
+        }),
+    };
+    assert_eq!(metadata.length_conflicts(), vec![]);
+
+    metadata.streaminfo.samples = Some(9_000);
+    assert_eq!(metadata.length_conflicts(), vec![
+        LengthConflict::SeekPointPastEnd {
+            total_samples: 9_000,
+            seek_point: point(8192, 1808),
+        },
+        LengthConflict::LeadOutMismatch {
+            total_samples: 9_000,
+            lead_out: 10_000,
+        },
+    ]);
+
+    // Without a total, there is nothing to compare to.
+    metadata.streaminfo.samples = None;
+    assert_eq!(metadata.length_conflicts(), vec![]);
+}
+
+/// The largest body that a metadata block can have; the length field is 24 bits.
+const MAX_BLOCK_LENGTH: u64 = (1 << 24) - 1;
+
+/// Writes a metadata block header for a block of the given type and length.
+fn write_metadata_block_header<W: io::Write>(output: &mut W,
+                                             is_last: bool,
+                                             block_type: u8,
+                                             length: u32)
+                                             -> io::Result<()> {
+    debug_assert!(block_type < 127);
+    debug_assert!(length as u64 <= MAX_BLOCK_LENGTH);
+    let is_last_bit = if is_last { 0b1000_0000 } else { 0 };
+    let header = [
+        is_last_bit | block_type,
+        (length >> 16) as u8,
+        (length >> 8) as u8,
+        length as u8,
+    ];
+    output.write_all(&header)
+}
+
+fn push_le_u32(buffer: &mut Vec<u8>, x: u32) {
+    buffer.push(x as u8);
+    buffer.push((x >> 8) as u8);
+    buffer.push((x >> 16) as u8);
+    buffer.push((x >> 24) as u8);
+}
+
+fn push_be_u32(buffer: &mut Vec<u8>, x: u32) {
+    buffer.push((x >> 24) as u8);
+    buffer.push((x >> 16) as u8);
+    buffer.push((x >> 8) as u8);
+    buffer.push(x as u8);
+}
+
+/// Serializes the body of a Vorbis comment block.
+fn serialize_vorbis_comment(vorbis_comment: &VorbisComment) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(vorbis_comment.serialized_len() as usize - 4);
+    push_le_u32(&mut data, vorbis_comment.vendor.len() as u32);
+    data.extend_from_slice(vorbis_comment.vendor.as_bytes());
+    push_le_u32(&mut data, vorbis_comment.entries.len() as u32);
+    let mut start = 0;
+    for &(_, end) in &vorbis_comment.entries {
+        push_le_u32(&mut data, (end - start) as u32);
+        data.extend_from_slice(vorbis_comment.comments[start..end].as_bytes());
+        start = end;
+    }
+
+    if data.len() as u64 > MAX_BLOCK_LENGTH {
+        return Err(Error::Unsupported(UnsupportedFeature::MetadataTooLargeToWrite))
+    }
+
+    Ok(data)
+}
+
+/// Writes a padding block with a body of `length` zero bytes.
+///
+/// The block occupies `4 + length` bytes, including its header. `is_last`
+/// indicates whether this is the last metadata block before the audio data.
+///
+/// # Panics
+///
+/// Panics if `length` does not fit in the 24-bit length field of the header.
+pub fn write_padding_block<W: io::Write>(output: &mut W,
+                                         is_last: bool,
+                                         length: u32)
+                                         -> io::Result<()> {
+    use std::io::Read;
+    assert!(length as u64 <= MAX_BLOCK_LENGTH, "padding block too long");
+    try!(write_metadata_block_header(output, is_last, 1, length));
+    try!(io::copy(&mut io::repeat(0).take(length as u64), output));
+    Ok(())
+}
+
+/// Fills exactly `total_len` bytes with padding blocks, including their headers.
+///
+/// A single padding block can hold at most 2<sup>24</sup> - 1 bytes, so more
+/// padding is spread over multiple blocks. If `is_last` is true, the final
+/// padding block is marked as the last metadata block.
+///
+/// # Panics
+///
+/// Panics if `total_len` is 1, 2 or 3, as a block needs 4 bytes for its header.
+pub fn write_padding<W: io::Write>(output: &mut W,
+                                   is_last: bool,
+                                   total_len: u64)
+                                   -> io::Result<()> {
+    assert!(total_len == 0 || total_len >= 4, "padding must be 0 or at least 4 bytes");
+
+    // Take care that the final block never gets fewer than the 4 bytes of its
+    // header.
+    let mut padding_len = total_len;
+    while padding_len > 0 {
+        let body_len = if padding_len - 4 > MAX_BLOCK_LENGTH {
+            cmp::min(MAX_BLOCK_LENGTH, padding_len - 8)
+        } else {
+            padding_len - 4
+        };
+        padding_len -= 4 + body_len;
+        try!(write_padding_block(output, is_last && padding_len == 0, body_len as u32));
+    }
+    Ok(())
+}
+
+#[test]
+fn verify_write_padding() {
+    let mut output = Vec::new();
+    write_padding(&mut output, true, 10).unwrap();
+    assert_eq!(output, vec![0x81, 0, 0, 6, 0, 0, 0, 0, 0, 0]);
+
+    // More than fits in one block is split, without leaving a block that
+    // cannot hold its own header.
+    let mut output = Vec::new();
+    let total_len = MAX_BLOCK_LENGTH + 4 + 2;
+    write_padding(&mut output, false, total_len).unwrap();
+    assert_eq!(output.len() as u64, total_len);
+    let mut cursor = io::Cursor::new(&output[..]);
+    let first = read_metadata_block_header(&mut cursor).unwrap();
+    assert_eq!((first.is_last, first.block_type), (false, 1));
+    cursor.skip(first.length).unwrap();
+    let second = read_metadata_block_header(&mut cursor).unwrap();
+    assert_eq!((second.is_last, second.block_type), (false, 1));
+    assert_eq!(4 + first.length as u64 + 4 + second.length as u64, total_len);
+}
+
+/// Serializes the body of a streaminfo block, the inverse of `read_streaminfo_block()`.
+fn serialize_streaminfo(streaminfo: &StreamInfo) -> Vec<u8> {
+    let min_frame_size = streaminfo.min_frame_size.unwrap_or(0);
+    let max_frame_size = streaminfo.max_frame_size.unwrap_or(0);
+    let samples = streaminfo.samples.unwrap_or(0);
+    let sr = streaminfo.sample_rate;
+    let channels_bps = ((streaminfo.channels - 1) << 5) | (streaminfo.bits_per_sample - 1);
+    let mut data = vec![
+        (streaminfo.min_block_size >> 8) as u8,
+        streaminfo.min_block_size as u8,
+        (streaminfo.max_block_size >> 8) as u8,
+        streaminfo.max_block_size as u8,
+        (min_frame_size >> 16) as u8,
+        (min_frame_size >> 8) as u8,
+        min_frame_size as u8,
+        (max_frame_size >> 16) as u8,
+        (max_frame_size >> 8) as u8,
+        max_frame_size as u8,
+        // 20 bits sample rate, 3 bits channels - 1, 5 bits bits per sample - 1,
+        // and 36 bits of samples.
+        (sr >> 12) as u8,
+        (sr >> 4) as u8,
+        ((sr << 4) as u8) | (channels_bps >> 4) as u8,
+        ((channels_bps << 4) as u8) | (samples >> 32) as u8,
+        (samples >> 24) as u8,
+        (samples >> 16) as u8,
+        (samples >> 8) as u8,
+        samples as u8,
+    ];
+    data.extend_from_slice(&streaminfo.md5sum);
+    data
+}
+
+/// Serializes the body of a seek table block.
+fn serialize_seek_table(seek_table: &SeekTable) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(seek_table.seekpoints.len() * 18);
+    for point in &seek_table.seekpoints {
+        for &(value, bytes) in &[(point.sample, 8), (point.offset, 8), (point.samples as u64, 2)] {
+            for i in (0..bytes).rev() {
+                data.push((value >> (8 * i)) as u8);
+            }
+        }
+    }
+
+    if data.len() as u64 > MAX_BLOCK_LENGTH {
+        return Err(Error::Unsupported(UnsupportedFeature::MetadataTooLargeToWrite))
+    }
+
+    Ok(data)
+}
+
+/// Serializes the body of a cue sheet block.
+///
+/// Strings are truncated or padded with zeros to the size of their field.
+fn serialize_cue_sheet(cue_sheet: &CueSheet) -> Result<Vec<u8>> {
+    fn push_be_u64(buffer: &mut Vec<u8>, x: u64) {
+        push_be_u32(buffer, (x >> 32) as u32);
+        push_be_u32(buffer, x as u32);
+    }
+    fn push_padded(buffer: &mut Vec<u8>, string: &str, len: usize) {
+        let bytes = &string.as_bytes()[..cmp::min(string.len(), len)];
+        buffer.extend_from_slice(bytes);
+        buffer.extend(iter::repeat(0).take(len - bytes.len()));
+    }
+
+    // The numbers of tracks and index points are stored in a byte.
+    if cue_sheet.tracks.len() > 0xff || cue_sheet.tracks.iter().any(|t| t.indices.len() > 0xff) {
+        return fmt_err("cue sheet has too many tracks or index points")
+    }
+
+    let mut data = Vec::with_capacity(396 + cue_sheet.tracks.len() * 48);
+    push_padded(&mut data, &cue_sheet.media_catalog_number, 128);
+    push_be_u64(&mut data, cue_sheet.lead_in);
+    data.push(if cue_sheet.is_cd { 0x80 } else { 0x00 });
+    data.extend(iter::repeat(0).take(258));
+    data.push(cue_sheet.tracks.len() as u8);
+    for track in &cue_sheet.tracks {
+        push_be_u64(&mut data, track.offset);
+        data.push(track.number);
+        push_padded(&mut data, track.isrc.as_ref().map_or("", |isrc| &isrc[..]), 12);
+        let flags = (if track.is_audio { 0x00 } else { 0x80 }) |
+                    (if track.pre_emphasis { 0x40 } else { 0x00 });
+        data.push(flags);
+        data.extend(iter::repeat(0).take(13));
+        data.push(track.indices.len() as u8);
+        for index in &track.indices {
+            push_be_u64(&mut data, index.offset);
+            data.push(index.number);
+            data.extend_from_slice(&[0, 0, 0]);
+        }
+    }
+    Ok(data)
+}
+
+/// Serializes the body of a picture block.
+fn serialize_picture(picture: &Picture) -> Result<Vec<u8>> {
+    let len = 32 + picture.mime_type.len() as u64 + picture.description.len() as u64 +
+              picture.data.len() as u64;
+    if len > MAX_BLOCK_LENGTH {
+        return Err(Error::Unsupported(UnsupportedFeature::MetadataTooLargeToWrite))
+    }
+
+    let mut data = Vec::with_capacity(len as usize);
+    push_be_u32(&mut data, picture.picture_type);
+    for field in &[picture.mime_type.as_bytes(), picture.description.as_bytes()] {
+        push_be_u32(&mut data, field.len() as u32);
+        data.extend_from_slice(field);
+    }
+    for &x in &[picture.width, picture.height, picture.depth, picture.colors] {
+        push_be_u32(&mut data, x);
+    }
+    push_be_u32(&mut data, picture.data.len() as u32);
+    data.extend_from_slice(&picture.data);
+    Ok(data)
+}
+
+/// Writes a metadata block, including its header.
+///
+/// This is the inverse of `read_metadata_block_with_header()`: reading back
+/// the output yields a block equal to `block`. `is_last` indicates whether
+/// this is the last metadata block before the audio data.
+///
+/// Reserved blocks are not parsed by Claxon, so their contents are not known,
+/// and writing them returns `Error::Unsupported`.
+pub fn write_metadata_block<W: io::Write>(output: &mut W,
+                                          is_last: bool,
+                                          block: &MetadataBlock)
+                                          -> Result<()> {
+    let (block_type, data) = match *block {
+        MetadataBlock::StreamInfo(ref streaminfo) => (0, serialize_streaminfo(streaminfo)),
+        MetadataBlock::Padding { length } => {
+            if length as u64 > MAX_BLOCK_LENGTH {
+                return fmt_err("padding block too long")
+            }
+            try!(write_padding_block(output, is_last, length));
+            return Ok(())
+        }
+        MetadataBlock::Application { id, ref data } => {
+            if data.len() as u64 + 4 > MAX_BLOCK_LENGTH {
+                return Err(Error::Unsupported(UnsupportedFeature::MetadataTooLargeToWrite))
+            }
+            let mut body = Vec::with_capacity(data.len() + 4);
+            push_be_u32(&mut body, id);
+            body.extend_from_slice(data);
+            (2, body)
+        }
+        MetadataBlock::SeekTable(ref seek_table) => (3, try!(serialize_seek_table(seek_table))),
+        MetadataBlock::VorbisComment(ref vc) => (4, try!(serialize_vorbis_comment(vc))),
+        MetadataBlock::CueSheet(ref cue_sheet) => (5, try!(serialize_cue_sheet(cue_sheet))),
+        MetadataBlock::Picture(ref picture) => (6, try!(serialize_picture(picture))),
+        MetadataBlock::Reserved => {
+            return Err(Error::Unsupported(UnsupportedFeature::UnwritableMetadataBlock))
+        }
+    };
+    try!(write_metadata_block_header(output, is_last, block_type, data.len() as u32));
+    try!(output.write_all(&data));
+    Ok(())
+}
+
+/// Brings metadata blocks into canonical form, to write them out.
+///
+/// Tag editors that write the canonical form of the blocks that they read
+/// produce the same metadata when nothing changed, regardless of how the
+/// original encoder laid out the blocks. The canonical form is:
+///
+///  * The streaminfo block comes first.
+///  * Then the other blocks, in their original order, except that there is
+///    at most one Vorbis comment block. Comments of later Vorbis comment
+///    blocks are appended to the first one, and the first vendor string is
+///    kept. (The specification allows only one Vorbis comment block.)
+///  * Finally, at most one padding block. Padding blocks are merged into a
+///    single block that occupies the same number of bytes, including block
+///    headers, but at most the maximum block size.
+///
+/// Canonicalizing blocks that are in canonical form does not change them.
+/// Returns a format error if there is not exactly one streaminfo block.
+pub fn canonicalize(blocks: Vec<MetadataBlock>) -> Result<Vec<MetadataBlock>> {
+    let mut streaminfo = None;
+    let mut vorbis_comment_index = None;
+    let mut padding_len = None;
+    let mut result = Vec::with_capacity(blocks.len());
+
+    for block in blocks {
+        match block {
+            MetadataBlock::StreamInfo(si) => {
+                if streaminfo.is_some() {
+                    return fmt_err("encountered second streaminfo block")
+                }
+                streaminfo = Some(si);
+            }
+            MetadataBlock::Padding { length } => {
+                // Every merged block saves a block header of 4 bytes.
+                padding_len = Some(match padding_len {
+                    None => length as u64,
+                    Some(len) => len + 4 + length as u64,
+                });
+            }
+            MetadataBlock::VorbisComment(vc) => {
+                match vorbis_comment_index {
+                    Some(i) => {
+                        if let MetadataBlock::VorbisComment(ref mut first) = result[i] {
+                            for (name, value) in vc.tags() {
+                                first.push(name, value);
+                            }
+                        }
+                    }
+                    None => {
+                        vorbis_comment_index = Some(result.len());
+                        result.push(MetadataBlock::VorbisComment(vc));
+                    }
+                }
+            }
+            other => result.push(other),
+        }
+    }
+
+    match streaminfo {
+        Some(si) => result.insert(0, MetadataBlock::StreamInfo(si)),
+        None => return fmt_err("streaminfo block missing"),
+    }
+    if let Some(len) = padding_len {
+        let length = cmp::min(len, MAX_BLOCK_LENGTH) as u32;
+        result.push(MetadataBlock::Padding { length: length });
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+fn read_all_metadata_blocks(data: &[u8]) -> Vec<MetadataBlock> {
+    let mut input = io::Cursor::new(data);
+    MetadataBlockReader::new(&mut input).map(|b| b.unwrap()).collect()
+}
+
+#[cfg(test)]
+fn write_all_metadata_blocks(blocks: &[MetadataBlock]) -> Vec<u8> {
+    let mut output = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        write_metadata_block(&mut output, i + 1 == blocks.len(), block).unwrap();
+    }
+    output
+}
+
+#[test]
+#[cfg(feature = "full-metadata")]
+fn verify_write_metadata_block_round_trips() {
+    use std::fs;
+    use std::io::Read;
+
+    let mut vorbis_comment = VorbisComment::new("Claxon".to_string());
+    vorbis_comment.push("TITLE", "Round trip");
+    let mut seek_table = SeekTable { seekpoints: Vec::new() };
+    seek_table.seekpoints.push(SeekPoint { sample: 0, offset: 0, samples: 4096 });
+    seek_table.seekpoints.push(SeekPoint { sample: u64::max_value(), offset: 0, samples: 0 });
+    let blocks = vec![
+        MetadataBlock::StreamInfo(StreamInfo {
+            min_block_size: 16,
+            max_block_size: 65535,
+            min_frame_size: Some(0xfedcba),
+            max_frame_size: None,
+            sample_rate: 655_350,
+            channels: 8,
+            bits_per_sample: 24,
+            samples: Some(0xf_1234_5678),
+            md5sum: [0x5a; 16],
+        }),
+        MetadataBlock::Application { id: 0x436c6178, data: vec![1, 2, 3] },
+        MetadataBlock::SeekTable(seek_table.clone()),
+        MetadataBlock::VorbisComment(vorbis_comment),
+        MetadataBlock::CueSheet(CueSheet {
+            media_catalog_number: String::new(),
+            lead_in: 0,
+            is_cd: false,
+            tracks: vec![CueSheetTrack {
+                offset: 0xf_1234_5678,
+                number: 255,
+                isrc: None,
+                is_audio: true,
+                pre_emphasis: false,
+                indices: Vec::new(),
+            }],
+        }),
+        MetadataBlock::Picture(Picture {
+            picture_type: 3,
+            mime_type: "image/png".to_string(),
+            description: String::new(),
+            width: 0,
+            height: 0,
+            depth: 0,
+            colors: 0,
+            data: vec![0x89, 0x50, 0x4e, 0x47],
+        }),
+        MetadataBlock::Padding { length: 7 },
+    ];
+    let bytes = write_all_metadata_blocks(&blocks);
+    assert_eq!(read_all_metadata_blocks(&bytes), blocks);
+
+    // Check the serialization of the seek table directly too.
+    let data = serialize_seek_table(&seek_table).unwrap();
+    assert_eq!(data.len(), 36);
+    assert_eq!(&data[16..18], &[0x10, 0x00]);
+    assert_eq!(&data[18..26], &[0xff; 8]);
+
+    // Also round-trip the metadata of real files.
+    for fname in &["testsamples/pop.flac", "testsamples/wasted_bits.flac"] {
+        let mut data = Vec::new();
+        fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+        let blocks = read_all_metadata_blocks(&data[4..]);
+        let bytes = write_all_metadata_blocks(&blocks);
+        assert_eq!(read_all_metadata_blocks(&bytes), blocks);
+    }
+
+    assert!(write_metadata_block(&mut Vec::new(), true, &MetadataBlock::Reserved).is_err());
+}
+
+#[test]
+#[cfg(feature = "full-metadata")]
+fn verify_canonicalize() {
+    let streaminfo = StreamInfo {
+        min_block_size: 4096,
+        max_block_size: 4096,
+        min_frame_size: None,
+        max_frame_size: None,
+        sample_rate: 44100,
+        channels: 2,
+        bits_per_sample: 16,
+        samples: None,
+        md5sum: [0; 16],
+    };
+    let vorbis_comment = |vendor: &str, name: &str, value: &str| {
+        let mut vc = VorbisComment::new(vendor.to_string());
+        vc.push(name, value);
+        MetadataBlock::VorbisComment(vc)
+    };
+    let application = MetadataBlock::Application { id: 1, data: vec![7] };
+
+    let blocks = vec![
+        MetadataBlock::Padding { length: 10 },
+        vorbis_comment("first", "ARTIST", "Queen"),
+        MetadataBlock::StreamInfo(streaminfo),
+        MetadataBlock::Padding { length: 20 },
+        application.clone(),
+        vorbis_comment("second", "TITLE", "Bicycle Race"),
+    ];
+    let canonical = canonicalize(blocks).unwrap();
+
+    let mut merged = VorbisComment::new("first".to_string());
+    merged.push("ARTIST", "Queen");
+    merged.push("TITLE", "Bicycle Race");
+    assert_eq!(canonical, vec![
+        MetadataBlock::StreamInfo(streaminfo),
+        MetadataBlock::VorbisComment(merged),
+        application,
+        MetadataBlock::Padding { length: 34 },
+    ]);
+
+    // The canonical form is a fixed point, also after writing and reading.
+    assert_eq!(canonicalize(canonical.clone()).unwrap(), canonical);
+    let bytes = write_all_metadata_blocks(&canonical);
+    assert_eq!(canonicalize(read_all_metadata_blocks(&bytes)).unwrap(), canonical);
+
+    assert!(canonicalize(vec![MetadataBlock::Padding { length: 1 }]).is_err());
+    let two = vec![MetadataBlock::StreamInfo(streaminfo), MetadataBlock::StreamInfo(streaminfo)];
+    assert!(canonicalize(two).is_err());
+}
+
+/// How to lay out metadata blocks and padding, see `plan_layout()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetadataLayout {
+    /// The blocks fit in the space that the metadata currently occupies.
+    ///
+    /// The remaining space must be filled with `padding` bytes of padding,
+    /// including the headers of the padding blocks.
+    InPlace {
+        /// The number of bytes of padding, including block headers.
+        padding: u64,
+    },
+
+    /// The blocks do not fit, the audio data must be moved.
+    ///
+    /// The blocks should be followed by `padding` bytes of padding, including
+    /// block headers, to leave room for future edits. The metadata then
+    /// occupies `total` bytes, including all block headers.
+    Rewrite {
+        /// The number of bytes of padding, including block headers.
+        padding: u64,
+        /// The number of bytes of metadata, including padding and headers.
+        total: u64,
+    },
+}
+
+/// Plans where padding goes when metadata is written.
+///
+/// `block_lengths` are the lengths of the bodies of the metadata blocks to
+/// write, excluding padding. `available_len` is the number of bytes that the
+/// metadata blocks occupy in the file currently, including block headers,
+/// and excluding the 4-byte stream header. If the blocks fit in that space,
+/// the remainder becomes padding, and the audio data does not need to be
+/// moved. Otherwise, like libFLAC does, the metadata is laid out with
+/// `target_padding` bytes of padding after it, so later edits can be done in
+/// place.
+pub fn plan_layout(block_lengths: &[u32],
+                   available_len: u64,
+                   target_padding: u32)
+                   -> MetadataLayout {
+    let needed_len = block_lengths.iter().map(|&len| 4 + len as u64).sum::<u64>();
+
+    // A padding block needs at least 4 bytes for its header, so if there are
+    // 1 to 3 bytes left, we cannot fill them.
+    if needed_len <= available_len {
+        let padding = available_len - needed_len;
+        if padding == 0 || padding >= 4 {
+            return MetadataLayout::InPlace { padding: padding }
+        }
+    }
+
+    let padding = 4 + target_padding as u64;
+    MetadataLayout::Rewrite {
+        padding: padding,
+        total: needed_len + padding,
+    }
+}
+
+#[test]
+fn verify_plan_layout() {
+    assert_eq!(plan_layout(&[34, 40], 120, 8192), MetadataLayout::InPlace { padding: 38 });
+    assert_eq!(plan_layout(&[34, 40], 82, 8192), MetadataLayout::InPlace { padding: 0 });
+    assert_eq!(plan_layout(&[34, 40], 84, 8192),
+               MetadataLayout::Rewrite { padding: 8196, total: 8278 });
+    assert_eq!(plan_layout(&[34, 40], 50, 0), MetadataLayout::Rewrite { padding: 4, total: 86 });
+}
+
+/// Where the body of a metadata block comes from, used when rewriting metadata.
+enum BlockBody {
+    /// The block is preserved, its body is at the given offset in the file.
+    InFile(u64),
+    /// The block is new, with the given body.
+    New(Vec<u8>),
+}
+
+/// A metadata block to write when rewriting metadata.
+struct PlannedBlock {
+    block_type: u8,
+    length: u32,
+    body: BlockBody,
+}
+
+impl PlannedBlock {
+    fn new(block_type: u8, data: Vec<u8>) -> PlannedBlock {
+        PlannedBlock {
+            block_type: block_type,
+            length: data.len() as u32,
+            body: BlockBody::New(data),
+        }
+    }
+}
+
+/// Moves `len` bytes in the file from offset `src` to offset `dst`.
+///
+/// The ranges may overlap. Bytes are copied through `buffer`, so the data is
+/// never held in memory in full.
+fn move_bytes<F>(file: &mut F, src: u64, dst: u64, len: u64, buffer: &mut [u8]) -> io::Result<()>
+where F: io::Read + io::Write + io::Seek {
+    // When moving towards the start, copy front to back, otherwise copy back
+    // to front, so a chunk never overwrites bytes that still need to be read.
+    let mut done = 0;
+    while done < len {
+        let n = cmp::min(buffer.len() as u64, len - done);
+        let offset = if dst < src { done } else { len - done - n };
+        try!(file.seek(io::SeekFrom::Start(src + offset)));
+        try!(file.read_exact(&mut buffer[..n as usize]));
+        try!(file.seek(io::SeekFrom::Start(dst + offset)));
+        try!(file.write_all(&buffer[..n as usize]));
+        done += n;
+    }
+    Ok(())
+}
+
+/// Replaces the Vorbis comment block of a FLAC file, without moving the audio data.
+///
+/// This is `write_metadata_in_place()` that preserves the pictures of the
+/// file.
+pub fn write_vorbis_comment_in_place<F>(file: &mut F,
+                                        vorbis_comment: &VorbisComment)
+                                        -> Result<bool>
+where F: io::Read + io::Write + io::Seek {
+    write_metadata_in_place(file, vorbis_comment, None)
+}
+
+/// Replaces the Vorbis comment and picture blocks of a FLAC file, without moving the audio data.
+///
+/// The metadata blocks of the file are rewritten in the space that they
+/// already occupy. Padding blocks are merged into a single padding block after
+/// the last metadata block, which absorbs any change in size of the Vorbis
+/// comment and picture blocks. If the file did not contain a Vorbis comment
+/// block yet, the new block is placed directly after the streaminfo block.
+/// Other blocks are preserved verbatim, in their original order.
+///
+/// If `pictures` is `None`, the picture blocks of the file are preserved.
+/// Otherwise they are replaced by `pictures`, which take the place of the first
+/// existing picture block, or go after the other blocks if there was none.
+///
+/// Returns `Ok(true)` when the file was updated. When the new metadata does not
+/// fit in the space occupied by the existing metadata blocks, the file is not
+/// modified, and `Ok(false)` is returned. The caller then has to rewrite the
+/// entire file.
+///
+/// The input must be positioned such that the stream header starts at offset
+/// 0. Preserved blocks, such as embedded pictures, are never read into memory:
+/// a block that stays at the same offset is not touched, and a block that has
+/// to move is copied within the file in small chunks.
+pub fn write_metadata_in_place<F>(file: &mut F,
+                                  vorbis_comment: &VorbisComment,
+                                  pictures: Option<&[Picture]>)
+                                  -> Result<bool>
+where F: io::Read + io::Write + io::Seek {
+
+    // A FLAC stream starts with a 32-bit header 'fLaC'.
+    let mut stream_header = [0u8; 4];
+    try!(file.seek(io::SeekFrom::Start(0)));
+    try!(file.read_exact(&mut stream_header));
+    if &stream_header != b"fLaC" {
+        return fmt_err("invalid stream header")
+    }
+
+    // Record where the blocks that we must preserve are, and count how many
+    // bytes the metadata occupies in total, including block headers.
+    let mut blocks = Vec::new();
+    let mut vorbis_comment_index = None;
+    let mut picture_index = None;
+    let mut available_len = 0u64;
+    loop {
+        let mut header_bytes = [0u8; 4];
+        try!(file.read_exact(&mut header_bytes));
+        let header = try!(read_metadata_block_header(&mut io::Cursor::new(&header_bytes[..])));
+        let body_offset = 4 + available_len + 4;
+        available_len += 4 + header.length as u64;
+
+        match header.block_type {
+            // Padding is not preserved, we lay it out again afterwards.
+            1 => {}
+            4 => {
+                if vorbis_comment_index.is_some() {
+                    return fmt_err("encountered second Vorbis comment block")
+                }
+                vorbis_comment_index = Some(blocks.len());
+            }
+            6 if pictures.is_some() => {
+                if picture_index.is_none() {
+                    picture_index = Some(blocks.len());
+                }
+            }
+            127 => return fmt_err("invalid metadata block type"),
+            block_type => {
+                blocks.push(PlannedBlock {
+                    block_type: block_type,
+                    length: header.length,
+                    body: BlockBody::InFile(body_offset),
+                });
+            }
+        }
+        try!(file.seek(io::SeekFrom::Current(header.length as i64)));
+
+        if header.is_last {
+            break
+        }
+    }
+
+    match blocks.first() {
+        Some(block) if block.block_type == 0 => {}
+        _ => return fmt_err("streaminfo block missing"),
+    }
+
+    // Insert the new blocks, the one at the later index first, so the other
+    // index remains valid. If both go at the same index, the Vorbis comment
+    // goes first.
+    let vorbis_comment_data = try!(serialize_vorbis_comment(vorbis_comment));
+    let vorbis_comment_block = PlannedBlock::new(4, vorbis_comment_data);
+    let vorbis_comment_index = vorbis_comment_index.unwrap_or(1);
+    let picture_index = picture_index.unwrap_or(blocks.len());
+    let mut picture_blocks = Vec::new();
+    for picture in pictures.unwrap_or(&[]) {
+        picture_blocks.push(PlannedBlock::new(6, try!(serialize_picture(picture))));
+    }
+    fn insert_all(blocks: &mut Vec<PlannedBlock>, index: usize, new_blocks: Vec<PlannedBlock>) {
+        for (i, block) in new_blocks.into_iter().enumerate() {
+            blocks.insert(index + i, block);
+        }
+    }
+    if picture_index >= vorbis_comment_index {
+        insert_all(&mut blocks, picture_index, picture_blocks);
+        blocks.insert(vorbis_comment_index, vorbis_comment_block);
+    } else {
+        blocks.insert(vorbis_comment_index, vorbis_comment_block);
+        insert_all(&mut blocks, picture_index, picture_blocks);
+    }
+
+    // The space that is left must be filled with padding.
+    let block_lengths: Vec<u32> = blocks.iter().map(|b| b.length).collect();
+    let padding_len = match plan_layout(&block_lengths, available_len, 0) {
+        MetadataLayout::InPlace { padding } => padding,
+        MetadataLayout::Rewrite { .. } => return Ok(false),
+    };
+
+    // Everything fits. Compute the new offset of every block body, right after
+    // the stream header.
+    let mut offsets = Vec::with_capacity(blocks.len());
+    let mut offset = 4;
+    for block in &blocks {
+        offsets.push(offset + 4);
+        offset += 4 + block.length as u64;
+    }
+
+    // Blocks retain their relative order, so the preserved blocks that move
+    // towards the start can be moved front to back, and the ones that move
+    // towards the end back to front, without overwriting data that is yet to
+    // be moved. Headers and new blocks are written afterwards, they only
+    // overwrite space that is not occupied by preserved blocks any more.
+    let mut buffer = vec![0u8; 64 * 1024];
+    for (block, &dst) in blocks.iter().zip(offsets.iter()) {
+        if let BlockBody::InFile(src) = block.body {
+            if dst < src {
+                try!(move_bytes(file, src, dst, block.length as u64, &mut buffer));
+            }
+        }
+    }
+    for (block, &dst) in blocks.iter().zip(offsets.iter()).rev() {
+        if let BlockBody::InFile(src) = block.body {
+            if dst > src {
+                try!(move_bytes(file, src, dst, block.length as u64, &mut buffer));
+            }
+        }
+    }
+
+    let n_blocks = blocks.len();
+    for (i, (block, &dst)) in blocks.iter().zip(offsets.iter()).enumerate() {
+        let is_last = i + 1 == n_blocks && padding_len == 0;
+        try!(file.seek(io::SeekFrom::Start(dst - 4)));
+        try!(write_metadata_block_header(file, is_last, block.block_type, block.length));
+        if let BlockBody::New(ref data) = block.body {
+            try!(file.write_all(data));
+        }
+    }
+
+    try!(file.seek(io::SeekFrom::Start(offset)));
+    try!(write_padding(file, true, padding_len));
+
+    try!(file.flush());
+    Ok(true)
+}
+
+#[test]
+#[cfg(feature = "full-metadata")]
+fn verify_write_vorbis_comment_in_place() {
+    use std::fs;
+    use std::io::Read;
+
+    fn vorbis_comment(vendor: &str, comments: &[(&str, &str)]) -> VorbisComment {
+        let mut vorbis_comment = VorbisComment::new(vendor.to_string());
+        for &(name, value) in comments {
+            vorbis_comment.push(name, value);
+        }
+        vorbis_comment
+    }
+
+    fn read_vorbis_comment(bytes: &[u8]) -> (String, Vec<(String, String)>) {
+        let mut cursor = io::Cursor::new(bytes);
+        cursor.skip(4).unwrap();
+        for block in MetadataBlockReader::new(&mut cursor) {
+            if let MetadataBlock::VorbisComment(vc) = block.unwrap() {
+                let tags = vc.tags().map(|(n, v)| (n.to_string(), v.to_string())).collect();
+                return (vc.vendor, tags)
+            }
+        }
+        panic!("no Vorbis comment block found");
+    }
+
+    // This file contains a streaminfo block, a seek table, and a Vorbis comment
+    // block of 40 bytes, without padding.
+    let mut original = Vec::new();
+    fs::File::open("testsamples/short.flac").unwrap().read_to_end(&mut original).unwrap();
+    let audio = original[108..].to_vec();
+
+    // A smaller Vorbis comment block fits, the remainder becomes padding.
+    let mut file = io::Cursor::new(original.clone());
+    let small = vorbis_comment("claxon", &[("A", "b")]);
+    assert_eq!(write_vorbis_comment_in_place(&mut file, &small).ok(), Some(true));
+    let bytes = file.into_inner();
+    assert_eq!(bytes.len(), original.len());
+    assert_eq!(&bytes[..4 + 4 + 34 + 4 + 18], &original[..4 + 4 + 34 + 4 + 18]);
+    assert_eq!(&bytes[108..], &audio[..]);
+    assert_eq!(read_vorbis_comment(&bytes),
+               ("claxon".to_string(), vec![("A".to_string(), "b".to_string())]));
+
+    // A block that is larger than the original does not fit, and then the
+    // file must be left untouched.
+    let mut file = io::Cursor::new(bytes.clone());
+    let large = vorbis_comment("claxon", &[("TITLE", "this comment is far too long to fit")]);
+    assert_eq!(write_vorbis_comment_in_place(&mut file, &large).ok(), Some(false));
+    assert_eq!(file.into_inner(), bytes);
+
+    // The padding is reclaimed when the block grows again. If it fills all of
+    // the space, then no padding block should remain.
+    let mut file = io::Cursor::new(bytes);
+    let exact = vorbis_comment("claxon", &[("TITLE", "fills all space!")]);
+    assert_eq!(write_vorbis_comment_in_place(&mut file, &exact).ok(), Some(true));
+    let bytes = file.into_inner();
+    assert_eq!(&bytes[108..], &audio[..]);
+    assert_eq!(bytes[4 + 4 + 34 + 4 + 18] & 0b1000_0000, 0b1000_0000);
+    assert_eq!(read_vorbis_comment(&bytes).1,
+               vec![("TITLE".to_string(), "fills all space!".to_string())]);
+}
+
+#[test]
+#[cfg(feature = "full-metadata")]
+fn verify_write_metadata_in_place_with_pictures() {
+    use std::fs;
+    use std::io::Read;
+
+    fn picture(description: &str, len: usize) -> Picture {
+        Picture {
+            picture_type: 3,
+            mime_type: "image/png".to_string(),
+            description: description.to_string(),
+            width: 1,
+            height: 1,
+            depth: 24,
+            colors: 0,
+            data: (0..len).map(|i| i as u8).collect(),
+        }
+    }
+
+    fn read_pictures(bytes: &[u8]) -> (String, Vec<Picture>) {
+        let mut cursor = io::Cursor::new(bytes);
+        cursor.skip(4).unwrap();
+        let mut vendor = String::new();
+        let mut pictures = Vec::new();
+        for block in MetadataBlockReader::new(&mut cursor) {
+            match block.unwrap() {
+                MetadataBlock::VorbisComment(vc) => vendor = vc.vendor,
+                MetadataBlock::Picture(picture) => pictures.push(picture),
+                _ => {}
+            }
+        }
+        (vendor, pictures)
+    }
+
+    // Take the streaminfo block, seek table, and Vorbis comment block of 40
+    // bytes from this file, and append a picture block that is larger than the
+    // buffer used to move blocks.
+    let mut short = Vec::new();
+    fs::File::open("testsamples/short.flac").unwrap().read_to_end(&mut short).unwrap();
+    let audio = short[108..].to_vec();
+    let cover = picture("cover", 100_000);
+    let mut original = short[..108].to_vec();
+    original[64] &= 0b0111_1111;
+    write_metadata_block(&mut original, true, &MetadataBlock::Picture(cover.clone())).unwrap();
+    original.extend_from_slice(&audio);
+
+    // When the Vorbis comment shrinks, the picture moves towards the start.
+    let mut file = io::Cursor::new(original.clone());
+    let small = VorbisComment::new("small".to_string());
+    assert_eq!(write_metadata_in_place(&mut file, &small, None).ok(), Some(true));
+    let bytes = file.into_inner();
+    assert_eq!(bytes.len(), original.len());
+    assert_eq!(&bytes[bytes.len() - audio.len()..], &audio[..]);
+    assert_eq!(read_pictures(&bytes), ("small".to_string(), vec![cover.clone()]));
+
+    // When it grows again, the picture moves back towards the end.
+    let mut file = io::Cursor::new(bytes);
+    let large = VorbisComment::new("a larger vendor string".to_string());
+    assert_eq!(write_vorbis_comment_in_place(&mut file, &large).ok(), Some(true));
+    let bytes = file.into_inner();
+    assert_eq!(&bytes[bytes.len() - audio.len()..], &audio[..]);
+    let large_vendor = "a larger vendor string".to_string();
+    assert_eq!(read_pictures(&bytes), (large_vendor, vec![cover.clone()]));
+
+    // Pictures can be replaced, if they fit.
+    let mut file = io::Cursor::new(bytes.clone());
+    let replacements = [picture("back", 10), picture("front", 20)];
+    assert_eq!(write_metadata_in_place(&mut file, &small, Some(&replacements)).ok(), Some(true));
+    let bytes = file.into_inner();
+    assert_eq!(&bytes[bytes.len() - audio.len()..], &audio[..]);
+    assert_eq!(read_pictures(&bytes), ("small".to_string(), replacements.to_vec()));
+
+    let mut file = io::Cursor::new(bytes.clone());
+    let too_large = [picture("cover", 200_000)];
+    assert_eq!(write_metadata_in_place(&mut file, &small, Some(&too_large)).ok(), Some(false));
+    assert_eq!(file.into_inner(), bytes);
+
+    // And removed, then the space becomes padding.
+    let mut file = io::Cursor::new(bytes);
+    assert_eq!(write_metadata_in_place(&mut file, &small, Some(&[])).ok(), Some(true));
+    let bytes = file.into_inner();
+    assert_eq!(bytes.len(), original.len());
+    assert_eq!(read_pictures(&bytes), ("small".to_string(), vec![]));
+}
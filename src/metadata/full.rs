@@ -0,0 +1,688 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Parsing of the metadata blocks that carry tags, cue sheets, and pictures.
+//!
+//! This module is compiled only with the `full-metadata` feature. Without it,
+//! Vorbis comment, cue sheet, and picture blocks are skipped like padding, and
+//! only the streaminfo and seek table are parsed, which keeps minimal decoders
+//! small.
+
+use std::cmp;
+use std::io;
+use std::str;
+
+use error::{Error, Result, UnsupportedFeature, fmt_err};
+use input::ReadBytes;
+use super::{CueSheet, CueSheetIndex, CueSheetTrack, MetadataBlockReader, Picture, VorbisComment};
+use super::{new_read_buffer, read_metadata_block_header};
+
+pub fn read_vorbis_comment_block<R: ReadBytes>(input: &mut R,
+                                               length: u32,
+                                               lenient: bool)
+                                               -> Result<VorbisComment> {
+    if length < 8 {
+        // We expect at a minimum a 32-bit vendor string length, and a 32-bit
+        // comment count.
+        return fmt_err("Vorbis comment block is too short")
+    }
+
+    // Fail if the length of the Vorbis comment block is larger than 1 MiB. This
+    // block is full of length-prefixed strings for which we allocate memory up
+    // front. If there were no limit on these, a maliciously crafted file could
+    // cause OOM by claiming to contain large strings. But at least the strings
+    // cannot be longer than the size of the Vorbis comment block, and by
+    // limiting the size of that block, we can mitigate such DoS attacks.
+    //
+    // The typical size of a the Vorbis comment block is 1 KiB; on a corpus of
+    // real-world flac files, the 0.05 and 0.95 quantiles were 792 and 1257
+    // bytes respectively, with even the 0.99 quantile below 2 KiB. The only
+    // reason for having a large Vorbis comment block is when cover art is
+    // incorrectly embedded there, but the Vorbis comment block is not the right
+    // place for that anyway.
+    if length > 10 * 1024 * 1024 {
+        return Err(Error::Unsupported(UnsupportedFeature::TooLargeMetadata))
+    }
+
+    // The Vorbis comment block starts with a length-prefixed "vendor string".
+    // It cannot be larger than the block length - 8, because there are the
+    // 32-bit vendor string length, and comment count.
+    let vendor_len = try!(input.read_le_u32());
+    if vendor_len > length - 8 { return fmt_err("vendor string too long") }
+    let mut vendor_bytes = new_read_buffer(vendor_len as usize);
+    try!(input.read_into(&mut vendor_bytes));
+    let vendor = match String::from_utf8(vendor_bytes) {
+        Ok(vendor) => vendor,
+        Err(err) => {
+            if !lenient {
+                return Err(Error::from(err))
+            }
+            warn!("replacing invalid UTF-8 in Vorbis comment vendor string");
+            String::from_utf8_lossy(&err.into_bytes()).into_owned()
+        }
+    };
+
+    // Next up is the number of comments. Because every comment is at least 4
+    // bytes to indicate its length, there cannot be more comments than the
+    // length of the block divided by 4. This is only an upper bound to ensure
+    // that we don't allocate a big vector, to protect against DoS attacks.
+    let mut comments_len = try!(input.read_le_u32());
+    if comments_len >= length / 4 {
+        return fmt_err("too many entries for Vorbis comment block")
+    }
+    let mut entries = Vec::with_capacity(comments_len as usize);
+
+    // Read all comments into a single buffer at once. The buffer includes the
+    // 32-bit length prefixes. We copy every comment to the front, over the
+    // length prefixes, so the comments end up consecutively at the start of
+    // the buffer, and it can be turned into a string without a new allocation.
+    let bytes_len = (length - 8 - vendor_len) as usize;
+    let mut bytes = new_read_buffer(bytes_len);
+    try!(input.read_into(&mut bytes));
+
+    // The index of the next length prefix to read, and the end of the
+    // comments that have been moved to the front.
+    let mut read_pos = 0;
+    let mut write_pos = 0;
+
+    // Whether a comment is not valid UTF-8, which lenient mode repairs after
+    // all comments have been moved to the front.
+    let mut has_invalid_utf8 = false;
+
+    // For every comment, there is a length-prefixed string of the form
+    // "NAME=value".
+    while bytes_len - read_pos >= 4 && entries.len() < comments_len as usize {
+        let comment_len = (bytes[read_pos] as u32) |
+                          (bytes[read_pos + 1] as u32) << 8 |
+                          (bytes[read_pos + 2] as u32) << 16 |
+                          (bytes[read_pos + 3] as u32) << 24;
+        read_pos += 4;
+
+        if comment_len as usize > bytes_len - read_pos {
+            return fmt_err("Vorbis comment too long for Vorbis comment block")
+        }
+        let comment_len = comment_len as usize;
+
+        // Some older versions of libflac allowed writing zero-length Vorbis
+        // comments. ALthough such files are invalid, they do occur in the wild,
+        // so we skip over the empty comment.
+        if comment_len == 0 {
+            // Does not overflow because `comments_len > entries.len() >= 0`.
+            comments_len -= 1;
+            continue;
+        }
+
+        let comment_start = write_pos;
+        for k in 0..comment_len {
+            bytes[write_pos + k] = bytes[read_pos + k];
+        }
+        read_pos += comment_len;
+        write_pos += comment_len;
+
+        let sep_index = bytes[comment_start..write_pos].iter().position(|&x| x == b'=');
+        if let Some(sep_index) = sep_index {
+            {
+                let name_bytes = &mut bytes[comment_start..comment_start + sep_index];
+
+                // According to the Vorbis spec, the field name may consist of ascii
+                // bytes 0x20 through 0x7d, 0x3d (`=`) excluded. Verifying this has
+                // the advantage that if the check passes, the result is valid
+                // UTF-8, so the conversion to string will not fail. In lenient
+                // mode, invalid bytes are replaced, which preserves that.
+                if name_bytes.iter().any(|&x| x < 0x20 || x > 0x7d) {
+                    if !lenient {
+                        return fmt_err("Vorbis comment field name contains invalid byte")
+                    }
+                    warn!("replacing invalid bytes in Vorbis comment field name");
+                    for x in name_bytes.iter_mut().filter(|x| **x < 0x20 || **x > 0x7d) {
+                        *x = b'_';
+                    }
+                }
+            }
+
+            // Every comment must be valid UTF-8 by itself, it is not enough
+            // for the concatenation to be valid.
+            if str::from_utf8(&bytes[comment_start..write_pos]).is_err() {
+                if !lenient {
+                    return fmt_err("Vorbis comment or vendor string is not valid UTF-8")
+                }
+                warn!("replacing invalid UTF-8 in Vorbis comment value");
+                has_invalid_utf8 = true;
+            }
+
+            entries.push((comment_start + sep_index, write_pos));
+        } else if lenient {
+            // Drop the comment, as if it was never there.
+            warn!("skipping Vorbis comment that does not contain '='");
+            write_pos = comment_start;
+            comments_len -= 1;
+        } else {
+            return fmt_err("Vorbis comment does not contain '='")
+        }
+    }
+
+    if read_pos != bytes_len {
+        return fmt_err("Vorbis comment block has excess data")
+    }
+
+    if entries.len() != comments_len as usize {
+        return fmt_err("Vorbis comment block contains wrong number of entries")
+    }
+
+    bytes.truncate(write_pos);
+    let comments = if has_invalid_utf8 {
+        replace_invalid_utf8(&bytes, &mut entries)
+    } else {
+        try!(String::from_utf8(bytes))
+    };
+    let vorbis_comment = VorbisComment {
+        vendor: vendor,
+        comments: comments,
+        entries: entries,
+    };
+
+    Ok(vorbis_comment)
+}
+
+/// Concatenates the comments, replacing invalid UTF-8 in the values, and updates the entries.
+///
+/// The comments in `bytes` are consecutive, and `entries` holds the position
+/// of the `=` and the end of every comment. A replacement character can be
+/// longer than the bytes it replaces, so the entries move.
+fn replace_invalid_utf8(bytes: &[u8], entries: &mut [(usize, usize)]) -> String {
+    let mut comments = String::with_capacity(bytes.len());
+    let mut start = 0;
+    for entry in entries.iter_mut() {
+        let (sep_index, end) = *entry;
+        let new_sep_index = comments.len() + sep_index - start;
+        // The field name is ASCII, only the value can be invalid.
+        comments.push_str(&String::from_utf8_lossy(&bytes[start..sep_index]));
+        comments.push_str(&String::from_utf8_lossy(&bytes[sep_index..end]));
+        *entry = (new_sep_index, comments.len());
+        start = end;
+    }
+    comments
+}
+
+#[test]
+fn verify_read_vorbis_comment_block() {
+    use metadata::{GetTag, serialize_vorbis_comment};
+
+    let mut vorbis_comment = VorbisComment::new("claxon".to_string());
+    vorbis_comment.push("TITLE", "Ťitle");
+    vorbis_comment.push("ARTIST", "a=b");
+    let mut data = serialize_vorbis_comment(&vorbis_comment).unwrap();
+
+    // Insert an empty comment before the last one, which should be skipped.
+    data[10] += 1;
+    let tail = data.split_off(30);
+    data.extend_from_slice(&[0, 0, 0, 0]);
+    data.extend_from_slice(&tail);
+
+    let length = data.len() as u32;
+    let result = read_vorbis_comment_block(&mut io::Cursor::new(data), length, false).unwrap();
+    assert_eq!(result.vendor, "claxon");
+    assert_eq!(result.len(), 2);
+    let tags: Vec<(&str, &str)> = result.tags().collect();
+    assert_eq!(tags, vec![("TITLE", "Ťitle"), ("ARTIST", "a=b")]);
+    assert_eq!(GetTag::new(Some(&result), "artist").collect::<Vec<_>>(), vec!["a=b"]);
+
+    // Skipping the empty comment yields the same representation as pushing.
+    assert_eq!(result, vorbis_comment);
+}
+
+#[test]
+fn verify_read_vorbis_comment_block_lenient() {
+    use metadata::serialize_vorbis_comment;
+
+    let mut vorbis_comment = VorbisComment::new("claxon".to_string());
+    vorbis_comment.push("TITLE", "Title");
+    vorbis_comment.push("ARTIST", "Artist");
+    vorbis_comment.push("ALBUM", "Album");
+    let mut data = serialize_vorbis_comment(&vorbis_comment).unwrap();
+
+    // Turn "TITLE" into "TÏLE" and "ARTIST=Artist" into "ARTIST_Artist". The
+    // former has a non-ASCII name, the latter lacks a '='. Both keep their
+    // length, so the block structure remains intact.
+    assert_eq!(&data[18..23], b"TITLE");
+    data[19] = 0xc3;
+    data[20] = 0x8f;
+    assert_eq!(&data[33..40], b"ARTIST=");
+    data[39] = b'_';
+    let length = data.len() as u32;
+
+    let strict = read_vorbis_comment_block(&mut io::Cursor::new(&data[..]), length, false);
+    assert!(strict.is_err());
+
+    let result = read_vorbis_comment_block(&mut io::Cursor::new(&data[..]), length, true).unwrap();
+    let tags: Vec<(&str, &str)> = result.tags().collect();
+    assert_eq!(tags, vec![("T__LE", "Title"), ("ALBUM", "Album")]);
+}
+
+#[test]
+fn verify_read_vorbis_comment_block_invalid_utf8() {
+    use metadata::serialize_vorbis_comment;
+
+    let mut vorbis_comment = VorbisComment::new("claxon".to_string());
+    vorbis_comment.push("TITLE", "Caf\u{e9}");
+    vorbis_comment.push("ALBUM", "Album");
+    let mut data = serialize_vorbis_comment(&vorbis_comment).unwrap();
+
+    // Turn "Café" into "Cafés" encoded as Latin-1, as a legacy tagger would
+    // write it, and replace the "x" in the vendor string with an invalid byte.
+    assert_eq!(&data[27..29], &[0xc3, 0xa9]);
+    data[27] = 0xe9;
+    data[28] = b's';
+    assert_eq!(data[7], b'x');
+    data[7] = 0xff;
+    let length = data.len() as u32;
+
+    let strict = read_vorbis_comment_block(&mut io::Cursor::new(&data[..]), length, false);
+    assert!(strict.is_err());
+
+    let result = read_vorbis_comment_block(&mut io::Cursor::new(&data[..]), length, true).unwrap();
+    assert_eq!(result.vendor, "cla\u{fffd}on");
+    let tags: Vec<(&str, &str)> = result.tags().collect();
+    assert_eq!(tags, vec![("TITLE", "Caf\u{fffd}s"), ("ALBUM", "Album")]);
+}
+
+/// Decodes a zero-padded ASCII string field of a cue sheet.
+fn cue_sheet_string(bytes: &[u8]) -> Result<String> {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let is_padded = bytes[len..].iter().all(|&b| b == 0);
+    if !is_padded || bytes[..len].iter().any(|&b| b < 0x20 || b > 0x7e) {
+        return fmt_err("cue sheet string is not printable ASCII")
+    }
+    // The check above ensures that the bytes are ASCII, and therefore UTF-8.
+    Ok(str::from_utf8(&bytes[..len]).unwrap().to_string())
+}
+
+pub fn read_cue_sheet_block<R: ReadBytes>(input: &mut R, length: u32) -> Result<CueSheet> {
+    // The media catalog number, lead-in, flags, reserved bytes, and number of
+    // tracks take 396 bytes. Then every track takes 36 bytes, plus 12 bytes
+    // for every index point.
+    if length < 396 {
+        return fmt_err("cue sheet block too short")
+    }
+
+    let mut catalog_number = [0u8; 128];
+    try!(input.read_into(&mut catalog_number));
+    let media_catalog_number = try!(cue_sheet_string(&catalog_number));
+    let lead_in = try!(input.read_be_u64());
+    let is_cd = try!(input.read_u8()) & 0x80 != 0;
+    try!(input.skip(258));
+    let n_tracks = try!(input.read_u8());
+
+    let mut remaining = length - 396;
+    let mut tracks = Vec::with_capacity(n_tracks as usize);
+    for _ in 0..n_tracks {
+        if remaining < 36 {
+            return fmt_err("cue sheet track exceeds cue sheet block length")
+        }
+        let offset = try!(input.read_be_u64());
+        let number = try!(input.read_u8());
+        let mut isrc = [0u8; 12];
+        try!(input.read_into(&mut isrc));
+        let flags = try!(input.read_u8());
+        try!(input.skip(13));
+        let n_indices = try!(input.read_u8());
+        remaining -= 36;
+
+        if remaining < n_indices as u32 * 12 {
+            return fmt_err("cue sheet index points exceed cue sheet block length")
+        }
+        remaining -= n_indices as u32 * 12;
+        let mut indices = Vec::with_capacity(n_indices as usize);
+        for _ in 0..n_indices {
+            let index_offset = try!(input.read_be_u64());
+            let index_number = try!(input.read_u8());
+            try!(input.skip(3));
+            indices.push(CueSheetIndex {
+                offset: index_offset,
+                number: index_number,
+            });
+        }
+
+        if number == 0 {
+            return fmt_err("invalid cue sheet track number 0")
+        }
+        let isrc = try!(cue_sheet_string(&isrc));
+        tracks.push(CueSheetTrack {
+            offset: offset,
+            number: number,
+            isrc: if isrc.is_empty() { None } else { Some(isrc) },
+            is_audio: flags & 0x80 == 0,
+            pre_emphasis: flags & 0x40 != 0,
+            indices: indices,
+        });
+    }
+
+    if remaining != 0 {
+        return fmt_err("cue sheet block length does not match its tracks")
+    }
+    match tracks.last() {
+        Some(track) if track.is_lead_out() && track.indices.is_empty() => {}
+        _ => return fmt_err("cue sheet does not end with a lead-out track"),
+    }
+
+    Ok(CueSheet {
+        media_catalog_number: media_catalog_number,
+        lead_in: lead_in,
+        is_cd: is_cd,
+        tracks: tracks,
+    })
+}
+
+#[test]
+fn verify_read_cue_sheet_block() {
+    use metadata::serialize_cue_sheet;
+
+    let track = |offset: u64, number: u8, indices: &[(u64, u8)]| {
+        CueSheetTrack {
+            offset: offset,
+            number: number,
+            isrc: None,
+            is_audio: true,
+            pre_emphasis: false,
+            indices: indices.iter()
+                .map(|&(offset, number)| CueSheetIndex { offset: offset, number: number })
+                .collect(),
+        }
+    };
+    let mut cue_sheet = CueSheet {
+        media_catalog_number: "1234567890123".to_string(),
+        lead_in: 88_200,
+        is_cd: true,
+        tracks: vec![track(0, 1, &[(0, 1)]),
+                     track(588 * 1000, 2, &[(0, 0), (588 * 75, 1)]),
+                     track(588 * 3000, 170, &[])],
+    };
+    cue_sheet.tracks[1].isrc = Some("NLA123456789".to_string());
+    cue_sheet.tracks[1].pre_emphasis = true;
+
+    let data = serialize_cue_sheet(&cue_sheet).unwrap();
+    assert_eq!(data.len(), 396 + 3 * 36 + 3 * 12);
+    let mut input = io::Cursor::new(&data[..]);
+    assert_eq!(read_cue_sheet_block(&mut input, data.len() as u32).unwrap(), cue_sheet);
+    assert_eq!(cue_sheet.track_len(0), Some(588 * 1000));
+    assert_eq!(cue_sheet.track_len(1), Some(588 * 2000));
+    assert_eq!(cue_sheet.track_len(2), None);
+
+    // The block length must match the tracks exactly.
+    let mut input = io::Cursor::new(&data[..]);
+    assert!(read_cue_sheet_block(&mut input, data.len() as u32 - 12).is_err());
+
+    // The last track must be the lead-out track.
+    cue_sheet.tracks.pop();
+    let data = serialize_cue_sheet(&cue_sheet).unwrap();
+    let mut input = io::Cursor::new(&data[..]);
+    assert!(read_cue_sheet_block(&mut input, data.len() as u32).is_err());
+
+    // Strings must be printable ASCII.
+    cue_sheet.tracks.push(track(588 * 3000, 170, &[]));
+    cue_sheet.media_catalog_number = "12345\n".to_string();
+    let data = serialize_cue_sheet(&cue_sheet).unwrap();
+    let mut input = io::Cursor::new(&data[..]);
+    assert!(read_cue_sheet_block(&mut input, data.len() as u32).is_err());
+}
+
+/// Reads a length-prefixed field of a picture block, of at most `remaining` bytes.
+fn read_picture_field<R: ReadBytes>(input: &mut R, remaining: &mut u32) -> Result<Vec<u8>> {
+    let field_len = try!(input.read_be_u32());
+    if field_len > *remaining {
+        return fmt_err("picture field length exceeds picture block length")
+    }
+    *remaining -= field_len;
+    let mut bytes = new_read_buffer(field_len as usize);
+    try!(input.read_into(&mut bytes));
+    Ok(bytes)
+}
+
+pub fn read_picture_block<R: ReadBytes>(input: &mut R,
+                                        length: u32,
+                                        lenient: bool)
+                                        -> Result<Picture> {
+    // The fixed-size fields are the picture type, the MIME type and
+    // description lengths, width, height, depth, number of colors, and the
+    // data length, all 32 bits. There is no need for a size limit like for
+    // the Vorbis comment block: the fields cannot be longer than the block,
+    // and the block length is a 24-bit number, so the allocations are bounded
+    // by 16 MiB, about the size of a large cover art scan.
+    if length < 32 {
+        return fmt_err("picture block too short")
+    }
+    let mut remaining = length - 32;
+
+    let picture_type = try!(input.read_be_u32());
+
+    // The MIME type must be printable ASCII. MIME types are case-insensitive,
+    // so when lenient, normalize sloppy ones such as " Image/JPEG".
+    let is_printable = |b: &u8| *b >= 0x20 && *b <= 0x7e;
+    let mut mime_bytes = try!(read_picture_field(input, &mut remaining));
+    if !mime_bytes.iter().all(&is_printable) {
+        if !lenient {
+            return fmt_err("picture MIME type is not printable ASCII")
+        }
+        warn!("removing invalid bytes from picture MIME type");
+        mime_bytes.retain(&is_printable);
+    }
+    // The check above ensures that the bytes are ASCII, and therefore UTF-8.
+    let mut mime_type = String::from_utf8(mime_bytes).unwrap();
+    if lenient {
+        mime_type = mime_type.trim().chars().flat_map(|c| c.to_lowercase()).collect();
+    }
+
+    let description_bytes = try!(read_picture_field(input, &mut remaining));
+    let description = match String::from_utf8(description_bytes) {
+        Ok(description) => description,
+        Err(err) => {
+            if !lenient {
+                return Err(Error::from(err))
+            }
+            warn!("replacing invalid UTF-8 in picture description");
+            String::from_utf8_lossy(err.as_bytes()).into_owned()
+        }
+    };
+
+    let width = try!(input.read_be_u32());
+    let height = try!(input.read_be_u32());
+    let depth = try!(input.read_be_u32());
+    let colors = try!(input.read_be_u32());
+    let data_len = try!(input.read_be_u32());
+    if data_len != remaining {
+        return fmt_err("picture data length does not match picture block length")
+    }
+    let mut data = new_read_buffer(data_len as usize);
+    try!(input.read_into(&mut data));
+
+    Ok(Picture {
+        picture_type: picture_type,
+        mime_type: mime_type,
+        description: description,
+        width: width,
+        height: height,
+        depth: depth,
+        colors: colors,
+        data: data,
+    })
+}
+
+#[test]
+fn verify_read_picture_block() {
+    use metadata::serialize_picture;
+
+    let mut picture = Picture {
+        picture_type: 3,
+        mime_type: "image/png".to_string(),
+        description: "Cöver".to_string(),
+        width: 1,
+        height: 1,
+        depth: 24,
+        colors: 0,
+        data: (0..1000).map(|i| i as u8).collect(),
+    };
+    let data = serialize_picture(&picture).unwrap();
+    assert_eq!(data.len(), 32 + 9 + 6 + 1000);
+    let mut input = io::Cursor::new(&data[..]);
+    assert_eq!(read_picture_block(&mut input, data.len() as u32, false).unwrap(), picture);
+    assert_eq!(input.position(), data.len() as u64);
+    assert!(picture.is_front_cover());
+
+    // The data length must match the block length.
+    let mut input = io::Cursor::new(&data[..]);
+    assert!(read_picture_block(&mut input, data.len() as u32 - 1, false).is_err());
+
+    // A sloppy MIME type and invalid UTF-8 are accepted only when lenient.
+    picture.mime_type = " Image/PNG\n".to_string();
+    let mut data = serialize_picture(&picture).unwrap();
+    assert_eq!(&data[24..26], &[0xc3, 0xb6]);
+    data[25] = b'o';
+    let mut input = io::Cursor::new(&data[..]);
+    assert!(read_picture_block(&mut input, data.len() as u32, false).is_err());
+    let mut input = io::Cursor::new(&data[..]);
+    let result = read_picture_block(&mut input, data.len() as u32, true).unwrap();
+    assert_eq!(result.mime_type, "image/png");
+    assert_eq!(result.description, "C\u{fffd}over");
+    assert_eq!(result.data, picture.data);
+}
+
+/// Streams the image data of a picture block to `sink`, returns its length.
+///
+/// `input` must be positioned at the start of the body of a picture metadata
+/// block (block type 6) of `length` bytes. The MIME type, description, and
+/// image properties are skipped, and the image data is copied to the sink in
+/// small chunks, so embedded cover art of many megabytes can be extracted
+/// without buffering it in memory. Afterwards, the input is positioned at the
+/// end of the block.
+///
+/// To read the image data into memory, along with the other fields, use
+/// `read_metadata_block()`, which returns a `MetadataBlock::Picture`.
+pub fn write_picture_data_to<R: ReadBytes, W: io::Write>(input: &mut R,
+                                                         length: u32,
+                                                         sink: &mut W)
+                                                         -> Result<u64> {
+    if length < 4 {
+        return fmt_err("picture block too short")
+    }
+    try!(input.skip(4));
+    write_picture_data_after_type(input, length, sink)
+}
+
+/// Implements `write_picture_data_to()`, for an input past the picture type.
+fn write_picture_data_after_type<R: ReadBytes, W: io::Write>(input: &mut R,
+                                                             length: u32,
+                                                             sink: &mut W)
+                                                             -> Result<u64> {
+    // The fixed-size fields are the picture type, the MIME type and
+    // description lengths, width, height, depth, number of colors, and the
+    // data length, all 32 bits.
+    if length < 32 {
+        return fmt_err("picture block too short")
+    }
+
+    // Skip the MIME type and description. Their lengths must fit in the
+    // variable-length part of the block, and the image data gets what
+    // remains.
+    let mut remaining = length - 32;
+    for _ in 0..2 {
+        let field_len = try!(input.read_be_u32());
+        if field_len > remaining {
+            return fmt_err("picture field length exceeds picture block length")
+        }
+        try!(input.skip(field_len));
+        remaining -= field_len;
+    }
+
+    // Skip width, height, depth, and number of colors.
+    try!(input.skip(16));
+    let data_len = try!(input.read_be_u32());
+    if data_len != remaining {
+        return fmt_err("picture data length does not match picture block length")
+    }
+
+    let mut buffer = [0u8; 4096];
+    while remaining > 0 {
+        let n = cmp::min(remaining, buffer.len() as u32);
+        let chunk = &mut buffer[..n as usize];
+        try!(input.read_into(chunk));
+        try!(sink.write_all(chunk));
+        remaining -= n;
+    }
+
+    Ok(data_len as u64)
+}
+
+impl<R: ReadBytes> MetadataBlockReader<R> {
+    /// Streams the image data of the next picture of the given type to `sink`.
+    ///
+    /// Metadata blocks are read until a picture block with the given picture
+    /// type is found, such as 3 for the front cover. Its image data is copied
+    /// to the sink in small chunks, like `write_picture_data_to()` does, and
+    /// its length is returned. Other blocks are skipped without parsing them,
+    /// so also large pictures of other types are never held in memory. If
+    /// there is no such picture, `None` is returned, and the reader is
+    /// positioned after the last metadata block. Otherwise, iteration can
+    /// continue after the picture block.
+    pub fn write_picture_to<W: io::Write>(&mut self,
+                                          picture_type: u32,
+                                          sink: &mut W)
+                                          -> Result<Option<u64>> {
+        while !self.done {
+            // After a failure, no more attempts to read will be made, like
+            // for `next()`.
+            self.done = true;
+            let header = try!(read_metadata_block_header(&mut self.input));
+            if header.block_type == 6 && header.length >= 4 {
+                let block_picture_type = try!(self.input.read_be_u32());
+                if block_picture_type == picture_type {
+                    let len = try!(write_picture_data_after_type(&mut self.input,
+                                                                 header.length,
+                                                                 sink));
+                    self.done = header.is_last;
+                    return Ok(Some(len))
+                }
+                try!(self.input.skip(header.length - 4));
+            } else {
+                try!(self.input.skip(header.length));
+            }
+            self.done = header.is_last;
+        }
+        Ok(None)
+    }
+}
+
+#[test]
+fn verify_write_picture_data_to() {
+    let mut block = Vec::new();
+    block.extend_from_slice(&[0, 0, 0, 3]); // Front cover.
+    block.extend_from_slice(&[0, 0, 0, 9]);
+    block.extend_from_slice(b"image/png");
+    block.extend_from_slice(&[0, 0, 0, 5]);
+    block.extend_from_slice(b"cover");
+    block.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 24, 0, 0, 0, 0]);
+    let image: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+    block.extend_from_slice(&[0, 0, 0x27, 0x10]);
+    block.extend_from_slice(&image);
+
+    let mut sink = Vec::new();
+    let mut input = io::Cursor::new(&block[..]);
+    let len = write_picture_data_to(&mut input, block.len() as u32, &mut sink).unwrap();
+    assert_eq!(len, 10_000);
+    assert_eq!(sink, image);
+    assert_eq!(input.position(), block.len() as u64);
+
+    // A data length that disagrees with the block length is an error.
+    let mut input = io::Cursor::new(&block[..]);
+    let result = write_picture_data_to(&mut input, block.len() as u32 - 1, &mut Vec::new());
+    assert!(result.is_err());
+
+    // So is a description that does not fit in the block.
+    block[17] = 0xff;
+    let mut input = io::Cursor::new(&block[..]);
+    let result = write_picture_data_to(&mut input, block.len() as u32, &mut Vec::new());
+    assert!(result.is_err());
+}
@@ -0,0 +1,219 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `meter` module measures the peak and RMS level of decoded audio.
+//!
+//! Level meters and silence detection need the level of the audio over short
+//! windows of time, not the samples themselves. The `Levels` iterator computes
+//! these directly from the planar channels of decoded blocks, so no
+//! interleaved copy of the samples is made.
+
+use std::cmp;
+use std::mem;
+
+use error::{Result, fmt_err};
+use frame::{Block, FrameReader};
+use input::ReadBytes;
+
+/// The peak and RMS level of every channel over a window of samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowLevels {
+    /// The inter-channel sample number of the first sample in the window.
+    pub time: u64,
+
+    /// The number of inter-channel samples in the window.
+    ///
+    /// This is the configured window length, except for the final window,
+    /// which may be shorter.
+    pub duration: u32,
+
+    /// The largest absolute sample value of every channel.
+    pub peak: Vec<u32>,
+
+    /// The root mean square of the samples of every channel.
+    ///
+    /// Like `peak`, this is in units of the samples. To get a level relative to
+    /// full scale, divide by 2<sup>bits per sample - 1</sup>.
+    pub rms: Vec<f64>,
+}
+
+/// An iterator that yields the levels of consecutive windows of a stream.
+///
+/// Windows are independent of block boundaries. Iteration ends after the
+/// final window, or after an error.
+pub struct Levels<R: ReadBytes> {
+    frame_reader: FrameReader<R>,
+
+    /// The block being measured, empty before the first block.
+    block: Block,
+
+    /// The index in the block of the next sample to measure.
+    pos: u32,
+
+    /// The number of inter-channel samples per window.
+    window_len: u32,
+
+    /// The sample number at which the current window starts.
+    window_time: u64,
+
+    /// The number of samples measured in the current window.
+    count: u32,
+
+    /// The largest absolute value of every channel in the current window.
+    peak: Vec<u32>,
+
+    /// The sum of squares of every channel in the current window.
+    sum_squares: Vec<f64>,
+
+    /// Set at the end of the stream, or when decoding failed.
+    done: bool,
+}
+
+impl<R: ReadBytes> Levels<R> {
+    /// Creates an iterator over windows of `window_len` inter-channel samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window_len` is 0.
+    pub fn new(frame_reader: FrameReader<R>, window_len: u32, channels: u32) -> Levels<R> {
+        assert!(window_len > 0, "windows must contain at least one sample");
+        Levels {
+            frame_reader: frame_reader,
+            block: Block::empty(),
+            pos: 0,
+            window_len: window_len,
+            window_time: 0,
+            count: 0,
+            peak: vec![0; channels as usize],
+            sum_squares: vec![0.0; channels as usize],
+            done: false,
+        }
+    }
+
+    /// Adds the next samples of the block to the current window.
+    fn measure(&mut self) {
+        let n = cmp::min(self.window_len - self.count, self.block.duration() - self.pos);
+        let (from, to) = (self.pos as usize, (self.pos + n) as usize);
+        let channels = self.peak.iter_mut().zip(self.sum_squares.iter_mut());
+        for (ch, (peak, sum_squares)) in channels.enumerate() {
+            for &sample in &self.block.channel(ch as u32)[from..to] {
+                // The absolute value of `i32::MIN` does not fit in an `i32`.
+                let abs = (sample as i64).abs() as u32;
+                if abs > *peak {
+                    *peak = abs;
+                }
+                let s = sample as f64;
+                *sum_squares += s * s;
+            }
+        }
+        self.pos += n;
+        self.count += n;
+    }
+
+    /// Returns the levels of the current window, and starts a new one.
+    fn take_window(&mut self) -> WindowLevels {
+        let n = self.count as f64;
+        let levels = WindowLevels {
+            time: self.window_time,
+            duration: self.count,
+            peak: mem::replace(&mut self.peak, vec![0; self.sum_squares.len()]),
+            rms: self.sum_squares.iter().map(|&s| (s / n).sqrt()).collect(),
+        };
+        for s in self.sum_squares.iter_mut() {
+            *s = 0.0;
+        }
+        self.window_time += self.count as u64;
+        self.count = 0;
+        levels
+    }
+
+    /// Decodes the next block, returns false at the end of the stream.
+    fn read_block(&mut self) -> Result<bool> {
+        let buffer = mem::replace(&mut self.block, Block::empty()).into_buffer();
+        match try!(self.frame_reader.read_next_or_eof(buffer)) {
+            Some(block) => {
+                if block.channels() as usize != self.peak.len() {
+                    return fmt_err("block has a different number of channels than the stream");
+                }
+                if self.count == 0 {
+                    self.window_time = block.time();
+                }
+                self.block = block;
+                self.pos = 0;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl<R: ReadBytes> Iterator for Levels<R> {
+    type Item = Result<WindowLevels>;
+
+    fn next(&mut self) -> Option<Result<WindowLevels>> {
+        while !self.done {
+            if self.pos == self.block.duration() {
+                match self.read_block() {
+                    Ok(true) => {}
+                    Ok(false) => self.done = true,
+                    Err(err) => {
+                        self.done = true;
+                        return Some(Err(err));
+                    }
+                }
+                continue
+            }
+
+            self.measure();
+            if self.count == self.window_len {
+                return Some(Ok(self.take_window()));
+            }
+        }
+
+        // The final window may be partial.
+        if self.count > 0 {
+            Some(Ok(self.take_window()))
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn verify_levels() {
+    use std::io;
+    use frame::{FrameSpec, write_frame};
+
+    // Two frames of 7 and 5 samples, measured in windows of 5 samples.
+    let mut data = Vec::new();
+    for &(start, len) in &[(0, 7), (7, 5)] {
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start,
+            sample_rate: Some(8_000),
+            bits_per_sample: 24,
+        };
+        let left: Vec<i32> = (start..start + len).map(|i| i as i32 - 6).collect();
+        let right: Vec<i32> = vec![-0x80_0000; len as usize];
+        write_frame(&spec, &[&left, &right], &mut data).unwrap();
+    }
+
+    let frame_reader = FrameReader::new(io::Cursor::new(data));
+    let levels = Levels::new(frame_reader, 5, 2);
+    let windows: Vec<WindowLevels> = levels.map(|w| w.unwrap()).collect();
+
+    assert_eq!(windows.len(), 3);
+    let expected = [(0, 5, 6, 90), (5, 5, 3, 15), (10, 2, 5, 41)];
+    for (window, &(time, duration, peak, sum_squares)) in windows.iter().zip(&expected) {
+        assert_eq!(window.time, time);
+        assert_eq!(window.duration, duration);
+        assert_eq!(window.peak, vec![peak, 0x80_0000]);
+        let left_rms = (sum_squares as f64 / duration as f64).sqrt();
+        assert!((window.rms[0] - left_rms).abs() < 1e-9);
+        assert_eq!(window.rms[1], 0x80_0000 as f64);
+    }
+}
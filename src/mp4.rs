@@ -0,0 +1,563 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `mp4` module reads FLAC streams embedded in an MP4 container.
+//!
+//! This module is only available when the `mp4` feature is enabled. It locates
+//! a FLAC track in an MP4 (ISO base media) file, such as an `.mp4` or `.m4a`
+//! file, parses the metadata in its FLAC specific box, and uses the sample
+//! tables to find the FLAC frames. Fragmented MP4 files are not supported.
+//!
+//! See <https://github.com/xiph/flac/blob/master/doc/isoflac.txt> for the
+//! specification of FLAC in MP4.
+
+use std::cmp;
+use std::io;
+use std::io::{Read, Seek};
+use std::mem;
+use error::{Error, Result, UnsupportedFeature, fmt_err};
+use frame::{Block, FrameReader};
+use metadata::{MetadataBlock, MetadataBlockReader, StreamInfo, Tags, VorbisComment};
+
+/// An MP4 box, with the header stripped off.
+struct Mp4Box<'a> {
+    box_type: [u8; 4],
+    data: &'a [u8],
+}
+
+fn be_u32(data: &[u8], pos: usize) -> Result<u32> {
+    if data.len() < pos + 4 {
+        return fmt_err("mp4 box is too short");
+    }
+    Ok((data[pos] as u32) << 24 | (data[pos + 1] as u32) << 16 |
+       (data[pos + 2] as u32) << 8 | (data[pos + 3] as u32))
+}
+
+fn be_u64(data: &[u8], pos: usize) -> Result<u64> {
+    let high = try!(be_u32(data, pos)) as u64;
+    let low = try!(be_u32(data, pos + 4)) as u64;
+    Ok(high << 32 | low)
+}
+
+/// Splits the contents of a container box into its child boxes.
+fn read_boxes<'a>(mut data: &'a [u8]) -> Result<Vec<Mp4Box<'a>>> {
+    let mut boxes = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 8 {
+            return fmt_err("mp4 box header is truncated");
+        }
+        let size = try!(be_u32(data, 0)) as u64;
+        let box_type = [data[4], data[5], data[6], data[7]];
+        let (header_len, size) = match size {
+            // A size of 0 means that the box extends to the end of its parent.
+            0 => (8, data.len() as u64),
+            // A size of 1 means that a 64-bit size follows the box type.
+            1 => (16, try!(be_u64(data, 8))),
+            n => (8, n),
+        };
+        if size < header_len || size > data.len() as u64 {
+            return fmt_err("invalid mp4 box size");
+        }
+        boxes.push(Mp4Box {
+            box_type: box_type,
+            data: &data[header_len as usize..size as usize],
+        });
+        data = &data[size as usize..];
+    }
+    Ok(boxes)
+}
+
+/// Returns the contents of the first box of the given type, if any.
+fn find_box<'a>(boxes: &[Mp4Box<'a>], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes.iter().find(|b| &b.box_type == box_type).map(|b| b.data)
+}
+
+/// Returns the contents of the first box of the given type, or an error.
+fn require_box<'a>(boxes: &[Mp4Box<'a>], box_type: &[u8; 4]) -> Result<&'a [u8]> {
+    match find_box(boxes, box_type) {
+        Some(data) => Ok(data),
+        None => fmt_err("missing mp4 box in FLAC track"),
+    }
+}
+
+/// Reads the contents of the top-level `moov` box, skipping over other boxes.
+fn read_moov<R: Read + Seek>(input: &mut R) -> Result<Vec<u8>> {
+    try!(input.seek(io::SeekFrom::Start(0)));
+    loop {
+        let mut header = [0u8; 8];
+        if let Err(err) = input.read_exact(&mut header) {
+            return match err.kind() {
+                io::ErrorKind::UnexpectedEof => fmt_err("no moov box found in mp4 file"),
+                _ => Err(Error::IoError(err)),
+            };
+        }
+
+        let size = try!(be_u32(&header, 0)) as u64;
+        let (header_len, size) = match size {
+            0 => {
+                // The box extends to the end of the file.
+                let pos = try!(input.seek(io::SeekFrom::Current(0)));
+                let end = try!(input.seek(io::SeekFrom::End(0)));
+                try!(input.seek(io::SeekFrom::Start(pos)));
+                (8, end - pos + 8)
+            }
+            1 => {
+                let mut large_size = [0u8; 8];
+                try!(input.read_exact(&mut large_size));
+                (16, try!(be_u64(&large_size, 0)))
+            }
+            n => (8, n),
+        };
+        if size < header_len {
+            return fmt_err("invalid mp4 box size");
+        }
+
+        let body_len = size - header_len;
+        if &header[4..8] == b"moov" {
+            // The box size is not trusted to allocate up front, the buffer
+            // grows as data is actually read.
+            let mut moov = Vec::new();
+            try!(input.take(body_len).read_to_end(&mut moov));
+            if (moov.len() as u64) < body_len {
+                return fmt_err("mp4 moov box is truncated");
+            }
+            return Ok(moov);
+        }
+
+        if body_len > i64::max_value() as u64 {
+            return fmt_err("invalid mp4 box size");
+        }
+        try!(input.seek(io::SeekFrom::Current(body_len as i64)));
+    }
+}
+
+/// The location of a single FLAC frame (an MP4 "sample") in the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FrameLocation {
+    offset: u64,
+    size: u32,
+}
+
+/// A FLAC track found in the `moov` box.
+struct FlacTrack {
+    streaminfo: StreamInfo,
+    vorbis_comment: Option<VorbisComment>,
+    frames: Vec<FrameLocation>,
+}
+
+/// Parses the metadata blocks from a FLAC specific box (`dfLa`).
+fn read_flac_specific_box(dfla: &[u8]) -> Result<(StreamInfo, Option<VorbisComment>)> {
+    // The box is a "full box", with one byte version and three bytes flags.
+    if dfla.len() < 4 {
+        return fmt_err("FLAC specific box is too short");
+    }
+    if dfla[0] != 0 {
+        return Err(Error::Unsupported(UnsupportedFeature::Mp4BoxVersion));
+    }
+
+    let mut cursor = io::Cursor::new(&dfla[4..]);
+    let mut blocks = MetadataBlockReader::new(&mut cursor);
+    let streaminfo = match blocks.next() {
+        Some(Ok(MetadataBlock::StreamInfo(si))) => si,
+        Some(Err(err)) => return Err(err),
+        _ => return fmt_err("streaminfo block missing"),
+    };
+
+    let mut vorbis_comment = None;
+    for block in blocks {
+        match try!(block) {
+            MetadataBlock::VorbisComment(vc) => {
+                if vorbis_comment.is_some() {
+                    return fmt_err("encountered second Vorbis comment block");
+                }
+                vorbis_comment = Some(vc);
+            }
+            MetadataBlock::StreamInfo(..) => {
+                return fmt_err("encountered second streaminfo block");
+            }
+            _ => {}
+        }
+    }
+
+    Ok((streaminfo, vorbis_comment))
+}
+
+/// Locates all frames of a track using its sample table.
+///
+/// The data in an MP4 file is split into chunks, which start at the offsets in
+/// the chunk offset box (`stco` or `co64`). Every chunk contains one or more
+/// "samples", which in a FLAC track are FLAC frames, stored adjacently. The
+/// sample to chunk box (`stsc`) stores how many samples every chunk contains,
+/// and the sample size box (`stsz`) stores the size of every sample.
+fn read_frame_locations(stbl: &[Mp4Box]) -> Result<Vec<FrameLocation>> {
+    let chunk_offsets: Vec<u64> = match (find_box(stbl, b"stco"), find_box(stbl, b"co64")) {
+        (Some(stco), _) => {
+            let count = try!(be_u32(stco, 4)) as usize;
+            if (stco.len() as u64) < 8 + count as u64 * 4 {
+                return fmt_err("chunk offset box is too short");
+            }
+            try!((0..count).map(|i| be_u32(stco, 8 + i * 4).map(|x| x as u64)).collect())
+        }
+        (None, Some(co64)) => {
+            let count = try!(be_u32(co64, 4)) as usize;
+            if (co64.len() as u64) < 8 + count as u64 * 8 {
+                return fmt_err("chunk offset box is too short");
+            }
+            try!((0..count).map(|i| be_u64(co64, 8 + i * 8)).collect())
+        }
+        (None, None) => return fmt_err("missing chunk offset box in FLAC track"),
+    };
+
+    let stsc = try!(require_box(stbl, b"stsc"));
+    let stsc_count = try!(be_u32(stsc, 4)) as usize;
+    if (stsc.len() as u64) < 8 + stsc_count as u64 * 12 {
+        return fmt_err("sample to chunk box is too short");
+    }
+
+    let stsz = try!(require_box(stbl, b"stsz"));
+    let fixed_size = try!(be_u32(stsz, 4));
+    let num_frames = try!(be_u32(stsz, 8)) as usize;
+    if fixed_size == 0 && (stsz.len() as u64) < 12 + num_frames as u64 * 4 {
+        return fmt_err("sample size box is too short");
+    }
+
+    // The number of frames is only trusted to allocate up front when the
+    // sample size table backs it.
+    let capacity = if fixed_size == 0 { num_frames } else { cmp::min(num_frames, 4096) };
+    let mut frames = Vec::with_capacity(capacity);
+    let mut stsc_index = 0;
+    let mut frames_per_chunk = 0;
+    for (i, &chunk_offset) in chunk_offsets.iter().enumerate() {
+        // The sample to chunk box only has entries for chunks where the number
+        // of samples per chunk changes. Its first_chunk field is 1-based.
+        while stsc_index < stsc_count &&
+              try!(be_u32(stsc, 8 + stsc_index * 12)) as usize <= i + 1 {
+            frames_per_chunk = try!(be_u32(stsc, 8 + stsc_index * 12 + 4));
+            stsc_index += 1;
+        }
+
+        let mut offset = chunk_offset;
+        for _ in 0..frames_per_chunk {
+            if frames.len() == num_frames {
+                return fmt_err("mp4 sample tables are inconsistent");
+            }
+            let size = match fixed_size {
+                0 => try!(be_u32(stsz, 12 + frames.len() * 4)),
+                n => n,
+            };
+            frames.push(FrameLocation {
+                offset: offset,
+                size: size,
+            });
+            offset += size as u64;
+        }
+    }
+
+    if frames.len() != num_frames {
+        return fmt_err("mp4 sample tables are inconsistent");
+    }
+
+    Ok(frames)
+}
+
+/// Parses a `trak` box, returning `None` if it is not a FLAC track.
+fn read_flac_track(trak: &[u8]) -> Result<Option<FlacTrack>> {
+    let trak_boxes = try!(read_boxes(trak));
+    let mdia = match find_box(&trak_boxes, b"mdia") {
+        Some(mdia) => mdia,
+        None => return Ok(None),
+    };
+    let mdia_boxes = try!(read_boxes(mdia));
+    let minf = match find_box(&mdia_boxes, b"minf") {
+        Some(minf) => minf,
+        None => return Ok(None),
+    };
+    let minf_boxes = try!(read_boxes(minf));
+    let stbl = match find_box(&minf_boxes, b"stbl") {
+        Some(stbl) => stbl,
+        None => return Ok(None),
+    };
+    let stbl_boxes = try!(read_boxes(stbl));
+
+    // The sample description box is a full box, with a 4-byte entry count
+    // after the version and flags, followed by the sample entries.
+    let stsd = try!(require_box(&stbl_boxes, b"stsd"));
+    if stsd.len() < 8 {
+        return fmt_err("sample description box is too short");
+    }
+    let entries = try!(read_boxes(&stsd[8..]));
+    let flac_entry = match find_box(&entries, b"fLaC") {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+
+    // An audio sample entry has 28 bytes of fields (channel count, sample rate,
+    // etc.), which duplicate the streaminfo, followed by child boxes.
+    if flac_entry.len() < 28 {
+        return fmt_err("FLAC sample entry is too short");
+    }
+    let flac_entry_boxes = try!(read_boxes(&flac_entry[28..]));
+    let dfla = match find_box(&flac_entry_boxes, b"dfLa") {
+        Some(dfla) => dfla,
+        None => return fmt_err("missing FLAC specific box in FLAC track"),
+    };
+
+    let (streaminfo, vorbis_comment) = try!(read_flac_specific_box(dfla));
+    let frames = try!(read_frame_locations(&stbl_boxes));
+
+    let track = FlacTrack {
+        streaminfo: streaminfo,
+        vorbis_comment: vorbis_comment,
+        frames: frames,
+    };
+    Ok(Some(track))
+}
+
+/// A FLAC decoder for a FLAC track in an MP4 container.
+pub struct Mp4FlacReader<R: Read + Seek> {
+    track: FlacTrack,
+    input: R,
+}
+
+impl<R: Read + Seek> Mp4FlacReader<R> {
+    /// Reads the MP4 structure and locates the first FLAC track in it.
+    ///
+    /// Returns a format error if the file contains no FLAC track.
+    pub fn new(mut input: R) -> Result<Mp4FlacReader<R>> {
+        let moov = try!(read_moov(&mut input));
+        for moov_box in try!(read_boxes(&moov)) {
+            if &moov_box.box_type != b"trak" {
+                continue
+            }
+            if let Some(track) = try!(read_flac_track(moov_box.data)) {
+                let reader = Mp4FlacReader {
+                    track: track,
+                    input: input,
+                };
+                return Ok(reader);
+            }
+        }
+        fmt_err("no FLAC track found in mp4 file")
+    }
+
+    /// Returns the streaminfo metadata from the FLAC specific box.
+    pub fn streaminfo(&self) -> StreamInfo {
+        self.track.streaminfo
+    }
+
+    /// Returns name-value pairs of Vorbis comments, if the FLAC specific box
+    /// contains a Vorbis comment block.
+    ///
+    /// See `FlacReader::tags()` for more details.
+    pub fn tags<'a>(&'a self) -> Tags<'a> {
+        Tags::new(self.track.vorbis_comment.as_ref())
+    }
+
+    /// Returns the number of FLAC frames in the track.
+    pub fn num_frames(&self) -> usize {
+        self.track.frames.len()
+    }
+
+    /// Returns a reader that decodes a single frame at a time.
+    ///
+    /// This is the equivalent of `FlacReader::blocks()`.
+    pub fn blocks<'r>(&'r mut self) -> Mp4Blocks<'r, R> {
+        Mp4Blocks {
+            reader: self,
+            next_frame: 0,
+            frame_data: Vec::new(),
+        }
+    }
+
+    /// Returns an iterator over all samples, with the channel data interleaved.
+    ///
+    /// This is the equivalent of `FlacReader::samples()`.
+    pub fn samples<'r>(&'r mut self) -> Mp4Samples<'r, R> {
+        Mp4Samples {
+            blocks: self.blocks(),
+            block: Block::empty(),
+            sample: 0,
+            channel: 0,
+            has_failed: false,
+        }
+    }
+
+    /// Destroys the reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
+
+/// Reads the frames of a FLAC track in an MP4 container one by one.
+pub struct Mp4Blocks<'r, R: 'r + Read + Seek> {
+    reader: &'r mut Mp4FlacReader<R>,
+    next_frame: usize,
+    frame_data: Vec<u8>,
+}
+
+impl<'r, R: Read + Seek> Mp4Blocks<'r, R> {
+    /// Decodes the next frame, or returns `None` after the last frame.
+    ///
+    /// See `FrameReader::read_next_or_eof()` for more details.
+    pub fn read_next_or_eof(&mut self, buffer: Vec<i32>) -> Result<Option<Block>> {
+        let location = match self.reader.track.frames.get(self.next_frame) {
+            Some(&location) => location,
+            None => return Ok(None),
+        };
+        self.next_frame += 1;
+
+        try!(self.reader.input.seek(io::SeekFrom::Start(location.offset)));
+        self.frame_data.clear();
+        let input = &mut self.reader.input;
+        try!(input.take(location.size as u64).read_to_end(&mut self.frame_data));
+        if self.frame_data.len() < location.size as usize {
+            return fmt_err("mp4 file is truncated");
+        }
+
+        // Every frame is decoded by a new frame reader, which cannot infer the
+        // block size of the stream from earlier frames.
+        let mut frame_reader = FrameReader::new(io::Cursor::new(&self.frame_data[..]));
+        frame_reader.set_fixed_block_size(self.reader.track.streaminfo.max_block_size);
+        match try!(frame_reader.read_next_or_eof(buffer)) {
+            Some(block) => Ok(Some(block)),
+            None => fmt_err("empty FLAC frame in mp4 file"),
+        }
+    }
+}
+
+/// An iterator that yields samples read from an `Mp4FlacReader`.
+pub struct Mp4Samples<'r, R: 'r + Read + Seek> {
+    blocks: Mp4Blocks<'r, R>,
+    block: Block,
+    sample: u32,
+    channel: u32,
+    has_failed: bool,
+}
+
+impl<'r, R: Read + Seek> Iterator for Mp4Samples<'r, R> {
+    type Item = Result<i32>;
+
+    fn next(&mut self) -> Option<Result<i32>> {
+        if self.has_failed {
+            return None;
+        }
+
+        self.channel += 1;
+        if self.channel >= self.block.channels() {
+            self.channel = 0;
+            self.sample += 1;
+
+            if self.sample >= self.block.duration() {
+                self.sample = 0;
+                let current_block = mem::replace(&mut self.block, Block::empty());
+                match self.blocks.read_next_or_eof(current_block.into_buffer()) {
+                    Ok(Some(next_block)) => self.block = next_block,
+                    Ok(None) => return None,
+                    Err(error) => {
+                        self.has_failed = true;
+                        return Some(Err(error));
+                    }
+                }
+            }
+        }
+
+        Some(Ok(self.block.sample(self.channel, self.sample)))
+    }
+}
+
+#[test]
+fn verify_mp4_flac_reader() {
+    use std::fs;
+    use FlacReader;
+
+    fn make_box(box_type: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let size = body.len() as u32 + 8;
+        let mut result = vec![(size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8];
+        result.extend_from_slice(box_type);
+        result.extend_from_slice(body);
+        result
+    }
+
+    fn push_u32(buffer: &mut Vec<u8>, x: u32) {
+        buffer.extend_from_slice(&[(x >> 24) as u8, (x >> 16) as u8, (x >> 8) as u8, x as u8]);
+    }
+
+    // Take the frames from a FLAC file, which has its streaminfo at byte 8, and
+    // frames at byte 152 and 1129.
+    let mut flac = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut flac).unwrap();
+    let frame_sizes = [1129 - 152, flac.len() as u32 - 1129];
+
+    // Put the frames in an mdat box, both in their own chunk, and with some
+    // junk in between the chunks, to verify that the offsets are respected.
+    let ftyp = make_box(b"ftyp", b"M4A \0\0\0\0");
+    let mut mdat_body = flac[152..1129].to_vec();
+    mdat_body.extend_from_slice(b"junk");
+    mdat_body.extend_from_slice(&flac[1129..]);
+    let mdat = make_box(b"mdat", &mdat_body);
+    let first_frame_offset = ftyp.len() as u32 + 8;
+    let chunk_offsets = [first_frame_offset, first_frame_offset + frame_sizes[0] + 4];
+
+    // The FLAC specific box contains the streaminfo block, with its header,
+    // marked as the last metadata block.
+    let mut dfla_body = vec![0, 0, 0, 0, 0x80];
+    dfla_body.extend_from_slice(&flac[5..42]);
+    let dfla = make_box(b"dfLa", &dfla_body);
+    let mut flac_entry_body = vec![0; 28];
+    flac_entry_body.extend_from_slice(&dfla);
+    let flac_entry = make_box(b"fLaC", &flac_entry_body);
+
+    let mut stsd_body = vec![0, 0, 0, 0, 0, 0, 0, 1];
+    stsd_body.extend_from_slice(&flac_entry);
+    let mut stsc_body = vec![0, 0, 0, 0];
+    push_u32(&mut stsc_body, 1);
+    push_u32(&mut stsc_body, 1);
+    push_u32(&mut stsc_body, 1);
+    push_u32(&mut stsc_body, 1);
+    let mut stsz_body = vec![0; 8];
+    push_u32(&mut stsz_body, 2);
+    push_u32(&mut stsz_body, frame_sizes[0]);
+    push_u32(&mut stsz_body, frame_sizes[1]);
+    let mut stco_body = vec![0, 0, 0, 0];
+    push_u32(&mut stco_body, 2);
+    push_u32(&mut stco_body, chunk_offsets[0]);
+    push_u32(&mut stco_body, chunk_offsets[1]);
+
+    let mut stbl_body = make_box(b"stsd", &stsd_body);
+    stbl_body.extend_from_slice(&make_box(b"stsc", &stsc_body));
+    stbl_body.extend_from_slice(&make_box(b"stsz", &stsz_body));
+    stbl_body.extend_from_slice(&make_box(b"stco", &stco_body));
+    let minf = make_box(b"minf", &make_box(b"stbl", &stbl_body));
+    let trak = make_box(b"trak", &make_box(b"mdia", &minf));
+    let moov = make_box(b"moov", &trak);
+
+    let mut mp4 = ftyp;
+    mp4.extend_from_slice(&mdat);
+    mp4.extend_from_slice(&moov);
+
+    let mut reader = Mp4FlacReader::new(io::Cursor::new(mp4)).unwrap();
+    let mut flac_reader = FlacReader::new(io::Cursor::new(&flac[..])).unwrap();
+    assert_eq!(reader.streaminfo(), flac_reader.streaminfo());
+    assert_eq!(reader.num_frames(), 2);
+
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    let expected: Vec<i32> = flac_reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, expected);
+
+    // The short last frame starts where the first frame ends, its frame number
+    // times the block size of the stream.
+    let mut blocks = reader.blocks();
+    let first = blocks.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    let last = blocks.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert!(blocks.read_next_or_eof(Vec::new()).unwrap().is_none());
+    assert_eq!((first.time(), first.duration()), (0, 4096));
+    assert_eq!((last.time(), last.duration()), (4096, 314));
+
+    // A file without FLAC track should be rejected.
+    let not_flac = make_box(b"moov", &make_box(b"trak", &[]));
+    assert!(Mp4FlacReader::new(io::Cursor::new(not_flac)).is_err());
+}
@@ -0,0 +1,224 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `resample` module provides an integration point for sample rate conversion.
+//!
+//! Claxon does not implement resampling itself. Instead, it defines the
+//! `Resampler` trait, and an iterator that feeds decoded blocks through a
+//! resampler, taking care of buffer management, interleaving, and flushing the
+//! resampler at the end of the stream.
+
+use std::mem;
+use error::{Result, fmt_err};
+use frame::{Block, FrameReader};
+use input::ReadBytes;
+use metadata::StreamSpec;
+
+/// A sample rate converter that processes planar audio.
+pub trait Resampler {
+    /// Resamples a block of planar audio.
+    ///
+    /// `input` contains one slice per channel, all of the same length. The
+    /// resampled samples for channel `ch` must be appended to `output[ch]`,
+    /// and the same number of samples must be appended for every channel. The
+    /// resampler may buffer input internally and produce output later.
+    fn process(&mut self, input: &[&[i32]], output: &mut [Vec<i32>]);
+
+    /// Appends any samples still buffered in the resampler to `output`.
+    ///
+    /// This is called once, at the end of the stream.
+    fn flush(&mut self, output: &mut [Vec<i32>]);
+}
+
+/// An iterator that yields resampled samples read from a `FlacReader`.
+///
+/// The channel data is interleaved, like in `FlacSamples`.
+pub struct ResampledSamples<R: ReadBytes, S: Resampler> {
+    frame_reader: FrameReader<R>,
+    resampler: S,
+
+    /// The format of the input, every block must match it.
+    spec: StreamSpec,
+
+    /// The buffer of the previous block, to be reused for decoding.
+    buffer: Vec<i32>,
+
+    /// The resampled output, one vector per channel.
+    output: Vec<Vec<i32>>,
+
+    /// The index of the next sample to yield, counted in interleaved samples.
+    pos: usize,
+
+    /// Set after the resampler has been flushed, or when decoding failed.
+    done: bool,
+}
+
+impl<R: ReadBytes, S: Resampler> ResampledSamples<R, S> {
+    /// Creates an iterator that resamples the blocks read by the frame reader.
+    ///
+    /// Every block must have the given spec, the resampler is configured for
+    /// a single format. A block with a different spec is an error.
+    pub fn new(frame_reader: FrameReader<R>,
+               resampler: S,
+               spec: StreamSpec)
+               -> ResampledSamples<R, S> {
+        ResampledSamples {
+            frame_reader: frame_reader,
+            resampler: resampler,
+            spec: spec,
+            buffer: Vec::new(),
+            output: vec![Vec::new(); spec.channels as usize],
+            pos: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the format of the input, before resampling.
+    pub fn spec(&self) -> StreamSpec {
+        self.spec
+    }
+
+    /// Destroys the iterator, returning the resampler.
+    pub fn into_resampler(self) -> S {
+        self.resampler
+    }
+
+    /// Decodes the next block and feeds it through the resampler.
+    ///
+    /// At the end of the stream, flushes the resampler instead.
+    fn refill(&mut self) -> Result<()> {
+        for channel in self.output.iter_mut() {
+            channel.clear();
+        }
+        self.pos = 0;
+
+        let buffer = mem::replace(&mut self.buffer, Vec::new());
+        match try!(self.frame_reader.read_next_or_eof(buffer)) {
+            Some(block) => {
+                if block.spec(self.spec) != self.spec {
+                    return fmt_err("block format differs from the streaminfo");
+                }
+                self.process_block(&block);
+                self.buffer = block.into_buffer();
+            }
+            None => {
+                self.resampler.flush(&mut self.output);
+                self.done = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_block(&mut self, block: &Block) {
+        // A FLAC stream has at most 8 channels, so there is no need to
+        // allocate for the channel slices.
+        let mut channels: [&[i32]; 8] = [&[]; 8];
+        let num_channels = block.channels() as usize;
+        for (ch, slice) in channels[..num_channels].iter_mut().enumerate() {
+            *slice = block.channel(ch as u32);
+        }
+        self.resampler.process(&channels[..num_channels], &mut self.output);
+    }
+}
+
+impl<R: ReadBytes, S: Resampler> Iterator for ResampledSamples<R, S> {
+    type Item = Result<i32>;
+
+    fn next(&mut self) -> Option<Result<i32>> {
+        let channels = self.output.len();
+        loop {
+            let available = self.output.first().map_or(0, |ch| channels * ch.len());
+            if self.pos < available {
+                let sample = self.output[self.pos % channels][self.pos / channels];
+                self.pos += 1;
+                return Some(Ok(sample));
+            }
+
+            if self.done {
+                return None;
+            }
+
+            if let Err(error) = self.refill() {
+                self.done = true;
+                return Some(Err(error));
+            }
+        }
+    }
+}
+
+#[test]
+fn verify_resampled_samples() {
+    use std::fs;
+    use std::io;
+    use std::io::Read;
+
+    struct RepeatResampler {
+        /// The last sample of every channel, held back until the next block.
+        held: Option<Vec<i32>>,
+    }
+
+    impl Resampler for RepeatResampler {
+        fn process(&mut self, input: &[&[i32]], output: &mut [Vec<i32>]) {
+            // Upsample by a factor 2 by repeating every sample, but hold back the
+            // last sample, to exercise flushing.
+            for (ch, samples) in input.iter().enumerate() {
+                if let Some(ref held) = self.held {
+                    output[ch].push(held[ch]);
+                    output[ch].push(held[ch]);
+                }
+                for &s in &samples[..samples.len() - 1] {
+                    output[ch].push(s);
+                    output[ch].push(s);
+                }
+            }
+            self.held = Some(input.iter().map(|samples| samples[samples.len() - 1]).collect());
+        }
+
+        fn flush(&mut self, output: &mut [Vec<i32>]) {
+            if let Some(held) = self.held.take() {
+                for (ch, &s) in held.iter().enumerate() {
+                    output[ch].push(s);
+                    output[ch].push(s);
+                }
+            }
+        }
+    }
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut data).unwrap();
+
+    // The frames of this file start at byte 152, and it is mono.
+    let spec = StreamSpec { sample_rate: 44_100, channels: 1, bits_per_sample: 16 };
+    let frame_reader = FrameReader::new(io::Cursor::new(&data[152..]));
+    let resampler = RepeatResampler { held: None };
+    let resampled: Vec<i32> = ResampledSamples::new(frame_reader, resampler, spec)
+        .map(|s| s.unwrap())
+        .collect();
+
+    let mut expected = Vec::new();
+    let mut frame_reader = FrameReader::new(io::Cursor::new(&data[152..]));
+    while let Some(block) = frame_reader.read_next_or_eof(Vec::new()).unwrap() {
+        for &s in block.channel(0) {
+            expected.push(s);
+            expected.push(s);
+        }
+    }
+
+    assert_eq!(resampled, expected);
+
+    // A block that does not match the spec is an error, not a panic.
+    let stereo = StreamSpec { channels: 2, .. spec };
+    let frame_reader = FrameReader::new(io::Cursor::new(&data[152..]));
+    let resampler = RepeatResampler { held: None };
+    let mut resampled = ResampledSamples::new(frame_reader, resampler, stereo);
+    match resampled.next() {
+        Some(Err(::error::Error::FormatError(..))) => {}
+        _ => panic!("expected a format error"),
+    }
+    assert!(resampled.next().is_none());
+}
@@ -0,0 +1,450 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2014 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `rice` module decodes the Rice codes that make up a subframe residual.
+//!
+//! Subframe decoding uses these functions internally, they are exposed for
+//! tools that inspect or verify residuals. The functions here decode a single
+//! run of samples with a known Rice parameter; reading the partition structure
+//! and the parameters themselves is up to the caller.
+//!
+//! For the encoding side, `best_parameter()` and `best_partitioning()` find
+//! the Rice parameters and partition order that code a residual in the fewest
+//! bits. They compute exact sizes, they do not write any bits.
+
+use error::{Error, Result};
+use input::{Bitstream, ReadBytes};
+
+/// Decodes a signed number from Rice coding to the two's complement.
+///
+/// The Rice coding used by FLAC operates on unsigned integers, but the
+/// residual is signed. The mapping is done as follows:
+///
+///  0 -> 0
+/// -1 -> 1
+///  1 -> 2
+/// -2 -> 3
+///  2 -> 4
+///  etc.
+///
+/// This function takes the unsigned value and converts it into a signed
+/// number.
+#[inline(always)]
+pub fn rice_to_signed(val: u32) -> i32 {
+    // The following bit-level hackery compiles to only four instructions on
+    // x64. It is equivalent to the following code:
+    //
+    //   if val & 1 == 1 {
+    //       -1 - (val / 2) as i32
+    //   } else {
+    //       (val / 2) as i32
+    //   }
+    //
+    let half = (val >> 1) as i32;
+    let extended_bit_0 = ((val << 31) as i32) >> 31;
+    half ^ extended_bit_0
+}
+
+#[test]
+fn verify_rice_to_signed() {
+    assert_eq!(rice_to_signed(0), 0);
+    assert_eq!(rice_to_signed(1), -1);
+    assert_eq!(rice_to_signed(2), 1);
+    assert_eq!(rice_to_signed(3), -2);
+    assert_eq!(rice_to_signed(4), 2);
+}
+
+/// Encodes a signed number into the unsigned form that Rice coding operates on.
+///
+/// This is the inverse of `rice_to_signed()`.
+#[inline(always)]
+pub fn signed_to_rice(val: i32) -> u32 {
+    ((val << 1) ^ (val >> 31)) as u32
+}
+
+#[test]
+fn verify_signed_to_rice() {
+    for &val in &[0, -1, 1, -2, 2, 1000, -1000, i32::max_value(), i32::min_value()] {
+        assert_eq!(rice_to_signed(signed_to_rice(val)), val);
+    }
+    assert_eq!(signed_to_rice(-2), 3);
+    assert_eq!(signed_to_rice(i32::min_value()), u32::max_value());
+}
+
+/// Builds the error for a Rice quotient that exceeds the configured limit.
+///
+/// The reported value is a lower bound, as reading stops once the quotient is
+/// known to exceed the limit.
+#[cold]
+fn rice_quotient_limit_exceeded(q: u32, max: u32) -> Error {
+    Error::LimitExceeded {
+        limit: "Rice quotient",
+        value: q as u64,
+        max: max as u64,
+    }
+}
+
+/// Decodes `out.len()` Rice-coded samples with parameter `param`.
+///
+/// Every sample consists of a unary quotient followed by a remainder of
+/// `param` bits. The samples are stored signed, see `rice_to_signed()`. The
+/// escape codes of the partition header are not Rice parameters, the caller
+/// must handle those. No memory is allocated.
+///
+/// A quotient greater than `input.max_unary()` results in
+/// `Error::LimitExceeded`.
+///
+/// # Panics
+///
+/// Panics if `param` is greater than 30, the largest parameter that a Rice2
+/// partition can hold.
+#[inline(always)]
+pub fn decode<R: ReadBytes>(input: &mut Bitstream<R>,
+                            param: u32,
+                            out: &mut [i32])
+                            -> Result<()> {
+    assert!(param <= 30, "Rice parameter must be at most 30");
+
+    // About the decoding below: the first part of the sample is the quotient,
+    // unary encoded. This means that there are q zeros, and then a one.
+    //
+    // The reference decoder supports sample widths up to 24 bits, so with
+    // the additional bytes for difference in channels and for prediction, a
+    // sample fits in 26 bits. The Rice parameter could be as little as 1,
+    // so the quotient can potentially be very large. However, in practice
+    // it is rarely greater than 5. Values as large as 75 still occur though.
+    //
+    // Next up is the remainder in param bits. Depending on the number of
+    // bits, at most two or three bytes need to be read, so the code below is
+    // split into cases to allow a more efficient reading function to be used
+    // when possible. About 45% of the time, param is less than 9 (measured
+    // from real-world FLAC files). Parameters greater than 16 only occur in
+    // Rice2 partitions, which are very uncommon.
+    //
+    // The quotient is checked against the configured maximum, by default
+    // 2^20 for frames (see `FrameLimits::max_rice_quotient`). Legitimate
+    // quotients never come close, so the check is well-predicted.
+
+    let max_q = input.max_unary();
+
+    if param <= 8 {
+        for sample in out.iter_mut() {
+            let q = try!(input.read_unary());
+            if q > max_q {
+                return Err(rice_quotient_limit_exceeded(q, max_q));
+            }
+            let r = try!(input.read_leq_u8(param)) as u32;
+            *sample = rice_to_signed((q << param) | r);
+        }
+    } else if param <= 16 {
+        for sample in out.iter_mut() {
+            let q = try!(input.read_unary());
+            if q > max_q {
+                return Err(rice_quotient_limit_exceeded(q, max_q));
+            }
+            let r = try!(input.read_gt_u8_leq_u16(param));
+            *sample = rice_to_signed((q << param) | r);
+        }
+    } else {
+        for sample in out.iter_mut() {
+            let q = try!(input.read_unary());
+            if q > max_q {
+                return Err(rice_quotient_limit_exceeded(q, max_q));
+            }
+            let r = try!(input.read_leq_u32(param));
+            *sample = rice_to_signed((q << param) | r);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads past `n` Rice-coded samples with parameter `param`, without storing them.
+///
+/// This reads the same bits as `decode()`, and applies the same limit on the
+/// quotient, but it does not reconstruct the samples.
+///
+/// # Panics
+///
+/// Panics if `param` is greater than 30.
+pub fn skip<R: ReadBytes>(input: &mut Bitstream<R>, param: u32, n: u32) -> Result<()> {
+    assert!(param <= 30, "Rice parameter must be at most 30");
+
+    let max_q = input.max_unary();
+
+    for _ in 0..n {
+        let q = try!(input.read_unary());
+        if q > max_q {
+            return Err(rice_quotient_limit_exceeded(q, max_q));
+        }
+        if param <= 8 {
+            try!(input.read_leq_u8(param));
+        } else {
+            try!(input.read_leq_u32(param));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn verify_decode() {
+    use std::io;
+    use input::BufferedReader;
+
+    // With parameter 2: 0 is "1 00", -1 is "1 01", 5 (unsigned 10) is
+    // "001 10", and -3 (unsigned 5) is "01 01". Padded with ones.
+    let data = vec![0b1001_0100, 0b1100_1011];
+    let mut bits = Bitstream::new(BufferedReader::new(io::Cursor::new(data)));
+    let mut out = [0i32; 4];
+    decode(&mut bits, 2, &mut out).unwrap();
+    assert_eq!(out, [0, -1, 5, -3]);
+
+    // The same values with parameters from every branch must round-trip.
+    for &param in &[0, 3, 8, 9, 16, 17, 30] {
+        let values = [0u32, 1, 2, 3, 1000, 0x7fff];
+        let mut encoded = Vec::new();
+        let mut acc = 0u64;
+        let mut n = 0;
+        for &v in &values {
+            let q = v >> param;
+            let r = v & ((1u64 << param) - 1) as u32;
+            // Write q zeros, a one, and the remainder, most significant first.
+            let mut push = |bit: u64| {
+                acc = (acc << 1) | bit;
+                n += 1;
+                if n == 8 {
+                    encoded.push(acc as u8);
+                    acc = 0;
+                    n = 0;
+                }
+            };
+            for _ in 0..q {
+                push(0);
+            }
+            push(1);
+            for i in 0..param {
+                push(((r >> (param - 1 - i)) & 1) as u64);
+            }
+        }
+        if n != 0 {
+            encoded.push(((acc << (8 - n)) | (0xff >> n)) as u8);
+        }
+
+        let mut bits = Bitstream::new(BufferedReader::new(io::Cursor::new(encoded)));
+        let mut out = [0i32; 6];
+        decode(&mut bits, param, &mut out).unwrap();
+        let expected: Vec<i32> = values.iter().map(|&v| rice_to_signed(v)).collect();
+        assert_eq!(&out[..], &expected[..]);
+    }
+}
+
+#[test]
+fn verify_decode_rejects_long_quotient() {
+    use std::io;
+    use input::BufferedReader;
+
+    // A quotient of 20 (20 zeros and a one), with parameter 0.
+    let data = vec![0, 0, 0b0000_1000];
+    let mut bits = Bitstream::new(BufferedReader::new(io::Cursor::new(data)));
+    bits.set_max_unary(10);
+    let mut out = [0i32; 1];
+    match decode(&mut bits, 0, &mut out) {
+        Err(Error::LimitExceeded { limit: "Rice quotient", value, max: 10 }) => {
+            assert!(value > 10);
+        }
+        other => panic!("expected Rice quotient limit error, got {:?}", other),
+    }
+}
+
+#[test]
+fn verify_skip_reads_same_bits_as_decode() {
+    use std::io;
+    use input::BufferedReader;
+
+    // The same data as in `verify_decode()`, followed by a marker byte.
+    let data = vec![0b1001_0100, 0b1100_1011, 0x5a];
+    let mut bits = Bitstream::new(BufferedReader::new(io::Cursor::new(data)));
+    skip(&mut bits, 2, 4).unwrap();
+    // The remaining padding bit, then the marker.
+    assert_eq!(bits.read_leq_u8(1).unwrap(), 1);
+    assert_eq!(bits.read_leq_u8(8).unwrap(), 0x5a);
+}
+
+/// The largest Rice parameter, that of a Rice2 partition, excluding the escape code.
+const MAX_PARAMETER: u32 = 30;
+
+/// The largest parameter of a Rice partition, larger ones need Rice2.
+const MAX_RICE1_PARAMETER: u32 = 14;
+
+/// Returns the number of bits that Rice coding the residual with `param` takes.
+///
+/// Every sample takes its quotient plus one bits of unary code, and `param`
+/// bits of remainder.
+pub fn encoded_len(residual: &[i32], param: u32) -> u64 {
+    residual.iter().fold(0, |len, &r| {
+        len + (signed_to_rice(r) >> param) as u64 + 1 + param as u64
+    })
+}
+
+/// Returns the Rice parameter that codes the residual in the fewest bits.
+///
+/// Returns the parameter and the number of bits, as `encoded_len()` computes
+/// it. All parameters up to 30 are considered, on a tie the smallest one is
+/// returned. An empty residual has parameter 0 and takes no bits.
+pub fn best_parameter(residual: &[i32]) -> (u32, u64) {
+    // The remainders take the same number of bits for every sample, so only
+    // the quotients need to be summed, for every parameter at once.
+    let mut quotients = [0u64; MAX_PARAMETER as usize + 1];
+    for &r in residual {
+        let u = signed_to_rice(r);
+        for (param, sum) in quotients.iter_mut().enumerate() {
+            *sum += (u >> param) as u64;
+        }
+    }
+
+    let n = residual.len() as u64;
+    let mut best = (0, quotients[0] + n);
+    for (param, &sum) in quotients.iter().enumerate().skip(1) {
+        let len = sum + n * (param as u64 + 1);
+        if len < best.1 {
+            best = (param as u32, len);
+        }
+    }
+    best
+}
+
+/// The partitioning of a residual, as chosen by `best_partitioning()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Partitioning {
+    /// The partition order, the residual has 2<sup>order</sup> partitions.
+    pub order: u32,
+
+    /// The Rice parameter of every partition.
+    ///
+    /// If any of these is greater than 14, the residual must be coded with
+    /// Rice2 partitions.
+    pub params: Vec<u32>,
+
+    /// The size of the coded residual in bits.
+    ///
+    /// This includes the coding method, the partition order, and the
+    /// parameters of the partitions.
+    pub cost_bits: u64,
+}
+
+/// Finds the partition order and Rice parameters that code a residual in the fewest bits.
+///
+/// The residual belongs to a subframe with `n_warm_up` warm-up samples, the
+/// predictor order, so the block size is that plus the length of the residual.
+/// Every partition order up to `max_order` that is valid for the block size is
+/// tried, with the best parameter for every partition. On a tie, the lowest
+/// order is returned. Escape codes, which store a partition unencoded, are not
+/// considered.
+///
+/// # Panics
+///
+/// Panics if `max_order` is greater than 15, the largest order that the
+/// format can express, or if the block size does not fit in 16 bits.
+pub fn best_partitioning(residual: &[i32], n_warm_up: u32, max_order: u32) -> Partitioning {
+    assert!(max_order <= 15, "partition order must be at most 15");
+    let block_size = residual.len() as u32 + n_warm_up;
+    assert!(block_size <= 0xffff, "block size must fit in 16 bits");
+
+    let mut best: Option<Partitioning> = None;
+    for order in 0..max_order + 1 {
+        // The same conditions as when decoding: the partitions must divide the
+        // block, and the first partition must hold at least the warm-up.
+        let partition_len = block_size >> order;
+        let is_valid = order <= block_size.trailing_zeros() && partition_len >= n_warm_up;
+        if order > 0 && (!is_valid || partition_len == 0) {
+            break
+        }
+
+        let mut params = Vec::with_capacity(1 << order);
+        let mut residual_bits = 0;
+        let mut start = 0;
+        let mut len = partition_len - n_warm_up;
+        for _ in 0..1u32 << order {
+            let (param, bits) = best_parameter(&residual[start..start + len as usize]);
+            params.push(param);
+            residual_bits += bits;
+            start += len as usize;
+            len = partition_len;
+        }
+
+        let is_rice2 = params.iter().any(|&p| p > MAX_RICE1_PARAMETER);
+        let param_bits = if is_rice2 { 5 } else { 4 };
+        let cost_bits = 2 + 4 + param_bits * params.len() as u64 + residual_bits;
+
+        if best.as_ref().map_or(true, |b| cost_bits < b.cost_bits) {
+            best = Some(Partitioning {
+                order: order,
+                params: params,
+                cost_bits: cost_bits,
+            });
+        }
+    }
+
+    best.unwrap()
+}
+
+#[test]
+fn verify_best_parameter() {
+    let residuals: [&[i32]; 5] = [
+        &[],
+        &[0, 0, 0, 0],
+        &[1, -1, 2, -3, 0, 5],
+        &[1000, -2000, 1500, -700, 30000],
+        &[i32::max_value(), i32::min_value()],
+    ];
+    for residual in residuals.iter() {
+        let (param, bits) = best_parameter(residual);
+        assert_eq!(encoded_len(residual, param), bits);
+        for p in 0..MAX_PARAMETER + 1 {
+            let len = encoded_len(residual, p);
+            assert!(len > bits || (len == bits && p >= param));
+        }
+    }
+    assert_eq!(best_parameter(&[]), (0, 0));
+    assert_eq!(best_parameter(&[0, 0, 0, 0]), (0, 4));
+}
+
+#[test]
+fn verify_best_partitioning() {
+    // A quiet half followed by a loud half benefits from two partitions.
+    let mut residual: Vec<i32> = (0..30).map(|i| i % 3 - 1).collect();
+    residual.extend((0..32).map(|i| (i * 7919 % 4001) - 2000));
+    let partitioning = best_partitioning(&residual, 2, 8);
+    assert!(partitioning.order >= 1);
+    assert_eq!(partitioning.params.len(), 1 << partitioning.order);
+
+    // The cost must match the sum over the chosen partitions, and it must not
+    // be worse than a single partition.
+    let partition_len = 64 >> partitioning.order;
+    let mut start = 0;
+    let mut bits = 2 + 4 + 4 * partitioning.params.len() as u64;
+    for (i, &param) in partitioning.params.iter().enumerate() {
+        let len = if i == 0 { partition_len - 2 } else { partition_len };
+        bits += encoded_len(&residual[start..start + len], param);
+        start += len;
+    }
+    assert_eq!(partitioning.cost_bits, bits);
+    let single = best_partitioning(&residual, 2, 0);
+    assert_eq!(single.order, 0);
+    assert!(partitioning.cost_bits < single.cost_bits);
+
+    // With 33 warm-up samples, the partitions of order 1 would be too small.
+    let residual = vec![0; 31];
+    assert_eq!(best_partitioning(&residual, 33, 15).order, 0);
+
+    // Large parameters require Rice2, with 5 bits per parameter.
+    let residual = vec![1 << 20; 16];
+    let partitioning = best_partitioning(&residual, 0, 0);
+    assert_eq!(partitioning.params, vec![20]);
+    assert_eq!(partitioning.cost_bits, 2 + 4 + 5 + 16 * 23);
+}
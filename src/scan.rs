@@ -0,0 +1,267 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `scan` module reads the metadata of all FLAC files in a directory tree.
+//!
+//! Importing a music library is a matter of reading the streaminfo and a few
+//! tags of many files. This module does so in parallel, using the
+//! metadata-only fast path of `FlacReader`, so no audio is decoded. It is
+//! available only with the `scan` feature enabled, which pulls in the `rayon`
+//! and `walkdir` crates.
+
+extern crate rayon;
+extern crate walkdir;
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+use std::thread;
+
+use error::{Error, Result};
+use metadata::{MetadataLimits, StreamInfo};
+use {FlacReader, FlacReaderOptions, LengthCheck};
+
+/// Controls what `directory()` reads and where it looks.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScanOptions {
+    /// The names of the tags to collect, compared case-insensitively.
+    ///
+    /// When empty, the contents of the Vorbis comment block are skipped
+    /// rather than parsed. Defaults to empty.
+    pub tags: Vec<String>,
+
+    /// Whether to follow symbolic links while walking the tree.
+    ///
+    /// Defaults to false.
+    pub follow_links: bool,
+
+    /// Limits on the metadata read per file, see `MetadataLimits`.
+    ///
+    /// A file that exceeds the limits results in `Error::LimitExceeded` for
+    /// that file only. Defaults to no limits.
+    pub metadata_limits: MetadataLimits,
+}
+
+/// The metadata read from a single file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileInfo {
+    /// The streaminfo of the file.
+    pub streaminfo: StreamInfo,
+
+    /// The selected tags, as (name, value) pairs in the order of the file.
+    ///
+    /// The name is as stored in the file, which may differ in case from the
+    /// name in `ScanOptions::tags`.
+    pub tags: Vec<(String, String)>,
+}
+
+/// The outcome of scanning a single file, yielded by `Scan`.
+#[derive(Debug)]
+pub struct ScanResult {
+    /// The path of the file.
+    ///
+    /// For an error that occurred while walking the tree, this is the path
+    /// that could not be read, or the root if the path is unknown.
+    pub path: PathBuf,
+
+    /// The metadata of the file, or the error that occurred reading it.
+    pub result: Result<FileInfo>,
+}
+
+/// An iterator over the results of `directory()`.
+///
+/// Results are yielded as soon as they are available, so the order is
+/// unspecified. Iteration ends once all files have been scanned.
+pub struct Scan {
+    receiver: mpsc::Receiver<ScanResult>,
+}
+
+impl Iterator for Scan {
+    type Item = ScanResult;
+
+    fn next(&mut self) -> Option<ScanResult> {
+        // When all senders are gone, the scan is complete.
+        self.receiver.recv().ok()
+    }
+}
+
+/// Returns whether the path has a `.flac` extension, in any case.
+fn is_flac_file(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("flac"),
+        None => false,
+    }
+}
+
+/// Reads the streaminfo and the selected tags of one file.
+pub fn file<P: AsRef<Path>>(path: P, options: &ScanOptions) -> Result<FileInfo> {
+    let reader_options = FlacReaderOptions {
+        metadata_only: true,
+        read_vorbis_comment: !options.tags.is_empty(),
+        read_seek_table: false,
+        read_pictures: false,
+        lenient_metadata: false,
+        strict_metadata: false,
+        length_check: LengthCheck::Warn,
+        metadata_limits: options.metadata_limits,
+        max_junk_len: 0,
+    };
+    let reader = try!(FlacReader::open_ext(path, reader_options));
+
+    let tags = reader.tags()
+        .filter(|&(name, _)| options.tags.iter().any(|t| t.eq_ignore_ascii_case(name)))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+    Ok(FileInfo {
+        streaminfo: reader.streaminfo(),
+        tags: tags,
+    })
+}
+
+/// Walks the tree at `path`, and reads the metadata of every FLAC file in it.
+///
+/// Files are recognized by their `.flac` extension, at any depth; other files
+/// are ignored, and an empty tree yields no results. The files are read in
+/// parallel on the Rayon thread pool, and the walk itself happens on a
+/// separate thread, so this function returns immediately. Errors are isolated
+/// per file: a file that fails to read, for example because it is not a FLAC
+/// file after all, results in a `ScanResult` with an error, and the scan
+/// continues with the other files. A directory that cannot be read results in
+/// an `Error::IoError` for that directory. Every file is opened with its own
+/// reader; buffers are not shared between files.
+pub fn directory<P: AsRef<Path>>(path: P, options: ScanOptions) -> Scan {
+    let root = path.as_ref().to_path_buf();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let walk = walkdir::WalkDir::new(&root).follow_links(options.follow_links);
+        let options = Arc::new(options);
+        for entry in walk {
+            match entry {
+                Ok(entry) => {
+                    if !entry.file_type().is_file() || !is_flac_file(entry.path()) {
+                        continue
+                    }
+                    let path = entry.path().to_path_buf();
+                    let options = options.clone();
+                    let sender = sender.clone();
+                    rayon::spawn(move || {
+                        let result = file(&path, &options);
+                        // If the receiver is gone, nobody is interested in the
+                        // result any more, so it is fine to drop it.
+                        let _ = sender.send(ScanResult { path: path, result: result });
+                    });
+                }
+                Err(err) => {
+                    let path = err.path().unwrap_or(&root).to_path_buf();
+                    let result = Err(Error::IoError(io::Error::from(err)));
+                    let _ = sender.send(ScanResult { path: path, result: result });
+                }
+            }
+        }
+        // The receiver sees the end of the scan once the spawned tasks, which
+        // hold the remaining senders, have completed.
+    });
+
+    Scan { receiver: receiver }
+}
+
+#[test]
+fn verify_directory_scans_every_flac_file() {
+    let options = ScanOptions {
+        tags: vec!["artist".to_string()],
+        follow_links: false,
+        metadata_limits: MetadataLimits::default(),
+    };
+
+    let mut results: Vec<ScanResult> = directory("testsamples", options.clone()).collect();
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut expected: Vec<PathBuf> = walkdir::WalkDir::new("testsamples")
+        .into_iter()
+        .map(|entry| entry.unwrap().path().to_path_buf())
+        .filter(|path| is_flac_file(path))
+        .collect();
+    expected.sort();
+
+    let paths: Vec<PathBuf> = results.iter().map(|r| r.path.clone()).collect();
+    assert_eq!(paths, expected);
+
+    // The result of a parallel scan is the same as reading one file.
+    let pop = results.iter().find(|r| r.path.ends_with("pop.flac")).unwrap();
+    let info = pop.result.as_ref().unwrap();
+    assert_eq!(*info, file("testsamples/pop.flac", &options).unwrap());
+    assert_eq!(info.streaminfo.sample_rate, 44_100);
+    assert!(info.tags.iter().all(|&(ref name, _)| name.eq_ignore_ascii_case("artist")));
+}
+
+#[cfg(test)]
+fn make_test_dir(name: &str) -> PathBuf {
+    use std::env;
+    use std::fs;
+
+    let dir = env::temp_dir().join(name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn verify_directory_isolates_errors_per_file() {
+    use std::fs;
+
+    let dir = make_test_dir("claxon-scan-errors");
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    fs::copy("testsamples/pop.flac", dir.join("a/b/pop.FLAC")).unwrap();
+    fs::copy("testsamples/short.flac", dir.join("short.flac")).unwrap();
+    fs::copy("testsamples/pop.flac", dir.join("a/pop.wav")).unwrap();
+    fs::File::create(dir.join("a/not_flac.flac")).unwrap();
+
+    let mut results: Vec<ScanResult> = directory(&dir, ScanOptions::default()).collect();
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+    fs::remove_dir_all(&dir).unwrap();
+
+    // The nested file is found regardless of the case of its extension, the
+    // file without `.flac` extension is ignored, and the empty file fails
+    // without affecting the others.
+    let paths: Vec<PathBuf> = results.iter().map(|r| r.path.clone()).collect();
+    assert_eq!(paths, vec![dir.join("a/b/pop.FLAC"),
+                           dir.join("a/not_flac.flac"),
+                           dir.join("short.flac")]);
+    assert!(results[0].result.is_ok());
+    assert!(results[1].result.is_err());
+    assert!(results[2].result.is_ok());
+}
+
+#[test]
+fn verify_directory_of_empty_tree_is_empty() {
+    use std::fs;
+
+    let dir = make_test_dir("claxon-scan-empty");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    let count = directory(&dir, ScanOptions::default()).count();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn verify_file_respects_metadata_limits() {
+    let mut options = ScanOptions::default();
+    options.tags.push("artist".to_string());
+    options.metadata_limits.max_blocks = 1;
+    match file("testsamples/short.flac", &options) {
+        Err(Error::LimitExceeded { .. }) => {}
+        other => panic!("expected LimitExceeded, got {:?}", other),
+    }
+
+    // The file has three metadata blocks.
+    options.metadata_limits.max_blocks = 3;
+    assert!(file("testsamples/short.flac", &options).is_ok());
+}
@@ -0,0 +1,313 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `split` module cuts a stream into standalone FLAC files.
+//!
+//! Splitting happens at sample accuracy, without re-encoding the audio where
+//! possible. Frames that lie entirely inside a range are copied as they are,
+//! only their header is rewritten. Frames at the boundary of a range are
+//! trimmed, and are written with verbatim subframes, because Claxon has no
+//! encoder. The outputs use a variable block size, so trimmed frames can be
+//! mixed with copied ones.
+
+use std::cmp;
+use std::io;
+use std::ops::Range;
+
+use error::Result;
+use frame::{self, Block, FrameSpec};
+use input::{BufferedReader, ReadBytes};
+use metadata::{self, MetadataBlock, StreamInfo, VorbisComment};
+use {FlacReader, FlacReaderState};
+
+/// Wraps a reader and stores the bytes read from it.
+struct RecordingReader<'r, R: 'r + ReadBytes> {
+    inner: &'r mut R,
+    bytes: Vec<u8>,
+}
+
+impl<'r, R: ReadBytes> ReadBytes for RecordingReader<'r, R> {
+    #[inline(always)]
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let byte = try!(self.inner.read_u8());
+        self.bytes.push(byte);
+        Ok(byte)
+    }
+
+    fn read_u8_or_eof(&mut self) -> io::Result<Option<u8>> {
+        let byte = try!(self.inner.read_u8_or_eof());
+        if let Some(b) = byte {
+            self.bytes.push(b);
+        }
+        Ok(byte)
+    }
+
+    fn read_into(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        try!(self.inner.read_into(buffer));
+        self.bytes.extend_from_slice(buffer);
+        Ok(())
+    }
+
+    fn skip(&mut self, amount: u32) -> io::Result<()> {
+        for _ in 0..amount {
+            try!(self.read_u8());
+        }
+        Ok(())
+    }
+}
+
+/// The output for a single range, and the statistics for its streaminfo.
+struct Segment<W: io::Write + io::Seek> {
+    output: W,
+    /// The position in the output where the streaminfo block header starts.
+    streaminfo_pos: u64,
+    streaminfo: StreamInfo,
+    has_vorbis_comment: bool,
+    /// The number of samples per channel written so far.
+    samples: u64,
+    /// The smallest block size, excluding the most recent frame.
+    min_block_size: Option<u16>,
+    max_block_size: u16,
+    last_block_size: Option<u16>,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    /// Trimmed samples per channel that have not been written yet.
+    pending: Vec<Vec<i32>>,
+}
+
+impl<W: io::Write + io::Seek> Segment<W> {
+    fn new(mut output: W,
+           streaminfo: &StreamInfo,
+           vorbis_comment: Option<&VorbisComment>)
+           -> Result<Segment<W>> {
+        let start = try!(output.seek(io::SeekFrom::Current(0)));
+        try!(output.write_all(b"fLaC"));
+
+        // The streaminfo is written again once the frames are known, it has
+        // a fixed size, so it can be overwritten in place.
+        let streaminfo_block = MetadataBlock::StreamInfo(*streaminfo);
+        let is_last = vorbis_comment.is_none();
+        try!(metadata::write_metadata_block(&mut output, is_last, &streaminfo_block));
+        if let Some(vc) = vorbis_comment {
+            let vc_block = MetadataBlock::VorbisComment(vc.clone());
+            try!(metadata::write_metadata_block(&mut output, true, &vc_block));
+        }
+
+        Ok(Segment {
+            output: output,
+            streaminfo_pos: start + 4,
+            streaminfo: *streaminfo,
+            has_vorbis_comment: vorbis_comment.is_some(),
+            samples: 0,
+            min_block_size: None,
+            max_block_size: 0,
+            last_block_size: None,
+            min_frame_size: u32::max_value(),
+            max_frame_size: 0,
+            pending: (0..streaminfo.channels).map(|_| Vec::new()).collect(),
+        })
+    }
+
+    /// Adds samples `from..to` of the block, `frame_bytes` is its encoded frame.
+    fn push(&mut self, block: &Block, frame_bytes: &[u8], from: u32, to: u32) -> Result<()> {
+        let is_whole_frame = from == 0 && to == block.duration();
+        if is_whole_frame && self.pending[0].is_empty() {
+            let bytes = try!(frame::renumber_frame(frame_bytes, self.samples));
+            return self.write_frame_bytes(&bytes, block.duration() as u16);
+        }
+
+        // Keep the block size within what a frame header can express.
+        if self.pending[0].len() + (to - from) as usize > 0xffff {
+            try!(self.flush());
+        }
+        for (ch, pending) in self.pending.iter_mut().enumerate() {
+            pending.extend_from_slice(&block.channel(ch as u32)[from as usize..to as usize]);
+        }
+
+        // A frame of fewer than 16 samples is only valid as the final frame,
+        // so a short head is merged with the next frame. If there is no next
+        // frame, `finish()` writes it.
+        if self.pending[0].len() >= 16 {
+            try!(self.flush());
+        }
+        Ok(())
+    }
+
+    /// Writes the pending samples as a frame with verbatim subframes.
+    fn flush(&mut self) -> Result<()> {
+        if self.pending[0].is_empty() {
+            return Ok(())
+        }
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: self.samples,
+            sample_rate: None,
+            bits_per_sample: self.streaminfo.bits_per_sample,
+        };
+        let mut bytes = Vec::new();
+        {
+            let channels: Vec<&[i32]> = self.pending.iter().map(|ch| &ch[..]).collect();
+            try!(frame::write_frame(&spec, &channels, &mut bytes));
+        }
+        let block_size = self.pending[0].len() as u16;
+        for pending in self.pending.iter_mut() {
+            pending.clear();
+        }
+        self.write_frame_bytes(&bytes, block_size)
+    }
+
+    fn write_frame_bytes(&mut self, bytes: &[u8], block_size: u16) -> Result<()> {
+        try!(self.output.write_all(bytes));
+        if let Some(last) = self.last_block_size {
+            self.min_block_size = Some(cmp::min(self.min_block_size.unwrap_or(last), last));
+        }
+        self.last_block_size = Some(block_size);
+        self.max_block_size = cmp::max(self.max_block_size, block_size);
+        self.min_frame_size = cmp::min(self.min_frame_size, bytes.len() as u32);
+        self.max_frame_size = cmp::max(self.max_frame_size, bytes.len() as u32);
+        self.samples += block_size as u64;
+        Ok(())
+    }
+
+    /// Writes the remaining samples and the final streaminfo.
+    fn finish(mut self) -> Result<W> {
+        try!(self.flush());
+
+        let mut streaminfo = self.streaminfo;
+        if let Some(last) = self.last_block_size {
+            // The minimum block size excludes the final frame, unless it is the
+            // only one. Readers reject a minimum below 16, which occurs only
+            // for ranges that are shorter than that, so it is rounded up.
+            let min = cmp::max(self.min_block_size.unwrap_or(last), 16);
+            streaminfo.min_block_size = min;
+            streaminfo.max_block_size = cmp::max(self.max_block_size, min);
+            streaminfo.min_frame_size = Some(self.min_frame_size);
+            streaminfo.max_frame_size = Some(self.max_frame_size);
+        } else {
+            streaminfo.min_frame_size = None;
+            streaminfo.max_frame_size = None;
+        }
+        streaminfo.samples = Some(self.samples);
+        // Computing the MD5 signature would require an MD5 implementation,
+        // all zeros indicates that it is unknown.
+        streaminfo.md5sum = [0; 16];
+
+        let end = try!(self.output.seek(io::SeekFrom::Current(0)));
+        try!(self.output.seek(io::SeekFrom::Start(self.streaminfo_pos)));
+        let block = MetadataBlock::StreamInfo(streaminfo);
+        let is_last = !self.has_vorbis_comment;
+        try!(metadata::write_metadata_block(&mut self.output, is_last, &block));
+        try!(self.output.seek(io::SeekFrom::Start(end)));
+        Ok(self.output)
+    }
+}
+
+/// Splits a stream into standalone FLAC files, one for every range of samples.
+///
+/// The ranges are in samples per channel, relative to the start of the
+/// stream; use `frame::duration_to_samples()` to convert times. For every
+/// range, `sink_factory` is called with the index of the range, to create the
+/// output. The outputs are returned in the same order. Every output contains a
+/// streaminfo block and the Vorbis comment of the input, if it has one, and
+/// the samples of the range. Ranges that extend past the end of the stream
+/// contain fewer samples, possibly none.
+///
+/// The MD5 signature in the streaminfo of the outputs is not computed, it is
+/// set to all zeros, which indicates that it is unknown.
+///
+/// # Panics
+///
+/// Panics if a range is empty, or if the ranges are not in increasing order
+/// or overlap.
+pub fn by_ranges<R, W, F>(input: R, ranges: &[Range<u64>], mut sink_factory: F) -> Result<Vec<W>>
+    where R: io::Read,
+          W: io::Write + io::Seek,
+          F: FnMut(usize) -> io::Result<W>
+{
+    for (i, range) in ranges.iter().enumerate() {
+        assert!(range.start < range.end, "ranges must not be empty");
+        if i > 0 {
+            assert!(ranges[i - 1].end <= range.start,
+                    "ranges must be in order and must not overlap");
+        }
+    }
+
+    let mut reader = try!(FlacReader::new(input));
+    let streaminfo = reader.streaminfo;
+    let settings = reader.frame_settings;
+    let vorbis_comment = reader.vorbis_comment.take();
+    let input: &mut BufferedReader<R> = match reader.input {
+        FlacReaderState::Full(ref mut inp) => inp,
+        FlacReaderState::MetadataOnly(..) => unreachable!(),
+    };
+    let mut recorder = RecordingReader {
+        inner: input,
+        bytes: Vec::new(),
+    };
+
+    let mut outputs = Vec::with_capacity(ranges.len());
+    let mut current: Option<Segment<W>> = None;
+    let mut buffer = Vec::new();
+
+    while outputs.len() < ranges.len() {
+        recorder.bytes.clear();
+        let block = {
+            // The frame reader does not carry state between frames that
+            // matters here, so a new one is used for every frame, which gives
+            // access to the recorded bytes in between.
+            let mut frame_reader = settings.new_frame_reader(&mut recorder);
+            match try!(frame_reader.read_next_or_eof(buffer)) {
+                Some(block) => block,
+                None => break,
+            }
+        };
+        let frame_start = block.time();
+        let frame_end = frame_start + block.duration() as u64;
+
+        // A frame can overlap with multiple ranges.
+        while outputs.len() < ranges.len() {
+            let range = &ranges[outputs.len()];
+            if frame_end <= range.start {
+                break
+            }
+            if current.is_none() {
+                let output = try!(sink_factory(outputs.len()));
+                current = Some(try!(Segment::new(output, &streaminfo, vorbis_comment.as_ref())));
+            }
+
+            // A range may also lie entirely before the frame, if the stream
+            // does not start at sample zero.
+            if range.end > frame_start {
+                let from = cmp::max(range.start, frame_start) - frame_start;
+                let to = cmp::min(range.end, frame_end) - frame_start;
+                let segment = current.as_mut().unwrap();
+                try!(segment.push(&block, &recorder.bytes, from as u32, to as u32));
+            }
+
+            if range.end > frame_end {
+                break
+            }
+            outputs.push(try!(current.take().unwrap().finish()));
+        }
+
+        buffer = block.into_buffer();
+    }
+
+    // The stream ended before all ranges were complete.
+    while outputs.len() < ranges.len() {
+        let segment = match current.take() {
+            Some(segment) => segment,
+            None => {
+                let output = try!(sink_factory(outputs.len()));
+                try!(Segment::new(output, &streaminfo, vorbis_comment.as_ref()))
+            }
+        };
+        outputs.push(try!(segment.finish()));
+    }
+
+    Ok(outputs)
+}
@@ -8,9 +8,14 @@
 //! The `subframe` module deals with subframes that make up a frame of the FLAC stream.
 
 use std::cmp;
+use std::i32;
 use std::num;
-use error::{Error, Result, fmt_err};
+use dispatch;
+use error;
+use error::{Error, Result, UnsupportedFeature, fmt_err};
+use explain::{Element, SubframeKind, Tap};
 use input::{Bitstream, ReadBytes};
+use rice;
 
 #[derive(Clone, Copy, Debug)]
 enum SubframeType {
@@ -44,7 +49,7 @@ fn read_subframe_header<R: ReadBytes>(input: &mut Bitstream<R>) -> Result<Subfra
         // implemented.
         n if (n & 0b111_110 == 0b000_010) || (n & 0b111_100 == 0b000_100) ||
              (n & 0b110_000 == 0b010_000) => {
-            return fmt_err("invalid subframe header, encountered reserved value");
+            return fmt_err(error::RESERVED_SUBFRAME_HEADER);
         }
 
         n if n & 0b111_000 == 0b001_000 => {
@@ -52,7 +57,7 @@ fn read_subframe_header<R: ReadBytes>(input: &mut Bitstream<R>) -> Result<Subfra
 
             // A fixed frame has order up to 4, other bit patterns are reserved.
             if order > 4 {
-                return fmt_err("invalid subframe header, encountered reserved value");
+                return fmt_err(error::RESERVED_SUBFRAME_HEADER);
             }
 
             SubframeType::Fixed(order)
@@ -139,45 +144,6 @@ fn verify_extend_sign_u32() {
     assert_eq!(-7061, extend_sign_u32(124011, 17));
 }
 
-/// Decodes a signed number from Rice coding to the two's complement.
-///
-/// The Rice coding used by FLAC operates on unsigned integers, but the
-/// residual is signed. The mapping is done as follows:
-///
-///  0 -> 0
-/// -1 -> 1
-///  1 -> 2
-/// -2 -> 3
-///  2 -> 4
-///  etc.
-///
-/// This function takes the unsigned value and converts it into a signed
-/// number.
-#[inline(always)]
-fn rice_to_signed(val: u32) -> i32 {
-    // The following bit-level hackery compiles to only four instructions on
-    // x64. It is equivalent to the following code:
-    //
-    //   if val & 1 == 1 {
-    //       -1 - (val / 2) as i32
-    //   } else {
-    //       (val / 2) as i32
-    //   }
-    //
-    let half = (val >> 1) as i32;
-    let extended_bit_0 = ((val << 31) as i32) >> 31;
-    half ^ extended_bit_0
-}
-
-#[test]
-fn verify_rice_to_signed() {
-    assert_eq!(rice_to_signed(0), 0);
-    assert_eq!(rice_to_signed(1), -1);
-    assert_eq!(rice_to_signed(2), 1);
-    assert_eq!(rice_to_signed(3), -2);
-    assert_eq!(rice_to_signed(4), 2);
-}
-
 /// Decodes a subframe into the provided block-size buffer.
 ///
 /// It is assumed that the length of the buffer is the block size.
@@ -185,6 +151,43 @@ pub fn decode<R: ReadBytes>(input: &mut Bitstream<R>,
                             bps: u32,
                             buffer: &mut [i32])
                             -> Result<()> {
+    try!(decode_with_width(input, bps, buffer));
+    Ok(())
+}
+
+/// Decodes a subframe, and returns the number of bits per sample it is coded with.
+///
+/// This is `bps` minus the number of wasted bits per sample in the subframe.
+pub fn decode_with_width<R: ReadBytes>(input: &mut Bitstream<R>,
+                                       bps: u32,
+                                       buffer: &mut [i32])
+                                       -> Result<u32> {
+    let (sf_bps, wasted_bits) = try!(decode_unshifted(input, bps, buffer));
+
+    // For a valid FLAC file, this shift does not overflow. For an invalid file
+    // it might, and then we decode garbage, but we don't crash the program in
+    // debug mode due to shift overflow.
+    if wasted_bits > 0 {
+        for s in buffer {
+            *s = s.wrapping_shl(wasted_bits);
+        }
+    }
+
+    Ok(sf_bps)
+}
+
+/// Decodes a subframe, but leaves shifting in the wasted bits to the caller.
+///
+/// Returns the bits per sample that the subframe is coded with, and the number
+/// of wasted bits by which the samples must still be shifted left. This allows
+/// the frame decoder to fold the shift into inter-channel decorrelation, which
+/// touches every sample anyway, instead of making a separate pass. With the
+/// `checked-arith` feature, the shift is checked and applied here, and the
+/// returned number of wasted bits is 0.
+pub fn decode_unshifted<R: ReadBytes>(input: &mut Bitstream<R>,
+                                      bps: u32,
+                                      buffer: &mut [i32])
+                                      -> Result<(u32, u32)> {
     // The sample type i32 should be wide enough to accomodate for all bits of
     // the stream, but this can be verified at a higher level than here. Still,
     // it is a good idea to make the assumption explicit. FLAC supports up to
@@ -211,17 +214,171 @@ pub fn decode<R: ReadBytes>(input: &mut Bitstream<R>,
     }
 
     // Finally, everything must be shifted by 'wasted bits per sample' to
-    // the left. Note: it might be better performance-wise to do this on
-    // the fly while decoding. That could be done if this is a bottleneck.
-    if header.wasted_bits_per_sample > 0 {
-        debug_assert!(header.wasted_bits_per_sample <= 31,
-                      "Cannot shift by more than the sample width.");
+    // the left. Unless the arithmetic is checked, that is up to the caller.
+    debug_assert!(header.wasted_bits_per_sample <= 31,
+                  "Cannot shift by more than the sample width.");
+    if cfg!(feature = "checked-arith") && header.wasted_bits_per_sample > 0 {
+        try!(check_wasted_bits_shift(header.wasted_bits_per_sample, buffer));
         for s in buffer {
-            // For a valid FLAC file, this shift does not overflow. For an
-            // invalid file it might, and then we decode garbage, but we don't
-            // crash the program in debug mode due to shift overflow.
-            *s = s.wrapping_shl(header.wasted_bits_per_sample);
+            *s <<= header.wasted_bits_per_sample;
         }
+        return Ok((sf_bps, 0))
+    }
+
+    Ok((sf_bps, header.wasted_bits_per_sample))
+}
+
+/// Checks that shifting the samples left does not lose bits, for `checked-arith`.
+fn check_wasted_bits_shift(wasted_bits: u32, buffer: &[i32]) -> Result<()> {
+    for &s in buffer {
+        if s.wrapping_shl(wasted_bits) >> wasted_bits != s {
+            return fmt_err("invalid subframe, sample overflows with wasted bits");
+        }
+    }
+    Ok(())
+}
+
+/// Converts a predicted sample to `i32`, for `checked-arith`.
+#[inline(always)]
+fn checked_sample(sample: i64) -> Result<i32> {
+    if sample < i32::MIN as i64 || sample > i32::MAX as i64 {
+        fmt_err("invalid subframe, predicted sample overflows")
+    } else {
+        Ok(sample as i32)
+    }
+}
+
+/// Reads past a subframe without decoding its samples.
+///
+/// The subframe is parsed only as far as needed to find where it ends: the
+/// Rice codes of the residual are read but not stored, and no prediction
+/// happens. This saves most of the work for channels that are not needed,
+/// and no buffer is required. Like `decode_with_width()`, returns the bits
+/// per sample that the subframe is coded with.
+pub fn skip<R: ReadBytes>(input: &mut Bitstream<R>,
+                          bps: u32,
+                          block_size: u16)
+                          -> Result<u32> {
+    walk(input, bps, block_size, &mut ())
+}
+
+/// Reads past a subframe like `skip()`, and reports its elements to the tap.
+///
+/// The elements are those of `explain::Element`, starting with the subframe
+/// header. Samples and coefficients are reported, residuals only per partition.
+pub fn walk<R: ReadBytes, T: Tap>(input: &mut Bitstream<R>,
+                                  bps: u32,
+                                  block_size: u16,
+                                  tap: &mut T)
+                                  -> Result<u32> {
+    debug_assert!(32 >= bps);
+
+    let header = try!(read_subframe_header(input));
+
+    if header.wasted_bits_per_sample >= bps {
+        return fmt_err("subframe has no non-wasted bits");
+    }
+
+    let sf_bps = bps - header.wasted_bits_per_sample;
+
+    tap.element(Element::SubframeHeader {
+        kind: match header.sf_type {
+            SubframeType::Constant => SubframeKind::Constant,
+            SubframeType::Verbatim => SubframeKind::Verbatim,
+            SubframeType::Fixed(ord) => SubframeKind::Fixed { order: ord },
+            SubframeType::Lpc(ord) => SubframeKind::Lpc { order: ord },
+        },
+        wasted_bits: header.wasted_bits_per_sample,
+        bits_per_sample: sf_bps,
+    });
+
+    match header.sf_type {
+        SubframeType::Constant => {
+            try!(walk_samples(input, sf_bps, 1, tap));
+        }
+        SubframeType::Verbatim => {
+            try!(walk_samples(input, sf_bps, block_size, tap));
+        }
+        SubframeType::Fixed(ord) => {
+            if block_size < ord as u16 {
+                return fmt_err("invalid fixed subframe, order is larger than block size")
+            }
+            try!(walk_samples(input, sf_bps, ord as u16, tap));
+            try!(walk_residual(input, block_size, ord as u16, tap));
+        }
+        SubframeType::Lpc(ord) => {
+            if block_size < ord as u16 {
+                return fmt_err("invalid LPC subframe, lpc order is larger than block size")
+            }
+            try!(walk_samples(input, sf_bps, ord as u16, tap));
+            let qlp_precision = try!(input.read_leq_u8(4)) as u32 + 1;
+            if qlp_precision - 1 == 0b1111 {
+                return fmt_err("invalid subframe, qlp precision value invalid");
+            }
+            // The shift does not affect the size of the subframe, so unlike in
+            // `decode_lpc()`, a negative shift is not an error here.
+            let qlp_shift = extend_sign_u16(try!(input.read_leq_u16(5)), 5);
+            tap.element(Element::LpcParameters {
+                precision: qlp_precision,
+                shift: qlp_shift as i32,
+            });
+            for _ in 0..ord {
+                let coef_unsig = try!(input.read_leq_u16(qlp_precision));
+                let coef = extend_sign_u16(coef_unsig, qlp_precision);
+                tap.element(Element::LpcCoefficient { value: coef as i32 });
+            }
+            try!(walk_residual(input, block_size, ord as u16, tap));
+        }
+    }
+
+    Ok(sf_bps)
+}
+
+/// Reads `n` unencoded samples, and reports them to the tap.
+fn walk_samples<R: ReadBytes, T: Tap>(input: &mut Bitstream<R>,
+                                      bps: u32,
+                                      n: u16,
+                                      tap: &mut T)
+                                      -> Result<()> {
+    for _ in 0..n {
+        let sample = try!(input.read_leq_u32(bps));
+        tap.element(Element::Sample { value: extend_sign_u32(sample, bps) });
+    }
+    Ok(())
+}
+
+/// Builds the error for a partition order that does not fit the block.
+///
+/// The sample number of the frame is not known at this level, it is filled in
+/// by the frame decoder.
+#[cold]
+fn invalid_partition_order(block_size: u16, order: u8) -> Error {
+    Error::InvalidPartitionOrder {
+        block_size: block_size,
+        order: order,
+        frame_sample: None,
+    }
+}
+
+/// Checks a partition order against the block size and the number of warm-up samples.
+///
+/// Both are known from the frame and subframe headers, so a corrupt order is
+/// reported before any partition is decoded.
+fn check_partition_order(block_size: u16, order: u8, n_warm_up: u16) -> Result<()> {
+    // The partitions together must fill the block, so the block size must be a
+    // multiple of 2^order. Because that is a power of two, the largest valid
+    // order for this block size is its number of trailing zeros. Check this
+    // before doing anything with the order, so a corrupt order never reaches
+    // the shift below.
+    if order as u32 > block_size.trailing_zeros() {
+        return Err(invalid_partition_order(block_size, order));
+    }
+
+    // The partition size must be at least as big as the number of warm-up
+    // samples, otherwise the size of the first partition is negative. This too
+    // means that the partition order is too high for the block.
+    if n_warm_up > block_size >> order {
+        return Err(invalid_partition_order(block_size, order));
     }
 
     Ok(())
@@ -233,48 +390,48 @@ enum RicePartitionType {
     Rice2,
 }
 
-fn decode_residual<R: ReadBytes>(input: &mut Bitstream<R>,
-                                 block_size: u16,
-                                 buffer: &mut [i32])
-                                 -> Result<()> {
+/// Reads the residual coding method and partition order, and checks them.
+///
+/// Returns the partition type, the number of partitions, and the number of
+/// samples per partition, of which the first partition has `n_warm_up` less.
+fn read_residual_header<R: ReadBytes>(input: &mut Bitstream<R>,
+                                      block_size: u16,
+                                      n_warm_up: u16)
+                                      -> Result<(RicePartitionType, u32, u16)> {
     // Residual starts with two bits of coding method.
     let partition_type = match try!(input.read_leq_u8(2)) {
         0b00 => RicePartitionType::Rice,
         0b01 => RicePartitionType::Rice2,
         // 10 and 11 are reserved.
-        _ => return fmt_err("invalid residual, encountered reserved value"),
+        _ => return fmt_err(error::RESERVED_RESIDUAL),
     };
 
-    // Next are 4 bits partition order.
+    // Next are 4 bits partition order. Validate it against the block size and
+    // the predictor order straight away, before any partition is read.
     let order = try!(input.read_leq_u8(4));
+    try!(check_partition_order(block_size, order, n_warm_up));
 
     // There are 2^order partitions. Note: the specification states a 4-bit
-    // partition order, so the order is at most 31, so there could be 2^31
-    // partitions, but the block size is a 16-bit number, so there are at
-    // most 2^16 - 1 samples in the block. No values have been marked as
-    // invalid by the specification though.
+    // partition order, so the order is at most 15, so there could be 2^15
+    // partitions. The block size is a 16-bit number, and the check above
+    // ensures that there are no more partitions than samples.
     let n_partitions = 1u32 << order;
     let n_samples_per_partition = block_size >> order;
 
-    // The partitions together must fill the block. If the block size is not a
-    // multiple of 2^order; if we shifted off some bits, then we would not fill
-    // the entire block. Such a partition order is invalid for this block size.
-    if block_size & (n_partitions - 1) as u16 != 0 {
-        return fmt_err("invalid partition order")
-    }
-
-    // NOTE: the check above checks that block_size is a multiple of n_partitions
-    // (this works because n_partitions is a power of 2). The check below is
-    // equivalent but more expensive.
+    // NOTE: the check above ensures that block_size is a multiple of
+    // n_partitions. The check below is equivalent but more expensive.
     debug_assert_eq!(n_partitions * n_samples_per_partition as u32, block_size as u32);
 
-    let n_warm_up = block_size - buffer.len() as u16;
+    Ok((partition_type, n_partitions, n_samples_per_partition))
+}
 
-    // The partition size must be at least as big as the number of warm-up
-    // samples, otherwise the size of the first partition is negative.
-    if n_warm_up > n_samples_per_partition {
-        return fmt_err("invalid residual");
-    }
+fn decode_residual<R: ReadBytes>(input: &mut Bitstream<R>,
+                                 block_size: u16,
+                                 buffer: &mut [i32])
+                                 -> Result<()> {
+    let n_warm_up = block_size - buffer.len() as u16;
+    let (partition_type, n_partitions, n_samples_per_partition) =
+        try!(read_residual_header(input, block_size, n_warm_up));
 
     // Finally decode the partitions themselves.
     match partition_type {
@@ -303,6 +460,114 @@ fn decode_residual<R: ReadBytes>(input: &mut Bitstream<R>,
     Ok(())
 }
 
+/// Reads past a residual without storing it, see `walk()`.
+fn walk_residual<R: ReadBytes, T: Tap>(input: &mut Bitstream<R>,
+                                       block_size: u16,
+                                       n_warm_up: u16,
+                                       tap: &mut T)
+                                       -> Result<()> {
+    let (partition_type, n_partitions, n_samples_per_partition) =
+        try!(read_residual_header(input, block_size, n_warm_up));
+
+    let (param_bits, escape_code) = match partition_type {
+        RicePartitionType::Rice => (4, 0b1111),
+        RicePartitionType::Rice2 => (5, 0b11111),
+    };
+    tap.element(Element::ResidualHeader {
+        parameter_bits: param_bits,
+        partition_order: n_partitions.trailing_zeros() as u8,
+    });
+
+    let mut len = n_samples_per_partition - n_warm_up;
+    for _ in 0..n_partitions {
+        let rice_param = try!(input.read_leq_u8(param_bits)) as u32;
+        if rice_param == escape_code {
+            return Err(Error::Unsupported(UnsupportedFeature::EscapedPartition))
+        }
+        tap.element(Element::RicePartition {
+            parameter: rice_param,
+            samples: len,
+        });
+        try!(rice::skip(input, rice_param, len as u32));
+        len = n_samples_per_partition;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn verify_decode_residual_rejects_invalid_partition_order() {
+    use std::io;
+    use input::BufferedReader;
+
+    // Rice coding method (00), partition order 2 (0010). A block size of 6 is
+    // not a multiple of 4 partitions. The input ends after the residual header,
+    // so this also checks that the order is rejected before reading partitions.
+    let data = io::Cursor::new(vec![0b0000_1000]);
+    let mut bits = Bitstream::new(BufferedReader::new(data));
+    let mut buffer = [0i32; 6];
+    assert_eq!(decode_residual(&mut bits, 6, &mut buffer).err().unwrap(),
+               Error::InvalidPartitionOrder {
+                   block_size: 6,
+                   order: 2,
+                   frame_sample: None,
+               });
+
+    // Partition order 1 for a block of 4 samples, of which 3 are warm-up
+    // samples: the first partition would have a negative size.
+    let data = io::Cursor::new(vec![0b0000_0100]);
+    let mut bits = Bitstream::new(BufferedReader::new(data));
+    let mut buffer = [0i32; 1];
+    assert_eq!(decode_residual(&mut bits, 4, &mut buffer).err().unwrap(),
+               Error::InvalidPartitionOrder {
+                   block_size: 4,
+                   order: 1,
+                   frame_sample: None,
+               });
+}
+
+#[test]
+fn verify_check_partition_order() {
+    // Order 4 splits a block of 4096 samples into 16 partitions of 256.
+    assert!(check_partition_order(4096, 4, 32).is_ok());
+    assert!(check_partition_order(4096, 12, 1).is_ok());
+    assert!(check_partition_order(4096, 12, 2).is_err());
+    assert!(check_partition_order(4096, 13, 0).is_err());
+    // An odd block size only allows a single partition.
+    assert!(check_partition_order(4095, 0, 32).is_ok());
+    assert!(check_partition_order(4095, 1, 0).is_err());
+    assert!(check_partition_order(1, 0, 1).is_ok());
+    assert_eq!(check_partition_order(192, 7, 0).err().unwrap(),
+               Error::InvalidPartitionOrder {
+                   block_size: 192,
+                   order: 7,
+                   frame_sample: None,
+               });
+}
+
+#[test]
+fn verify_decode_residual_rejects_long_rice_quotient() {
+    use std::io;
+    use input::BufferedReader;
+
+    // Rice coding method (00), partition order 0 (0000), Rice parameter 0
+    // (0000), followed by a quotient of 20 (20 zeros and a one).
+    let data = io::Cursor::new(vec![0, 0, 0, 0b0000_0010]);
+    let mut bits = Bitstream::new(BufferedReader::new(data.clone()));
+    let mut buffer = [0i32; 1];
+    assert!(decode_residual(&mut bits, 1, &mut buffer).is_ok());
+    assert_eq!(buffer[0], rice::rice_to_signed(20));
+
+    let mut bits = Bitstream::new(BufferedReader::new(data));
+    bits.set_max_unary(10);
+    match decode_residual(&mut bits, 1, &mut buffer) {
+        Err(Error::LimitExceeded { limit: "Rice quotient", value, max: 10 }) => {
+            assert!(value > 10);
+        }
+        other => panic!("expected Rice quotient limit error, got {:?}", other),
+    }
+}
+
 // Performance note: all Rice partitions in real-world FLAC files are Rice
 // partitions, not Rice2 partitions. Therefore it makes sense to inline this
 // function into decode_residual.
@@ -315,39 +580,10 @@ fn decode_rice_partition<R: ReadBytes>(input: &mut Bitstream<R>,
 
     // All ones is an escape code that indicates unencoded binary.
     if rice_param == 0b1111 {
-        return Err(Error::Unsupported("unencoded binary is not yet implemented"))
-    }
-
-    // About the decoding below: the first part of the sample is the quotient,
-    // unary encoded. This means that there are q zeros, and then a one.
-    //
-    // The reference decoder supports sample widths up to 24 bits, so with
-    // the additional bytes for difference in channels and for prediction, a
-    // sample fits in 26 bits. The Rice parameter could be as little as 1,
-    // so the quotient can potentially be very large. However, in practice
-    // it is rarely greater than 5. Values as large as 75 still occur though.
-    //
-    // Next up is the remainder in rice_param bits. Depending on the number of
-    // bits, at most two or three bytes need to be read, so the code below is
-    // split into two cases to allow a more efficient reading function to be
-    // used when possible. About 45% of the time, rice_param is less than 9
-    // (measured from real-world FLAC files).
-
-    if rice_param <= 8 {
-        for sample in buffer.iter_mut() {
-            let q = try!(input.read_unary());
-            let r = try!(input.read_leq_u8(rice_param)) as u32;
-            *sample = rice_to_signed((q << rice_param) | r);
-        }
-    } else {
-        for sample in buffer.iter_mut() {
-            let q = try!(input.read_unary());
-            let r = try!(input.read_gt_u8_leq_u16(rice_param));
-            *sample = rice_to_signed((q << rice_param) | r);
-        }
+        return Err(Error::Unsupported(UnsupportedFeature::EscapedPartition))
     }
 
-    Ok(())
+    rice::decode(input, rice_param, buffer)
 }
 
 // Performance note: a Rice2 partition is extremely uncommon, I haven’t seen a
@@ -363,20 +599,10 @@ fn decode_rice2_partition<R: ReadBytes>(input: &mut Bitstream<R>,
 
     // All ones is an escape code that indicates unencoded binary.
     if rice_param == 0b11111 {
-        return Err(Error::Unsupported("unencoded binary is not yet implemented"))
-    }
-
-    for sample in buffer.iter_mut() {
-        // First part of the sample is the quotient, unary encoded.
-        let q = try!(input.read_unary());
-
-        // Next is the remainder, in rice_param bits. Because at this
-        // point rice_param is at most 30, we can safely read into a u32.
-        let r = try!(input.read_leq_u32(rice_param));
-        *sample = rice_to_signed((q << rice_param) | r);
+        return Err(Error::Unsupported(UnsupportedFeature::EscapedPartition))
     }
 
-    Ok(())
+    rice::decode(input, rice_param, buffer)
 }
 
 fn decode_constant<R: ReadBytes>(input: &mut Bitstream<R>,
@@ -467,7 +693,18 @@ fn predict_fixed(order: u32, buffer: &mut [i32]) -> Result<()> {
 
         // The delta is stored, so the sample is the prediction + delta.
         let delta = window[coefficients.len()];
-        window[coefficients.len()] = prediction.wrapping_add(delta);
+
+        if cfg!(feature = "checked-arith") {
+            // The products of at most 32-bit samples with coefficients of at
+            // most 6 fit in 35 bits, so an i64 sum of five does not overflow.
+            let prediction = coefficients.iter()
+                                         .zip(window.iter())
+                                         .map(|(&c, &s)| c as i64 * s as i64)
+                                         .fold(0, |a, x| a + x);
+            window[coefficients.len()] = try!(checked_sample(prediction + delta as i64));
+        } else {
+            window[coefficients.len()] = prediction.wrapping_add(delta);
+        }
     }
 
     Ok(())
@@ -521,7 +758,10 @@ fn decode_fixed<R: ReadBytes>(input: &mut Bitstream<R>,
 /// files that occur in the wild are subset-compliant files, which have an order
 /// of at most 12, so it makes sense to optimize for this. A simpler (but
 /// slower) fallback is implemented in `predict_lpc_high_order`.
-fn predict_lpc_low_order(
+///
+/// This is the scalar kernel, decoding calls the one selected by `dispatch`.
+/// `raw_coefficients` holds at most 12 coefficients, in reverse order.
+pub fn predict_lpc_low_order(
     raw_coefficients: &[i16],
     qlp_shift: i16,
     buffer: &mut [i32],
@@ -582,6 +822,55 @@ fn predict_lpc_low_order(
     }
 }
 
+/// Apply LPC prediction for subframes with LPC order of at most 12, on 32-bit targets.
+///
+/// This computes the same as `predict_lpc_low_order`, but it keeps the
+/// coefficients narrow, so every product is a 32x32 to 64-bit multiply that
+/// accumulates into a 64-bit sum. On cores such as ARMv7-M (Cortex-M3, M4 and
+/// M7), that is a single `SMLAL` instruction per coefficient. The 12 64-bit
+/// coefficients of `predict_lpc_low_order` vectorize well on 64-bit CPUs, but
+/// on a 32-bit core every 64x64-bit product takes three multiplies and two
+/// adds. ARMv6-M (Cortex-M0) lacks a long multiply, so there every product is
+/// a call into the compiler runtime, with either kernel.
+pub fn predict_lpc_low_order_mac32(
+    raw_coefficients: &[i16],
+    qlp_shift: i16,
+    buffer: &mut [i32],
+) {
+    debug_assert!(qlp_shift >= 0, "Right-shift by negative value is not allowed.");
+    debug_assert!(qlp_shift < 64, "Cannot shift by more than integer width.");
+    debug_assert!(raw_coefficients.len() <= 12, "Order must be at most 12.");
+
+    let order = raw_coefficients.len();
+    for i in order..buffer.len() {
+        let mut prediction = 0i64;
+        for (&c, &s) in raw_coefficients.iter().zip(&buffer[i - order..i]) {
+            prediction += c as i32 as i64 * s as i64;
+        }
+        let delta = buffer[i] as i64;
+        buffer[i] = ((prediction >> qlp_shift) + delta) as i32;
+    }
+}
+
+/// Apply LPC prediction of any order, failing on overflow, for `checked-arith`.
+fn predict_lpc_checked(coefficients: &[i16], qlp_shift: i16, buffer: &mut [i32]) -> Result<()> {
+    let order = coefficients.len();
+    debug_assert!(buffer.len() >= order, "Buffer must fit at least `order` warm-up samples.");
+
+    // A product of a 16-bit coefficient and a 32-bit sample fits in 48 bits,
+    // so the sum of at most 32 of them fits in an i64.
+    for i in order..buffer.len() {
+        let prediction = coefficients.iter()
+                                     .zip(&buffer[i - order..i])
+                                     .map(|(&c, &s)| c as i64 * s as i64)
+                                     .fold(0, |a, x| a + x) >> qlp_shift;
+        let delta = buffer[i] as i64;
+        buffer[i] = try!(checked_sample(prediction + delta));
+    }
+
+    Ok(())
+}
+
 /// Apply LPC prediction for non-subset subframes, with LPC order > 12.
 fn predict_lpc_high_order(
     coefficients: &[i16],
@@ -613,6 +902,20 @@ fn predict_lpc_high_order(
     }
 }
 
+#[test]
+fn verify_predict_lpc_low_order_mac32() {
+    let coefficients = [119, -255, 555, -836, 879, -1199, 1757, -75, 166, 121, -269, -75];
+    let input: Vec<i32> = (0..64).map(|i| (i * 7919 % 65536) - 32768).collect();
+    for order in 1..coefficients.len() + 1 {
+        let coefs = &coefficients[coefficients.len() - order..];
+        let mut expected = input.clone();
+        predict_lpc_low_order(coefs, 10, &mut expected);
+        let mut actual = input.clone();
+        predict_lpc_low_order_mac32(coefs, 10, &mut actual);
+        assert_eq!(actual, expected);
+    }
+}
+
 #[test]
 fn verify_predict_lpc() {
     // The following data is from an actual FLAC stream and has been verified
@@ -648,6 +951,30 @@ fn verify_predict_lpc() {
     ]);
 }
 
+#[test]
+fn verify_predict_checked() {
+    // The checked predictor agrees with the fast one for valid input.
+    let coefficients = [-75, 166,  121, -269, -75, -399, 1042];
+    let input = [-796, -547, -285, -32, 199, 443, 670, -2, -23, 14, 6, 3, -4, 12, -2, 10];
+    let mut expected = input;
+    predict_lpc_low_order(&coefficients, 9, &mut expected);
+    let mut actual = input;
+    predict_lpc_checked(&coefficients, 9, &mut actual).unwrap();
+    assert_eq!(actual, expected);
+
+    // But it reports overflow, where the fast one wraps.
+    let mut buffer = [i32::MAX, 1];
+    assert!(predict_lpc_checked(&[1], 0, &mut buffer).is_err());
+
+    let mut buffer = [i32::MAX, 1];
+    let result = predict_fixed(1, &mut buffer);
+    if cfg!(feature = "checked-arith") {
+        assert!(result.is_err());
+    } else {
+        assert_eq!(buffer[1], i32::MIN);
+    }
+}
+
 fn decode_lpc<R: ReadBytes>(input: &mut Bitstream<R>,
                             bps: u32,
                             order: u32,
@@ -685,9 +1012,7 @@ fn decode_lpc<R: ReadBytes>(input: &mut Bitstream<R>,
     // branch. If a real-world file ever hits this case, then we should consider
     // making two LPC predictors, one for positive, and one for negative qlp.
     if qlp_shift < 0 {
-        let msg = "a negative quantized linear predictor coefficient shift is \
-                   not supported, please file a bug.";
-        return Err(Error::Unsupported(msg))
+        return Err(Error::Unsupported(UnsupportedFeature::NegativeQlpShift))
     }
 
     // Finally, the coefficients themselves. The order is at most 32, so all
@@ -707,11 +1032,16 @@ fn decode_lpc<R: ReadBytes>(input: &mut Bitstream<R>,
                          buffer.len() as u16,
                          &mut buffer[order as usize..]));
 
+    if cfg!(feature = "checked-arith") {
+        return predict_lpc_checked(&coefficients[..order as usize], qlp_shift, buffer);
+    }
+
     // In "subset"-compliant files, the LPC order is at most 12. For LPC
     // prediction of such files we have a special fast path that takes advantage
     // of the low order. We can still decode non-subset file using a less
     // specialized implementation. Non-subset files are rare in the wild.
     if order <= 12 {
+        let predict_lpc_low_order = dispatch::kernels().predict_lpc_low_order;
         predict_lpc_low_order(&coefficients[..order as usize], qlp_shift, buffer);
     } else {
         predict_lpc_high_order(&coefficients[..order as usize], qlp_shift, buffer);
@@ -0,0 +1,512 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `symphonia` module plugs Claxon into Symphonia as a FLAC decoder and format reader.
+//!
+//! Symphonia splits media files into packets with its format readers, and
+//! decodes the packets with a decoder from its codec registry. For FLAC, every
+//! packet holds one frame. Registering `ClaxonDecoder` in a codec registry
+//! makes an application that is built on Symphonia decode FLAC with Claxon,
+//! while the rest of its pipeline stays the same, for instance to compare the
+//! output or speed of the two decoders. Registering `ClaxonFormatReader` in a
+//! probe makes Claxon split the file into packets as well, and seek in it.
+//! This module is available only with the `symphonia-core` feature enabled.
+
+extern crate symphonia_core;
+
+use std::io;
+
+use self::symphonia_core::audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef};
+use self::symphonia_core::audio::{Channels, Signal, SignalSpec};
+use self::symphonia_core::codecs::{CODEC_TYPE_FLAC, CodecDescriptor, CodecParameters};
+use self::symphonia_core::codecs::{Decoder, DecoderOptions, FinalizeResult};
+use self::symphonia_core::errors::{self, SeekErrorKind};
+use self::symphonia_core::formats::{Cue, FormatOptions, FormatReader, Packet};
+use self::symphonia_core::formats::{SeekMode, SeekTo, SeekedTo, Track};
+use self::symphonia_core::io::MediaSourceStream;
+use self::symphonia_core::meta::{Metadata, MetadataBuilder, MetadataLog, Tag, Value};
+use self::symphonia_core::probe::{Descriptor, Instantiate, QueryDescriptor};
+use self::symphonia_core::units::TimeBase;
+
+use error::Error;
+use frame::{ChannelSelection, FrameReader};
+use input::ReadBytes;
+use metadata::{self, MetadataBlock, StreamInfo};
+use {FlacReader, FlacReaderState};
+
+impl From<Error> for errors::Error {
+    fn from(err: Error) -> errors::Error {
+        match err {
+            Error::IoError(err) => errors::Error::IoError(err),
+            Error::FormatError(reason) => errors::Error::DecodeError(reason),
+            Error::Unsupported(feature) => errors::Error::Unsupported(feature.description()),
+            Error::InvalidPartitionOrder { .. } => {
+                errors::Error::DecodeError("invalid partition order")
+            }
+            Error::LimitExceeded { limit, .. } => errors::Error::LimitError(limit),
+            Error::BoundExceeded { .. } => {
+                let msg = "read past the bound of the stream";
+                errors::Error::IoError(io::Error::new(io::ErrorKind::UnexpectedEof, msg))
+            }
+        }
+    }
+}
+
+/// Returns the channel mask for the channel assignment that FLAC specifies.
+fn flac_channels(channels: u32) -> Channels {
+    let front = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+    let rear = Channels::REAR_LEFT | Channels::REAR_RIGHT;
+    let side = Channels::SIDE_LEFT | Channels::SIDE_RIGHT;
+    let centre_lfe = Channels::FRONT_CENTRE | Channels::LFE1;
+    match channels {
+        1 => Channels::FRONT_LEFT,
+        2 => front,
+        3 => front | Channels::FRONT_CENTRE,
+        4 => front | rear,
+        5 => front | Channels::FRONT_CENTRE | rear,
+        6 => front | centre_lfe | rear,
+        7 => front | centre_lfe | Channels::REAR_CENTRE | side,
+        _ => front | centre_lfe | rear | side,
+    }
+}
+
+fn new_decoder(params: &CodecParameters,
+               options: &DecoderOptions)
+               -> errors::Result<Box<Decoder>> {
+    let decoder = try!(ClaxonDecoder::try_new(params, options));
+    Ok(Box::new(decoder))
+}
+
+static SUPPORTED_CODECS: [CodecDescriptor; 1] = [CodecDescriptor {
+    codec: CODEC_TYPE_FLAC,
+    short_name: "flac",
+    long_name: "Free Lossless Audio Codec (Claxon)",
+    inst_func: new_decoder,
+}];
+
+/// A Symphonia decoder for FLAC packets, backed by Claxon.
+///
+/// Like Symphonia's own FLAC decoder, this decodes into a buffer of `i32`
+/// samples that are scaled to the full range of an `i32`, regardless of the
+/// bits per sample of the stream. The streaminfo is taken from the extra data
+/// of the codec parameters, where Symphonia's FLAC format reader puts it.
+///
+/// Claxon does not compute the MD5 signature of the decoded audio, so
+/// `finalize()` never reports a verification result.
+pub struct ClaxonDecoder {
+    params: CodecParameters,
+    streaminfo: StreamInfo,
+    /// The buffer that Claxon decodes into, reused for every frame.
+    buffer: Vec<i32>,
+    output: AudioBuffer<i32>,
+}
+
+impl ClaxonDecoder {
+    fn decode_frame(&mut self, packet: &Packet) -> errors::Result<()> {
+        let mut reader = FrameReader::new(io::Cursor::new(packet.buf()));
+        reader.set_fixed_block_size(self.streaminfo.max_block_size);
+        // When decoding fails, the reader puts the buffer back.
+        let block = match try!(reader.read_next_into(&mut self.buffer)) {
+            Some(block) => block,
+            None => return Err(errors::Error::DecodeError("packet does not contain a frame")),
+        };
+
+        let msg = if block.channels() != self.streaminfo.channels {
+            Some("frame has a different number of channels than the stream")
+        } else if block.duration() as usize > self.output.capacity() {
+            Some("frame has more samples than the maximum block size")
+        } else {
+            None
+        };
+        if let Some(msg) = msg {
+            self.buffer = block.into_buffer();
+            return Err(errors::Error::DecodeError(msg))
+        }
+
+        self.output.render_reserved(Some(block.duration() as usize));
+        let shift = 32 - block.bits_per_sample();
+        for ch in 0..block.channels() {
+            let output = self.output.chan_mut(ch as usize);
+            for (out, &sample) in output.iter_mut().zip(block.channel(ch)) {
+                *out = sample << shift;
+            }
+        }
+
+        self.buffer = block.into_buffer();
+        Ok(())
+    }
+}
+
+impl Decoder for ClaxonDecoder {
+    fn try_new(params: &CodecParameters, _options: &DecoderOptions) -> errors::Result<Self> {
+        if params.codec != CODEC_TYPE_FLAC {
+            return Err(errors::Error::Unsupported("claxon: codec is not FLAC"))
+        }
+
+        let streaminfo = match params.extra_data {
+            Some(ref data) => {
+                let mut input = io::Cursor::new(&data[..]);
+                match try!(metadata::read_metadata_block(&mut input, 0, data.len() as u32)) {
+                    MetadataBlock::StreamInfo(streaminfo) => streaminfo,
+                    _ => unreachable!(),
+                }
+            }
+            None => return Err(errors::Error::Unsupported("claxon: streaminfo is missing")),
+        };
+
+        let channels = flac_channels(streaminfo.channels);
+        let mut params = params.clone();
+        params.with_sample_rate(streaminfo.sample_rate)
+              .with_time_base(TimeBase::new(1, streaminfo.sample_rate))
+              .with_bits_per_sample(streaminfo.bits_per_sample)
+              .with_max_frames_per_packet(streaminfo.max_block_size as u64)
+              .with_channels(channels);
+        if let Some(samples) = streaminfo.samples {
+            params.with_n_frames(samples);
+        }
+
+        let spec = SignalSpec::new(streaminfo.sample_rate, channels);
+        Ok(ClaxonDecoder {
+            params: params,
+            streaminfo: streaminfo,
+            buffer: Vec::new(),
+            output: AudioBuffer::new(streaminfo.max_block_size as u64, spec),
+        })
+    }
+
+    fn supported_codecs() -> &'static [CodecDescriptor] {
+        &SUPPORTED_CODECS
+    }
+
+    fn reset(&mut self) {
+        // Frames are decoded independently, there is no state to reset.
+    }
+
+    fn codec_params(&self) -> &CodecParameters {
+        &self.params
+    }
+
+    fn decode<'a>(&'a mut self, packet: &Packet) -> errors::Result<AudioBufferRef<'a>> {
+        self.output.clear();
+        try!(self.decode_frame(packet));
+        Ok(self.output.as_audio_buffer_ref())
+    }
+
+    fn finalize(&mut self) -> FinalizeResult {
+        FinalizeResult::default()
+    }
+
+    fn last_decoded<'a>(&'a self) -> AudioBufferRef<'a> {
+        self.output.as_audio_buffer_ref()
+    }
+}
+
+/// A reader that keeps a copy of the bytes that it reads.
+struct PacketReader<'a, R: 'a + ReadBytes> {
+    inner: R,
+    bytes: &'a mut Vec<u8>,
+}
+
+impl<'a, R: ReadBytes> ReadBytes for PacketReader<'a, R> {
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let byte = try!(self.inner.read_u8());
+        self.bytes.push(byte);
+        Ok(byte)
+    }
+
+    fn read_u8_or_eof(&mut self) -> io::Result<Option<u8>> {
+        let result = try!(self.inner.read_u8_or_eof());
+        if let Some(byte) = result {
+            self.bytes.push(byte);
+        }
+        Ok(result)
+    }
+
+    fn read_into(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        try!(self.inner.read_into(buffer));
+        self.bytes.extend_from_slice(buffer);
+        Ok(())
+    }
+
+    fn skip(&mut self, amount: u32) -> io::Result<()> {
+        let start = self.bytes.len();
+        self.bytes.resize(start + amount as usize, 0);
+        self.inner.read_into(&mut self.bytes[start..])
+    }
+}
+
+fn new_format_reader(source: MediaSourceStream,
+                     options: &FormatOptions)
+                     -> errors::Result<Box<FormatReader>> {
+    let reader = try!(ClaxonFormatReader::try_new(source, options));
+    Ok(Box::new(reader))
+}
+
+static FORMAT_DESCRIPTORS: [Descriptor; 1] = [Descriptor {
+    short_name: "flac",
+    long_name: "Free Lossless Audio Codec Native (Claxon)",
+    extensions: &["flac"],
+    mime_types: &["audio/flac"],
+    markers: &[b"fLaC"],
+    score: ClaxonFormatReader::score,
+    inst: Instantiate::Format(new_format_reader),
+}];
+
+/// A Symphonia format reader for native FLAC streams, backed by Claxon.
+///
+/// The stream has a single track with id 0, and every packet holds one frame.
+/// The codec parameters of the track carry the streaminfo as extra data, so
+/// the packets can be decoded with `ClaxonDecoder`, or with Symphonia's own
+/// FLAC decoder. The Vorbis comments become the tags of the metadata, and the
+/// tracks of the cue sheet become cues.
+///
+/// Frames have no length field, so to find where a frame ends, the reader
+/// parses it, and decodes only its first channel where the channel coding
+/// allows it. Seeking uses `FlacReader::seek()`, which is sample-accurate
+/// only when the frames around the target can be decoded. In a damaged stream,
+/// seeking may fail, or stop at an earlier frame; `SeekedTo::actual_ts` is the
+/// sample where the reader ends up.
+pub struct ClaxonFormatReader {
+    reader: FlacReader<MediaSourceStream>,
+    tracks: Vec<Track>,
+    cues: Vec<Cue>,
+    metadata: MetadataLog,
+    /// The buffer that frames are parsed into, reused for every frame.
+    buffer: Vec<i32>,
+}
+
+impl QueryDescriptor for ClaxonFormatReader {
+    fn query() -> &'static [Descriptor] {
+        &FORMAT_DESCRIPTORS
+    }
+
+    fn score(_context: &[u8]) -> u8 {
+        255
+    }
+}
+
+impl FormatReader for ClaxonFormatReader {
+    fn try_new(source: MediaSourceStream, _options: &FormatOptions) -> errors::Result<Self> {
+        let reader = try!(FlacReader::new(source));
+
+        let streaminfo = reader.streaminfo();
+        let mut params = CodecParameters::new();
+        params.for_codec(CODEC_TYPE_FLAC)
+              .with_sample_rate(streaminfo.sample_rate)
+              .with_time_base(TimeBase::new(1, streaminfo.sample_rate))
+              .with_bits_per_sample(streaminfo.bits_per_sample)
+              .with_max_frames_per_packet(streaminfo.max_block_size as u64)
+              .with_channels(flac_channels(streaminfo.channels));
+        if let Some(samples) = streaminfo.samples {
+            params.with_n_frames(samples);
+        }
+        // The extra data is the body of the streaminfo block, without header.
+        if let Some(bytes) = reader.streaminfo_bytes() {
+            params.with_extra_data(bytes[4..].to_vec().into_boxed_slice());
+        }
+
+        let mut builder = MetadataBuilder::new();
+        for (name, value) in reader.tags() {
+            builder.add_tag(Tag::new(None, name, Value::from(value)));
+        }
+        let mut metadata = MetadataLog::default();
+        metadata.push(builder.metadata());
+
+        let mut cues = Vec::new();
+        if let Some(cue_sheet) = reader.cue_sheet() {
+            for track in cue_sheet.tracks.iter().filter(|t| !t.is_lead_out()) {
+                cues.push(Cue {
+                    index: track.number as u32,
+                    start_ts: track.offset,
+                    tags: Vec::new(),
+                    points: Vec::new(),
+                });
+            }
+        }
+
+        Ok(ClaxonFormatReader {
+            reader: reader,
+            tracks: vec![Track::new(0, params)],
+            cues: cues,
+            metadata: metadata,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn cues(&self) -> &[Cue] {
+        &self.cues
+    }
+
+    fn metadata<'a>(&'a mut self) -> Metadata<'a> {
+        self.metadata.metadata()
+    }
+
+    fn seek(&mut self, _mode: SeekMode, to: SeekTo) -> errors::Result<SeekedTo> {
+        let streaminfo = self.reader.streaminfo();
+        let ts = match to {
+            SeekTo::Time { time, track_id } => {
+                if track_id.map_or(false, |id| id != 0) {
+                    return Err(errors::Error::SeekError(SeekErrorKind::InvalidTrack))
+                }
+                TimeBase::new(1, streaminfo.sample_rate).calc_timestamp(time)
+            }
+            SeekTo::TimeStamp { ts, track_id } => {
+                if track_id != 0 {
+                    return Err(errors::Error::SeekError(SeekErrorKind::InvalidTrack))
+                }
+                ts
+            }
+        };
+        if streaminfo.samples.map_or(false, |samples| ts >= samples) {
+            return Err(errors::Error::SeekError(SeekErrorKind::OutOfRange))
+        }
+
+        let report = try!(self.reader.seek(ts));
+        Ok(SeekedTo {
+            track_id: 0,
+            required_ts: ts,
+            actual_ts: report.frame_sample,
+        })
+    }
+
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn next_packet(&mut self) -> errors::Result<Packet> {
+        let settings = self.reader.frame_settings;
+        let input = match self.reader.input {
+            FlacReaderState::Full(ref mut inp) => inp,
+            FlacReaderState::MetadataOnly(..) => unreachable!(),
+        };
+
+        // The packet is the frame as it is in the stream. Its samples are not
+        // needed, the frame is only parsed to find its end, so decode as few
+        // channels as possible.
+        let mut data = Vec::new();
+        let result = {
+            let packet_reader = PacketReader {
+                inner: input,
+                bytes: &mut data,
+            };
+            let mut frame_reader = settings.new_frame_reader(packet_reader);
+            frame_reader.set_channels(ChannelSelection::Mask(1));
+            // When decoding fails, the reader puts the buffer back.
+            frame_reader.read_next_into(&mut self.buffer)
+        };
+
+        match try!(result) {
+            Some(block) => {
+                let packet = Packet::new_from_boxed_slice(0,
+                                                          block.time(),
+                                                          block.duration() as u64,
+                                                          data.into_boxed_slice());
+                self.buffer = block.into_buffer();
+                Ok(packet)
+            }
+            None => errors::end_of_stream_error(),
+        }
+    }
+
+    fn into_inner(self: Box<Self>) -> MediaSourceStream {
+        self.reader.into_inner()
+    }
+}
+
+#[test]
+fn verify_claxon_decoder() {
+    use std::fs;
+    use std::io::Read;
+    use FlacReader;
+
+    // This file has its streaminfo at bytes 8 through 42, followed by a single
+    // frame of 24-bit stereo audio.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/non_subset.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut reader = FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+    let mut params = CodecParameters::new();
+    params.for_codec(CODEC_TYPE_FLAC).with_extra_data(data[8..42].to_vec().into_boxed_slice());
+    let mut decoder = ClaxonDecoder::try_new(&params, &DecoderOptions::default()).unwrap();
+    assert_eq!(decoder.codec_params().sample_rate, Some(44_100));
+    assert_eq!(decoder.codec_params().channels.map(|c| c.count()), Some(2));
+
+    let packet = Packet::new_from_slice(0, 0, 4096, &data[42..]);
+    let mut decoded = Vec::new();
+    match decoder.decode(&packet).unwrap() {
+        AudioBufferRef::S32(buffer) => {
+            assert_eq!(buffer.frames(), 4096);
+            for i in 0..buffer.frames() {
+                decoded.push(buffer.chan(0)[i] >> 8);
+                decoded.push(buffer.chan(1)[i] >> 8);
+            }
+        }
+        _ => panic!("expected 32-bit signed samples"),
+    }
+    assert_eq!(decoded, samples);
+
+    // A packet that is not a frame results in a decode error, and the decode
+    // buffer is kept for the next packet.
+    let packet = Packet::new_from_slice(0, 0, 0, &data[8..42]);
+    assert!(decoder.decode(&packet).is_err());
+    assert!(decoder.buffer.capacity() >= 2 * 4096);
+}
+
+#[test]
+fn verify_claxon_format_reader() {
+    use std::fs;
+    use self::symphonia_core::audio::Signal;
+    use self::symphonia_core::io::MediaSourceStreamOptions;
+    use self::symphonia_core::units::Time;
+
+    let open = |fname: &str| {
+        let file = fs::File::open(fname).unwrap();
+        let source = MediaSourceStream::new(Box::new(file), MediaSourceStreamOptions::default());
+        ClaxonFormatReader::try_new(source, &FormatOptions::default()).unwrap()
+    };
+
+    let mut reader = FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+    // This file is mono, and has a block of 4096 samples, and one of 314.
+    let mut format = open("testsamples/wasted_bits.flac");
+    let params = format.default_track().unwrap().codec_params.clone();
+    assert_eq!(params.n_frames, Some(4410));
+    let mut decoder = ClaxonDecoder::try_new(&params, &DecoderOptions::default()).unwrap();
+    let mut decoded = Vec::new();
+    let mut times = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(errors::Error::IoError(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(err) => panic!("{}", err),
+        };
+        times.push((packet.ts, packet.dur));
+        match decoder.decode(&packet).unwrap() {
+            AudioBufferRef::S32(buffer) => {
+                decoded.extend(buffer.chan(0).iter().map(|&s| s >> 16));
+            }
+            _ => panic!("expected 32-bit signed samples"),
+        }
+    }
+    assert_eq!(times, vec![(0, 4096), (4096, 314)]);
+    assert_eq!(decoded, samples);
+
+    // Seeking positions the reader at the frame that contains the target.
+    let seeked = format.seek(SeekMode::Accurate, SeekTo::TimeStamp { ts: 4200, track_id: 0 });
+    assert_eq!(seeked.unwrap().actual_ts, 4096);
+    assert_eq!(format.next_packet().unwrap().ts, 4096);
+    let time = Time::new(0, 0.05);
+    let seeked = format.seek(SeekMode::Accurate, SeekTo::Time { time: time, track_id: None });
+    assert_eq!(seeked.unwrap().required_ts, 2205);
+    assert_eq!(format.next_packet().unwrap().ts, 0);
+    let to = SeekTo::TimeStamp { ts: 4410, track_id: 0 };
+    assert!(format.seek(SeekMode::Accurate, to).is_err());
+}
@@ -0,0 +1,138 @@
+// Claxon -- A FLAC decoding library in Rust
+// Copyright 2017 Ruud van Asseldonk
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! The `wav` module writes decoded audio with Hound, a wav encoding library.
+//!
+//! Decoding FLAC to wav is a common use of Claxon, and getting the details of
+//! the conversion right is subtle: wav stores samples in whole bytes, so a
+//! stream of 12 or 20 bits per sample must be widened. This module is
+//! available only with the `hound` feature enabled.
+
+extern crate hound;
+
+use std::io;
+
+use frame::Block;
+use metadata::{StreamInfo, left_justify_shift};
+
+impl<'a> From<&'a StreamInfo> for hound::WavSpec {
+    /// Returns the spec of a wav file that can hold the samples of the stream.
+    ///
+    /// The bits per sample are rounded up to 16, 24, or 32 bits, see
+    /// `StreamInfo::container_bits_per_sample()`. `write_block()` shifts the
+    /// samples into the most significant bits.
+    fn from(streaminfo: &'a StreamInfo) -> hound::WavSpec {
+        hound::WavSpec {
+            channels: streaminfo.channels as u16,
+            sample_rate: streaminfo.sample_rate,
+            bits_per_sample: streaminfo.container_bits_per_sample() as u16,
+            sample_format: hound::SampleFormat::Int,
+        }
+    }
+}
+
+/// Writes the samples of the block to the wav writer, channels interleaved.
+///
+/// If the writer has more bits per sample than the block, for instance when
+/// its spec was obtained from a stream of 20 bits per sample, the samples are
+/// shifted into the most significant bits, as wav requires. A block with more
+/// bits per sample than the writer results in `hound::Error::TooWide`. A
+/// block with a different number of channels than the writer results in an
+/// `IoError` of kind `InvalidInput`.
+pub fn write_block<W>(writer: &mut hound::WavWriter<W>, block: &Block) -> hound::Result<()>
+    where W: io::Write + io::Seek
+{
+    let spec = writer.spec();
+    if block.channels() != spec.channels as u32 {
+        let msg = "block must have as many channels as the wav writer";
+        return Err(hound::Error::IoError(io::Error::new(io::ErrorKind::InvalidInput, msg)))
+    }
+
+    let wav_bits = spec.bits_per_sample as u32;
+    if block.bits_per_sample() > wav_bits {
+        return Err(hound::Error::TooWide)
+    }
+    let shift = left_justify_shift(block.bits_per_sample(), wav_bits);
+
+    for i in 0..block.duration() {
+        for ch in 0..block.channels() {
+            try!(writer.write_sample(block.sample(ch, i) << shift));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn verify_write_block() {
+    use frame::{FrameReader, FrameSpec, write_frame};
+
+    // A stream of 12 bits per sample is stored in 16 bits.
+    let left: Vec<i32> = (0..100).map(|i| i * 40 - 2048).collect();
+    let right: Vec<i32> = (0..100).map(|i| 2047 - i * 3).collect();
+    let spec = FrameSpec {
+        variable_block_size: false,
+        number: 0,
+        sample_rate: Some(8_000),
+        bits_per_sample: 12,
+    };
+    let mut data = Vec::new();
+    write_frame(&spec, &[&left, &right], &mut data).unwrap();
+    let mut frame_reader = FrameReader::new(io::Cursor::new(data));
+    let block = frame_reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+
+    let streaminfo = StreamInfo {
+        min_block_size: 100,
+        max_block_size: 100,
+        min_frame_size: None,
+        max_frame_size: None,
+        sample_rate: 8_000,
+        channels: 2,
+        bits_per_sample: 12,
+        samples: Some(100),
+        md5sum: [0; 16],
+    };
+    let wav_spec = hound::WavSpec::from(&streaminfo);
+    assert_eq!(wav_spec.channels, 2);
+    assert_eq!(wav_spec.bits_per_sample, 16);
+
+    let mut wav = io::Cursor::new(Vec::new());
+    {
+        let mut writer = hound::WavWriter::new(&mut wav, wav_spec).unwrap();
+        write_block(&mut writer, &block).unwrap();
+        writer.finalize().unwrap();
+    }
+
+    wav.set_position(0);
+    let mut reader = hound::WavReader::new(wav).unwrap();
+    let samples: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples.len(), 200);
+    for i in 0..100 {
+        assert_eq!(samples[i * 2] as i32, left[i] << 4);
+        assert_eq!(samples[i * 2 + 1] as i32, right[i] << 4);
+    }
+
+    // A writer narrower than the block cannot hold the samples.
+    let narrow = hound::WavSpec { bits_per_sample: 8, ..wav_spec };
+    let mut writer = hound::WavWriter::new(io::Cursor::new(Vec::new()), narrow).unwrap();
+    match write_block(&mut writer, &block) {
+        Err(hound::Error::TooWide) => {}
+        _ => panic!("expected TooWide"),
+    }
+
+    // A writer with a different number of channels is an error, not a panic.
+    let mono = hound::WavSpec { channels: 1, ..wav_spec };
+    let mut writer = hound::WavWriter::new(io::Cursor::new(Vec::new()), mono).unwrap();
+    match write_block(&mut writer, &block) {
+        Err(hound::Error::IoError(ref err)) if err.kind() == io::ErrorKind::InvalidInput => {}
+        _ => panic!("expected InvalidInput"),
+    }
+
+    // Streams of 8 bits per sample are widened to 16 bits too, because
+    // 8-bit wav samples are unsigned.
+    let streaminfo = StreamInfo { bits_per_sample: 8, ..streaminfo };
+    assert_eq!(hound::WavSpec::from(&streaminfo).bits_per_sample, 16);
+}
@@ -420,16 +420,81 @@ fn verify_limits_on_vorbis_comment_block() {
     // make the decoder go OOM.
     match claxon::FlacReader::open("testsamples/large_vorbis_comment_block.flac") {
         Ok(..) => panic!("This file should fail to load"),
-        Err(claxon::Error::Unsupported(..)) => { }
+        Err(claxon::Error::Unsupported(claxon::UnsupportedFeature::TooLargeMetadata)) => { }
         Err(..) => panic!("Expected 'Unsupported' error."),
     }
 }
 
+#[test]
+fn small_buffer_capacity_reads_streaminfo_and_samples() {
+    let options = claxon::FlacReaderOptions {
+        metadata_only: true,
+        read_vorbis_comment: false,
+        read_seek_table: false,
+        read_pictures: false,
+        lenient_metadata: false,
+        strict_metadata: false,
+        length_check: claxon::LengthCheck::Warn,
+        metadata_limits: claxon::metadata::MetadataLimits::default(),
+        max_junk_len: 0,
+    };
+    let file = fs::File::open("testsamples/pop.flac").unwrap();
+    let reader = claxon::FlacReader::new_ext_with_capacity(file, options, 42).unwrap();
+    let expected = claxon::read_streaminfo("testsamples/pop.flac").unwrap();
+    assert_eq!(reader.streaminfo(), expected);
+
+    // Decoding does not depend on the buffer size.
+    let mut reader = claxon::FlacReader::open("testsamples/pop.flac").unwrap();
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    let file = fs::File::open("testsamples/pop.flac").unwrap();
+    let options = claxon::FlacReaderOptions::default();
+    let mut reader = claxon::FlacReader::new_ext_with_capacity(file, options, 7).unwrap();
+    let samples_small: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples_small, samples);
+
+    // Capacities too small to hold a frame header are rounded up.
+    for &capacity in &[0, 1, 2] {
+        let file = fs::File::open("testsamples/pop.flac").unwrap();
+        let mut reader =
+            claxon::FlacReader::new_ext_with_capacity(file, options, capacity).unwrap();
+        let samples_tiny: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(samples_tiny, samples);
+    }
+}
+
+#[test]
+fn caller_supplied_buffer_is_reused() {
+    let mut buffer = Vec::with_capacity(64);
+    let ptr = buffer.as_ptr();
+    for fname in &["testsamples/pop.flac", "testsamples/short.flac"] {
+        let file = fs::File::open(fname).unwrap();
+        let options = claxon::FlacReaderOptions::default();
+        let reader = claxon::FlacReader::new_ext_with_buffer(file, options, buffer).unwrap();
+        assert_eq!(reader.streaminfo(), claxon::read_streaminfo(fname).unwrap());
+        let (_, returned) = reader.into_parts();
+        buffer = returned;
+        assert_eq!(buffer.as_ptr(), ptr);
+    }
+
+    // An empty buffer is grown to fit a frame header.
+    let file = fs::File::open("testsamples/pop.flac").unwrap();
+    let options = claxon::FlacReaderOptions::default();
+    let mut reader = claxon::FlacReader::new_ext_with_buffer(file, options, Vec::new()).unwrap();
+    assert_eq!(reader.samples().count(), 100);
+}
+
 #[test]
 fn metadata_only_still_reads_vorbis_comment_block() {
     let opts = claxon::FlacReaderOptions {
         metadata_only: true,
         read_vorbis_comment: true,
+        read_seek_table: false,
+        read_pictures: false,
+        lenient_metadata: false,
+        strict_metadata: false,
+        length_check: claxon::LengthCheck::Warn,
+        metadata_limits: claxon::metadata::MetadataLimits::default(),
+        max_junk_len: 0,
     };
     let reader = claxon::FlacReader::open_ext("testsamples/short.flac", opts).unwrap();
     assert_eq!(reader.vendor(), Some("reference libFLAC 1.3.2 20170101"));
@@ -440,17 +505,1602 @@ fn no_read_vorbis_comment_block_does_not_contain_vendor_string() {
     let opts = claxon::FlacReaderOptions {
         metadata_only: true,
         read_vorbis_comment: false,
+        read_seek_table: false,
+        read_pictures: false,
+        lenient_metadata: false,
+        strict_metadata: false,
+        length_check: claxon::LengthCheck::Warn,
+        metadata_limits: claxon::metadata::MetadataLimits::default(),
+        max_junk_len: 0,
     };
     let reader = claxon::FlacReader::open_ext("testsamples/short.flac", opts).unwrap();
     assert_eq!(reader.vendor(), None);
 }
 
+#[test]
+fn samples_agrees_with_blocks() {
+    // This covers the mono (the others) and stereo (non_subset) fast paths.
+    for fname in &["testsamples/pop.flac",
+                   "testsamples/short.flac",
+                   "testsamples/wasted_bits.flac",
+                   "testsamples/non_subset.flac"] {
+        let mut reader = claxon::FlacReader::open(fname).unwrap();
+        let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+        let mut reader = claxon::FlacReader::open(fname).unwrap();
+        let mut expected = Vec::new();
+        let mut blocks = reader.blocks();
+        while let Some(block) = blocks.read_next_or_eof(Vec::new()).unwrap() {
+            for i in 0..block.duration() {
+                for ch in 0..block.channels() {
+                    expected.push(block.sample(ch, i));
+                }
+            }
+        }
+
+        assert_eq!(samples, expected);
+    }
+}
+
+#[test]
+fn for_each_sample_agrees_with_samples() {
+    for fname in &["testsamples/pop.flac",
+                   "testsamples/short.flac",
+                   "testsamples/wasted_bits.flac",
+                   "testsamples/non_subset.flac"] {
+        let mut reader = claxon::FlacReader::open(fname).unwrap();
+        let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+        let mut reader = claxon::FlacReader::open(fname).unwrap();
+        let mut samples = Vec::new();
+        reader.samples().for_each_sample(|s| samples.push(s)).unwrap();
+        assert_eq!(samples, expected);
+
+        // Continuing after the iterator stopped in the middle of a block, and
+        // of an inter-channel sample for the stereo file.
+        let mut reader = claxon::FlacReader::open(fname).unwrap();
+        let mut iter = reader.samples();
+        let mut samples: Vec<i32> = iter.by_ref().take(3).map(|s| s.unwrap()).collect();
+        iter.for_each_sample(|s| samples.push(s)).unwrap();
+        assert_eq!(samples, expected);
+        assert!(iter.next().is_none());
+    }
+}
+
+#[test]
+fn read_streaminfo_agrees_with_flac_reader() {
+    for fname in &["testsamples/pop.flac",
+                   "testsamples/short.flac",
+                   "testsamples/wasted_bits.flac",
+                   "testsamples/non_subset.flac"] {
+        let streaminfo = claxon::read_streaminfo(fname).unwrap();
+        let reader = claxon::FlacReader::open(fname).unwrap();
+        assert_eq!(streaminfo, reader.streaminfo());
+    }
+}
+
+#[test]
+fn chunks_agree_with_samples() {
+    for fname in &["testsamples/pop.flac", "testsamples/non_subset.flac"] {
+        let mut expected = claxon::FlacReader::open(fname).unwrap();
+        let expected_samples: Vec<i32> = expected.samples().map(|s| s.unwrap()).collect();
+        let channels = expected.streaminfo().channels;
+
+        let mut reader = claxon::FlacReader::open(fname).unwrap();
+        let mut chunks = reader.chunks(1000);
+        assert_eq!(chunks.spec(), expected.streaminfo().spec());
+        let mut samples = Vec::new();
+        let mut buffer = Vec::new();
+        let mut saw_partial = false;
+        while let Some(chunk) = chunks.read_next_or_eof(buffer).unwrap() {
+            // Only the final chunk can be partial.
+            assert!(!saw_partial);
+            saw_partial = chunk.is_partial();
+            if !chunk.is_partial() {
+                assert_eq!(chunk.frames(), 1000);
+            }
+            assert_eq!(chunk.samples().len(), chunk.frames() as usize * channels as usize);
+            samples.extend_from_slice(chunk.samples());
+            buffer = chunk.into_buffer();
+        }
+
+        assert_eq!(samples, expected_samples);
+    }
+}
+
+#[test]
+fn chunks_span_block_boundaries() {
+    // This file is mono, and has blocks of 4096 and 314 samples.
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    let expected_samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(expected_samples.len(), 4410);
+
+    // 4410 is an exact multiple of 441, so no chunk is partial, and the chunk
+    // at 3969 contains the block boundary at 4096.
+    reader.seek(0).unwrap();
+    let chunks: Vec<_> = reader.chunks(441).map(|c| c.unwrap()).collect();
+    assert_eq!(chunks.len(), 10);
+    assert!(chunks.iter().all(|c| c.frames() == 441 && !c.is_partial()));
+    let samples: Vec<i32> = chunks.iter().flat_map(|c| c.samples().iter().cloned()).collect();
+    assert_eq!(samples, expected_samples);
+
+    // A chunk size equal to the stream length yields one full chunk.
+    reader.seek(0).unwrap();
+    let mut chunks = reader.chunks(4410);
+    let chunk = chunks.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert_eq!(chunk.frames(), 4410);
+    assert!(!chunk.is_partial());
+    assert!(chunks.read_next_or_eof(chunk.into_buffer()).unwrap().is_none());
+
+    // A chunk larger than the stream yields one partial chunk.
+    reader.seek(0).unwrap();
+    let chunks: Vec<_> = reader.chunks(10_000).map(|c| c.unwrap()).collect();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].frames(), 4410);
+    assert!(chunks[0].is_partial());
+    assert_eq!(chunks[0].samples(), &expected_samples[..]);
+}
+
+#[test]
+fn chunks_convert_to_i16_and_f32() {
+    let mut expected = claxon::FlacReader::open("testsamples/pop.flac").unwrap();
+    assert_eq!(expected.streaminfo().bits_per_sample, 16);
+    let expected_samples: Vec<i32> = expected.samples().take(2048).map(|s| s.unwrap()).collect();
+
+    let mut reader = claxon::FlacReader::open("testsamples/pop.flac").unwrap();
+    let chunk = reader.chunks(1024).next().unwrap().unwrap();
+    let mut samples_i16 = Vec::new();
+    chunk.samples_i16_into(&mut samples_i16);
+    let mut samples_f32 = Vec::new();
+    chunk.samples_f32_into(&mut samples_f32);
+
+    assert_eq!(samples_i16.len(), expected_samples.len());
+    assert_eq!(samples_f32.len(), expected_samples.len());
+    for ((&s, &s16), &sf) in expected_samples.iter().zip(&samples_i16).zip(&samples_f32) {
+        assert_eq!(s16 as i32, s);
+        assert_eq!(sf, s as f32 / 32768.0);
+        assert!(sf >= -1.0 && sf < 1.0);
+    }
+}
+
+#[test]
+fn chunks_of_empty_stream_are_none() {
+    use std::io::Read;
+
+    // See also `stream_without_frames_is_empty()`.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+    data.truncate(42);
+    data[21] &= 0xf0;
+    for b in &mut data[22..26] {
+        *b = 0;
+    }
+
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    assert!(reader.chunks(1024).read_next_or_eof(Vec::new()).unwrap().is_none());
+    assert_eq!(reader.chunks(1024).count(), 0);
+}
+
+#[test]
+#[should_panic]
+fn chunks_of_zero_frames_panics() {
+    let mut reader = claxon::FlacReader::open("testsamples/short.flac").unwrap();
+    let _ = reader.chunks(0);
+}
+
+#[test]
+fn first_frame_offset_allows_skipping_metadata() {
+    use std::io::{Seek, SeekFrom};
+
+    for fname in &["testsamples/pop.flac", "testsamples/wasted_bits.flac"] {
+        let mut reader = claxon::FlacReader::open(fname).unwrap();
+        let offset = reader.first_frame_offset().unwrap();
+        let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+        // Reopen the file, and decode from the offset without reading metadata.
+        let mut file = fs::File::open(fname).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        let input = claxon::input::BufferedReader::new(file);
+        let mut frame_reader = claxon::frame::FrameReader::new(input);
+        let mut samples = Vec::new();
+        while let Some(block) = frame_reader.read_next_or_eof(Vec::new()).unwrap() {
+            for i in 0..block.duration() {
+                for ch in 0..block.channels() {
+                    samples.push(block.sample(ch, i));
+                }
+            }
+        }
+        assert_eq!(samples, expected);
+    }
+
+    // When the reader stops after the desired metadata, the offset is unknown.
+    let options = claxon::FlacReaderOptions {
+        metadata_only: true,
+        read_vorbis_comment: false,
+        read_seek_table: false,
+        read_pictures: false,
+        lenient_metadata: false,
+        strict_metadata: false,
+        length_check: claxon::LengthCheck::Warn,
+        metadata_limits: claxon::metadata::MetadataLimits::default(),
+        max_junk_len: 0,
+    };
+    let fname = "testsamples/repeated_vorbis_comment.flac";
+    let reader = claxon::FlacReader::open_ext(fname, options).unwrap();
+    assert_eq!(reader.first_frame_offset(), None);
+}
+
+#[test]
+fn wide_streams_are_rejected_up_front() {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+
+    // Patch the streaminfo to claim 28 bits per sample. The 5-bit field holds
+    // the bits per sample minus one, split over bytes 20 and 21 of the file.
+    data[20] = data[20] | 0x01;
+    data[21] = (data[21] & 0x0f) | 0xb0;
+
+    match claxon::FlacReader::new(io::Cursor::new(&data[..])) {
+        Err(claxon::Error::Unsupported(claxon::UnsupportedFeature::BitsPerSampleAbove24)) => {}
+        Err(err) => panic!("expected Unsupported, got {:?}", err),
+        Ok(..) => panic!("expected Unsupported error"),
+    }
+
+    // Reading only the metadata of such a stream is fine.
+    let options = claxon::FlacReaderOptions {
+        metadata_only: true,
+        read_vorbis_comment: false,
+        read_seek_table: false,
+        read_pictures: false,
+        lenient_metadata: false,
+        strict_metadata: false,
+        length_check: claxon::LengthCheck::Warn,
+        metadata_limits: claxon::metadata::MetadataLimits::default(),
+        max_junk_len: 0,
+    };
+    let reader = claxon::FlacReader::new_ext(io::Cursor::new(&data[..]), options).unwrap();
+    assert_eq!(reader.streaminfo().bits_per_sample, 28);
+    assert!(reader.streaminfo().bits_per_sample > claxon::MAX_BITS_PER_SAMPLE);
+}
+
+#[test]
+fn lenient_metadata_accepts_invalid_tag_names() {
+    use claxon::metadata::{self, MetadataBlock, VorbisComment};
+    use std::io::Read;
+
+    let mut pop = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut pop).unwrap();
+
+    // Insert a Vorbis comment block after the streaminfo, and then put a
+    // control character in the name of the first tag.
+    let mut vorbis_comment = VorbisComment::new("claxon".to_string());
+    vorbis_comment.push("ARTIST", "Queen");
+    vorbis_comment.push("TITLE", "Bicycle Race");
+    let mut data = pop[..42].to_vec();
+    data[4] &= 0x7f;
+    let block = MetadataBlock::VorbisComment(vorbis_comment);
+    metadata::write_metadata_block(&mut data, true, &block).unwrap();
+    let pos = data.windows(7).position(|w| w == b"ARTIST=").unwrap();
+    data[pos] = 0x7f;
+    data.extend_from_slice(&pop[42..]);
+
+    assert!(claxon::FlacReader::new(io::Cursor::new(&data[..])).is_err());
+
+    let options = claxon::FlacReaderOptions {
+        lenient_metadata: true,
+        strict_metadata: false,
+        length_check: claxon::LengthCheck::Warn,
+        ..claxon::FlacReaderOptions::default()
+    };
+    let reader = claxon::FlacReader::new_ext(io::Cursor::new(&data[..]), options).unwrap();
+    let tags: Vec<(&str, &str)> = reader.tags().collect();
+    assert_eq!(tags, vec![("_RTIST", "Queen"), ("TITLE", "Bicycle Race")]);
+}
+
+#[test]
+fn take_duration_stops_after_duration() {
+    use std::time::Duration;
+
+    // This file holds 100 ms of mono audio, in a block of 4096 samples and a
+    // block of 314 samples.
+    let fname = "testsamples/wasted_bits.flac";
+    let mut reader = claxon::FlacReader::open(fname).unwrap();
+    let all: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(all.len(), 4410);
+
+    // A cut at 50 ms lands inside the first block.
+    let mut reader = claxon::FlacReader::open(fname).unwrap();
+    let preview: Vec<i32> = reader.take_duration(Duration::from_millis(50))
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(&preview[..], &all[..2205]);
+
+    // No input was read past the block that contains the cut.
+    let next = reader.blocks().read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert_eq!(next.duration(), 314);
+
+    // A duration longer than the stream yields the entire stream.
+    let mut reader = claxon::FlacReader::open(fname).unwrap();
+    let long: Vec<i32> = reader.take_duration(Duration::from_secs(3600))
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(long, all);
+}
+
+#[test]
+fn blocks_can_loop_with_snapshot() {
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    let mut blocks = reader.blocks();
+    let first = blocks.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    let loop_start = blocks.snapshot().unwrap();
+    let second = blocks.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert_eq!(loop_start.next_sample, Some(first.duration() as u64));
+
+    for _ in 0..2 {
+        blocks.restore(loop_start).unwrap();
+        let again = blocks.read_next_or_eof(Vec::new()).unwrap().unwrap();
+        assert_eq!(again, second);
+    }
+}
+
+#[test]
+fn split_by_ranges_produces_standalone_files() {
+    use std::io::{Cursor, Read};
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut reader = claxon::FlacReader::new(Cursor::new(&data[..])).unwrap();
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples.len(), 4410);
+
+    // The first two ranges coincide with the frames, so those are copied; the
+    // others cut frames, including a head of fewer than 16 samples, and one
+    // range is past the end of the stream.
+    let range_sets = [
+        vec![0..4096, 4096..4410],
+        vec![5..12, 12..4100, 4100..4410, 5000..6000],
+    ];
+    for (i, ranges) in range_sets.iter().enumerate() {
+        let outputs = claxon::split::by_ranges(Cursor::new(&data[..]),
+                                               ranges,
+                                               |_| Ok(Cursor::new(Vec::new()))).unwrap();
+        assert_eq!(outputs.len(), ranges.len());
+
+        for (range, output) in ranges.iter().zip(outputs) {
+            let end = std::cmp::min(range.end as usize, samples.len());
+            let start = std::cmp::min(range.start as usize, end);
+            let expected = &samples[start..end];
+
+            let bytes = output.into_inner();
+            // Copied frames stay compressed, verbatim ones would not fit.
+            if i == 0 {
+                assert!(bytes.len() < data.len());
+            }
+            let mut split = claxon::FlacReader::new(Cursor::new(&bytes[..])).unwrap();
+            // A count of zero means unknown in the streaminfo.
+            assert_eq!(split.streaminfo().samples.unwrap_or(0), expected.len() as u64);
+            let actual: Vec<i32> = split.samples().map(|s| s.unwrap()).collect();
+            assert_eq!(&actual[..], expected);
+        }
+    }
+}
+
+#[test]
+fn levels_match_samples() {
+    use std::time::Duration;
+
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+    // 30 ms at 44.1 kHz is 1323 samples, so the final window is partial.
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    let windows: Vec<_> = reader.levels(Duration::from_millis(30)).map(|w| w.unwrap()).collect();
+    assert_eq!(windows.len(), 4);
+    assert_eq!(windows[3].duration, 4410 - 3 * 1323);
+
+    for window in &windows {
+        let start = window.time as usize;
+        let part = &samples[start..start + window.duration as usize];
+        let peak = part.iter().map(|&s| (s as i64).abs() as u32).max().unwrap();
+        let sum_squares: f64 = part.iter().map(|&s| s as f64 * s as f64).sum();
+        assert_eq!(window.peak, vec![peak]);
+        let rms = (sum_squares / part.len() as f64).sqrt();
+        assert!((window.rms[0] - rms).abs() < 1e-6 * rms.max(1.0));
+    }
+}
+
+#[test]
+fn audio_checksum_ignores_metadata() {
+    use std::io::{Cursor, Read};
+
+    // In wasted_bits.flac, the first frame starts at byte 152.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut data).unwrap();
+    let frames = &data[152..];
+
+    // A copy with only the streaminfo block has different metadata, but the
+    // same audio.
+    let mut stripped = data[..42].to_vec();
+    stripped[4] |= 0x80;
+    stripped.extend_from_slice(frames);
+
+    let checksum = |bytes: &[u8]| {
+        let reader = claxon::FlacReader::new(Cursor::new(bytes)).unwrap();
+        reader.audio_checksum().unwrap()
+    };
+    assert_eq!(checksum(&data), checksum(&stripped));
+
+    // Corrupting a single byte of a frame changes the checksum.
+    let mut corrupted = data.clone();
+    corrupted[1000] ^= 1;
+    assert!(checksum(&data) != checksum(&corrupted));
+
+    // The frame sizes cover all of the audio data.
+    let mut frame_reader = claxon::frame::FrameReader::new(Cursor::new(frames));
+    let mut total_len = 0;
+    while let Some(_) = frame_reader.read_next_or_eof(Vec::new()).unwrap() {
+        let footer = frame_reader.last_frame_checksum().unwrap();
+        total_len += footer.len as usize;
+        assert_eq!(&frames[total_len - 2..total_len],
+                   &[(footer.crc16 >> 8) as u8, footer.crc16 as u8]);
+    }
+    assert_eq!(total_len, frames.len());
+}
+
+#[test]
+fn samples_reuse_buffers_from_pool() {
+    use std::sync::{Arc, Mutex};
+
+    let pool = Arc::new(Mutex::new(Vec::new()));
+    for i in 0..3 {
+        let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+        reader.set_collect_stats(true);
+        let mut samples = reader.into_samples();
+        samples.set_buffer_pool(pool.clone());
+        assert_eq!(samples.by_ref().map(|s| s.unwrap()).count(), 4410);
+
+        // Only the first iterator allocates, later ones reuse its buffer.
+        let stats = samples.buffer_stats();
+        assert_eq!(stats.allocated, if i == 0 { 1 } else { 0 });
+        drop(samples);
+        assert_eq!(pool.lock().unwrap().len(), 1);
+    }
+}
+
+#[test]
+fn blocks_between_trims_first_and_last_block() {
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+    // The stream has a block of 4096 samples, and one of 314 samples.
+    let ranges = [(4000, 4200, 2), (100, 200, 1), (0, 4096, 1), (4096, 5000, 1), (5000, 6000, 0)];
+    for &(start, end, num_blocks) in &ranges {
+        let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+        let mut actual = Vec::new();
+        let mut n = 0;
+        for range_block in reader.blocks_between(start, end).unwrap() {
+            let range_block = range_block.unwrap();
+            let block = range_block.block();
+            let range = range_block.range();
+            assert_eq!(range.start, range_block.trim_start());
+            assert_eq!(range.end + range_block.trim_end(), block.duration());
+            actual.extend_from_slice(&block.channel(0)[range.start as usize..range.end as usize]);
+            n += 1;
+        }
+        assert_eq!(n, num_blocks);
+
+        let end = std::cmp::min(end as usize, samples.len());
+        let start = std::cmp::min(start as usize, end);
+        assert_eq!(&actual[..], &samples[start..end]);
+    }
+
+    // After a range that ends in the first block, the second block is next.
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    assert_eq!(reader.blocks_between(100, 200).unwrap().count(), 1);
+    assert_eq!(reader.samples().count(), 314);
+
+    // The reader seeks to the range, also when it is before the current
+    // position.
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    assert_eq!(reader.blocks_between(4200, 4300).unwrap().count(), 1);
+    let mut blocks = reader.blocks_between(0, 100).unwrap();
+    let range_block = blocks.read_next_or_eof(Vec::new()).unwrap().unwrap();
+    assert_eq!(range_block.block().time(), 0);
+}
+
+#[test]
+fn flac_probe_upgrades_to_full_reader() {
+    use std::io::Read;
+
+    for fname in &["testsamples/pop.flac", "testsamples/wasted_bits.flac"] {
+        let probe = claxon::FlacProbe::open(fname).unwrap();
+        let mut expected = claxon::FlacReader::open(fname).unwrap();
+        assert_eq!(probe.streaminfo(), expected.streaminfo());
+        assert!(probe.tags().eq(expected.tags()));
+
+        let mut reader = probe.into_full_reader().unwrap();
+        assert_eq!(reader.first_frame_offset(), expected.first_frame_offset());
+        assert_eq!(reader.seek_table(), expected.seek_table());
+        let samples = reader.samples().map(|s| s.unwrap());
+        assert!(expected.samples().map(|s| s.unwrap()).eq(samples));
+    }
+
+    // Move the last-block flag from the Vorbis comment to a new padding block
+    // after it. The probe stops after the Vorbis comment, and the upgrade has
+    // to skip the padding.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut data).unwrap();
+    let vc_len = (data[65] as usize) << 16 | (data[66] as usize) << 8 | data[67] as usize;
+    assert_eq!(data[64], 0x84);
+    data[64] = 0x04;
+    let frames = data.split_off(68 + vc_len);
+    data.extend_from_slice(&[0x81, 0, 0, 10]);
+    data.extend_from_slice(&[0; 10]);
+    data.extend_from_slice(&frames);
+
+    let probe = claxon::FlacProbe::new(&data[..]).unwrap();
+    assert_eq!(probe.get_tag("waveformatextensible_channel_mask").count(), 1);
+    let mut reader = probe.into_full_reader().unwrap();
+    assert_eq!(reader.first_frame_offset(), Some(68 + vc_len as u64 + 14));
+    let mut expected = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    let samples = reader.samples().map(|s| s.unwrap());
+    assert!(expected.samples().map(|s| s.unwrap()).eq(samples));
+}
+
+#[test]
+fn flac_reader_enforces_metadata_limits() {
+    use claxon::metadata::{MetadataBlock, MetadataLimits, VorbisComment, write_metadata_block};
+    use std::io::Read;
+
+    // Insert 1000 empty padding blocks after the streaminfo block.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data[4], 0x80);
+    data[4] = 0x00;
+    let frames = data.split_off(42);
+    for i in 0..1000 {
+        data.extend_from_slice(&[if i == 999 { 0x81 } else { 0x01 }, 0, 0, 0]);
+    }
+    data.extend_from_slice(&frames);
+
+    let new_reader = |limits: MetadataLimits| {
+        let options = claxon::FlacReaderOptions {
+            metadata_limits: limits,
+            max_junk_len: 0,
+            ..claxon::FlacReaderOptions::default()
+        };
+        claxon::FlacReader::new_ext(&data[..], options)
+    };
+    match new_reader(MetadataLimits { max_blocks: 100, max_len: u64::max_value() }) {
+        Err(claxon::Error::LimitExceeded { limit: "metadata blocks", value: 101, max: 100 }) => {}
+        other => panic!("expected metadata block limit error, got {:?}", other.err()),
+    }
+    match new_reader(MetadataLimits { max_blocks: 10_000, max_len: 1000 }) {
+        Err(claxon::Error::LimitExceeded { limit: "metadata length", value: 1002, .. }) => {}
+        other => panic!("expected metadata length limit error, got {:?}", other.err()),
+    }
+    let limits = MetadataLimits { max_blocks: 1001, max_len: 4038 };
+    let reader = new_reader(limits).unwrap();
+    assert_eq!(reader.first_frame_offset(), Some(4042));
+
+    // The limits apply to a probe and its upgrade together. With a Vorbis
+    // comment block first, the probe stops early, and the upgrade reads the
+    // padding blocks.
+    let mut with_vc = data[..42].to_vec();
+    let vc = MetadataBlock::VorbisComment(VorbisComment::new("claxon".to_string()));
+    write_metadata_block(&mut with_vc, false, &vc).unwrap();
+    with_vc.extend_from_slice(&data[42..]);
+    let options = claxon::FlacReaderOptions {
+        metadata_limits: MetadataLimits { max_blocks: 10, max_len: u64::max_value() },
+        ..claxon::FlacReaderOptions::default()
+    };
+    let probe = claxon::FlacProbe::new_ext(&with_vc[..], options).unwrap();
+    match probe.into_full_reader() {
+        Err(claxon::Error::LimitExceeded { limit: "metadata blocks", value: 11, max: 10 }) => {}
+        other => panic!("expected metadata block limit error, got {:?}", other.err()),
+    }
+
+    // Blocks after the desired metadata are not read, so they do not count.
+    let options = claxon::FlacReaderOptions {
+        metadata_only: true,
+        read_vorbis_comment: false,
+        read_seek_table: false,
+        read_pictures: false,
+        lenient_metadata: false,
+        strict_metadata: false,
+        length_check: claxon::LengthCheck::Warn,
+        metadata_limits: MetadataLimits { max_blocks: 2, max_len: 42 },
+        max_junk_len: 0,
+    };
+    assert!(claxon::FlacReader::new_ext(&data[..], options).is_ok());
+}
+
+#[test]
+fn probe_replays_consumed_bytes() {
+    use std::io::Read;
+
+    for fname in &["testsamples/pop.flac", "testsamples/wasted_bits.flac"] {
+        let mut data = Vec::new();
+        fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+
+        let (streaminfo, probed) = claxon::probe(&data[..]);
+        assert_eq!(streaminfo.unwrap(), claxon::read_streaminfo(fname).unwrap());
+
+        // The probed reader yields the entire stream, so it can be decoded.
+        let mut reader = claxon::FlacReader::new(probed).unwrap();
+        let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+        let mut expected = claxon::FlacReader::open(fname).unwrap();
+        assert!(expected.samples().map(|s| s.unwrap()).eq(samples.into_iter()));
+    }
+
+    // When the stream is not FLAC, no bytes are lost either.
+    let not_flac = b"RIFF\x24\x00\x00\x00WAVEfmt ";
+    let (streaminfo, mut probed) = claxon::probe(&not_flac[..]);
+    assert!(streaminfo.is_err());
+    let mut replayed = Vec::new();
+    probed.read_to_end(&mut replayed).unwrap();
+    assert_eq!(&replayed[..], &not_flac[..]);
+}
+
+#[test]
+fn streaminfo_bytes_are_verbatim() {
+    use std::io::Read;
+
+    for fname in &["testsamples/pop.flac",
+                   "testsamples/short.flac",
+                   "testsamples/wasted_bits.flac",
+                   "testsamples/non_subset.flac"] {
+        let mut data = Vec::new();
+        fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+
+        let reader = claxon::FlacReader::open(fname).unwrap();
+        let bytes = reader.streaminfo_bytes().unwrap();
+        assert_eq!(bytes, &data[4..42]);
+
+        let mut input = io::Cursor::new(bytes);
+        match claxon::metadata::read_metadata_block_with_header(&mut input).unwrap() {
+            claxon::metadata::MetadataBlock::StreamInfo(si) => assert_eq!(si, reader.streaminfo()),
+            _ => panic!("expected streaminfo block"),
+        }
+    }
+}
+
+#[test]
+fn new_bounded_stops_at_end_of_embedded_stream() {
+    use std::io::Read;
+
+    let mut flac = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut flac).unwrap();
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&flac[..])).unwrap();
+    let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+    // Embed the stream between other data, which would not decode as frames.
+    let mut bundle = vec![0x55; 100];
+    bundle.extend_from_slice(&flac);
+    bundle.extend_from_slice(&[0xff; 100]);
+
+    let mut cursor = io::Cursor::new(&bundle[..]);
+    cursor.set_position(100);
+    let mut reader = claxon::FlacReader::new_bounded(cursor, flac.len() as u64).unwrap();
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, expected);
+
+    let cursor = reader.into_inner();
+    assert_eq!(cursor.position(), 100 + flac.len() as u64);
+
+    // A bound that cuts the last frame is a distinct error, even though more
+    // data follows in the bundle.
+    let len = flac.len() as u64 - 10;
+    let mut cursor = io::Cursor::new(&bundle[..]);
+    cursor.set_position(100);
+    let mut reader = claxon::FlacReader::new_bounded(cursor, len).unwrap();
+    match reader.samples().filter_map(|s| s.err()).next() {
+        Some(claxon::Error::BoundExceeded { bound }) => assert_eq!(bound, len),
+        other => panic!("expected BoundExceeded, got {:?}", other),
+    }
+    assert!(reader.into_inner().position() <= 100 + len);
+}
+
+#[test]
+fn estimate_duration_recovers_missing_total_samples() {
+    use std::io::Read;
+
+    for fname in &["testsamples/pop.flac",
+                   "testsamples/short.flac",
+                   "testsamples/wasted_bits.flac",
+                   "testsamples/non_subset.flac"] {
+        let mut data = Vec::new();
+        fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+        let samples = claxon::read_streaminfo(fname).unwrap().samples.unwrap();
+
+        // Clear the 36-bit total samples field of the streaminfo block.
+        data[21] &= 0xf0;
+        for b in &mut data[22..26] {
+            *b = 0;
+        }
+
+        let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+        assert_eq!(reader.streaminfo().samples, None);
+        let estimate = reader.estimate_duration().unwrap();
+        assert!(estimate.is_exact());
+        assert_eq!(estimate.samples, samples);
+
+        // Estimating should not disturb decoding afterwards.
+        assert_eq!(reader.samples().count() as u64, samples * reader.streaminfo().channels as u64);
+    }
+}
+
+#[test]
+fn seek_positions_reader_at_frame_with_target() {
+    use claxon::{SeekFallback, SeekOptions};
+    use claxon::frame::{FrameSpec, write_frame};
+    use claxon::metadata::{MetadataBlock, StreamInfo, write_metadata_block};
+
+    // A mono stream of 40 frames of 256 samples each.
+    let n_frames = 40;
+    let streaminfo = StreamInfo {
+        min_block_size: 256,
+        max_block_size: 256,
+        min_frame_size: None,
+        max_frame_size: None,
+        sample_rate: 8_000,
+        channels: 1,
+        bits_per_sample: 16,
+        samples: Some(n_frames * 256),
+        md5sum: [0; 16],
+    };
+    let samples: Vec<i32> = (0..n_frames as i32 * 256)
+        .map(|i| (i * 7919) % 65536 - 32768)
+        .collect();
+    let mut data = b"fLaC".to_vec();
+    write_metadata_block(&mut data, true, &MetadataBlock::StreamInfo(streaminfo)).unwrap();
+    for (i, frame_samples) in samples.chunks(256).enumerate() {
+        let spec = FrameSpec {
+            variable_block_size: false,
+            number: i as u64,
+            sample_rate: None,
+            bits_per_sample: 16,
+        };
+        write_frame(&spec, &[frame_samples], &mut data).unwrap();
+    }
+
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    for &target in &[5000, 0, 1, 255, 256, 9000, n_frames * 256 - 1] {
+        let report = reader.seek(target).unwrap();
+        assert!(report.is_exact);
+        assert_eq!(report.frame_sample, target / 256 * 256);
+        let skip = report.samples_to_skip() as usize;
+        assert_eq!(reader.samples().nth(skip).unwrap().unwrap(), samples[target as usize]);
+    }
+
+    // Without probes, the fallback decides where the reader ends up.
+    let mut options = SeekOptions::default();
+    options.max_probes = 0;
+    let report = reader.seek_ext(5000, options).unwrap();
+    assert!(report.is_exact);
+    assert_eq!((report.probes, report.frame_sample), (0, 19 * 256));
+
+    options.fallback = SeekFallback::Closest;
+    let report = reader.seek_ext(5000, options).unwrap();
+    assert!(!report.is_exact);
+    assert_eq!(report.frame_sample, 0);
+    assert_eq!(report.samples_to_skip(), 5000);
+    assert_eq!(reader.samples().nth(5000).unwrap().unwrap(), samples[5000]);
+
+    options.fallback = SeekFallback::Fail;
+    assert!(reader.seek_ext(5000, options).is_err());
+
+    // Seeking past the end positions the reader at the final frame.
+    let report = reader.seek(n_frames * 256 + 10).unwrap();
+    assert!(!report.is_exact);
+    assert_eq!(report.frame_sample, (n_frames - 1) * 256);
+
+    // A stream whose first frame header is corrupt is not an empty stream.
+    let mut corrupted = data.clone();
+    assert_eq!(&corrupted[42..44], &[0xff, 0xf8]);
+    corrupted[44] ^= 0x01;
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&corrupted[..])).unwrap();
+    assert!(reader.seek(5000).is_err());
+    assert!(reader.seek_ext(5000, options).is_err());
+}
+
+#[test]
+fn new_headerless_decodes_bare_frames() {
+    use std::io::Read;
+
+    // The first frame of these files is at the given offset.
+    for &(fname, offset) in &[("testsamples/pop.flac", 42),
+                              ("testsamples/wasted_bits.flac", 152),
+                              ("testsamples/non_subset.flac", 42)] {
+        let mut data = Vec::new();
+        fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+
+        let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+        let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+        let streaminfo = reader.streaminfo();
+
+        let hints = claxon::HeaderlessHints {
+            max_block_size: Some(streaminfo.max_block_size),
+            samples: streaminfo.samples,
+        };
+        let input = io::Cursor::new(&data[offset..]);
+        let mut reader = claxon::FlacReader::new_headerless(input, hints).unwrap();
+        assert_eq!(reader.streaminfo().sample_rate, streaminfo.sample_rate);
+        assert_eq!(reader.streaminfo().channels, streaminfo.channels);
+        assert_eq!(reader.streaminfo().bits_per_sample, streaminfo.bits_per_sample);
+        assert_eq!(reader.streaminfo().samples, streaminfo.samples);
+
+        let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, expected);
+    }
+
+    // A stream with a header is not a bare frame sequence.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+    let hints = claxon::HeaderlessHints::default();
+    assert!(claxon::FlacReader::new_headerless(io::Cursor::new(data), hints).is_err());
+}
+
+#[test]
+fn capabilities_agree_with_decoding() {
+    let capabilities = claxon::capabilities();
+
+    // The 24-bit sample should decode, as it does not exceed the maximum.
+    let streaminfo = claxon::read_streaminfo("testsamples/non_subset.flac").unwrap();
+    assert!(streaminfo.bits_per_sample <= capabilities.max_bits_per_sample);
+    let mut reader = claxon::FlacReader::open("testsamples/non_subset.flac").unwrap();
+    assert!(reader.samples().all(|s| s.is_ok()));
+
+    assert_eq!(capabilities.mp4, cfg!(feature = "mp4"));
+}
+
+#[test]
+fn wrong_is_last_flag_is_tolerated() {
+    use claxon::metadata::MetadataWarning;
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert!(reader.metadata_warnings().is_empty());
+
+    let mut opts = claxon::FlacReaderOptions::default();
+    opts.lenient_metadata = true;
+
+    // The streaminfo block is the only metadata block in this file. Clear its
+    // is_last flag, even though audio follows.
+    let mut not_last = data.clone();
+    assert_eq!(not_last[4], 0x80);
+    not_last[4] = 0x00;
+    let mut reader = claxon::FlacReader::new_ext(io::Cursor::new(&not_last[..]), opts).unwrap();
+    assert_eq!(reader.metadata_warnings(), &[MetadataWarning::LastBlockNotMarked]);
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, expected);
+
+    // Insert a padding block after the streaminfo block, which is still
+    // marked as the last one.
+    let mut after_last = data.clone();
+    let padding = [0x81, 0, 0, 2, 0, 0];
+    for (i, &b) in padding.iter().enumerate() {
+        after_last.insert(42 + i, b);
+    }
+    let mut reader = claxon::FlacReader::new_ext(io::Cursor::new(&after_last[..]), opts)
+        .unwrap();
+    assert_eq!(reader.metadata_warnings(), &[MetadataWarning::BlockAfterLast { block_type: 1 }]);
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, expected);
+
+    // Without lenient mode, the flag is trusted, and neither stream decodes.
+    match claxon::FlacReader::new(io::Cursor::new(&not_last[..])) {
+        Err(claxon::Error::FormatError("invalid metadata block type")) => {}
+        other => panic!("expected invalid metadata block type, got {:?}", other.err()),
+    }
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&after_last[..])).unwrap();
+    assert!(reader.samples().next().unwrap().is_err());
+}
+
+#[test]
+fn junk_before_first_frame_is_skipped() {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(reader.junk_len(), 0);
+
+    // Insert junk between the streaminfo block and the first frame. It
+    // includes a frame sync code that is not followed by a valid header.
+    let mut junk_data = data.clone();
+    let junk = [0xff, 0xf8, 0x00, 0x00, 0x13, 0x37, 0xff, 0xff, 0x00, 0x42];
+    for (i, &b) in junk.iter().enumerate() {
+        junk_data.insert(42 + i, b);
+    }
+
+    // By default, the junk is not skipped.
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&junk_data[..])).unwrap();
+    assert!(reader.samples().next().unwrap().is_err());
+
+    let mut opts = claxon::FlacReaderOptions::default();
+    opts.max_junk_len = 64;
+    let mut reader = claxon::FlacReader::new_ext(io::Cursor::new(&junk_data[..]), opts).unwrap();
+    assert_eq!(reader.junk_len(), junk.len() as u32);
+    assert_eq!(reader.first_frame_offset(), Some(42 + junk.len() as u64));
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, expected);
+
+    // Without junk, nothing is skipped, and more junk than allowed is an error.
+    let mut reader = claxon::FlacReader::new_ext(io::Cursor::new(&data[..]), opts).unwrap();
+    assert_eq!(reader.junk_len(), 0);
+    assert_eq!(reader.samples().count(), expected.len());
+    opts.max_junk_len = 9;
+    assert!(claxon::FlacReader::new_ext(io::Cursor::new(&junk_data[..]), opts).is_err());
+}
+
+#[test]
+fn stream_without_frames_is_empty() {
+    use std::io::Read;
+
+    // Keep only the stream header and streaminfo block, and clear the 36-bit
+    // total samples field, which is how an encoder marks an empty stream.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+    data.truncate(42);
+    data[21] &= 0xf0;
+    for b in &mut data[22..26] {
+        *b = 0;
+    }
+
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    assert_eq!(reader.streaminfo().samples, None);
+    assert_eq!(reader.streaminfo().duration(), None);
+    assert_eq!(reader.first_frame_offset(), Some(42));
+    let estimate = reader.estimate_duration().unwrap();
+    assert!(estimate.is_exact());
+    assert_eq!(estimate.samples, 0);
+    assert_eq!(reader.samples().count(), 0);
+    assert!(reader.samples().next().is_none());
+    assert!(reader.blocks().read_next_or_eof(Vec::new()).unwrap().is_none());
+    let mut n = 0;
+    reader.samples().for_each_sample(|_| n += 1).unwrap();
+    assert_eq!(n, 0);
+
+    // Skipping junk stops at the end of the stream too.
+    let mut opts = claxon::FlacReaderOptions::default();
+    opts.max_junk_len = 64;
+    let reader = claxon::FlacReader::new_ext(io::Cursor::new(&data[..]), opts).unwrap();
+    assert_eq!(reader.junk_len(), 0);
+    assert_eq!(reader.into_samples().count(), 0);
+
+    // Reading only the metadata of such a stream is no different.
+    opts.metadata_only = true;
+    let reader = claxon::FlacReader::new_ext(io::Cursor::new(&data[..]), opts).unwrap();
+    assert_eq!(reader.streaminfo().duration(), None);
+}
+
+#[test]
+fn invalid_seek_tables_are_dropped() {
+    use claxon::metadata::{MetadataBlockReader, MetadataLimits, MetadataWarning, SeekPoint};
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+
+    // Builds a seek table block with points at the given samples, and inserts
+    // the blocks after the streaminfo block.
+    let seek_table = |samples: &[u64]| {
+        let mut block = vec![0x03, 0, 0, samples.len() as u8 * 18];
+        for &sample in samples {
+            for i in 0..8 {
+                block.push((sample >> (56 - 8 * i)) as u8);
+            }
+            block.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0x10, 0x00]);
+        }
+        block
+    };
+    let with_blocks = |blocks: &[Vec<u8>]| {
+        let mut result = data[..42].to_vec();
+        result[4] = 0x00;
+        for (i, block) in blocks.iter().enumerate() {
+            result.extend_from_slice(block);
+            if i + 1 == blocks.len() {
+                let header_pos = result.len() - block.len();
+                result[header_pos] |= 0x80;
+            }
+        }
+        result.extend_from_slice(&data[42..]);
+        result
+    };
+    let new_reader = |data: &[u8], lenient: bool, strict: bool| {
+        let mut opts = claxon::FlacReaderOptions::default();
+        opts.lenient_metadata = lenient;
+        opts.strict_metadata = strict;
+        claxon::FlacReader::new_ext(io::Cursor::new(data.to_vec()), opts)
+    };
+
+    // A seek table with points out of order is dropped, unless lenient.
+    let unordered = with_blocks(&[seek_table(&[0, 8192, 4096])]);
+    let reader = new_reader(&unordered, false, false).unwrap();
+    assert!(reader.seek_table().is_none());
+    assert_eq!(reader.metadata_warnings(), &[MetadataWarning::UnorderedSeekTable]);
+    let expected = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap().into_samples();
+    assert!(reader.into_samples().map(|s| s.unwrap()).eq(expected.map(|s| s.unwrap())));
+    let reader = new_reader(&unordered, true, false).unwrap();
+    let samples: Vec<u64> = reader.seek_table().unwrap().seekpoints().iter()
+        .map(|p| p.sample)
+        .collect();
+    assert_eq!(samples, vec![0, 8192]);
+    match new_reader(&unordered, false, true) {
+        Err(claxon::Error::FormatError("seek points are not in ascending order")) => {}
+        other => panic!("expected seek point order error, got {:?}", other.err()),
+    }
+
+    // Two seek tables are dropped too.
+    let twice = with_blocks(&[seek_table(&[0]), seek_table(&[0])]);
+    let reader = new_reader(&twice, false, false).unwrap();
+    assert!(reader.seek_table().is_none());
+    assert_eq!(reader.metadata_warnings(), &[MetadataWarning::SecondSeekTable]);
+    match new_reader(&twice, false, true) {
+        Err(claxon::Error::FormatError("encountered second seek table block")) => {}
+        other => panic!("expected second seek table error, got {:?}", other.err()),
+    }
+
+    // Collecting the metadata drops them in the same way.
+    for stream in &[&unordered, &twice] {
+        let metadata = MetadataBlockReader::new(io::Cursor::new(&stream[4..]))
+            .collect_all(MetadataLimits::default())
+            .unwrap();
+        assert_eq!(metadata.seek_table, None);
+    }
+    let valid = with_blocks(&[seek_table(&[0, 4096])]);
+    let metadata = MetadataBlockReader::new(io::Cursor::new(&valid[4..]))
+        .collect_all(MetadataLimits::default())
+        .unwrap();
+    let points = metadata.seek_table.unwrap().seekpoints().to_vec();
+    assert_eq!(points[1], SeekPoint { sample: 4096, offset: 0, samples: 4096 });
+}
+
+#[test]
+fn seek_table_points_at_frames() {
+    use claxon::frame::FrameReader;
+    use std::io::Read;
+
+    // This file has a seek table with at least one point that is not a placeholder.
+    let fname = "testsamples/short.flac";
+    let reader = claxon::FlacReader::open(fname).unwrap();
+    let first_frame_offset = reader.first_frame_offset().unwrap();
+    let seek_table = reader.seek_table().unwrap().clone();
+    let points: Vec<_> = seek_table.seekpoints()
+        .iter()
+        .filter(|p| p.sample != u64::max_value())
+        .collect();
+    assert!(points.len() > 0);
+
+    let mut data = Vec::new();
+    fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+    for point in points {
+        let offset = (first_frame_offset + point.offset) as usize;
+        let mut frame_reader = FrameReader::new(io::Cursor::new(&data[offset..]));
+        let block = frame_reader.read_next_or_eof(Vec::new()).unwrap().unwrap();
+        assert_eq!(block.time(), point.sample);
+        assert_eq!(block.duration(), point.samples as u32);
+        assert_eq!(seek_table.point_at_or_before(point.sample + 1), Some(*point));
+    }
+
+    let mut opts = claxon::FlacReaderOptions::default();
+    opts.read_seek_table = false;
+    let reader = claxon::FlacReader::open_ext(fname, opts).unwrap();
+    assert!(reader.seek_table().is_none());
+}
+
+#[test]
+fn pictures_are_read_when_requested() {
+    use claxon::metadata::{MetadataBlock, Picture, write_metadata_block};
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+    // Insert a picture block after the streaminfo block, which is no longer
+    // the last one.
+    let picture = Picture {
+        picture_type: 3,
+        mime_type: "image/jpeg".to_string(),
+        description: "Cover".to_string(),
+        width: 600,
+        height: 600,
+        depth: 24,
+        colors: 0,
+        data: (0..100_000).map(|i| (i * 7) as u8).collect(),
+    };
+    let mut block = Vec::new();
+    write_metadata_block(&mut block, true, &MetadataBlock::Picture(picture.clone())).unwrap();
+    let mut with_picture = data[..42].to_vec();
+    with_picture[4] = 0x00;
+    with_picture.extend_from_slice(&block);
+    with_picture.extend_from_slice(&data[42..]);
+
+    // By default, the picture is skipped.
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&with_picture[..])).unwrap();
+    assert!(reader.pictures().is_empty());
+    assert!(reader.memory_usage().metadata < 100_000);
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, expected);
+
+    let mut opts = claxon::FlacReaderOptions::default();
+    opts.read_pictures = true;
+    let mut reader = claxon::FlacReader::new_ext(io::Cursor::new(&with_picture[..]), opts)
+        .unwrap();
+    assert_eq!(reader.pictures(), &[picture.clone()]);
+    assert!(reader.pictures()[0].is_front_cover());
+    assert!(reader.memory_usage().metadata >= 100_000);
+    let samples: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(samples, expected);
+
+    // Reading only the metadata still reads all pictures.
+    opts.metadata_only = true;
+    opts.read_vorbis_comment = false;
+    opts.read_seek_table = false;
+    let reader = claxon::FlacReader::new_ext(io::Cursor::new(&with_picture[..]), opts).unwrap();
+    assert_eq!(reader.pictures(), &[picture]);
+}
+
+/// Returns the file with a cue sheet block inserted after the streaminfo block.
+fn with_cue_sheet(fname: &str, cue_sheet: &claxon::metadata::CueSheet) -> Vec<u8> {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    fs::File::open(fname).unwrap().read_to_end(&mut data).unwrap();
+    insert_cue_sheet(&data, cue_sheet)
+}
+
+/// Returns the stream with a cue sheet block inserted after the streaminfo.
+fn insert_cue_sheet(data: &[u8], cue_sheet: &claxon::metadata::CueSheet) -> Vec<u8> {
+    use claxon::metadata::{MetadataBlock, write_metadata_block};
+
+    let is_last = data[4] & 0x80 != 0;
+    let block = MetadataBlock::CueSheet(cue_sheet.clone());
+    let mut result = data[..42].to_vec();
+    result[4] &= 0x7f;
+    write_metadata_block(&mut result, is_last, &block).unwrap();
+    result.extend_from_slice(&data[42..]);
+    result
+}
+
+/// Returns a cue sheet track at the given offset, with index point 1 at its start.
+fn cue_sheet_track(offset: u64, number: u8) -> claxon::metadata::CueSheetTrack {
+    use claxon::metadata::{CueSheetIndex, CueSheetTrack};
+    let indices = if number == 170 {
+        vec![]
+    } else {
+        vec![CueSheetIndex { offset: 0, number: 1 }]
+    };
+    CueSheetTrack {
+        offset: offset,
+        number: number,
+        isrc: None,
+        is_audio: true,
+        pre_emphasis: false,
+        indices: indices,
+    }
+}
+
+#[test]
+fn cue_sheet_is_read() {
+    use claxon::metadata::{CueSheet, LengthConflict, MetadataBlockReader, MetadataLimits};
+
+    let total = claxon::FlacReader::open("testsamples/pop.flac").unwrap()
+        .streaminfo().samples.unwrap();
+    let mut cue_sheet = CueSheet {
+        media_catalog_number: String::new(),
+        lead_in: 88_200,
+        is_cd: true,
+        tracks: vec![cue_sheet_track(0, 1), cue_sheet_track(total, 170)],
+    };
+    let data = with_cue_sheet("testsamples/pop.flac", &cue_sheet);
+    let reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    assert_eq!(reader.cue_sheet(), Some(&cue_sheet));
+
+    let metadata = MetadataBlockReader::new(io::Cursor::new(&data[4..]))
+        .collect_all(MetadataLimits::default())
+        .unwrap();
+    assert_eq!(metadata.cue_sheet.as_ref(), Some(&cue_sheet));
+    assert_eq!(metadata.length_conflicts(), vec![]);
+
+    // A lead-out that does not match the streaminfo is a conflict.
+    cue_sheet.tracks[1].offset = total + 588;
+    let data = with_cue_sheet("testsamples/pop.flac", &cue_sheet);
+    let metadata = MetadataBlockReader::new(io::Cursor::new(&data[4..]))
+        .collect_all(MetadataLimits::default())
+        .unwrap();
+    assert_eq!(metadata.length_conflicts(), vec![LengthConflict::LeadOutMismatch {
+        total_samples: total,
+        lead_out: total + 588,
+    }]);
+
+    // Without cue sheet, there is none to return.
+    let reader = claxon::FlacReader::open("testsamples/pop.flac").unwrap();
+    assert_eq!(reader.cue_sheet(), None);
+}
+
+#[test]
+fn second_cue_sheet_is_ignored_unless_strict() {
+    use claxon::metadata::{CueSheet, MetadataWarning};
+
+    let total = claxon::FlacReader::open("testsamples/pop.flac").unwrap()
+        .streaminfo().samples.unwrap();
+    let first = CueSheet {
+        media_catalog_number: String::new(),
+        lead_in: 0,
+        is_cd: false,
+        tracks: vec![cue_sheet_track(0, 1), cue_sheet_track(total, 170)],
+    };
+    let mut second = first.clone();
+    second.media_catalog_number = "1234567890123".to_string();
+
+    // Insert the second cue sheet first, so it ends up after the first one.
+    let once = with_cue_sheet("testsamples/pop.flac", &second);
+    let data = insert_cue_sheet(&once, &first);
+
+    let reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    assert_eq!(reader.cue_sheet(), Some(&first));
+    assert_eq!(reader.metadata_warnings(), &[MetadataWarning::SecondCueSheet]);
+
+    let mut opts = claxon::FlacReaderOptions::default();
+    opts.strict_metadata = true;
+    match claxon::FlacReader::new_ext(io::Cursor::new(&data[..]), opts) {
+        Err(claxon::Error::FormatError("encountered second cue sheet block")) => {}
+        other => panic!("expected second cue sheet error, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn seek_to_track_seeks_to_index_point_1() {
+    use claxon::metadata::{CueSheet, CueSheetIndex};
+    use std::time::Duration;
+
+    let mut expected = Vec::new();
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    reader.samples().for_each_sample(|s| expected.push(s)).unwrap();
+
+    // Track 2 has a pregap of 200 samples. This mono file has 4410 samples.
+    let mut cue_sheet = CueSheet {
+        media_catalog_number: String::new(),
+        lead_in: 0,
+        is_cd: false,
+        tracks: vec![cue_sheet_track(0, 1), cue_sheet_track(4000, 2), cue_sheet_track(4410, 170)],
+    };
+    cue_sheet.tracks[1].indices.insert(0, CueSheetIndex { offset: 0, number: 0 });
+    cue_sheet.tracks[1].indices[1].offset = 200;
+    let data = with_cue_sheet("testsamples/wasted_bits.flac", &cue_sheet);
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(data)).unwrap();
+    assert_eq!(reader.track_duration(1), Some(Duration::new(0, 95_238_095)));
+    assert_eq!(reader.cue_sheet().unwrap().track_samples(2), Some(210));
+    assert_eq!(reader.track_duration(170), None);
+
+    let report = reader.seek_to_track(2).unwrap().unwrap();
+    assert_eq!(report.target, 4200);
+    assert!(report.is_exact);
+    let samples: Vec<i32> = reader.samples()
+        .skip(report.samples_to_skip() as usize)
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(&samples[..], &expected[4200..]);
+
+    let report = reader.seek_to_track(1).unwrap().unwrap();
+    assert_eq!(report.frame_sample, 0);
+    assert!(reader.seek_to_track(3).unwrap().is_none());
+
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    assert!(reader.seek_to_track(1).unwrap().is_none());
+    assert_eq!(reader.track_duration(1), None);
+}
+
+#[test]
+fn length_conflicts_are_checked() {
+    use claxon::LengthCheck;
+    use claxon::metadata::{CueSheet, LengthConflict, MetadataWarning};
+    use std::io::Read;
+
+    let new_reader = |data: &[u8], length_check: LengthCheck| {
+        let mut opts = claxon::FlacReaderOptions::default();
+        opts.length_check = length_check;
+        claxon::FlacReader::new_ext(io::Cursor::new(data.to_vec()), opts)
+    };
+
+    // A cue sheet whose lead-out is past the end of the stream.
+    let cue_sheet = CueSheet {
+        media_catalog_number: String::new(),
+        lead_in: 0,
+        is_cd: false,
+        tracks: vec![cue_sheet_track(0, 1), cue_sheet_track(588, 170)],
+    };
+    let data = with_cue_sheet("testsamples/pop.flac", &cue_sheet);
+    let conflict = LengthConflict::LeadOutMismatch { total_samples: 100, lead_out: 588 };
+    let reader = new_reader(&data, LengthCheck::Warn).unwrap();
+    assert_eq!(reader.metadata_warnings(), &[MetadataWarning::LengthConflict(conflict)]);
+    let reader = new_reader(&data, LengthCheck::Ignore).unwrap();
+    assert!(reader.metadata_warnings().is_empty());
+    match new_reader(&data, LengthCheck::Fail) {
+        Err(claxon::Error::FormatError(..)) => {}
+        other => panic!("expected format error, got {:?}", other.err()),
+    }
+
+    // A streaminfo that claims 200 samples, while the frames hold 100.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data[25], 100);
+    data[25] = 200;
+    let conflict = LengthConflict::DecodedLengthMismatch {
+        total_samples: 200,
+        decoded_samples: 100,
+    };
+    let mut reader = new_reader(&data, LengthCheck::Warn).unwrap();
+    let mut samples = reader.samples();
+    assert!(samples.by_ref().all(|s| s.is_ok()));
+    assert_eq!(samples.length_conflict(), Some(conflict));
+
+    let mut reader = new_reader(&data, LengthCheck::Warn).unwrap();
+    let mut samples = reader.samples();
+    samples.for_each_sample(|_| ()).unwrap();
+    assert_eq!(samples.length_conflict(), Some(conflict));
+
+    let mut reader = new_reader(&data, LengthCheck::Fail).unwrap();
+    let results: Vec<_> = reader.samples().collect();
+    assert!(results[..results.len() - 1].iter().all(|s| s.is_ok()));
+    assert!(results[results.len() - 1].is_err());
+
+    // Skipping corrupt frames does not hide the conflict, no frame was lost.
+    let mut reader = new_reader(&data, LengthCheck::Fail).unwrap();
+    let mut samples = reader.samples().lenient(64);
+    assert_eq!(samples.by_ref().filter(|s| s.is_err()).count(), 1);
+    assert_eq!(samples.skipped_frames(), 0);
+    assert_eq!(samples.skipped_bytes(), 0);
+
+    let reader = new_reader(&data, LengthCheck::Ignore).unwrap();
+    let mut samples = reader.into_samples();
+    assert!(samples.by_ref().all(|s| s.is_ok()));
+    assert_eq!(samples.length_conflict(), None);
+
+    // The unmodified stream has no conflict.
+    let mut reader = claxon::FlacReader::open("testsamples/pop.flac").unwrap();
+    let mut samples = reader.samples();
+    assert!(samples.by_ref().all(|s| s.is_ok()));
+    assert_eq!(samples.length_conflict(), None);
+}
+
+#[test]
+fn frame_stats_cover_all_audio_data() {
+    for fname in &["testsamples/pop.flac",
+                   "testsamples/short.flac",
+                   "testsamples/wasted_bits.flac",
+                   "testsamples/non_subset.flac"] {
+        let file_len = fs::metadata(fname).unwrap().len();
+        let mut reader = claxon::FlacReader::open(fname).unwrap();
+        reader.set_collect_stats(true);
+        let streaminfo = reader.streaminfo();
+        let audio_len = file_len - reader.first_frame_offset().unwrap();
+        let mut samples = reader.samples();
+        assert_eq!(samples.by_ref().count() as u64,
+                   streaminfo.samples.unwrap() * streaminfo.channels as u64);
+
+        let stats = samples.frame_stats();
+        assert_eq!(stats.compressed_bytes, audio_len);
+        assert_eq!(stats.samples, streaminfo.samples.unwrap());
+        assert_eq!(stats.uncompressed_bits,
+                   stats.samples * (streaminfo.channels * streaminfo.bits_per_sample) as u64);
+        assert_eq!(stats.size_histogram.iter().sum::<u64>(), stats.frames);
+        assert!(stats.min_frame_size <= stats.max_frame_size);
+        assert_eq!(stats.compression_ratio(),
+                   Some(audio_len as f64 * 8.0 / stats.uncompressed_bits as f64));
+    }
+}
+
+#[test]
+fn samples_are_reordered_within_window() {
+    use claxon::frame::{FrameOrdering, FrameSpec, write_frame};
+    use claxon::metadata::{MetadataBlock, StreamInfo, write_metadata_block};
+
+    // A variable block size mono stream whose second and third frame are swapped.
+    let streaminfo = StreamInfo {
+        min_block_size: 16,
+        max_block_size: 16,
+        min_frame_size: None,
+        max_frame_size: None,
+        sample_rate: 8_000,
+        channels: 1,
+        bits_per_sample: 16,
+        samples: Some(64),
+        md5sum: [0; 16],
+    };
+    let samples: Vec<i32> = (0..64).collect();
+    let mut data = b"fLaC".to_vec();
+    write_metadata_block(&mut data, true, &MetadataBlock::StreamInfo(streaminfo)).unwrap();
+    for &start in &[0, 32, 16, 48] {
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start as u64,
+            sample_rate: None,
+            bits_per_sample: 16,
+        };
+        write_frame(&spec, &[&samples[start..start + 16]], &mut data).unwrap();
+    }
+
+    // By default, iteration stops at the frame that is out of order.
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    let decoded: Vec<_> = reader.samples().collect();
+    assert_eq!(decoded.len(), 33);
+    assert!(decoded[32].is_err());
+
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    reader.set_frame_ordering(FrameOrdering::Reorder(1));
+    let decoded: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(decoded, samples);
+}
+
+#[test]
+fn memory_usage_drops_after_shrinking_block_buffer() {
+    use claxon::frame::{FrameSpec, write_frame};
+    use claxon::metadata::{MetadataBlock, StreamInfo, VorbisComment, write_metadata_block};
+
+    // A variable block size mono stream with one large frame, and then small ones.
+    let streaminfo = StreamInfo {
+        min_block_size: 256,
+        max_block_size: 4096,
+        min_frame_size: None,
+        max_frame_size: None,
+        sample_rate: 8_000,
+        channels: 1,
+        bits_per_sample: 16,
+        samples: Some(4096 + 4 * 256),
+        md5sum: [0; 16],
+    };
+    let mut vorbis_comment = VorbisComment::new("claxon".to_string());
+    vorbis_comment.push("TITLE", "Peaks");
+    let samples: Vec<i32> = (0..4096 + 4 * 256).map(|i| (i * 7919) % 65536 - 32768).collect();
+    let mut data = b"fLaC".to_vec();
+    write_metadata_block(&mut data, false, &MetadataBlock::StreamInfo(streaminfo)).unwrap();
+    write_metadata_block(&mut data, true, &MetadataBlock::VorbisComment(vorbis_comment)).unwrap();
+    let mut start = 0;
+    for &len in &[4096, 256, 256, 256, 256] {
+        let spec = FrameSpec {
+            variable_block_size: true,
+            number: start as u64,
+            sample_rate: None,
+            bits_per_sample: 16,
+        };
+        write_frame(&spec, &[&samples[start..start + len]], &mut data).unwrap();
+        start += len;
+    }
+
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    let usage = reader.memory_usage();
+    assert!(usage.input_buffer > 0);
+    assert!(usage.metadata >= "claxon".len() + "TITLE=Peaks".len());
+    assert_eq!(usage.block_buffer, 0);
+
+    // Once in the second frame, the buffer still holds the first one's capacity.
+    let mut iter = reader.samples();
+    let decoded: Vec<i32> = iter.by_ref().take(4097).map(|s| s.unwrap()).collect();
+    assert_eq!(iter.memory_usage().block_buffer, 4096 * 4);
+    iter.shrink_block_buffer(4096);
+    assert_eq!(iter.memory_usage().block_buffer, 4096 * 4);
+    iter.shrink_block_buffer(256);
+    assert_eq!(iter.memory_usage().block_buffer, 256 * 4);
+    assert_eq!(iter.memory_usage().total(), 256 * 4);
+
+    let rest: Vec<i32> = iter.map(|s| s.unwrap()).collect();
+    assert_eq!(&decoded[..], &samples[..4097]);
+    assert_eq!(&rest[..], &samples[4097..]);
+}
+
+#[test]
+fn take_exact_pads_or_trims() {
+    use claxon::ShortStream;
+
+    let fname = "testsamples/non_subset.flac";
+    let mut reader = claxon::FlacReader::open(fname).unwrap();
+    let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    let samples = reader.streaminfo().samples.unwrap();
+    assert_eq!(reader.streaminfo().channels, 2);
+
+    // Trimming yields a prefix of the stream.
+    let reader = claxon::FlacReader::open(fname).unwrap();
+    let trimmed: Vec<i32> = reader.into_samples()
+        .take_exact(samples - 10, ShortStream::Error)
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(&trimmed[..], &expected[..expected.len() - 20]);
+
+    // Padding appends silence for all channels.
+    let reader = claxon::FlacReader::open(fname).unwrap();
+    let padded: Vec<i32> = reader.into_samples()
+        .take_exact(samples + 10, ShortStream::PadWithSilence)
+        .map(|s| s.unwrap())
+        .collect();
+    assert_eq!(&padded[..expected.len()], &expected[..]);
+    assert_eq!(&padded[expected.len()..], &[0; 20][..]);
+
+    // Otherwise, a short stream ends with an error.
+    let reader = claxon::FlacReader::open(fname).unwrap();
+    let results: Vec<_> = reader.into_samples()
+        .take_exact(samples + 10, ShortStream::Error)
+        .collect();
+    assert_eq!(results.len(), expected.len() + 1);
+    match results[expected.len()] {
+        Err(claxon::Error::IoError(ref err)) => {
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof)
+        }
+        _ => panic!("expected an unexpected EOF error"),
+    }
+}
+
+#[test]
+fn readers_and_metadata_are_send_and_sync() {
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    assert_send::<claxon::FlacReader<fs::File>>();
+    assert_sync::<claxon::FlacReader<fs::File>>();
+    assert_send::<claxon::FlacIntoSamples<claxon::input::BufferedReader<fs::File>>>();
+    assert_send::<claxon::FlacSamples<&mut claxon::input::BufferedReader<fs::File>>>();
+    assert_send::<claxon::metadata::StreamInfo>();
+    assert_sync::<claxon::metadata::StreamInfo>();
+    assert_send::<claxon::metadata::VorbisComment>();
+    assert_sync::<claxon::metadata::VorbisComment>();
+    assert_send::<claxon::Error>();
+}
+
+#[test]
+fn channel_selection_applies_to_samples() {
+    use claxon::frame::ChannelSelection;
+
+    // This stereo file uses side channel coding, so both subframes are needed
+    // to select either channel.
+    let fname = "testsamples/non_subset.flac";
+    let mut reader = claxon::FlacReader::open(fname).unwrap();
+    let stereo: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+    let mut reader = claxon::FlacReader::open(fname).unwrap();
+    reader.set_channel_selection(ChannelSelection::Mask(0b10));
+    let right: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    let expected: Vec<i32> = stereo.chunks(2).map(|pair| pair[1]).collect();
+    assert_eq!(right, expected);
+
+    let mut reader = claxon::FlacReader::open(fname).unwrap();
+    reader.set_channel_selection(ChannelSelection::Stereo);
+    let downmix: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(downmix, stereo);
+
+    // A mono stream is duplicated into both channels of a stereo downmix.
+    let fname = "testsamples/wasted_bits.flac";
+    let mut reader = claxon::FlacReader::open(fname).unwrap();
+    let mono: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    let mut reader = claxon::FlacReader::open(fname).unwrap();
+    reader.set_channel_selection(ChannelSelection::Stereo);
+    let downmix: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    let expected: Vec<i32> = mono.iter().flat_map(|&s| vec![s, s]).collect();
+    assert_eq!(downmix, expected);
+}
+
 #[test]
 #[should_panic]
 fn samples_panics_when_metadata_only_is_set() {
     let opts = claxon::FlacReaderOptions {
         metadata_only: true,
         read_vorbis_comment: true,
+        read_seek_table: false,
+        read_pictures: false,
+        lenient_metadata: false,
+        strict_metadata: false,
+        length_check: claxon::LengthCheck::Warn,
+        metadata_limits: claxon::metadata::MetadataLimits::default(),
+        max_junk_len: 0,
     };
     let mut reader = claxon::FlacReader::open_ext("testsamples/short.flac", opts).unwrap();
     let _samples = reader.samples();
@@ -462,11 +2112,133 @@ fn blocks_panics_when_metadata_only_is_set() {
     let opts = claxon::FlacReaderOptions {
         metadata_only: true,
         read_vorbis_comment: true,
+        read_seek_table: false,
+        read_pictures: false,
+        lenient_metadata: false,
+        strict_metadata: false,
+        length_check: claxon::LengthCheck::Warn,
+        metadata_limits: claxon::metadata::MetadataLimits::default(),
+        max_junk_len: 0,
     };
     let mut reader = claxon::FlacReader::open_ext("testsamples/short.flac", opts).unwrap();
     let _blocks = reader.blocks();
 }
 
+#[test]
+fn samples_resume_skips_frame_with_crc_mismatch() {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut data).unwrap();
+
+    // This file has two frames, the second one starts at byte 1129. Corrupt the
+    // CRC-16 footer of the first frame.
+    let mut corrupted = data.clone();
+    corrupted[1127] ^= 0xff;
+
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(data)).unwrap();
+    let mut blocks = reader.blocks();
+    let first_len = blocks.read_next_or_eof(Vec::new()).unwrap().unwrap().len();
+    let second_len = blocks.read_next_or_eof(Vec::new()).unwrap().unwrap().len();
+
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(corrupted)).unwrap();
+    let mut samples = reader.samples();
+    let mut n_samples = 0;
+    let mut n_errors = 0;
+    while let Some(sample) = samples.next() {
+        match sample {
+            Ok(..) => n_samples += 1,
+            Err(..) => {
+                // The buffer that the corrupt frame was decoded into is kept
+                // for the next frame.
+                assert!(samples.memory_usage().block_buffer > 0);
+                n_errors += 1;
+                assert!(samples.resume());
+            }
+        }
+    }
+
+    assert!(first_len > 0);
+    assert_eq!(n_errors, 1);
+    assert_eq!(n_samples, second_len);
+    assert_eq!(samples.skipped_frames(), 1);
+}
+
+#[test]
+fn samples_resync_skips_junk_between_frames() {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+    // This file has two frames, the second one starts at byte 1129. Insert
+    // junk before it, which includes a frame sync code.
+    let mut corrupted = data[..1129].to_vec();
+    let junk = [0xff, 0xf8, 0x13, 0x37, 0x00, 0x00, 0xff, 0xff, 0x42];
+    corrupted.extend_from_slice(&junk);
+    corrupted.extend_from_slice(&data[1129..]);
+
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(corrupted)).unwrap();
+    let mut samples = reader.samples();
+    let mut decoded = Vec::new();
+    let mut n_errors = 0;
+    while let Some(sample) = samples.next() {
+        match sample {
+            Ok(s) => decoded.push(s),
+            Err(..) => {
+                n_errors += 1;
+                // The error is in the middle of the junk, not at a frame boundary.
+                assert!(!samples.resume());
+                assert!(samples.resync(64));
+            }
+        }
+    }
+
+    // No samples were lost, because the junk did not replace a frame.
+    assert_eq!(n_errors, 1);
+    assert_eq!(decoded, expected);
+    assert_eq!(samples.skipped_frames(), 1);
+    assert!(samples.skipped_bytes() > 0);
+    assert!(samples.skipped_bytes() < junk.len() as u64);
+}
+
+#[test]
+fn lenient_samples_skip_corrupt_frames() {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&data[..])).unwrap();
+    let expected: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+
+    // Corrupt the CRC-16 footer of the first frame, which ends at byte 1129,
+    // and insert junk before the second frame.
+    let mut corrupted = data[..1129].to_vec();
+    corrupted[1127] ^= 0xff;
+    corrupted.extend_from_slice(&[0xff, 0xf8, 0x13, 0x37, 0x00, 0x00, 0xff, 0xff, 0x42]);
+    corrupted.extend_from_slice(&data[1129..]);
+
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&corrupted[..])).unwrap();
+    let mut samples = reader.samples().lenient(64);
+    let decoded: Vec<i32> = samples.by_ref().map(|s| s.unwrap()).collect();
+
+    // Only the samples of the first frame are lost. Looking for the next frame
+    // after it skips the junk.
+    let first_len = expected.len() - decoded.len();
+    assert!(first_len > 0);
+    assert_eq!(&decoded[..], &expected[first_len..]);
+    assert_eq!(samples.skipped_frames(), 1);
+    assert!(samples.skipped_bytes() > 0);
+
+    // When there is no frame header in range, the error is returned.
+    let mut reader = claxon::FlacReader::new(io::Cursor::new(&corrupted[..])).unwrap();
+    let mut samples = reader.samples().lenient(2);
+    assert!(samples.by_ref().any(|s| s.is_err()));
+    assert_eq!(samples.next().map(|s| s.is_ok()), None);
+}
+
 #[test]
 fn verify_extra_samples() {
     use std::ffi::OsStr;
@@ -538,3 +2310,84 @@ fn regression_test_fuzz_samples() {
         }
     }
 }
+
+#[test]
+fn inspect_encoder_observes_lpc_orders_and_restores_position() {
+    use claxon::explain::{Element, SubframeKind};
+    use std::cmp;
+    use std::io::Read;
+
+    let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+    let expected: Vec<i32> = {
+        let mut reader = claxon::FlacReader::open("testsamples/wasted_bits.flac").unwrap();
+        reader.samples().map(|s| s.unwrap()).collect()
+    };
+
+    // Compute the highest LPC order of the first two frames by hand.
+    let mut data = Vec::new();
+    fs::File::open("testsamples/wasted_bits.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut frame_reader = claxon::frame::FrameReader::new(io::Cursor::new(&data[152..]));
+    let mut max_order = 0;
+    for _ in 0..2 {
+        for element in frame_reader.explain_next_or_eof().unwrap().unwrap() {
+            if let Element::SubframeHeader { kind: SubframeKind::Lpc { order }, .. } = element {
+                max_order = cmp::max(max_order, order as u32);
+            }
+        }
+    }
+
+    // Inspecting after the first block does not disturb decoding.
+    let first = reader.blocks().read_next_or_eof(Vec::new()).unwrap().unwrap();
+    let info = reader.inspect_encoder(2).unwrap();
+    assert_eq!(info.max_lpc_order, Some(max_order));
+    assert_eq!(info.encoder, reader.encoder_info().encoder);
+    let rest: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+    assert_eq!(&rest[..], &expected[first.duration() as usize..]);
+}
+
+#[test]
+fn picture_data_is_streamed_to_sink() {
+    use claxon::input::BufferedReader;
+    use claxon::metadata::{MetadataBlock, MetadataBlockReader, Picture, write_metadata_block};
+    use std::io::Read;
+
+    let picture = |picture_type: u32, len: usize| Picture {
+        picture_type: picture_type,
+        mime_type: "image/png".to_string(),
+        description: String::new(),
+        width: 1,
+        height: 1,
+        depth: 24,
+        colors: 0,
+        data: (0..len).map(|i| (i * 7) as u8).collect(),
+    };
+    let back = picture(4, 100);
+    let front = picture(3, 10_000);
+
+    let mut data = Vec::new();
+    fs::File::open("testsamples/pop.flac").unwrap().read_to_end(&mut data).unwrap();
+    let mut flac = data[..42].to_vec();
+    flac[4] &= 0x7f;
+    write_metadata_block(&mut flac, false, &MetadataBlock::Picture(back)).unwrap();
+    write_metadata_block(&mut flac, true, &MetadataBlock::Picture(front.clone())).unwrap();
+    flac.extend_from_slice(&data[42..]);
+
+    let mut sink = Vec::new();
+    assert_eq!(front.write_to(&mut sink).unwrap(), 10_000);
+    assert_eq!(sink, front.data);
+
+    // The back cover is skipped, the front cover is streamed.
+    let input = BufferedReader::new(io::Cursor::new(&flac[4..]));
+    let mut reader = MetadataBlockReader::new(input);
+    let mut sink = Vec::new();
+    assert_eq!(reader.write_picture_to(3, &mut sink).unwrap(), Some(10_000));
+    assert_eq!(sink, front.data);
+    assert!(reader.next().is_none());
+
+    // Without a matching picture, the reader ends after the metadata.
+    let input = BufferedReader::new(io::Cursor::new(&flac[4..]));
+    let mut reader = MetadataBlockReader::new(input);
+    assert_eq!(reader.write_picture_to(0, &mut Vec::new()).unwrap(), None);
+    let input = reader.into_inner();
+    assert_eq!(input.position() as usize, flac.len() - 4 - (data.len() - 42));
+}